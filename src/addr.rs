@@ -0,0 +1,156 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::Wrapper;
+use bitcoin_hashes::sha256t;
+use secp256k1::PublicKey;
+
+use crate::app::UnknownAppName;
+use crate::{MesgId, StormApp};
+
+/// URI scheme prefix used by [`StormAddr`] string representation.
+pub const STORM_ADDR_SCHEME: &str = "storm:";
+
+/// Errors parsing [`StormAddr`] string representation.
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AddrParseError {
+    /// storm address must start with `storm:` prefix.
+    NoScheme,
+
+    /// storm address must contain node id and app name.
+    Incomplete,
+
+    /// invalid node id. Details: {0}
+    #[from]
+    NodeId(secp256k1::Error),
+
+    #[display(inner)]
+    #[from]
+    App(UnknownAppName),
+
+    /// invalid topic id. Details: {0}
+    #[from]
+    TopicId(bitcoin_hashes::hex::Error),
+}
+
+/// Address of a storm app at a remote node, optionally pointing to a
+/// specific topic.
+///
+/// String representation has form of `storm:<node_id>/<app>[/<topic_id>]`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct StormAddr {
+    pub node_id: PublicKey,
+    pub app: StormApp,
+    pub topic_id: Option<MesgId>,
+}
+
+impl StormAddr {
+    pub fn with(node_id: PublicKey, app: StormApp) -> StormAddr {
+        StormAddr {
+            node_id,
+            app,
+            topic_id: None,
+        }
+    }
+
+    pub fn with_topic(
+        node_id: PublicKey,
+        app: StormApp,
+        topic_id: MesgId,
+    ) -> StormAddr {
+        StormAddr {
+            node_id,
+            app,
+            topic_id: Some(topic_id),
+        }
+    }
+}
+
+impl Display for StormAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}/{}", STORM_ADDR_SCHEME, self.node_id, self.app)?;
+        if let Some(topic_id) = self.topic_id {
+            write!(f, "/{}", topic_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StormAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .strip_prefix(STORM_ADDR_SCHEME)
+            .ok_or(AddrParseError::NoScheme)?;
+        let mut parts = s.split('/');
+        let (node_id, app) = match (parts.next(), parts.next()) {
+            (Some(node_id), Some(app)) => (node_id, app),
+            _ => return Err(AddrParseError::Incomplete),
+        };
+        let topic_id = parts
+            .next()
+            .map(|topic_id| {
+                sha256t::Hash::from_str(topic_id).map(MesgId::from_inner)
+            })
+            .transpose()?;
+        if parts.next().is_some() {
+            return Err(AddrParseError::Incomplete);
+        }
+        Ok(StormAddr {
+            node_id: PublicKey::from_str(node_id)?,
+            app: StormApp::from_str(app)?,
+            topic_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::CommitVerify;
+
+    use super::*;
+
+    #[test]
+    fn test_display_from_str() {
+        let node_id = PublicKey::from_str(
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+        )
+        .unwrap();
+        let addr = StormAddr::with(node_id, StormApp::Chat);
+        assert_eq!(
+            addr.to_string(),
+            "storm:02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443/chat"
+        );
+        assert_eq!(StormAddr::from_str(&addr.to_string()), Ok(addr));
+
+        let addr = StormAddr::with_topic(
+            node_id,
+            StormApp::Vendor(0x8001),
+            MesgId::commit(b"topic"),
+        );
+        assert_eq!(StormAddr::from_str(&addr.to_string()), Ok(addr));
+
+        assert_eq!(
+            StormAddr::from_str("storm:02e6642f"),
+            Err(AddrParseError::Incomplete)
+        );
+    }
+}
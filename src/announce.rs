@@ -0,0 +1,309 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Cache of container announcements received from peers.
+//!
+//! Each container announced with `AnnounceContainer` or `AnnounceContainers`
+//! message is kept together with the peer which sent it, which is assumed to be
+//! a provider of the container. Records expire after a time-to-live chosen by
+//! the node; expired records are not returned by queries and are removed with
+//! [`AnnouncementCache::prune`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use secp256k1::PublicKey;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::p2p::{AppMsg, MAX_ANNOUNCE_BATCH};
+use crate::{
+    ContainerFullId, ContainerHeader, ContainerId, ContainerInfo, StormApp, Tag,
+};
+
+/// Default time during which a received announcement is kept, in seconds.
+pub const DEFAULT_ANNOUNCEMENT_TTL: u64 = 24 * 60 * 60;
+
+/// Container announcement received from a peer.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{app}:{id} from {source} until {expiry}")]
+pub struct AnnouncementRecord {
+    pub app: StormApp,
+    pub header: ContainerHeader,
+    pub id: ContainerFullId,
+    /// Tags of the container provided with the announcement.
+    pub tags: BTreeSet<Tag>,
+    /// Peer which sent the announcement.
+    pub source: PublicKey,
+    /// Unix timestamp when the announcement was received.
+    pub received: u64,
+    /// Unix timestamp after which the announcement is discarded.
+    pub expiry: u64,
+}
+
+impl AnnouncementRecord {
+    fn with(
+        app: StormApp,
+        info: &ContainerInfo,
+        source: PublicKey,
+        now: u64,
+        ttl: u64,
+    ) -> AnnouncementRecord {
+        AnnouncementRecord {
+            app,
+            header: info.header.clone(),
+            id: info.id,
+            tags: info.tags.clone(),
+            source,
+            received: now,
+            expiry: now.saturating_add(ttl),
+        }
+    }
+
+    pub fn container_id(&self) -> ContainerId { self.id.container_id }
+
+    /// Container information as it was announced.
+    pub fn info(&self) -> ContainerInfo {
+        ContainerInfo {
+            header: self.header.clone(),
+            id: self.id,
+            tags: self.tags.clone(),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool { self.expiry <= now }
+}
+
+/// Cache of container announcements indexed by container id and app.
+///
+/// For each container the cache keeps a single record per source peer and
+/// app: repeated announcements replace the previous record.
+///
+/// Only the records are persisted with strict encoding; the app index is
+/// rebuilt on decoding.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct AnnouncementCache {
+    records: BTreeMap<ContainerId, Vec<AnnouncementRecord>>,
+    by_app: BTreeMap<StormApp, BTreeSet<ContainerId>>,
+}
+
+impl StrictEncode for AnnouncementCache {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        // Same encoding as `Vec<AnnouncementRecord>`
+        let count = self.len();
+        if count > u16::MAX as usize {
+            return Err(strict_encoding::Error::ExceedMaxItems(count));
+        }
+        let mut len = (count as u16).strict_encode(&mut e)?;
+        for record in self.records.values().flatten() {
+            len += record.strict_encode(&mut e)?;
+        }
+        Ok(len)
+    }
+}
+
+impl StrictDecode for AnnouncementCache {
+    fn strict_decode<D: io::Read>(
+        d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let records = Vec::<AnnouncementRecord>::strict_decode(d)?;
+        let mut cache = AnnouncementCache::new();
+        for record in records {
+            cache.insert(record);
+        }
+        Ok(cache)
+    }
+}
+
+impl AnnouncementCache {
+    pub fn new() -> AnnouncementCache { AnnouncementCache::default() }
+
+    /// Number of stored records, including expired ones.
+    pub fn len(&self) -> usize { self.records.values().map(Vec::len).sum() }
+
+    pub fn is_empty(&self) -> bool { self.records.is_empty() }
+
+    /// Adds record to the cache, replacing the record for the same container
+    /// and app received from the same peer.
+    pub fn insert(&mut self, record: AnnouncementRecord) {
+        let container_id = record.container_id();
+        self.by_app.entry(record.app).or_default().insert(container_id);
+        let records = self.records.entry(container_id).or_default();
+        records.retain(|r| r.source != record.source || r.app != record.app);
+        records.push(record);
+    }
+
+    /// Stores `AnnounceContainer` message received from `source` at time
+    /// `now`, keeping it for `ttl` seconds.
+    pub fn announce(
+        &mut self,
+        msg: &AppMsg<ContainerInfo>,
+        source: PublicKey,
+        now: u64,
+        ttl: u64,
+    ) {
+        self.insert(AnnouncementRecord::with(
+            msg.app, &msg.data, source, now, ttl,
+        ))
+    }
+
+    /// Stores all containers from `AnnounceContainers` message received from
+    /// `source` at time `now`, keeping them for `ttl` seconds. Batches with
+    /// more than [`MAX_ANNOUNCE_BATCH`] containers are ignored; returns
+    /// whether the batch was stored.
+    pub fn announce_batch(
+        &mut self,
+        msg: &AppMsg<Vec<ContainerInfo>>,
+        source: PublicKey,
+        now: u64,
+        ttl: u64,
+    ) -> bool {
+        if msg.data.len() > MAX_ANNOUNCE_BATCH {
+            return false;
+        }
+        for info in &msg.data {
+            self.insert(AnnouncementRecord::with(
+                msg.app, info, source, now, ttl,
+            ));
+        }
+        true
+    }
+
+    /// Unexpired announcements of the container.
+    pub fn container(
+        &self,
+        container_id: ContainerId,
+        now: u64,
+    ) -> impl Iterator<Item = &AnnouncementRecord> + '_ {
+        self.records
+            .get(&container_id)
+            .into_iter()
+            .flatten()
+            .filter(move |record| !record.is_expired(now))
+    }
+
+    /// Unexpired announcements made within the app.
+    pub fn app(
+        &self,
+        app: StormApp,
+        now: u64,
+    ) -> impl Iterator<Item = &AnnouncementRecord> + '_ {
+        self.by_app
+            .get(&app)
+            .into_iter()
+            .flatten()
+            .flat_map(move |container_id| self.container(*container_id, now))
+            .filter(move |record| record.app == app)
+    }
+
+    /// Unexpired announcements made within the app of the containers tagged
+    /// with all the given tags.
+    pub fn tagged<'cache>(
+        &'cache self,
+        app: StormApp,
+        tags: &'cache BTreeSet<Tag>,
+        now: u64,
+    ) -> impl Iterator<Item = &'cache AnnouncementRecord> + 'cache {
+        self.app(app, now).filter(move |record| tags.is_subset(&record.tags))
+    }
+
+    /// Peers which announced the container and whose announcements have not
+    /// expired at `now`.
+    pub fn providers(
+        &self,
+        container_id: ContainerId,
+        now: u64,
+    ) -> BTreeSet<PublicKey> {
+        self.container(container_id, now).map(|record| record.source).collect()
+    }
+
+    /// Discards announcements which have expired at `now`.
+    pub fn prune(&mut self, now: u64) {
+        self.records.retain(|_, records| {
+            records.retain(|record| !record.is_expired(now));
+            !records.is_empty()
+        });
+        let records = &self.records;
+        self.by_app.retain(|app, container_ids| {
+            container_ids.retain(|container_id| {
+                records.get(container_id).map_or(false, |records| {
+                    records.iter().any(|record| record.app == *app)
+                })
+            });
+            !container_ids.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::{Secp256k1, SecretKey};
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::{Container, MesgId};
+
+    #[test]
+    fn test_cache() {
+        let secp = Secp256k1::new();
+        let alice = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x11; 32]).unwrap(),
+        );
+        let bob = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x22; 32]).unwrap(),
+        );
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", b"announced", 64)
+                .unwrap();
+        let container_id = container.container_id();
+        let info = ContainerInfo {
+            header: container.header,
+            id: ContainerFullId {
+                message_id: MesgId::default(),
+                container_id,
+            },
+            tags: bset! { "video".parse().unwrap() },
+        };
+        let msg = AppMsg::new(StormApp::Storage, info);
+
+        let mut cache = AnnouncementCache::new();
+        cache.announce(&msg, alice, 100, 50);
+        cache.announce(&msg, bob, 120, 50);
+        cache.announce(&msg, alice, 130, 50);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.providers(container_id, 160), bset! { alice, bob });
+        assert_eq!(cache.providers(container_id, 175), bset! { alice });
+        assert_eq!(cache.app(StormApp::Storage, 160).count(), 2);
+        assert_eq!(cache.app(StormApp::Chat, 160).count(), 0);
+        let video = bset! { "video".parse().unwrap() };
+        let audio = bset! { "audio".parse().unwrap() };
+        assert_eq!(cache.tagged(StormApp::Storage, &video, 160).count(), 2);
+        assert_eq!(cache.tagged(StormApp::Storage, &audio, 160).count(), 0);
+
+        let data = cache.strict_serialize().unwrap();
+        assert_eq!(AnnouncementCache::strict_deserialize(data).unwrap(), cache);
+
+        cache.prune(175);
+        assert_eq!(cache.len(), 1);
+        cache.prune(180);
+        assert!(cache.is_empty());
+        assert_eq!(cache.app(StormApp::Storage, 100).count(), 0);
+    }
+}
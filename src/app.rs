@@ -9,6 +9,7 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::io;
+use std::str::FromStr;
 
 use strict_encoding::{StrictDecode, StrictEncode};
 
@@ -130,3 +131,36 @@ impl From<u16> for StormApp {
         }
     }
 }
+
+/// Error parsing storm app name.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display("unknown storm app name '{0}'")]
+pub struct UnknownAppName(pub String);
+
+impl FromStr for StormApp {
+    type Err = UnknownAppName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_code = |code: &str| match code.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => u16::from_str(code).ok(),
+        };
+        Ok(match s {
+            "system" => StormApp::System,
+            "chat" => StormApp::Chat,
+            "file-transfer" => StormApp::FileTransfer,
+            "storage" => StormApp::Storage,
+            "search" => StormApp::Search,
+            "rgb-contracts" => StormApp::RgbContracts,
+            "rgb-transfers" => StormApp::RgbTransfers,
+            other => other
+                .strip_prefix("future(")
+                .or_else(|| other.strip_prefix("vendor("))
+                .and_then(|rest| rest.strip_suffix(')'))
+                .or(Some(other))
+                .and_then(parse_code)
+                .map(StormApp::from)
+                .ok_or_else(|| UnknownAppName(s.to_owned()))?,
+        })
+    }
+}
@@ -0,0 +1,1379 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Proptest generators for the core storm types.
+//!
+//! Generated containers are consistent with the generated data: they are
+//! produced by [`Container::chunkify`], so their chunk ids and sizes match.
+//! Signed data carry valid signatures of random keys, and locked chunks and
+//! revealed keys match their payment hashes. Data sizes are kept small to
+//! allow thousands of cases per test.
+
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use bitcoin_hashes::{sha256t, Hash};
+use once_cell::sync::Lazy;
+use proptest::collection::{btree_map, btree_set, vec};
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::{select, Index};
+use secp256k1::{All, PublicKey, Secp256k1, SecretKey};
+use stens::AsciiString;
+use strict_encoding::MediumVec;
+
+use crate::bloom::BloomFilter;
+use crate::broadcast::Broadcast;
+use crate::credit::{Credit, Iou, SignedIou};
+use crate::encryption::{SealedPost, WrappedKey};
+use crate::gcs::ContainerFilter;
+use crate::mailbox::{CollectMail, DepositMail, MailDelivery, OfflineDelivery};
+use crate::membership::{Invite, Join, Leave, MemberList, MemberRole};
+use crate::moderation::{Ban, RemoveMesg, Unban};
+use crate::p2p::{
+    Accept, AcceptTerms, AppMsg, ChunkBloomFilter, ChunkMismatch, ChunkPull,
+    ChunkPush, ChunkSetSketch, CompactChunkPull, ContainerList,
+    ContainerPartPull, ContainerQuery, Decline, FindTopics, KeyLookup,
+    KeyReveal, LockedChunkPush, MesgBatch, MesgSync, Messages, PrefetchHint,
+    RefusalReason, Reject, StreamChunkPull, SyncCursor,
+};
+use crate::posting::{
+    DeliveryFailure, DeliveryFailureReason, DeliveryPolicy, PaymentProof, Post,
+    PostInvoice, PostInvoiceRequest, PostRequirements, PostingToken, PowStamp,
+    TokenUse, TopicProposal,
+};
+use crate::reconcile::{MesgIdRange, RangeItem, Reconciliation};
+use crate::replication::{AgreementId, ReplicationTerms};
+use crate::rgb::ContractAnnouncement;
+use crate::sketch::PinSketch;
+use crate::sla::{
+    ChallengeResponse, Dispute, DisputeClaim, DisputeEvidence, DisputeId,
+    DisputeResolution, DisputeVerdict, Evidence, StorageChallenge,
+    ViolationProof,
+};
+use crate::storage::{
+    LeaseTerms, RemovalAck, RemovalRequest, Replica, ReplicaPlacement,
+    ReplicationConstraints, StorageQuota, StorageReceipt, StorageRequest,
+};
+use crate::swap::{SwapQuote, SwapRequest};
+use crate::varint::VarInt;
+use crate::{
+    AppsAdvert, BlockReuse, BlockSum, BlockSums, Chunk, ChunkId, ChunkPriority,
+    ChunkProof, ChunkSelection, Container, ContainerFullId, ContainerHeader,
+    ContainerId, ContainerInfo, ContainerPage, ContainerPart, MapProof, Mesg,
+    MesgId, PaymentPreimage, PeerLimits, RateLimit, RetrievalReceipt,
+    ReusePlan, Signable, Signed, SignedEnvelope, StormApp, StormIdentity, Tag,
+    Topic,
+};
+
+/// Maximal size of the generated message bodies and container data.
+pub const MAX_ARBITRARY_DATA: usize = 2048;
+
+/// Maximal number of items in the generated collections.
+const MAX_ARBITRARY_ITEMS: usize = 4;
+
+static SECP: Lazy<Secp256k1<All>> = Lazy::new(Secp256k1::new);
+
+/// Strategy generating chunk ids.
+pub fn chunk_id() -> impl Strategy<Value = ChunkId> {
+    any::<[u8; 32]>().prop_map(ChunkId::from_inner)
+}
+
+/// Strategy generating container MIME types.
+pub fn mime() -> impl Strategy<Value = AsciiString> {
+    select(vec![
+        "application/octet-stream",
+        "text/plain",
+        "image/png",
+        "audio/ogg",
+        "video/mp4",
+    ])
+    .prop_map(|mime| {
+        AsciiString::try_from(mime).expect("MIME types are ASCII strings")
+    })
+}
+
+/// Strategy generating reasons for declining messages and rejecting
+/// containers.
+pub fn refusal_reason() -> impl Strategy<Value = RefusalReason> {
+    select(vec![
+        RefusalReason::NotInterested,
+        RefusalReason::OverQuota,
+        RefusalReason::PaymentRequired,
+        RefusalReason::PolicyViolation,
+        RefusalReason::TemporarilyUnavailable,
+    ])
+}
+
+/// Strategy generating node secret keys.
+pub fn secret_key() -> impl Strategy<Value = SecretKey> {
+    any::<[u8; 32]>().prop_filter_map("invalid secret key", |bytes| {
+        SecretKey::from_slice(&bytes).ok()
+    })
+}
+
+/// Strategy generating node ids.
+pub fn public_key() -> impl Strategy<Value = PublicKey> {
+    secret_key().prop_map(|key| PublicKey::from_secret_key(&SECP, &key))
+}
+
+/// Strategy signing the generated data with random node keys.
+pub fn signed<T>(
+    data: impl Strategy<Value = T>,
+) -> impl Strategy<Value = Signed<T>>
+where T: Signable + Debug {
+    (data, secret_key()).prop_map(|(data, key)| Signed::sign(&SECP, data, &key))
+}
+
+/// Strategy generating topic and container tags.
+pub fn tag() -> impl Strategy<Value = Tag> {
+    "[a-z0-9-]{1,32}".prop_map(|tag| tag.parse::<Tag>().expect("valid tag"))
+}
+
+/// Strategy generating container headers of the current version.
+pub fn container_header() -> impl Strategy<Value = ContainerHeader> {
+    any::<Container>().prop_map(|container| container.header)
+}
+
+fn hash<H>() -> impl Strategy<Value = H>
+where H: Hash<Inner = [u8; 32]> {
+    any::<[u8; 32]>().prop_map(H::from_inner)
+}
+
+fn text() -> impl Strategy<Value = String> { "[a-zA-Z0-9 ]{0,32}" }
+
+fn bytes() -> impl Strategy<Value = Vec<u8>> { vec(any::<u8>(), 0..64) }
+
+fn tags() -> impl Strategy<Value = BTreeSet<Tag>> {
+    btree_set(tag(), 0..MAX_ARBITRARY_ITEMS)
+}
+
+fn app_msg<T>(data: T) -> impl Strategy<Value = AppMsg<T::Value>>
+where T: Strategy {
+    (any::<StormApp>(), data).prop_map(|(app, data)| AppMsg::new(app, data))
+}
+
+impl Arbitrary for MesgId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<[u8; 32]>()
+            .prop_map(|bytes| MesgId::from(sha256t::Hash::from_inner(bytes)))
+            .boxed()
+    }
+}
+
+impl Arbitrary for ContainerId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<[u8; 32]>()
+            .prop_map(|bytes| {
+                ContainerId::from(sha256t::Hash::from_inner(bytes))
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for ContainerFullId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<MesgId>(), any::<ContainerId>())
+            .prop_map(|(message_id, container_id)| ContainerFullId {
+                message_id,
+                container_id,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for StormApp {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<u16>().prop_map(StormApp::from).boxed()
+    }
+}
+
+impl Arbitrary for Chunk {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        vec(any::<u8>(), 0..MAX_ARBITRARY_DATA)
+            .prop_map(|data| {
+                Chunk::try_from(data).expect("data fit into a chunk")
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Container {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (
+            mime(),
+            "[a-zA-Z0-9 ]{0,32}",
+            vec(any::<u8>(), 0..MAX_ARBITRARY_DATA),
+            1u32..512,
+        )
+            .prop_map(|(mime, info, data, chunk_size)| {
+                Container::chunkify(mime, info, &data, chunk_size)
+                    .expect("small data fit into a container")
+                    .0
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Mesg {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<MesgId>(), any::<Chunk>(), vec(any::<ContainerId>(), 0..4))
+            .prop_map(|(parent_id, body, container_ids)| Mesg {
+                parent_id,
+                body,
+                container_ids,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Topic {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<Chunk>(), vec(any::<ContainerId>(), 0..4), tags())
+            .prop_map(|(body, container_ids, tags)| Topic {
+                body,
+                container_ids,
+                tags,
+            })
+            .boxed()
+    }
+}
+
+// Containers and chunks
+
+fn container_info() -> impl Strategy<Value = ContainerInfo> {
+    (any::<Container>(), any::<MesgId>(), tags()).prop_map(
+        |(container, message_id, tags)| ContainerInfo {
+            id: ContainerFullId {
+                message_id,
+                container_id: container.container_id(),
+            },
+            header: container.header,
+            tags,
+        },
+    )
+}
+
+fn chunk_selection() -> impl Strategy<Value = ChunkSelection> {
+    (0u32..64, 0u32..64, option::of(vec(any::<u8>(), 0..8))).prop_map(
+        |(a, b, bitmap)| ChunkSelection {
+            start: a.min(b),
+            end: a.max(b),
+            bitmap,
+        },
+    )
+}
+
+fn chunk_proof() -> impl Strategy<Value = ChunkProof> {
+    (any::<u32>(), vec(hash(), 0..8))
+        .prop_map(|(index, path)| ChunkProof { index, path })
+}
+
+fn container_part() -> impl Strategy<Value = ContainerPart> {
+    (any::<Container>(), chunk_selection())
+        .prop_map(|(container, selection)| container.part(&selection))
+}
+
+fn container_page() -> impl Strategy<Value = ContainerPage> {
+    (any::<Container>(), 1usize..16, any::<Index>()).prop_map(
+        |(container, page_chunks, index)| {
+            let mut pages = container.to_pages(page_chunks);
+            let index = index.index(pages.len());
+            pages.swap_remove(index)
+        },
+    )
+}
+
+fn chunk_priority() -> impl Strategy<Value = ChunkPriority> {
+    any::<Container>().prop_flat_map(|container| {
+        let count = container.chunks.len() as u32;
+        let container_id = container.container_id();
+        Just((0..count).collect::<Vec<_>>()).prop_shuffle().prop_map(
+            move |order| ChunkPriority {
+                container_id,
+                order: MediumVec::try_from(order)
+                    .expect("small containers have few chunks"),
+            },
+        )
+    })
+}
+
+fn map_proof() -> impl Strategy<Value = MapProof> {
+    (
+        any::<ContainerId>(),
+        bytes(),
+        container_header(),
+        vec((any::<Chunk>(), chunk_proof()), 0..MAX_ARBITRARY_ITEMS),
+    )
+        .prop_map(|(container_id, key, header, chunks)| MapProof {
+            container_id,
+            key,
+            header,
+            chunks,
+        })
+}
+
+fn chunk_pull() -> impl Strategy<Value = ChunkPull> {
+    (
+        any::<StormApp>(),
+        any::<MesgId>(),
+        any::<ContainerId>(),
+        btree_set(chunk_id(), 0..16),
+    )
+        .prop_map(|(app, message_id, container_id, chunk_ids)| ChunkPull {
+            app,
+            message_id,
+            container_id,
+            chunk_ids,
+        })
+}
+
+fn chunk_push() -> impl Strategy<Value = ChunkPush> {
+    (any::<StormApp>(), any::<ContainerId>(), any::<Chunk>()).prop_map(
+        |(app, container_id, chunk)| ChunkPush {
+            app,
+            container_id,
+            chunk_id: chunk.chunk_id(),
+            chunk,
+        },
+    )
+}
+
+fn compact_chunk_pull() -> impl Strategy<Value = CompactChunkPull> {
+    chunk_pull().prop_map(|pull| CompactChunkPull {
+        app: pull.app,
+        message_id: pull.message_id,
+        container_id: pull.container_id,
+        chunk_ids: pull.chunk_ids.into(),
+    })
+}
+
+fn stream_chunk_pull() -> impl Strategy<Value = StreamChunkPull> {
+    (
+        any::<StormApp>(),
+        any::<MesgId>(),
+        any::<ContainerId>(),
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(|(app, message_id, container_id, a, b)| {
+            StreamChunkPull {
+                app,
+                message_id,
+                container_id,
+                start_ms: a.min(b),
+                end_ms: a.max(b),
+            }
+        })
+}
+
+fn prefetch_hint() -> impl Strategy<Value = PrefetchHint> {
+    chunk_pull().prop_map(|pull| PrefetchHint {
+        app: pull.app,
+        container_id: pull.container_id,
+        chunk_ids: pull.chunk_ids,
+    })
+}
+
+fn chunk_mismatch() -> impl Strategy<Value = ChunkMismatch> {
+    (any::<StormApp>(), any::<ContainerId>(), chunk_id(), chunk_id()).prop_map(
+        |(app, container_id, expected, actual)| ChunkMismatch {
+            app,
+            container_id,
+            expected,
+            actual,
+        },
+    )
+}
+
+fn chunk_sketch() -> impl Strategy<Value = ChunkSetSketch> {
+    (prefetch_hint(), 0u16..32).prop_map(|(hint, capacity)| ChunkSetSketch {
+        app: hint.app,
+        container_id: hint.container_id,
+        sketch: PinSketch::with_chunks(capacity, &hint.chunk_ids),
+    })
+}
+
+fn chunk_filter() -> impl Strategy<Value = ChunkBloomFilter> {
+    (prefetch_hint(), any::<u32>()).prop_map(|(hint, tweak)| ChunkBloomFilter {
+        app: hint.app,
+        container_id: hint.container_id,
+        filter: BloomFilter::with_chunks(hint.chunk_ids.iter(), 0.01, tweak)
+            .expect("valid false-positive rate"),
+    })
+}
+
+fn container_filter() -> impl Strategy<Value = ContainerFilter> {
+    (any::<[u8; 16]>(), vec(any::<ContainerId>(), 0..16)).prop_map(
+        |(key, container_ids)| {
+            ContainerFilter::with(key, container_ids)
+                .expect("small filters fit into the encoding")
+        },
+    )
+}
+
+fn container_query() -> impl Strategy<Value = ContainerQuery> {
+    ("[a-z/]{0,12}", option::of(any::<ContainerId>()), any::<u16>()).prop_map(
+        |(mime_prefix, after, limit)| ContainerQuery {
+            mime_prefix,
+            after,
+            limit,
+        },
+    )
+}
+
+fn container_list() -> impl Strategy<Value = ContainerList> {
+    (
+        "[a-z/]{0,12}",
+        vec(container_info(), 0..MAX_ARBITRARY_ITEMS),
+        any::<bool>(),
+    )
+        .prop_map(|(mime_prefix, containers, more)| ContainerList {
+            mime_prefix,
+            containers,
+            more,
+        })
+}
+
+fn key_lookup() -> impl Strategy<Value = KeyLookup> {
+    (any::<ContainerId>(), bytes())
+        .prop_map(|(container_id, key)| KeyLookup { container_id, key })
+}
+
+fn block_sums() -> impl Strategy<Value = BlockSums> {
+    (
+        any::<ContainerId>(),
+        any::<u32>(),
+        vec(
+            (any::<u32>(), any::<u64>())
+                .prop_map(|(weak, strong)| BlockSum { weak, strong }),
+            0..16,
+        ),
+    )
+        .prop_map(|(container_id, block_size, sums)| BlockSums {
+            container_id,
+            block_size,
+            sums,
+        })
+}
+
+fn reuse_plan() -> impl Strategy<Value = ReusePlan> {
+    (
+        any::<ContainerId>(),
+        any::<u32>(),
+        vec(
+            (any::<u64>(), any::<u32>())
+                .prop_map(|(offset, block)| BlockReuse { offset, block }),
+            0..16,
+        ),
+        vec(chunk_id(), 0..16),
+    )
+        .prop_map(|(container_id, block_size, reuse, pull)| ReusePlan {
+            container_id,
+            block_size,
+            reuse: MediumVec::try_from(reuse).expect("few blocks"),
+            pull: MediumVec::try_from(pull).expect("few chunks"),
+        })
+}
+
+// Payments
+
+fn locked_chunk_push() -> impl Strategy<Value = LockedChunkPush> {
+    (chunk_push(), any::<[u8; 32]>()).prop_map(|(push, preimage)| {
+        let preimage = PaymentPreimage::from(preimage);
+        LockedChunkPush {
+            app: push.app,
+            container_id: push.container_id,
+            chunk_id: push.chunk_id,
+            payment_hash: preimage.payment_hash(),
+            locked_chunk: preimage
+                .lock_chunk(&push.chunk)
+                .expect("small chunks can be locked"),
+        }
+    })
+}
+
+fn key_reveal() -> impl Strategy<Value = KeyReveal> {
+    (any::<StormApp>(), any::<ContainerId>(), any::<[u8; 32]>()).prop_map(
+        |(app, container_id, preimage)| {
+            let preimage = PaymentPreimage::from(preimage);
+            KeyReveal {
+                app,
+                container_id,
+                payment_hash: preimage.payment_hash(),
+                preimage,
+            }
+        },
+    )
+}
+
+fn swap_request() -> impl Strategy<Value = SwapRequest> {
+    (any::<ContainerId>(), btree_set(chunk_id(), 0..16)).prop_map(
+        |(container_id, chunk_ids)| SwapRequest {
+            container_id,
+            chunk_ids,
+        },
+    )
+}
+
+fn swap_quote() -> impl Strategy<Value = SwapQuote> {
+    (swap_request(), any::<u64>(), hash(), text(), any::<u64>()).prop_map(
+        |(request, amount_msat, payment_hash, invoice, expiry)| SwapQuote {
+            container_id: request.container_id,
+            chunk_ids: request.chunk_ids,
+            amount_msat,
+            payment_hash,
+            invoice,
+            expiry,
+        },
+    )
+}
+
+fn credit() -> impl Strategy<Value = Credit> {
+    (any::<u64>(), any::<u64>()).prop_map(|(bytes_relayed, bytes_stored)| {
+        Credit {
+            bytes_relayed,
+            bytes_stored,
+        }
+    })
+}
+
+fn signed_iou() -> impl Strategy<Value = SignedIou> {
+    (secret_key(), public_key(), any::<u64>(), credit(), any::<u64>()).prop_map(
+        |(key, beneficiary, seq, credit, timestamp)| {
+            Iou {
+                issuer: PublicKey::from_secret_key(&SECP, &key),
+                beneficiary,
+                seq,
+                credit,
+                timestamp,
+            }
+            .sign(&SECP, &key)
+        },
+    )
+}
+
+// Topics and posts
+
+fn apps_advert() -> impl Strategy<Value = AppsAdvert> {
+    (
+        btree_set(any::<StormApp>(), 0..MAX_ARBITRARY_ITEMS),
+        (any::<u32>(), any::<u64>(), any::<u16>()),
+        btree_map(
+            any::<StormApp>(),
+            (any::<u32>(), any::<u32>()),
+            0..MAX_ARBITRARY_ITEMS,
+        ),
+    )
+        .prop_map(|(apps, limits, rate_limits)| AppsAdvert {
+            apps,
+            limits: PeerLimits {
+                max_chunk_size: limits.0,
+                max_container_size: limits.1,
+                max_transfers: limits.2,
+            },
+            rate_limits: rate_limits
+                .into_iter()
+                .map(|(app, (msgs_per_minute, bytes_per_minute))| {
+                    (app, RateLimit {
+                        msgs_per_minute,
+                        bytes_per_minute,
+                    })
+                })
+                .collect(),
+        })
+}
+
+fn pow_stamp() -> impl Strategy<Value = PowStamp> {
+    any::<u64>().prop_map(|nonce| PowStamp { nonce })
+}
+
+fn topic_proposal() -> impl Strategy<Value = TopicProposal> {
+    (any::<Topic>(), option::of(pow_stamp()))
+        .prop_map(|(topic, stamp)| TopicProposal { topic, stamp })
+}
+
+fn find_topics() -> impl Strategy<Value = FindTopics> {
+    (tags(), any::<u16>()).prop_map(|(tags, limit)| FindTopics { tags, limit })
+}
+
+fn post_invoice() -> impl Strategy<Value = PostInvoice> {
+    (any::<MesgId>(), any::<MesgId>(), any::<u64>(), hash(), text()).prop_map(
+        |(topic_id, mesg_id, amount_msat, payment_hash, invoice)| PostInvoice {
+            topic_id,
+            mesg_id,
+            amount_msat,
+            payment_hash,
+            invoice,
+        },
+    )
+}
+
+fn payment_proof() -> impl Strategy<Value = PaymentProof> {
+    (post_invoice(), any::<[u8; 32]>(), secret_key()).prop_map(
+        |(invoice, preimage, key)| {
+            let preimage = PaymentPreimage::from(preimage);
+            let invoice = PostInvoice {
+                payment_hash: preimage.payment_hash(),
+                ..invoice
+            };
+            PaymentProof {
+                invoice: Signed::sign(&SECP, invoice, &key),
+                preimage,
+            }
+        },
+    )
+}
+
+fn posting_token() -> impl Strategy<Value = PostingToken> {
+    (any::<MesgId>(), public_key(), any::<u32>(), any::<u64>(), any::<u64>())
+        .prop_map(|(topic_id, holder, posts, period, expiry)| PostingToken {
+            topic_id,
+            holder,
+            posts,
+            period,
+            expiry,
+        })
+}
+
+fn delivery_policy() -> impl Strategy<Value = DeliveryPolicy> {
+    (any::<u64>(), any::<u16>(), any::<u32>(), any::<bool>()).prop_map(
+        |(deadline, max_attempts, retry_interval, notify_failure)| {
+            DeliveryPolicy {
+                deadline,
+                max_attempts,
+                retry_interval,
+                notify_failure,
+            }
+        },
+    )
+}
+
+fn post() -> impl Strategy<Value = Post> {
+    (
+        any::<Mesg>(),
+        option::of(pow_stamp()),
+        option::of(payment_proof()),
+        option::of((signed(posting_token()), secret_key())),
+        option::of(delivery_policy()),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(mesg, stamp, payment, token, delivery, body_container)| {
+                let token = token.map(|(token, key)| {
+                    let token_use = TokenUse {
+                        mesg_id: mesg.mesg_id(),
+                        token,
+                    };
+                    Signed::sign(&SECP, token_use, &key)
+                });
+                let body_container = mesg
+                    .container_ids
+                    .first()
+                    .copied()
+                    .filter(|_| body_container);
+                Post {
+                    mesg,
+                    stamp,
+                    payment,
+                    token,
+                    delivery,
+                    body_container,
+                }
+            },
+        )
+}
+
+fn accept() -> impl Strategy<Value = Accept> {
+    (
+        any::<MesgId>(),
+        btree_set(any::<ContainerId>(), 0..MAX_ARBITRARY_ITEMS),
+        option::of(
+            (
+                option::of(any::<u64>()),
+                option::of(any::<u64>()),
+                option::of(any::<u64>()),
+            )
+                .prop_map(|(price_msat, bandwidth_cap, expiry)| {
+                    AcceptTerms {
+                        price_msat,
+                        bandwidth_cap,
+                        expiry,
+                    }
+                }),
+        ),
+    )
+        .prop_map(|(mesg_id, container_ids, terms)| Accept {
+            mesg_id,
+            container_ids,
+            terms,
+        })
+}
+
+fn post_requirements() -> impl Strategy<Value = PostRequirements> {
+    (any::<MesgId>(), any::<u8>(), any::<u64>(), any::<bool>()).prop_map(
+        |(topic_id, pow_difficulty, fee_msat, token_required)| {
+            PostRequirements {
+                topic_id,
+                pow_difficulty,
+                fee_msat,
+                token_required,
+            }
+        },
+    )
+}
+
+fn post_invoice_request() -> impl Strategy<Value = PostInvoiceRequest> {
+    (any::<MesgId>(), any::<MesgId>()).prop_map(|(topic_id, mesg_id)| {
+        PostInvoiceRequest { topic_id, mesg_id }
+    })
+}
+
+fn signed_envelope() -> impl Strategy<Value = SignedEnvelope> {
+    (secret_key(), option::of("[a-z]{1,16}"), any::<Mesg>(), any::<u64>())
+        .prop_map(|(key, alias, mesg, seq)| {
+            let author = StormIdentity::new(&SECP, &key, alias);
+            SignedEnvelope::seal(&SECP, &key, author, mesg, seq)
+                .expect("envelope is sealed with the author key")
+        })
+}
+
+fn delivery_failure() -> impl Strategy<Value = DeliveryFailure> {
+    (
+        any::<MesgId>(),
+        public_key(),
+        any::<u16>(),
+        select(vec![
+            DeliveryFailureReason::DeadlineExpired,
+            DeliveryFailureReason::AttemptsExhausted,
+        ]),
+    )
+        .prop_map(|(mesg_id, recipient, attempts, reason)| {
+            DeliveryFailure {
+                mesg_id,
+                recipient,
+                attempts,
+                reason,
+            }
+        })
+}
+
+fn sync_mesgs() -> impl Strategy<Value = MesgSync> {
+    (
+        any::<MesgId>(),
+        prop_oneof![
+            Just(SyncCursor::Start),
+            any::<MesgId>().prop_map(SyncCursor::After),
+            any::<u64>().prop_map(SyncCursor::Since),
+        ],
+        any::<u16>(),
+    )
+        .prop_map(|(topic_id, since, limit)| MesgSync {
+            topic_id,
+            since,
+            limit,
+        })
+}
+
+fn mesg_batch() -> impl Strategy<Value = MesgBatch> {
+    (any::<MesgId>(), vec(any::<Mesg>(), 0..MAX_ARBITRARY_ITEMS), any::<bool>())
+        .prop_map(|(topic_id, mesgs, more)| MesgBatch {
+            topic_id,
+            mesgs,
+            more,
+        })
+}
+
+fn mesg_id_range() -> impl Strategy<Value = MesgIdRange> {
+    (any::<MesgId>(), option::of(any::<MesgId>()))
+        .prop_map(|(lower, upper)| MesgIdRange { lower, upper })
+}
+
+fn reconciliation() -> impl Strategy<Value = Reconciliation> {
+    let item = prop_oneof![
+        (mesg_id_range(), any::<u32>(), hash()).prop_map(
+            |(range, count, fingerprint)| RangeItem::Fingerprint {
+                range,
+                count,
+                fingerprint,
+            }
+        ),
+        (mesg_id_range(), btree_set(any::<MesgId>(), 0..16))
+            .prop_map(|(range, ids)| RangeItem::Ids { range, ids }),
+    ];
+    (any::<MesgId>(), vec(item, 0..MAX_ARBITRARY_ITEMS))
+        .prop_map(|(topic_id, items)| Reconciliation { topic_id, items })
+}
+
+// Membership and moderation
+
+fn member_role() -> impl Strategy<Value = MemberRole> {
+    select(vec![MemberRole::Member, MemberRole::Admin, MemberRole::Owner])
+}
+
+fn invite() -> impl Strategy<Value = Invite> {
+    (any::<MesgId>(), public_key(), member_role(), any::<u64>()).prop_map(
+        |(topic_id, invitee, role, expiry)| Invite {
+            topic_id,
+            invitee,
+            role,
+            expiry,
+        },
+    )
+}
+
+fn join() -> impl Strategy<Value = Join> {
+    signed(invite()).prop_map(|invite| Join {
+        topic_id: invite.data.topic_id,
+        invite,
+    })
+}
+
+fn leave() -> impl Strategy<Value = Leave> {
+    (any::<MesgId>(), any::<u64>()).prop_map(|(topic_id, timestamp)| Leave {
+        topic_id,
+        timestamp,
+    })
+}
+
+fn member_list() -> impl Strategy<Value = MemberList> {
+    (
+        any::<MesgId>(),
+        any::<u64>(),
+        btree_map(public_key(), member_role(), 0..MAX_ARBITRARY_ITEMS),
+    )
+        .prop_map(|(topic_id, version, members)| MemberList {
+            topic_id,
+            version,
+            members,
+        })
+}
+
+fn ban() -> impl Strategy<Value = Ban> {
+    (any::<MesgId>(), public_key(), option::of(any::<u64>()), text()).prop_map(
+        |(topic_id, node_id, until, reason)| Ban {
+            topic_id,
+            node_id,
+            until,
+            reason,
+        },
+    )
+}
+
+fn unban() -> impl Strategy<Value = Unban> {
+    (any::<MesgId>(), public_key())
+        .prop_map(|(topic_id, node_id)| Unban { topic_id, node_id })
+}
+
+fn remove_mesg() -> impl Strategy<Value = RemoveMesg> {
+    (any::<MesgId>(), any::<MesgId>(), text()).prop_map(
+        |(topic_id, mesg_id, reason)| RemoveMesg {
+            topic_id,
+            mesg_id,
+            reason,
+        },
+    )
+}
+
+fn broadcast() -> impl Strategy<Value = Broadcast> {
+    (any::<MesgId>(), post(), any::<u64>()).prop_map(
+        |(topic_id, post, timestamp)| Broadcast {
+            topic_id,
+            post,
+            timestamp,
+        },
+    )
+}
+
+// Encryption and mailboxes
+
+fn sealed_post() -> impl Strategy<Value = SealedPost> {
+    let key = (public_key(), public_key(), bytes()).prop_map(
+        |(ephemeral, recipient, ciphertext)| WrappedKey {
+            ephemeral,
+            recipient,
+            ciphertext,
+        },
+    );
+    (vec(key, 0..MAX_ARBITRARY_ITEMS), bytes()).prop_map(
+        |(keys, ciphertext)| SealedPost {
+            recipients: keys
+                .into_iter()
+                .map(|key| (key.recipient, key))
+                .collect(),
+            ciphertext: MediumVec::try_from(ciphertext)
+                .expect("small ciphertext"),
+        },
+    )
+}
+
+fn deposit_mail() -> impl Strategy<Value = DepositMail> {
+    (public_key(), sealed_post(), any::<u64>()).prop_map(
+        |(recipient, sealed_mesg, expiry)| DepositMail {
+            recipient,
+            sealed_mesg,
+            expiry,
+        },
+    )
+}
+
+fn collect_mail() -> impl Strategy<Value = CollectMail> {
+    (public_key(), any::<u64>()).prop_map(|(recipient, timestamp)| {
+        CollectMail {
+            recipient,
+            timestamp,
+        }
+    })
+}
+
+fn mail_delivery() -> impl Strategy<Value = MailDelivery> {
+    (public_key(), vec(sealed_post(), 0..MAX_ARBITRARY_ITEMS))
+        .prop_map(|(recipient, mail)| MailDelivery { recipient, mail })
+}
+
+fn offline_delivery() -> impl Strategy<Value = OfflineDelivery> {
+    (storage_request(), deposit_mail()).prop_map(|(storage, notification)| {
+        OfflineDelivery {
+            storage,
+            notification,
+        }
+    })
+}
+
+// Storage, replication and disputes
+
+fn replication_terms() -> impl Strategy<Value = ReplicationTerms> {
+    (
+        public_key(),
+        btree_set(any::<ContainerId>(), 0..MAX_ARBITRARY_ITEMS),
+        btree_set(any::<ContainerId>(), 0..MAX_ARBITRARY_ITEMS),
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(
+                proposer,
+                mirrored_by_remote,
+                mirrored_by_proposer,
+                verify_interval,
+                expiry,
+            )| ReplicationTerms {
+                proposer,
+                mirrored_by_remote,
+                mirrored_by_proposer,
+                verify_interval,
+                expiry,
+            },
+        )
+}
+
+fn storage_request() -> impl Strategy<Value = StorageRequest> {
+    let replication = (
+        any::<u8>(),
+        any::<u8>(),
+        btree_set("[A-Z]{2}", 0..MAX_ARBITRARY_ITEMS),
+        btree_set(public_key(), 0..MAX_ARBITRARY_ITEMS),
+    )
+        .prop_map(
+            |(factor, min_regions, allowed_regions, excluded_nodes)| {
+                ReplicationConstraints {
+                    factor,
+                    min_regions,
+                    allowed_regions,
+                    excluded_nodes,
+                }
+            },
+        );
+    (any::<ContainerId>(), any::<u64>(), any::<u64>(), replication).prop_map(
+        |(container_id, size, duration, replication)| StorageRequest {
+            container_id,
+            size,
+            duration,
+            replication,
+        },
+    )
+}
+
+fn replica_placement() -> impl Strategy<Value = ReplicaPlacement> {
+    let replica = (public_key(), option::of("[A-Z]{2}"))
+        .prop_map(|(node_id, region)| Replica { node_id, region });
+    (any::<ContainerId>(), vec(replica, 0..MAX_ARBITRARY_ITEMS)).prop_map(
+        |(container_id, replicas)| ReplicaPlacement {
+            container_id,
+            replicas,
+        },
+    )
+}
+
+fn storage_quota() -> impl Strategy<Value = StorageQuota> {
+    (any::<u64>(), any::<u64>(), any::<u64>()).prop_map(|(available, a, b)| {
+        StorageQuota {
+            available,
+            min_container_size: a.min(b),
+            max_container_size: a.max(b),
+        }
+    })
+}
+
+fn removal_request() -> impl Strategy<Value = RemovalRequest> {
+    (any::<ContainerFullId>(), any::<u64>())
+        .prop_map(|(id, timestamp)| RemovalRequest { id, timestamp })
+}
+
+fn removal_ack() -> impl Strategy<Value = RemovalAck> {
+    (removal_request(), any::<u64>()).prop_map(|(request, removed_at)| {
+        RemovalAck {
+            request,
+            removed_at,
+        }
+    })
+}
+
+fn storage_receipt() -> impl Strategy<Value = StorageReceipt> {
+    let lease = (any::<u64>(), any::<u64>(), any::<u64>(), any::<u8>())
+        .prop_map(|(start, expiry, fee_msat, replicas)| LeaseTerms {
+            start,
+            expiry,
+            fee_msat,
+            replicas,
+        });
+    (public_key(), any::<ContainerId>(), any::<u64>(), lease, any::<u64>())
+        .prop_map(|(client, container_id, size, lease, timestamp)| {
+            StorageReceipt {
+                client,
+                container_id,
+                size: VarInt::from(size),
+                lease,
+                timestamp,
+            }
+        })
+}
+
+fn retrieval_receipt() -> impl Strategy<Value = RetrievalReceipt> {
+    (
+        public_key(),
+        any::<ContainerId>(),
+        any::<u64>(),
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(|(provider, container_id, size, chunks, timestamp)| {
+            RetrievalReceipt {
+                provider,
+                container_id,
+                size: VarInt::from(size),
+                chunks: VarInt::from(chunks),
+                timestamp,
+            }
+        })
+}
+
+fn storage_challenge() -> impl Strategy<Value = StorageChallenge> {
+    (any::<ContainerId>(), any::<u32>(), any::<[u8; 32]>(), any::<u64>())
+        .prop_map(|(container_id, chunk_index, nonce, timestamp)| {
+            StorageChallenge {
+                container_id,
+                chunk_index,
+                nonce,
+                timestamp,
+            }
+        })
+}
+
+fn challenge_response() -> impl Strategy<Value = ChallengeResponse> {
+    (storage_challenge(), any::<Chunk>()).prop_map(|(challenge, chunk)| {
+        ChallengeResponse::answer(challenge, &chunk)
+    })
+}
+
+fn dispute() -> impl Strategy<Value = Dispute> {
+    let violation = (
+        signed(storage_receipt()),
+        container_header(),
+        chunk_proof(),
+        any::<Chunk>(),
+        signed(challenge_response()),
+    )
+        .prop_map(|(receipt, header, chunk_proof, chunk, response)| {
+            ViolationProof {
+                receipt,
+                header,
+                chunk_proof,
+                chunk,
+                response,
+            }
+        });
+    let claim = prop_oneof![
+        violation.prop_map(DisputeClaim::Violation),
+        storage_challenge().prop_map(DisputeClaim::Unanswered),
+    ];
+    (signed(storage_receipt()), claim, any::<u64>()).prop_map(
+        |(receipt, claim, opened_at)| Dispute {
+            receipt,
+            claim,
+            opened_at,
+        },
+    )
+}
+
+fn dispute_id() -> impl Strategy<Value = DisputeId> {
+    hash::<sha256t::Hash<_>>().prop_map(DisputeId::from)
+}
+
+fn dispute_evidence() -> impl Strategy<Value = DisputeEvidence> {
+    let evidence = prop_oneof![
+        signed(challenge_response()).prop_map(Evidence::ChallengeResponse),
+        signed(retrieval_receipt()).prop_map(Evidence::RetrievalReceipt),
+        bytes().prop_map(Evidence::Other),
+    ];
+    (dispute_id(), evidence).prop_map(|(dispute_id, evidence)| {
+        DisputeEvidence {
+            dispute_id,
+            evidence,
+        }
+    })
+}
+
+fn dispute_resolution() -> impl Strategy<Value = DisputeResolution> {
+    (
+        dispute_id(),
+        select(vec![
+            DisputeVerdict::ProviderAtFault,
+            DisputeVerdict::Dismissed,
+        ]),
+        text(),
+    )
+        .prop_map(|(dispute_id, verdict, reason)| DisputeResolution {
+            dispute_id,
+            verdict,
+            reason,
+        })
+}
+
+fn contract_announcement() -> impl Strategy<Value = ContractAnnouncement> {
+    (hash(), hash(), any::<ContainerFullId>()).prop_map(
+        |(contract_id, schema_id, genesis)| ContractAnnouncement {
+            contract_id,
+            schema_id,
+            genesis,
+        },
+    )
+}
+
+/// Generates messages of all types of the core storm protocol.
+impl Arbitrary for Messages {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        prop_oneof![
+            Just(Messages::ListApps),
+            apps_advert().prop_map(Messages::ActiveApps),
+            app_msg(Just(())).prop_map(Messages::ListTopics),
+            app_msg(btree_set(any::<MesgId>(), 0..16))
+                .prop_map(Messages::AppTopics),
+            app_msg(topic_proposal()).prop_map(Messages::ProposeTopic),
+            app_msg(post()).prop_map(Messages::Post),
+            app_msg(any::<MesgId>()).prop_map(Messages::Read),
+            app_msg(
+                (any::<MesgId>(), refusal_reason())
+                    .prop_map(|(mesg_id, reason)| Decline { mesg_id, reason })
+            )
+            .prop_map(Messages::Decline),
+            app_msg(accept()).prop_map(Messages::Accept),
+            app_msg(container_info()).prop_map(Messages::AnnounceContainer),
+            app_msg(vec(container_info(), 0..MAX_ARBITRARY_ITEMS))
+                .prop_map(Messages::AnnounceContainers),
+            app_msg(any::<ContainerFullId>()).prop_map(Messages::PullContainer),
+            app_msg(any::<Container>()).prop_map(Messages::PushContainer),
+            app_msg(
+                (any::<ContainerFullId>(), refusal_reason()).prop_map(
+                    |(container, reason)| Reject { container, reason }
+                )
+            )
+            .prop_map(Messages::Reject),
+            chunk_pull().prop_map(Messages::PullChunk),
+            chunk_push().prop_map(Messages::PushChunk),
+            locked_chunk_push().prop_map(Messages::PushLockedChunk),
+            key_reveal().prop_map(Messages::RevealKey),
+            app_msg(swap_request()).prop_map(Messages::RequestQuote),
+            app_msg(swap_quote()).prop_map(Messages::Quote),
+            stream_chunk_pull().prop_map(Messages::PullStreamChunks),
+            app_msg(any::<u32>()).prop_map(Messages::ChunkSizeLimit),
+            app_msg(sync_mesgs()).prop_map(Messages::SyncMesgs),
+            app_msg(mesg_batch()).prop_map(Messages::SyncedMesgs),
+            app_msg(reconciliation()).prop_map(Messages::Reconcile),
+            chunk_sketch().prop_map(Messages::ChunkSketch),
+            chunk_filter().prop_map(Messages::ChunkFilter),
+            app_msg(Just(())).prop_map(Messages::GetContainerFilter),
+            app_msg(container_filter()).prop_map(Messages::ContainerFilter),
+            credit().prop_map(Messages::RequestIou),
+            signed_iou().prop_map(Messages::Iou),
+            app_msg(replication_terms()).prop_map(Messages::ProposeReplication),
+            app_msg(hash::<sha256t::Hash<_>>().prop_map(AgreementId::from))
+                .prop_map(Messages::AcceptReplication),
+            app_msg(hash::<sha256t::Hash<_>>().prop_map(AgreementId::from))
+                .prop_map(Messages::DeclineReplication),
+            app_msg(storage_request()).prop_map(Messages::RequestStorage),
+            app_msg(replica_placement()).prop_map(Messages::StoragePlacement),
+            app_msg(signed(invite())).prop_map(Messages::Invite),
+            app_msg(signed(join())).prop_map(Messages::Join),
+            app_msg(signed(leave())).prop_map(Messages::Leave),
+            app_msg(signed(member_list())).prop_map(Messages::MemberList),
+            app_msg(signed(ban())).prop_map(Messages::Ban),
+            app_msg(signed(unban())).prop_map(Messages::Unban),
+            app_msg(signed(remove_mesg())).prop_map(Messages::RemoveMesg),
+            app_msg(signed_envelope()).prop_map(Messages::SignedPost),
+            app_msg(post_requirements()).prop_map(Messages::PostRequirements),
+            app_msg(post_invoice_request())
+                .prop_map(Messages::RequestPostInvoice),
+            app_msg(signed(post_invoice())).prop_map(Messages::PostInvoice),
+            app_msg(signed(posting_token())).prop_map(Messages::PostingToken),
+            prefetch_hint().prop_map(Messages::PrefetchHint),
+            app_msg(container_query()).prop_map(Messages::ListContainers),
+            app_msg(container_list()).prop_map(Messages::ContainerList),
+            app_msg(signed(removal_request()))
+                .prop_map(Messages::RequestRemoval),
+            app_msg(signed(removal_ack())).prop_map(Messages::RemovalAck),
+            app_msg(signed(storage_receipt()))
+                .prop_map(Messages::StorageReceipt),
+            app_msg(signed(retrieval_receipt()))
+                .prop_map(Messages::RetrievalReceipt),
+            app_msg(storage_challenge()).prop_map(Messages::StorageChallenge),
+            app_msg(signed(challenge_response()))
+                .prop_map(Messages::ChallengeResponse),
+            app_msg(signed(dispute())).prop_map(Messages::OpenDispute),
+            app_msg(signed(dispute_evidence()))
+                .prop_map(Messages::DisputeEvidence),
+            app_msg(signed(dispute_resolution()))
+                .prop_map(Messages::ResolveDispute),
+            contract_announcement().prop_map(Messages::AnnounceContract),
+            app_msg(sealed_post()).prop_map(Messages::SealedPost),
+            app_msg(signed(broadcast())).prop_map(Messages::Broadcast),
+            app_msg(deposit_mail()).prop_map(Messages::DepositMail),
+            app_msg(signed(collect_mail())).prop_map(Messages::CollectMail),
+            app_msg(mail_delivery()).prop_map(Messages::MailDelivery),
+            app_msg(offline_delivery()).prop_map(Messages::DeliverOffline),
+            app_msg(delivery_failure()).prop_map(Messages::DeliveryFailure),
+            compact_chunk_pull().prop_map(Messages::PullChunkCompact),
+            app_msg((any::<ContainerFullId>(), chunk_selection()).prop_map(
+                |(container, selection)| ContainerPartPull {
+                    container,
+                    selection
+                }
+            ))
+            .prop_map(Messages::PullContainerPart),
+            app_msg(container_part()).prop_map(Messages::PushContainerPart),
+            app_msg(container_page()).prop_map(Messages::PushContainerPage),
+            app_msg(any::<u64>()).prop_map(Messages::AppPing),
+            app_msg(any::<u64>()).prop_map(Messages::AppPong),
+            app_msg(storage_quota()).prop_map(Messages::StorageQuota),
+            app_msg(find_topics()).prop_map(Messages::FindTopics),
+            app_msg(vec(any::<Topic>(), 0..MAX_ARBITRARY_ITEMS))
+                .prop_map(Messages::FoundTopics),
+            app_msg(key_lookup()).prop_map(Messages::LookupKey),
+            app_msg(map_proof()).prop_map(Messages::KeyProof),
+            chunk_mismatch().prop_map(Messages::ChunkMismatch),
+            app_msg(vec(any::<ContainerId>(), 0..MAX_ARBITRARY_ITEMS))
+                .prop_map(Messages::OfferDictionaries),
+            app_msg(option::of(any::<ContainerId>()))
+                .prop_map(Messages::SelectDictionary),
+            app_msg(block_sums()).prop_map(Messages::BlockSums),
+            app_msg(reuse_plan()).prop_map(Messages::ReuseBlocks),
+            app_msg(chunk_priority()).prop_map(Messages::ChunkPriority),
+        ]
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use internet2::TypedEnum;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn mesg_roundtrip(mesg in any::<Mesg>()) {
+            let data = mesg.strict_serialize().unwrap();
+            prop_assert_eq!(Mesg::strict_deserialize(data).unwrap(), mesg);
+        }
+
+        #[test]
+        fn container_roundtrip(container in any::<Container>()) {
+            prop_assert!(container.is_chunk_size_valid());
+            let data = container.strict_serialize().unwrap();
+            prop_assert_eq!(
+                Container::strict_deserialize(data).unwrap(),
+                container
+            );
+        }
+
+        #[test]
+        fn messages_roundtrip(mesg in any::<Messages>()) {
+            let data = mesg.serialize();
+            let decoded = crate::p2p::unmarshall(&data).unwrap();
+            prop_assert_eq!(decoded.serialize(), data);
+        }
+
+        #[test]
+        fn messages_signatures(mesg in any::<Messages>()) {
+            prop_assert!(mesg.has_valid_signatures(&*SECP));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "schema")]
+    fn messages_cover_schema() {
+        use proptest::strategy::ValueTree;
+        use proptest::test_runner::TestRunner;
+
+        use crate::schema::ProtocolSchema;
+
+        let described = ProtocolSchema::core()
+            .messages
+            .iter()
+            .map(|message| message.type_id)
+            .collect::<BTreeSet<_>>();
+        let strategy = any::<Messages>();
+        let mut runner = TestRunner::deterministic();
+        let generated = (0..described.len() * 32)
+            .map(|_| {
+                strategy.new_tree(&mut runner).unwrap().current().get_type()
+            })
+            .collect::<BTreeSet<_>>();
+        assert_eq!(generated, described);
+    }
+}
@@ -0,0 +1,352 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Assembly of container data from chunks arriving out of order.
+//!
+//! During swarm downloads chunks arrive from multiple peers in arbitrary
+//! order. [`ContainerAssembler`] writes each chunk at its offset within the
+//! container data into a seekable writer, which may be a file or a sparse
+//! in-memory buffer like [`std::io::Cursor`]. Received chunks are buffered
+//! to coalesce writes of adjacent chunks, but the buffer never exceeds the
+//! configured memory ceiling: once it is reached, buffered chunks are
+//! written out, so the node does not have to keep all pending chunks in
+//! memory. When used as a [`ChunkSink`], the assembler reports itself busy
+//! instead, leaving it to the node to write the buffered chunks out by
+//! polling it: each poll writes a single run of adjacent chunks, and the
+//! assembler is not ready until all buffered chunks are written. Chunks stay
+//! buffered until they are written, so writing can be retried after an I/O
+//! error.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::sink::{ChunkSink, SinkPoll};
+use crate::{Chunk, ChunkId, Container};
+
+/// Default memory ceiling for the chunks buffered by an assembler, in bytes.
+pub const DEFAULT_ASSEMBLY_MEMORY: usize = 16 * 1024 * 1024;
+
+/// Errors assembling container data.
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AssemblyError {
+    /// container chunk size or number of chunks is inconsistent with the
+    /// container size.
+    InvalidIndex,
+
+    /// chunk {0} does not belong to the container.
+    UnknownChunk(ChunkId),
+
+    /// container data are incomplete: {0} chunks are missing.
+    Incomplete(usize),
+
+    /// unable to write container data. Details: {0}
+    #[from]
+    Io(io::Error),
+}
+
+/// Writer of the container data from the chunks received in any order.
+#[derive(Debug)]
+pub struct ContainerAssembler<W: Write + Seek> {
+    container: Container,
+    positions: BTreeMap<ChunkId, Vec<usize>>,
+    missing: BTreeSet<usize>,
+    pending: BTreeMap<usize, Chunk>,
+    pending_size: usize,
+    memory_limit: usize,
+    writer: W,
+}
+
+impl<W: Write + Seek> ContainerAssembler<W> {
+    /// Constructs assembler writing data of the container to `writer`,
+    /// buffering at most `memory_limit` bytes of chunk data.
+    pub fn new(
+        container: Container,
+        writer: W,
+        memory_limit: usize,
+    ) -> Result<Self, AssemblyError> {
+        if !container.is_chunk_size_valid() {
+            return Err(AssemblyError::InvalidIndex);
+        }
+        let mut positions = BTreeMap::<ChunkId, Vec<usize>>::new();
+        for (index, chunk_id) in container.chunks.iter().enumerate() {
+            positions.entry(*chunk_id).or_default().push(index);
+        }
+        Ok(ContainerAssembler {
+            missing: (0..container.chunks.len()).collect(),
+            container,
+            positions,
+            pending: empty!(),
+            pending_size: 0,
+            memory_limit,
+            writer,
+        })
+    }
+
+    pub fn container(&self) -> &Container { &self.container }
+
+    /// Indexes of the chunks which were not received yet.
+    pub fn missing(&self) -> &BTreeSet<usize> { &self.missing }
+
+    pub fn is_complete(&self) -> bool { self.missing.is_empty() }
+
+    /// Size of the chunk data buffered in memory.
+    pub fn buffered(&self) -> usize { self.pending_size }
+
+    /// Checks whether the chunk with the given id is still needed.
+    pub fn needs(&self, chunk_id: ChunkId) -> bool {
+        self.positions.get(&chunk_id).map_or(false, |indexes| {
+            indexes.iter().any(|index| self.missing.contains(index))
+        })
+    }
+
+    // Positions of the chunk which are not received yet
+    fn positions(&self, chunk: &Chunk) -> Result<Vec<usize>, AssemblyError> {
+        let chunk_id = chunk.chunk_id();
+        Ok(self
+            .positions
+            .get(&chunk_id)
+            .ok_or(AssemblyError::UnknownChunk(chunk_id))?
+            .iter()
+            .filter(|index| self.missing.contains(index))
+            .copied()
+            .collect())
+    }
+
+    fn buffer(&mut self, chunk: Chunk, indexes: Vec<usize>) {
+        self.pending_size += chunk.len() * indexes.len();
+        for index in indexes {
+            self.missing.remove(&index);
+            self.pending.insert(index, chunk.clone());
+        }
+    }
+
+    // Writes the first run of adjacent buffered chunks with a single seek
+    fn write_run(&mut self) -> Result<(), AssemblyError> {
+        let mut index = match self.pending.keys().next() {
+            Some(index) => *index,
+            None => return Ok(()),
+        };
+        let chunk_size = self.container.header.chunk_size as u64;
+        self.writer.seek(SeekFrom::Start(index as u64 * chunk_size))?;
+        while let Some(chunk) = self.pending.get(&index) {
+            self.writer.write_all(chunk.as_ref())?;
+            self.pending_size -= chunk.len();
+            self.pending.remove(&index);
+            index += 1;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Accepts chunk received from any peer. Returns whether the chunk was
+    /// needed; chunks which are already received are ignored. If the chunk
+    /// data appear in the container several times, they are placed at all
+    /// of their positions.
+    pub fn accept(&mut self, chunk: Chunk) -> Result<bool, AssemblyError> {
+        let indexes = self.positions(&chunk)?;
+        if indexes.is_empty() {
+            return Ok(false);
+        }
+        if self.pending_size + chunk.len() * indexes.len() > self.memory_limit {
+            self.flush()?;
+        }
+        self.buffer(chunk, indexes);
+        if self.pending_size > self.memory_limit {
+            self.flush()?;
+        }
+        Ok(true)
+    }
+
+    /// Writes all buffered chunks at their offsets, seeking only between
+    /// non-adjacent chunks. Chunks which were not written because of an
+    /// error remain buffered.
+    pub fn flush(&mut self) -> Result<(), AssemblyError> {
+        let chunk_size = self.container.header.chunk_size as u64;
+        let mut next = None;
+        while let Some((&index, chunk)) = self.pending.iter().next() {
+            if next != Some(index) {
+                self.writer.seek(SeekFrom::Start(index as u64 * chunk_size))?;
+            }
+            self.writer.write_all(chunk.as_ref())?;
+            self.pending_size -= chunk.len();
+            self.pending.remove(&index);
+            next = Some(index + 1);
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Completes assembly, returning the writer with the container data.
+    pub fn finish(mut self) -> Result<W, AssemblyError> {
+        if !self.missing.is_empty() {
+            return Err(AssemblyError::Incomplete(self.missing.len()));
+        }
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Assembler is busy once buffering the chunk would exceed the memory
+/// ceiling. Each call to [`ChunkSink::poll_ready`] writes out a single run of
+/// adjacent buffered chunks; the assembler is ready only when nothing is left
+/// buffered.
+impl<W: Write + Seek> ChunkSink for ContainerAssembler<W> {
+    type Error = AssemblyError;
+
+    fn offer(&mut self, chunk: Chunk) -> Result<SinkPoll, Self::Error> {
+        let indexes = self.positions(&chunk)?;
+        let size = chunk.len() * indexes.len();
+        if !self.pending.is_empty()
+            && self.pending_size + size > self.memory_limit
+        {
+            return Ok(SinkPoll::Busy(chunk));
+        }
+        self.buffer(chunk, indexes);
+        Ok(SinkPoll::Accepted)
+    }
+
+    fn poll_ready(&mut self) -> Result<bool, Self::Error> {
+        self.write_run()?;
+        Ok(self.pending.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use stens::AsciiString;
+
+    use super::*;
+
+    /// Writer failing once it has written `budget` bytes.
+    struct FailingWriter {
+        inner: Cursor<Vec<u8>>,
+        budget: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.budget {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+            }
+            self.budget -= buf.len();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+    }
+
+    impl Seek for FailingWriter {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_assembly() {
+        let mut data = (0..200u8).collect::<Vec<_>>();
+        data.extend([7u8; 100]);
+        data.extend(200..250u8);
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        // Chunks #4 and #5 have the same data
+        assert_eq!(chunks[4], chunks[5]);
+
+        let mut assembler =
+            ContainerAssembler::new(container, Cursor::new(vec![]), 120)
+                .unwrap();
+        for index in [6usize, 1, 4, 0, 3] {
+            assert!(assembler.accept(chunks[index].clone()).unwrap());
+            assert!(assembler.buffered() <= 120);
+        }
+        assert!(!assembler.accept(chunks[5].clone()).unwrap());
+        assert!(!assembler.needs(chunks[5].chunk_id()));
+        assert_eq!(assembler.missing(), &bset! { 2 });
+        assert!(matches!(
+            assembler.accept(Chunk::try_from(vec![1u8; 50]).unwrap()),
+            Err(AssemblyError::UnknownChunk(_))
+        ));
+
+        assert!(assembler.accept(chunks[2].clone()).unwrap());
+        assert!(assembler.is_complete());
+        assert_eq!(assembler.finish().unwrap().into_inner(), data);
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 10).unwrap();
+        let mut assembler =
+            ContainerAssembler::new(container, Cursor::new(vec![]), 1000)
+                .unwrap();
+        assert!(assembler.accept(chunks[0].clone()).unwrap());
+        assert!(matches!(
+            assembler.finish(),
+            Err(AssemblyError::Incomplete(9))
+        ));
+    }
+
+    #[test]
+    fn test_write_error() {
+        let data = (0..200u8).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let writer = FailingWriter {
+            inner: Cursor::new(vec![]),
+            budget: 120,
+        };
+        let mut assembler =
+            ContainerAssembler::new(container, writer, 1000).unwrap();
+        for chunk in &chunks {
+            assert!(assembler.accept(chunk.clone()).unwrap());
+        }
+        assert!(matches!(assembler.flush(), Err(AssemblyError::Io(_))));
+        // Chunks which were not written are kept
+        assert_eq!(assembler.buffered(), 100);
+        assert!(matches!(assembler.poll_ready(), Err(AssemblyError::Io(_))));
+        assert_eq!(assembler.buffered(), 100);
+
+        assembler.writer.budget = usize::MAX;
+        assert!(assembler.is_complete());
+        let writer = assembler.finish().unwrap();
+        assert_eq!(writer.inner.into_inner(), data);
+    }
+
+    #[test]
+    fn test_sink() {
+        let data = (0..200u8).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut assembler =
+            ContainerAssembler::new(container, Cursor::new(vec![]), 100)
+                .unwrap();
+
+        for index in [3usize, 1] {
+            let poll = assembler.offer(chunks[index].clone()).unwrap();
+            assert_eq!(poll, SinkPoll::Accepted);
+        }
+        let poll = assembler.offer(chunks[0].clone()).unwrap();
+        assert_eq!(poll, SinkPoll::Busy(chunks[0].clone()));
+        assert_eq!(assembler.buffered(), 100);
+        // Chunks #1 and #3 are not adjacent and are written one per poll
+        assert!(!assembler.poll_ready().unwrap());
+        assert_eq!(assembler.buffered(), 50);
+        assert!(assembler.poll_ready().unwrap());
+        assert_eq!(assembler.buffered(), 0);
+        for index in [0usize, 2] {
+            let poll = assembler.offer(chunks[index].clone()).unwrap();
+            assert_eq!(poll, SinkPoll::Accepted);
+        }
+        assert_eq!(assembler.finish().unwrap().into_inner(), data);
+    }
+}
@@ -0,0 +1,249 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use bitcoin_hashes::{sha256, sha256t};
+use commit_verify::{
+    commit_encode, CommitVerify, ConsensusCommit, PrehashedProtocol, TaggedHash,
+};
+use stens::AsciiString;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::chunk::TooLargeData;
+use crate::{Chunk, Container};
+
+/// MIME type of containers holding audit logs.
+pub const AUDIT_LOG_MIME: &str = "application/x-storm-log";
+
+// "storm:log"
+static MIDSTATE_LOG_ENTRY_ID: [u8; 32] = [
+    206, 41, 148, 98, 91, 108, 198, 143, 253, 173, 82, 17, 20, 74, 91, 135,
+    192, 20, 56, 94, 12, 46, 190, 59, 135, 110, 121, 37, 30, 224, 102, 78,
+];
+
+/// Tag used for [`LogEntryId`] hash type
+pub struct LogEntryIdTag;
+
+impl sha256t::Tag for LogEntryIdTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_LOG_ENTRY_ID);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Unique audit log entry identifier
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(
+    Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, From
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[wrapper(Debug, Display)]
+pub struct LogEntryId(sha256t::Hash<LogEntryIdTag>);
+
+impl<Msg> CommitVerify<Msg, PrehashedProtocol> for LogEntryId
+where Msg: AsRef<[u8]>
+{
+    #[inline]
+    fn commit(msg: &Msg) -> LogEntryId { LogEntryId::hash(msg) }
+}
+
+/// Errors verifying audit log integrity.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AuditLogError {
+    /// entry {0} has wrong sequence number.
+    SeqMismatch(u64),
+
+    /// entry {0} does not commit to the previous entry.
+    BrokenChain(u64),
+
+    /// entry {0} has timestamp preceding the previous entry.
+    TimestampDecreased(u64),
+
+    /// log does not extend the known log head.
+    NotExtending,
+
+    /// container data can't be decoded as an audit log.
+    Decoding,
+}
+
+/// Entry of an append-only hash-chained log.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("#{seq} at {timestamp}")]
+pub struct LogEntry {
+    /// Sequence number of the entry, starting from zero.
+    pub seq: u64,
+    /// Id of the previous entry; absent for the first entry only.
+    pub prev: Option<LogEntryId>,
+    /// Unix timestamp of the entry.
+    pub timestamp: u64,
+    /// Application-specific entry data.
+    pub payload: Vec<u8>,
+}
+
+impl commit_encode::Strategy for LogEntry {
+    type Strategy = commit_encode::strategies::UsingStrict;
+}
+
+impl ConsensusCommit for LogEntry {
+    type Commitment = LogEntryId;
+}
+
+impl LogEntry {
+    pub fn entry_id(&self) -> LogEntryId { self.consensus_commit() }
+}
+
+/// Append-only tamper-evident log, where each entry commits to the previous
+/// one. The log is stored as a container, so a log head id together with
+/// the container id allows any party to verify the history.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct AuditLog {
+    entries: Vec<LogEntry>,
+}
+
+impl AuditLog {
+    pub fn new() -> AuditLog { AuditLog::default() }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    pub fn entries(&self) -> &[LogEntry] { &self.entries }
+
+    /// Entries with sequence numbers starting from `seq`.
+    pub fn entries_since(&self, seq: u64) -> &[LogEntry] {
+        let start = (seq as usize).min(self.entries.len());
+        &self.entries[start..]
+    }
+
+    /// Id of the last entry.
+    pub fn head(&self) -> Option<LogEntryId> {
+        self.entries.last().map(LogEntry::entry_id)
+    }
+
+    /// Appends new entry to the log, returning its id.
+    pub fn append(
+        &mut self,
+        payload: impl Into<Vec<u8>>,
+        timestamp: u64,
+    ) -> LogEntryId {
+        let entry = LogEntry {
+            seq: self.entries.len() as u64,
+            prev: self.head(),
+            timestamp: self
+                .entries
+                .last()
+                .map(|last| last.timestamp.max(timestamp))
+                .unwrap_or(timestamp),
+            payload: payload.into(),
+        };
+        let id = entry.entry_id();
+        self.entries.push(entry);
+        id
+    }
+
+    /// Verifies integrity of the hash chain.
+    pub fn verify(&self) -> Result<(), AuditLogError> {
+        let mut prev: Option<&LogEntry> = None;
+        for (no, entry) in self.entries.iter().enumerate() {
+            let seq = no as u64;
+            if entry.seq != seq {
+                return Err(AuditLogError::SeqMismatch(seq));
+            }
+            if entry.prev != prev.map(LogEntry::entry_id) {
+                return Err(AuditLogError::BrokenChain(seq));
+            }
+            if matches!(prev, Some(prev) if prev.timestamp > entry.timestamp) {
+                return Err(AuditLogError::TimestampDecreased(seq));
+            }
+            prev = Some(entry);
+        }
+        Ok(())
+    }
+
+    /// Verifies integrity of the log and that it extends a previously known
+    /// log of `len` entries ending with `head`.
+    pub fn verify_extends(
+        &self,
+        head: LogEntryId,
+        len: usize,
+    ) -> Result<(), AuditLogError> {
+        self.verify()?;
+        match len.checked_sub(1).and_then(|no| self.entries.get(no)) {
+            Some(entry) if entry.entry_id() == head => Ok(()),
+            _ => Err(AuditLogError::NotExtending),
+        }
+    }
+
+    /// Produces container holding the log, split into chunks.
+    pub fn to_container(
+        &self,
+        info: impl ToString,
+        chunk_size: u32,
+    ) -> Result<(Container, Vec<Chunk>), TooLargeData> {
+        let data = self.strict_serialize().map_err(|_| TooLargeData)?;
+        let mime = AsciiString::try_from(AUDIT_LOG_MIME)
+            .expect("static MIME type is ASCII");
+        Container::chunkify(mime, info, &data, chunk_size)
+    }
+
+    /// Restores log from the container data, verifying its integrity.
+    pub fn from_container_data(data: &[u8]) -> Result<AuditLog, AuditLogError> {
+        let log = AuditLog::strict_deserialize(data)
+            .map_err(|_| AuditLogError::Decoding)?;
+        log.verify()?;
+        Ok(log)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use commit_verify::tagged_hash;
+
+    use super::*;
+
+    #[test]
+    fn test_log_entry_id_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:log");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_LOG_ENTRY_ID);
+    }
+
+    #[test]
+    fn test_append_verify() {
+        let mut log = AuditLog::new();
+        log.append(b"first".to_vec(), 10);
+        let head = log.append(b"second".to_vec(), 20);
+        log.append(b"third".to_vec(), 30);
+        assert_eq!(log.verify(), Ok(()));
+        assert_eq!(log.verify_extends(head, 2), Ok(()));
+        assert_eq!(log.entries_since(2).len(), 1);
+
+        let (_, chunks) = log.to_container("log", 16).unwrap();
+        let data =
+            chunks.iter().flat_map(|chunk| chunk.to_vec()).collect::<Vec<_>>();
+        assert_eq!(AuditLog::from_container_data(&data), Ok(log.clone()));
+
+        let mut forged = log;
+        forged.entries[1].payload = b"forged".to_vec();
+        assert_eq!(forged.verify(), Err(AuditLogError::BrokenChain(2)));
+    }
+}
@@ -0,0 +1,344 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Authenticated map container profile.
+//!
+//! Map entries are sorted by key and packed into chunks, so that each chunk
+//! holds a strict-encoded list of whole entries (padded with zeros to the
+//! chunk size) and keys of each chunk follow the keys of the previous one.
+//! Since the container id commits to the Merkle root of the chunk ids, a
+//! node holding the map can prove the value of a key, or its absence, with
+//! one or two chunks and their [`ChunkProof`]s. A light client verifies
+//! such [`MapProof`] against the container id without downloading the map;
+//! proofs are requested with `LookupKey` and returned with `KeyProof`
+//! messages.
+
+use std::collections::BTreeMap;
+
+use stens::AsciiString;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::chunk::MAX_CHUNK_SIZE;
+use crate::{Chunk, ChunkProof, Container, ContainerHeader, ContainerId};
+
+/// MIME type of authenticated map containers.
+pub const AUTH_MAP_MIME: &str = "application/x-storm-authmap";
+
+/// Errors building authenticated maps and verifying their proofs.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AuthMapError {
+    /// map data are too large to fit into a single container.
+    TooLarge,
+
+    /// map entry takes {0} bytes and does not fit into a chunk.
+    EntryTooLarge(usize),
+
+    /// container is not an authenticated map or has malformed chunk #{0}.
+    Decoding(usize),
+
+    /// proof chunk does not belong to the container.
+    InvalidProof,
+
+    /// proof chunks do not cover the requested key.
+    IncompleteProof,
+}
+
+/// Entry of an authenticated map.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct MapEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Decodes entries stored in the map chunk, checking that they are sorted.
+fn decode_page(
+    index: usize,
+    chunk: &Chunk,
+) -> Result<Vec<MapEntry>, AuthMapError> {
+    let entries = Vec::<MapEntry>::strict_decode(&chunk[..])
+        .map_err(|_| AuthMapError::Decoding(index))?;
+    if entries.is_empty()
+        || !entries.windows(2).all(|pair| pair[0].key < pair[1].key)
+    {
+        return Err(AuthMapError::Decoding(index));
+    }
+    Ok(entries)
+}
+
+/// Builder for authenticated map containers.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct AuthMapBuilder {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl AuthMapBuilder {
+    pub fn new() -> AuthMapBuilder { AuthMapBuilder::default() }
+
+    /// Adds value under the key, returning previous value for the same key,
+    /// if any.
+    pub fn insert(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        self.entries.insert(key.into(), value.into())
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Produces authenticated map container and its chunks.
+    pub fn build(
+        &self,
+        info: impl ToString,
+        chunk_size: u32,
+    ) -> Result<(AuthMap, Vec<Chunk>), AuthMapError> {
+        let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE) as usize;
+        let mut data = vec![];
+        let mut page = vec![];
+        let mut page_len = 2usize;
+        for (key, value) in &self.entries {
+            let entry = MapEntry {
+                key: key.clone(),
+                value: value.clone(),
+            };
+            let len = entry
+                .strict_serialize()
+                .map_err(|_| AuthMapError::TooLarge)?
+                .len();
+            if len + 2 > chunk_size {
+                return Err(AuthMapError::EntryTooLarge(len));
+            }
+            if page_len + len > chunk_size {
+                let start = data.len();
+                page.strict_encode(&mut data)
+                    .map_err(|_| AuthMapError::TooLarge)?;
+                data.resize(start + chunk_size, 0);
+                page.clear();
+                page_len = 2;
+            }
+            page.push(entry);
+            page_len += len;
+        }
+        if !page.is_empty() {
+            page.strict_encode(&mut data)
+                .map_err(|_| AuthMapError::TooLarge)?;
+        }
+
+        let mime = AsciiString::try_from(AUTH_MAP_MIME)
+            .expect("static MIME type is ASCII");
+        let (container, chunks) =
+            Container::chunkify(mime, info, &data, chunk_size as u32)
+                .map_err(|_| AuthMapError::TooLarge)?;
+        let map = AuthMap::with(container, chunks.clone())?;
+        Ok((map, chunks))
+    }
+}
+
+/// Authenticated map container together with its chunks, held by nodes
+/// answering key queries.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AuthMap {
+    container: Container,
+    chunks: Vec<Chunk>,
+    pages: Vec<Vec<MapEntry>>,
+}
+
+impl AuthMap {
+    /// Decodes map from all of the container chunks, provided in the
+    /// container order.
+    pub fn with(
+        container: Container,
+        chunks: Vec<Chunk>,
+    ) -> Result<AuthMap, AuthMapError> {
+        if container.header.mime.as_str() != AUTH_MAP_MIME
+            || chunks.len() != container.chunks.len()
+        {
+            return Err(AuthMapError::Decoding(0));
+        }
+        let mut pages: Vec<Vec<MapEntry>> = Vec::with_capacity(chunks.len());
+        for (index, chunk) in chunks.iter().enumerate() {
+            if chunk.chunk_id() != container.chunks[index] {
+                return Err(AuthMapError::Decoding(index));
+            }
+            let entries = decode_page(index, chunk)?;
+            if let Some(last) = pages.last().and_then(|page| page.last()) {
+                if last.key >= entries[0].key {
+                    return Err(AuthMapError::Decoding(index));
+                }
+            }
+            pages.push(entries);
+        }
+        Ok(AuthMap {
+            container,
+            chunks,
+            pages,
+        })
+    }
+
+    pub fn container(&self) -> &Container { &self.container }
+
+    pub fn len(&self) -> usize { self.pages.iter().map(Vec::len).sum() }
+
+    pub fn is_empty(&self) -> bool { self.pages.is_empty() }
+
+    /// Indexes of the chunks proving the value of the key or its absence.
+    fn locate(&self, key: &[u8]) -> Vec<usize> {
+        if self.pages.is_empty() {
+            return vec![];
+        }
+        let pos =
+            self.pages.partition_point(|page| page[0].key.as_slice() <= key);
+        if pos == 0 {
+            return vec![0];
+        }
+        let index = pos - 1;
+        let last = &self.pages[index].last().expect("pages are not empty").key;
+        if key <= last.as_slice() || pos == self.pages.len() {
+            vec![index]
+        } else {
+            vec![index, pos]
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.locate(key).into_iter().find_map(|index| {
+            self.pages[index]
+                .iter()
+                .find(|entry| entry.key == key)
+                .map(|entry| entry.value.as_slice())
+        })
+    }
+
+    /// Produces proof of the key value or its absence.
+    pub fn prove(&self, key: &[u8]) -> MapProof {
+        let chunks = self
+            .locate(key)
+            .into_iter()
+            .map(|index| {
+                let proof = self
+                    .container
+                    .chunk_proof(index)
+                    .expect("located chunk belongs to the container");
+                (self.chunks[index].clone(), proof)
+            })
+            .collect();
+        MapProof {
+            container_id: self.container.container_id(),
+            key: key.to_vec(),
+            header: self.container.header.clone(),
+            chunks,
+        }
+    }
+}
+
+/// Proof of the value of a key in an authenticated map, or of the key
+/// absence.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, ...")]
+pub struct MapProof {
+    pub container_id: ContainerId,
+    pub key: Vec<u8>,
+    pub header: ContainerHeader,
+    pub chunks: Vec<(Chunk, ChunkProof)>,
+}
+
+impl MapProof {
+    /// Verifies the proof against the container id, returning the value of
+    /// the key or `None` if the key is proven to be absent.
+    pub fn verify(&self) -> Result<Option<Vec<u8>>, AuthMapError> {
+        if self.header.mime.as_str() != AUTH_MAP_MIME {
+            return Err(AuthMapError::Decoding(0));
+        }
+        let count =
+            self.header.chunk_count().ok_or(AuthMapError::InvalidProof)?;
+        let mut pages = Vec::with_capacity(self.chunks.len());
+        for (chunk, proof) in &self.chunks {
+            if !proof.verify(chunk.chunk_id(), self.container_id, &self.header)
+            {
+                return Err(AuthMapError::InvalidProof);
+            }
+            let index = proof.index as u64;
+            pages.push((index, decode_page(index as usize, chunk)?));
+        }
+        let key = self.key.as_slice();
+        for (_, entries) in &pages {
+            if let Some(entry) = entries.iter().find(|entry| entry.key == key) {
+                return Ok(Some(entry.value.clone()));
+            }
+        }
+
+        let first = |entries: &[MapEntry]| entries[0].key.clone();
+        let last =
+            |entries: &[MapEntry]| entries[entries.len() - 1].key.clone();
+        let absent = match pages.as_slice() {
+            [] => count == 0,
+            [(index, entries)] => {
+                (*index == 0 || first(entries).as_slice() <= key)
+                    && (*index + 1 == count || key <= last(entries).as_slice())
+            }
+            [(index, prev), (next_index, next)] => {
+                *next_index == *index + 1
+                    && last(prev).as_slice() < key
+                    && key < first(next).as_slice()
+            }
+            _ => false,
+        };
+        if !absent {
+            return Err(AuthMapError::IncompleteProof);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_auth_map() {
+        let mut builder = AuthMapBuilder::new();
+        for no in 0u8..50 {
+            builder.insert(vec![no * 2], vec![no; 8]);
+        }
+        let (map, chunks) = builder.build("dataset", 64).unwrap();
+        assert!(chunks.len() > 2);
+        assert_eq!(map.len(), 50);
+        assert_eq!(map.get(&[10]), Some(&[5u8; 8][..]));
+        assert_eq!(map.get(&[11]), None);
+
+        for key in [0u8, 10, 98] {
+            assert_eq!(map.prove(&[key]).verify(), Ok(Some(vec![key / 2; 8])));
+        }
+        for key in [1u8, 11, 99, 200] {
+            assert_eq!(map.prove(&[key]).verify(), Ok(None));
+        }
+
+        let mut proof = map.prove(&[10]);
+        proof.key = vec![200];
+        assert_eq!(proof.verify(), Err(AuthMapError::IncompleteProof));
+        proof.container_id = ContainerId::default();
+        assert_eq!(proof.verify(), Err(AuthMapError::InvalidProof));
+    }
+}
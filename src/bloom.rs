@@ -0,0 +1,211 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::io;
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use strict_encoding::{MediumVec, StrictDecode, StrictEncode};
+
+use crate::ChunkId;
+
+/// Maximum number of hash functions used by a filter.
+pub const BLOOM_MAX_HASH_FUNCS: u8 = 32;
+
+/// Maximum size of the filter bit array, in bytes.
+pub const BLOOM_MAX_SIZE: usize = (1 << 24) - 1;
+
+/// Errors constructing bloom filters.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BloomError {
+    /// false-positive rate must be within (0, 1) range.
+    InvalidRate,
+}
+
+/// Bloom filter over arbitrary byte strings.
+///
+/// Filter positions for an item are computed with double hashing over
+/// `SHA256(tweak || item)`; the tweak allows peers to use independent
+/// filters for the same data set.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct BloomFilter {
+    hash_funcs: u8,
+    tweak: u32,
+    bits: Vec<u8>,
+}
+
+// Filter bit array may exceed 2^16 bytes, so we encode it as a medium-sized
+// vector
+impl StrictEncode for BloomFilter {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let len = self.hash_funcs.strict_encode(&mut e)?
+            + self.tweak.strict_encode(&mut e)?;
+        Ok(len + MediumVec::try_from(self.bits.clone())?.strict_encode(e)?)
+    }
+}
+
+impl StrictDecode for BloomFilter {
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        Ok(BloomFilter {
+            hash_funcs: u8::strict_decode(&mut d)?,
+            tweak: u32::strict_decode(&mut d)?,
+            bits: MediumVec::<u8>::strict_decode(d)?.to_vec(),
+        })
+    }
+}
+
+impl BloomFilter {
+    /// Constructs empty filter sized for holding `items` elements with the
+    /// given false-positive rate. The rate must be in `(0, 1)` range.
+    pub fn with_rate(
+        items: usize,
+        fp_rate: f64,
+        tweak: u32,
+    ) -> Result<BloomFilter, BloomError> {
+        if !(fp_rate > 0.0 && fp_rate < 1.0) {
+            return Err(BloomError::InvalidRate);
+        }
+        let items = items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-items * fp_rate.ln() / (ln2 * ln2)).ceil().max(8.0);
+        let bytes = ((bits / 8.0).ceil() as usize).min(BLOOM_MAX_SIZE);
+        let hash_funcs = ((bytes * 8) as f64 / items * ln2).round() as u8;
+        Ok(BloomFilter {
+            hash_funcs: hash_funcs.clamp(1, BLOOM_MAX_HASH_FUNCS),
+            tweak,
+            bits: vec![0u8; bytes],
+        })
+    }
+
+    /// Constructs filter containing all of the provided chunk ids.
+    pub fn with_chunks<'a>(
+        chunk_ids: impl ExactSizeIterator<Item = &'a ChunkId>,
+        fp_rate: f64,
+        tweak: u32,
+    ) -> Result<BloomFilter, BloomError> {
+        let mut filter =
+            BloomFilter::with_rate(chunk_ids.len(), fp_rate, tweak)?;
+        for chunk_id in chunk_ids {
+            filter.insert(&chunk_id[..]);
+        }
+        Ok(filter)
+    }
+
+    /// Checks that the filter is well-formed, i.e. it has non-empty bit array
+    /// and a supported number of hash functions.
+    pub fn is_valid(&self) -> bool {
+        !self.bits.is_empty()
+            && self.hash_funcs > 0
+            && self.hash_funcs <= BLOOM_MAX_HASH_FUNCS
+    }
+
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.tweak.to_le_bytes());
+        engine.input(item);
+        let hash = sha256::Hash::from_engine(engine);
+        let mut h1 = [0u8; 8];
+        let mut h2 = [0u8; 8];
+        h1.copy_from_slice(&hash[..8]);
+        h2.copy_from_slice(&hash[8..16]);
+        let h1 = u64::from_le_bytes(h1);
+        let h2 = u64::from_le_bytes(h2);
+        let len = (self.bits.len() * 8) as u64;
+        (0..self.hash_funcs as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    /// Adds item to the filter.
+    pub fn insert(&mut self, item: &[u8]) {
+        if !self.is_valid() {
+            return;
+        }
+        for pos in self.positions(item).collect::<Vec<_>>() {
+            self.bits[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+
+    /// Checks whether the item may be present in the filter. Returns `false`
+    /// only if the item is definitely absent.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        if !self.is_valid() {
+            return false;
+        }
+        self.positions(item)
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+
+    /// Checks whether the chunk may be present in the filter.
+    pub fn contains_chunk(&self, chunk_id: ChunkId) -> bool {
+        self.contains(&chunk_id[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        for rate in [0.0, 1.0, -0.5, 2.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                BloomFilter::with_rate(100, rate, 0),
+                Err(BloomError::InvalidRate)
+            );
+        }
+        let filter = BloomFilter::with_rate(100, 0.01, 0).unwrap();
+        assert!(filter.is_valid());
+        // ~9.6 bits and ~7 hash functions per item for 1% rate
+        assert_eq!(filter.bits.len(), 120);
+        assert_eq!(filter.hash_funcs, 7);
+        assert!(!BloomFilter::default().is_valid());
+        assert!(!BloomFilter::default().contains(b"item"));
+    }
+
+    #[test]
+    fn test_membership() {
+        let chunk_ids =
+            (0u8..200).map(|byte| ChunkId::hash(&[byte])).collect::<Vec<_>>();
+        let filter =
+            BloomFilter::with_chunks(chunk_ids[..100].iter(), 0.01, 7).unwrap();
+        assert!(chunk_ids[..100]
+            .iter()
+            .all(|chunk_id| filter.contains_chunk(*chunk_id)));
+        let false_positives = chunk_ids[100..]
+            .iter()
+            .filter(|chunk_id| filter.contains_chunk(**chunk_id))
+            .count();
+        assert!(false_positives < 10);
+
+        // Filters with different tweaks set different bits
+        let other =
+            BloomFilter::with_chunks(chunk_ids[..100].iter(), 0.01, 8).unwrap();
+        assert_ne!(filter, other);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut filter = BloomFilter::with_rate(10, 0.1, 42).unwrap();
+        filter.insert(b"item");
+        let data = filter.strict_serialize().unwrap();
+        assert_eq!(data.len(), 1 + 4 + 3 + filter.bits.len());
+        assert_eq!(BloomFilter::strict_deserialize(&data).unwrap(), filter);
+    }
+}
@@ -0,0 +1,192 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Broadcasts of the topic owner to all topic subscribers.
+//!
+//! Broadcast is a post signed by the topic owner, which relays must forward
+//! to every peer subscribed to the topic, allowing announcement channels on
+//! top of the chat app. Each broadcast is identified by the id of its
+//! message; relays remember ids of the broadcasts they have forwarded and
+//! drop any broadcast arriving again, which prevents forwarding loops in the
+//! relay network.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::posting::Post;
+use crate::signed::{Signable, Signed};
+use crate::MesgId;
+
+/// Errors relaying topic broadcasts.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BroadcastError {
+    /// broadcast signature is invalid.
+    InvalidSignature,
+
+    /// broadcast is signed by {0}, who is not the topic owner.
+    NotOwner(PublicKey),
+
+    /// broadcast {0} was already relayed.
+    AlreadyRelayed(MesgId),
+}
+
+/// Post of the topic owner which must be forwarded to all topic
+/// subscribers.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("broadcast {post} to {topic_id}")]
+pub struct Broadcast {
+    pub topic_id: MesgId,
+    pub post: Post,
+    pub timestamp: u64,
+}
+
+impl Signable for Broadcast {
+    const SIGNED_TYPE: u8 = 20;
+}
+
+impl Broadcast {
+    /// Id of the broadcast used for loop prevention.
+    pub fn broadcast_id(&self) -> MesgId { self.post.mesg.mesg_id() }
+}
+
+/// Topic subscriptions and recently relayed broadcasts of a relay node.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BroadcastRelay {
+    subscribers: BTreeMap<MesgId, BTreeSet<PublicKey>>,
+    /// Ids of relayed broadcasts with the time they were first received.
+    relayed: BTreeMap<MesgId, u64>,
+}
+
+impl BroadcastRelay {
+    pub fn new() -> BroadcastRelay { BroadcastRelay::default() }
+
+    pub fn subscribe(&mut self, topic_id: MesgId, peer: PublicKey) -> bool {
+        self.subscribers.entry(topic_id).or_default().insert(peer)
+    }
+
+    pub fn unsubscribe(&mut self, topic_id: MesgId, peer: PublicKey) -> bool {
+        let removed = self
+            .subscribers
+            .get_mut(&topic_id)
+            .map(|peers| peers.remove(&peer))
+            .unwrap_or_default();
+        if self.subscribers.get(&topic_id).map(BTreeSet::is_empty) == Some(true)
+        {
+            self.subscribers.remove(&topic_id);
+        }
+        removed
+    }
+
+    pub fn subscribers(
+        &self,
+        topic_id: MesgId,
+    ) -> impl Iterator<Item = PublicKey> + '_ {
+        self.subscribers.get(&topic_id).into_iter().flatten().copied()
+    }
+
+    pub fn is_relayed(&self, broadcast_id: MesgId) -> bool {
+        self.relayed.contains_key(&broadcast_id)
+    }
+
+    /// Accepts broadcast received from the peer at time `now`, returning the
+    /// subscribers to which it must be forwarded. The sender and the topic
+    /// owner are never included.
+    pub fn relay<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        broadcast: &Signed<Broadcast>,
+        owner: PublicKey,
+        from: PublicKey,
+        now: u64,
+    ) -> Result<BTreeSet<PublicKey>, BroadcastError> {
+        broadcast.verify(secp).map_err(|_| BroadcastError::InvalidSignature)?;
+        if broadcast.signer != owner {
+            return Err(BroadcastError::NotOwner(broadcast.signer));
+        }
+        let broadcast_id = broadcast.data.broadcast_id();
+        if self.relayed.insert(broadcast_id, now).is_some() {
+            return Err(BroadcastError::AlreadyRelayed(broadcast_id));
+        }
+        Ok(self
+            .subscribers(broadcast.data.topic_id)
+            .filter(|peer| *peer != from && *peer != owner)
+            .collect())
+    }
+
+    /// Forgets broadcasts received before `before`. Broadcasts must be
+    /// remembered for longer than they may circulate in the network.
+    pub fn prune(&mut self, before: u64) {
+        self.relayed.retain(|_, received| *received >= before);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::SecretKey;
+
+    use super::*;
+    use crate::{Chunk, Mesg};
+
+    #[test]
+    fn test_relay() {
+        let secp = Secp256k1::new();
+        let owner_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let user_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let owner = PublicKey::from_secret_key(&secp, &owner_key);
+        let user = PublicKey::from_secret_key(&secp, &user_key);
+        let peer = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x33; 32]).unwrap(),
+        );
+        let topic_id = MesgId::default();
+
+        let mut relay = BroadcastRelay::new();
+        relay.subscribe(topic_id, owner);
+        relay.subscribe(topic_id, user);
+        relay.subscribe(topic_id, peer);
+
+        let broadcast = Broadcast {
+            topic_id,
+            post: Post::from(Mesg {
+                parent_id: topic_id,
+                body: Chunk::try_from(b"announcement".to_vec()).unwrap(),
+                container_ids: vec![],
+            }),
+            timestamp: 100,
+        };
+        let forged = Signed::sign(&secp, broadcast.clone(), &user_key);
+        assert_eq!(
+            relay.relay(&secp, &forged, owner, peer, 100),
+            Err(BroadcastError::NotOwner(user))
+        );
+
+        let broadcast_id = broadcast.broadcast_id();
+        let signed = Signed::sign(&secp, broadcast, &owner_key);
+        assert_eq!(
+            relay.relay(&secp, &signed, owner, peer, 100),
+            Ok(bset! { user })
+        );
+        assert_eq!(
+            relay.relay(&secp, &signed, owner, user, 101),
+            Err(BroadcastError::AlreadyRelayed(broadcast_id))
+        );
+
+        relay.prune(101);
+        assert!(!relay.is_relayed(broadcast_id));
+    }
+}
@@ -0,0 +1,387 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::p2p::{ContainerList, ContainerQuery};
+use crate::{
+    Container, ContainerFullId, ContainerId, ContainerInfo, Mesg, MesgId,
+    StormApp, Topic,
+};
+
+/// Reference to a container from a message (or topic) of some app.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{app}:{message_id}")]
+pub struct ContainerRef {
+    pub app: StormApp,
+    pub message_id: MesgId,
+}
+
+/// Time after which the container popularity score halves, in seconds.
+pub const POPULARITY_HALF_LIFE: u64 = 24 * 60 * 60;
+
+/// Score added to the container popularity for each request.
+const POPULARITY_REQUEST_SCORE: u64 = 1024;
+
+/// Exponentially decaying request frequency of a container.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+struct Popularity {
+    score: u64,
+    updated: u64,
+}
+
+impl Popularity {
+    fn score_at(self, now: u64) -> u64 {
+        let halvings = now.saturating_sub(self.updated) / POPULARITY_HALF_LIFE;
+        self.score.checked_shr(halvings as u32).unwrap_or_default()
+    }
+}
+
+/// Errors returned by [`Catalog`] operations.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CatalogError {
+    /// container {0} is not known.
+    UnknownContainer(ContainerId),
+
+    /// container {0} is still referenced by {1} message(s) and can't be
+    /// removed.
+    Referenced(ContainerId, usize),
+}
+
+/// Catalog of containers known to the node, tracking which messages of
+/// which apps reference each of the containers.
+///
+/// Containers are retained until the last message referencing them is
+/// released; after that they can be garbage-collected with
+/// [`Catalog::collect_garbage`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct Catalog {
+    containers: BTreeMap<ContainerId, Container>,
+    references: BTreeMap<ContainerId, BTreeSet<ContainerRef>>,
+    popularity: BTreeMap<ContainerId, Popularity>,
+}
+
+impl Catalog {
+    pub fn new() -> Catalog { Catalog::default() }
+
+    /// Adds container to the catalog, returning its id.
+    pub fn insert_container(&mut self, container: Container) -> ContainerId {
+        let container_id = container.container_id();
+        self.containers.insert(container_id, container);
+        container_id
+    }
+
+    pub fn container(&self, container_id: ContainerId) -> Option<&Container> {
+        self.containers.get(&container_id)
+    }
+
+    pub fn contains(&self, container_id: ContainerId) -> bool {
+        self.containers.contains_key(&container_id)
+    }
+
+    pub fn container_ids(&self) -> impl Iterator<Item = ContainerId> + '_ {
+        self.containers.keys().copied()
+    }
+
+    /// Registers reference to a container from a message of some app.
+    /// Returns `false` if the reference was already known.
+    pub fn add_reference(
+        &mut self,
+        container_id: ContainerId,
+        reference: ContainerRef,
+    ) -> bool {
+        self.references.entry(container_id).or_default().insert(reference)
+    }
+
+    /// Registers references to all containers attached to a message.
+    pub fn retain_mesg(&mut self, app: StormApp, mesg: &Mesg) {
+        let message_id = mesg.mesg_id();
+        for container_id in &mesg.container_ids {
+            self.add_reference(*container_id, ContainerRef { app, message_id });
+        }
+    }
+
+    /// Registers references to all containers attached to a topic.
+    pub fn retain_topic(&mut self, app: StormApp, topic: &Topic) {
+        let message_id = topic.mesg_id();
+        for container_id in &topic.container_ids {
+            self.add_reference(*container_id, ContainerRef { app, message_id });
+        }
+    }
+
+    /// Removes all references from a message (or a topic) which is no longer
+    /// retained by the app.
+    pub fn release_mesg(&mut self, app: StormApp, message_id: MesgId) {
+        let reference = ContainerRef { app, message_id };
+        self.references.retain(|_, refs| {
+            refs.remove(&reference);
+            !refs.is_empty()
+        });
+    }
+
+    /// Returns all messages referencing the container.
+    pub fn references(
+        &self,
+        container_id: ContainerId,
+    ) -> BTreeSet<ContainerRef> {
+        self.references.get(&container_id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_referenced(&self, container_id: ContainerId) -> bool {
+        self.references.contains_key(&container_id)
+    }
+
+    /// Lists page of containers referenced by the app messages which match
+    /// the query, ordered by container id.
+    pub fn list_containers(
+        &self,
+        app: StormApp,
+        query: &ContainerQuery,
+    ) -> ContainerList {
+        let mut matching = self
+            .containers
+            .iter()
+            .filter(|(id, container)| query.matches(**id, container))
+            .filter_map(|(id, container)| {
+                let reference = self
+                    .references
+                    .get(id)?
+                    .iter()
+                    .find(|reference| reference.app == app)?;
+                Some(ContainerInfo {
+                    header: container.header.clone(),
+                    id: ContainerFullId {
+                        message_id: reference.message_id,
+                        container_id: *id,
+                    },
+                    tags: empty!(),
+                })
+            });
+        let containers =
+            matching.by_ref().take(query.limit as usize).collect::<Vec<_>>();
+        ContainerList {
+            mime_prefix: query.mime_prefix.clone(),
+            containers,
+            more: matching.next().is_some(),
+        }
+    }
+
+    /// Removes container from the catalog. Fails if the container is still
+    /// referenced by some message.
+    pub fn remove_container(
+        &mut self,
+        container_id: ContainerId,
+    ) -> Result<Container, CatalogError> {
+        if let Some(refs) = self.references.get(&container_id) {
+            return Err(CatalogError::Referenced(container_id, refs.len()));
+        }
+        self.popularity.remove(&container_id);
+        self.containers
+            .remove(&container_id)
+            .ok_or(CatalogError::UnknownContainer(container_id))
+    }
+
+    /// Removes all containers which are not referenced by any message,
+    /// returning them.
+    pub fn collect_garbage(&mut self) -> Vec<(ContainerId, Container)> {
+        let unreferenced = self
+            .containers
+            .keys()
+            .filter(|id| !self.references.contains_key(id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in &unreferenced {
+            self.popularity.remove(id);
+        }
+        unreferenced
+            .into_iter()
+            .filter_map(|id| self.containers.remove(&id).map(|c| (id, c)))
+            .collect()
+    }
+
+    /// Registers request for the container at unix timestamp `now`. Returns
+    /// `false` if the container is not known.
+    pub fn record_request(
+        &mut self,
+        container_id: ContainerId,
+        now: u64,
+    ) -> bool {
+        if !self.containers.contains_key(&container_id) {
+            return false;
+        }
+        let popularity = self.popularity.entry(container_id).or_default();
+        let score = popularity.score_at(now);
+        *popularity = Popularity {
+            score: score.saturating_add(POPULARITY_REQUEST_SCORE),
+            updated: now.max(popularity.updated),
+        };
+        true
+    }
+
+    /// Popularity score of the container at unix timestamp `now`. The score
+    /// grows with each request and halves each [`POPULARITY_HALF_LIFE`]
+    /// seconds.
+    pub fn popularity(&self, container_id: ContainerId, now: u64) -> u64 {
+        self.popularity
+            .get(&container_id)
+            .map(|popularity| popularity.score_at(now))
+            .unwrap_or_default()
+    }
+
+    /// Lists all known containers ordered from the most to the least popular
+    /// at unix timestamp `now`.
+    pub fn popularity_ranking(&self, now: u64) -> Vec<(ContainerId, u64)> {
+        let mut ranking = self
+            .containers
+            .keys()
+            .map(|id| (*id, self.popularity(*id, now)))
+            .collect::<Vec<_>>();
+        ranking.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranking
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::TaggedHash;
+    use stens::AsciiString;
+
+    use super::*;
+
+    fn container(data: &[u8]) -> Container {
+        Container::chunkify(AsciiString::new(), "", data, 16).unwrap().0
+    }
+
+    fn reference(no: u8) -> ContainerRef {
+        ContainerRef {
+            app: StormApp::Storage,
+            message_id: MesgId::hash(&[no]),
+        }
+    }
+
+    #[test]
+    fn test_references() {
+        let mut catalog = Catalog::new();
+        let container_id = catalog.insert_container(container(b"data"));
+        assert!(!catalog.is_referenced(container_id));
+
+        assert!(catalog.add_reference(container_id, reference(1)));
+        assert!(!catalog.add_reference(container_id, reference(1)));
+        assert!(catalog.add_reference(container_id, reference(2)));
+        assert_eq!(catalog.references(container_id).len(), 2);
+
+        catalog.release_mesg(StormApp::Storage, reference(1).message_id);
+        assert_eq!(catalog.references(container_id), bset! { reference(2) });
+        // Releasing the same message again does not underflow the count
+        catalog.release_mesg(StormApp::Storage, reference(1).message_id);
+        assert_eq!(catalog.references(container_id), bset! { reference(2) });
+        // References are released per app
+        catalog.release_mesg(StormApp::Chat, reference(2).message_id);
+        assert!(catalog.is_referenced(container_id));
+
+        catalog.release_mesg(StormApp::Storage, reference(2).message_id);
+        assert!(!catalog.is_referenced(container_id));
+        assert!(catalog.references(container_id).is_empty());
+        catalog.release_mesg(StormApp::Storage, reference(2).message_id);
+        assert!(!catalog.is_referenced(container_id));
+        assert!(catalog.contains(container_id));
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        let mut catalog = Catalog::new();
+        let shared = catalog.insert_container(container(b"shared"));
+        let own = catalog.insert_container(container(b"own"));
+        let orphan = catalog.insert_container(container(b"orphan"));
+        catalog.add_reference(shared, reference(1));
+        catalog.add_reference(shared, reference(2));
+        catalog.add_reference(own, reference(1));
+        catalog.record_request(orphan, 0);
+
+        let collected = catalog.collect_garbage();
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].0, orphan);
+        assert_eq!(catalog.popularity(orphan, 0), 0);
+
+        catalog.release_mesg(StormApp::Storage, reference(1).message_id);
+        let collected = catalog.collect_garbage();
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].0, own);
+        // Container is kept while it is referenced by another message
+        assert!(catalog.contains(shared));
+        assert!(catalog.collect_garbage().is_empty());
+
+        catalog.release_mesg(StormApp::Storage, reference(2).message_id);
+        assert_eq!(catalog.collect_garbage().len(), 1);
+        assert_eq!(catalog.container_ids().count(), 0);
+    }
+
+    #[test]
+    fn test_remove_container() {
+        let mut catalog = Catalog::new();
+        let container_id = catalog.insert_container(container(b"data"));
+        catalog.add_reference(container_id, reference(1));
+        catalog.add_reference(container_id, reference(2));
+        assert_eq!(
+            catalog.remove_container(container_id),
+            Err(CatalogError::Referenced(container_id, 2))
+        );
+
+        catalog.release_mesg(StormApp::Storage, reference(1).message_id);
+        catalog.release_mesg(StormApp::Storage, reference(2).message_id);
+        assert_eq!(
+            catalog.remove_container(container_id),
+            Ok(container(b"data"))
+        );
+        assert_eq!(
+            catalog.remove_container(container_id),
+            Err(CatalogError::UnknownContainer(container_id))
+        );
+    }
+
+    #[test]
+    fn test_popularity() {
+        let mut catalog = Catalog::new();
+        let popular = catalog.insert_container(container(b"popular"));
+        let rare = catalog.insert_container(container(b"rare"));
+        assert!(!catalog.record_request(ContainerId::default(), 0));
+
+        assert!(catalog.record_request(popular, 0));
+        assert!(catalog.record_request(popular, 0));
+        assert!(catalog.record_request(rare, POPULARITY_HALF_LIFE));
+        assert_eq!(catalog.popularity(popular, 0), 2048);
+        assert_eq!(catalog.popularity(popular, POPULARITY_HALF_LIFE), 1024);
+        assert_eq!(catalog.popularity(rare, POPULARITY_HALF_LIFE), 1024);
+        assert_eq!(catalog.popularity(popular, 100 * POPULARITY_HALF_LIFE), 0);
+
+        // Equal scores are ordered by container id
+        let mut expected = vec![(popular, 1024), (rare, 1024)];
+        expected.sort();
+        assert_eq!(catalog.popularity_ranking(POPULARITY_HALF_LIFE), expected);
+        assert_eq!(catalog.popularity_ranking(0)[0], (popular, 2048));
+
+        // Requests reported out of order do not move the score back in time
+        assert!(catalog.record_request(rare, 0));
+        assert_eq!(catalog.popularity(rare, POPULARITY_HALF_LIFE), 2048);
+        assert_eq!(
+            catalog.popularity_ranking(2 * POPULARITY_HALF_LIFE)[0],
+            (rare, 1024)
+        );
+    }
+}
@@ -8,14 +8,30 @@
 // You should have received a copy of the MIT License along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::{btree_map, BTreeMap, BTreeSet};
 use std::convert::Infallible;
+use std::io;
 use std::ops::{Deref, DerefMut};
 
+use amplify::Wrapper;
 use bitcoin_hashes::{sha256, Hash};
 use commit_verify::{commit_encode, ConsensusCommit};
-use strict_encoding::{MediumVec, StrictEncode};
+use strict_encoding::{MediumVec, StrictDecode, StrictEncode};
 
-use crate::ContainerId;
+use crate::p2p::ChunkPull;
+use crate::{ContainerFullId, ContainerId, MesgId, StormApp};
+
+/// Maximal size of a chunk, limited by the size of a single Bifrost packet.
+pub const MAX_CHUNK_SIZE: u32 = (1 << 24) - 1;
+
+/// Chunk size used when the recipient has not advertised its limits.
+pub const DEFAULT_CHUNK_SIZE: u32 = MAX_CHUNK_SIZE;
+
+/// Computes chunk size suitable for both peers given the maximum chunk sizes
+/// advertised by each of them.
+pub fn negotiate_chunk_size(local_max: u32, remote_max: u32) -> u32 {
+    local_max.min(remote_max).clamp(1, MAX_CHUNK_SIZE)
+}
 
 /// ChunkId is a non-tagged hash of all of the chunk data. It is a single hash
 /// such that it can be length-extended; i.e. chunks are composable.
@@ -68,6 +84,208 @@ pub struct ChunkFullId {
     pub chunk_id: ChunkId,
 }
 
+/// Set of chunk full ids grouped by container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ChunkFullIdSet(BTreeMap<ContainerId, BTreeSet<ChunkId>>);
+
+impl ChunkFullIdSet {
+    pub fn new() -> ChunkFullIdSet { ChunkFullIdSet::default() }
+
+    pub fn insert(&mut self, full_id: ChunkFullId) -> bool {
+        self.0.entry(full_id.container_id).or_default().insert(full_id.chunk_id)
+    }
+
+    pub fn remove(&mut self, full_id: ChunkFullId) -> bool {
+        let chunk_ids = match self.0.get_mut(&full_id.container_id) {
+            Some(chunk_ids) => chunk_ids,
+            None => return false,
+        };
+        let removed = chunk_ids.remove(&full_id.chunk_id);
+        if chunk_ids.is_empty() {
+            self.0.remove(&full_id.container_id);
+        }
+        removed
+    }
+
+    pub fn contains(&self, full_id: ChunkFullId) -> bool {
+        self.0
+            .get(&full_id.container_id)
+            .map(|chunk_ids| chunk_ids.contains(&full_id.chunk_id))
+            .unwrap_or_default()
+    }
+
+    /// Total number of chunks in all containers.
+    pub fn len(&self) -> usize { self.0.values().map(BTreeSet::len).sum() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Containers having at least one chunk in the set.
+    pub fn containers(&self) -> impl Iterator<Item = ContainerId> + '_ {
+        self.0.keys().copied()
+    }
+
+    /// Chunks of the container present in the set.
+    pub fn chunk_ids(
+        &self,
+        container_id: ContainerId,
+    ) -> Option<&BTreeSet<ChunkId>> {
+        self.0.get(&container_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ChunkFullId> + '_ {
+        self.0.iter().flat_map(|(container_id, chunk_ids)| {
+            chunk_ids.iter().map(|chunk_id| ChunkFullId {
+                container_id: *container_id,
+                chunk_id: *chunk_id,
+            })
+        })
+    }
+
+    /// Produces request for the chunks of the container present in the set.
+    pub fn pull(
+        &self,
+        app: StormApp,
+        container: ContainerFullId,
+    ) -> Option<ChunkPull> {
+        self.0.get(&container.container_id).map(|chunk_ids| ChunkPull {
+            app,
+            message_id: container.message_id,
+            container_id: container.container_id,
+            chunk_ids: chunk_ids.clone(),
+        })
+    }
+
+    /// Produces requests for the chunks of all containers in the set, which
+    /// must be attached to the same message.
+    pub fn pulls(
+        &self,
+        app: StormApp,
+        message_id: MesgId,
+    ) -> impl Iterator<Item = ChunkPull> + '_ {
+        self.0.iter().map(move |(container_id, chunk_ids)| ChunkPull {
+            app,
+            message_id,
+            container_id: *container_id,
+            chunk_ids: chunk_ids.clone(),
+        })
+    }
+}
+
+impl FromIterator<ChunkFullId> for ChunkFullIdSet {
+    fn from_iter<I: IntoIterator<Item = ChunkFullId>>(iter: I) -> Self {
+        let mut set = ChunkFullIdSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<ChunkFullId> for ChunkFullIdSet {
+    fn extend<I: IntoIterator<Item = ChunkFullId>>(&mut self, iter: I) {
+        for full_id in iter {
+            self.insert(full_id);
+        }
+    }
+}
+
+impl IntoIterator for ChunkFullIdSet {
+    type Item = (ContainerId, BTreeSet<ChunkId>);
+    type IntoIter = btree_map::IntoIter<ContainerId, BTreeSet<ChunkId>>;
+
+    fn into_iter(self) -> Self::IntoIter { self.0.into_iter() }
+}
+
+impl From<ChunkFullIdSet> for BTreeMap<ContainerId, BTreeSet<ChunkId>> {
+    fn from(set: ChunkFullIdSet) -> Self { set.0 }
+}
+
+impl From<BTreeMap<ContainerId, BTreeSet<ChunkId>>> for ChunkFullIdSet {
+    fn from(mut map: BTreeMap<ContainerId, BTreeSet<ChunkId>>) -> Self {
+        map.retain(|_, chunk_ids| !chunk_ids.is_empty());
+        ChunkFullIdSet(map)
+    }
+}
+
+/// Sorted set of chunk ids with compact wire representation.
+///
+/// Each id except the first one is encoded as the length of the prefix it
+/// shares with the previous id followed by the remaining bytes. Sorted ids
+/// of a large container share prefixes of about `log2(n) / 8` bytes, which
+/// saves bandwidth when requesting tens of thousands of chunks. Decoding
+/// rejects unsorted and non-canonically encoded sets.
+#[derive(
+    Wrapper, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, From
+)]
+pub struct CompactChunkIds(BTreeSet<ChunkId>);
+
+impl StrictEncode for CompactChunkIds {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let count = u32::try_from(self.0.len()).map_err(|_| {
+            strict_encoding::Error::ExceedMaxItems(self.0.len())
+        })?;
+        let mut len = count.strict_encode(&mut e)?;
+        let mut prev: Option<&ChunkId> = None;
+        for chunk_id in &self.0 {
+            let shared = prev
+                .map(|prev| {
+                    prev[..]
+                        .iter()
+                        .zip(chunk_id[..].iter())
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                })
+                .unwrap_or_default();
+            if prev.is_some() {
+                len += (shared as u8).strict_encode(&mut e)?;
+            }
+            e.write_all(&chunk_id[shared..])?;
+            len += ChunkId::LEN - shared;
+            prev = Some(chunk_id);
+        }
+        Ok(len)
+    }
+}
+
+impl StrictDecode for CompactChunkIds {
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let count = u32::strict_decode(&mut d)?;
+        let mut set = BTreeSet::new();
+        let mut prev = [0u8; 32];
+        for index in 0..count {
+            let shared = if index == 0 {
+                0
+            } else {
+                u8::strict_decode(&mut d)? as usize
+            };
+            if shared >= ChunkId::LEN {
+                return Err(strict_encoding::Error::DataIntegrityError(
+                    format!("chunk id shares {} bytes with previous", shared),
+                ));
+            }
+            let mut id = prev;
+            d.read_exact(&mut id[shared..])?;
+            if index > 0 && id[shared] <= prev[shared] {
+                return Err(strict_encoding::Error::DataIntegrityError(s!(
+                    "chunk ids are not sorted or not canonically encoded"
+                )));
+            }
+            set.insert(ChunkId::from_inner(id));
+            prev = id;
+        }
+        Ok(CompactChunkIds(set))
+    }
+}
+
+impl FromIterator<ChunkId> for CompactChunkIds {
+    fn from_iter<I: IntoIterator<Item = ChunkId>>(iter: I) -> Self {
+        CompactChunkIds(iter.into_iter().collect())
+    }
+}
+
 #[derive(
     Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
 )]
@@ -175,7 +393,7 @@ pub mod encoding {
     Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default, From, Display
 )]
 #[derive(NetworkEncode, NetworkDecode)]
-#[display("<chunk>")]
+#[display(Chunk::to_summary_string)]
 pub struct Chunk(MediumVec<u8>);
 
 impl Deref for Chunk {
@@ -226,6 +444,16 @@ impl ConsensusCommit for Chunk {
 
 impl Chunk {
     pub fn chunk_id(&self) -> ChunkId { self.consensus_commit() }
+
+    /// Describes chunk with its size and the beginning of its id; used by the
+    /// display implementation, which should not flood logs with chunk data.
+    fn to_summary_string(&self) -> String {
+        format!(
+            "{} bytes, {}",
+            self.len(),
+            crate::p2p::short_id(self.chunk_id())
+        )
+    }
 }
 
 impl TryToChunk for Chunk {
@@ -237,3 +465,79 @@ impl TryFromChunk for Chunk {
 
     fn try_from_chunk(chunk: Chunk) -> Result<Self, Self::Error> { Ok(chunk) }
 }
+
+#[cfg(test)]
+mod test {
+    use commit_verify::CommitVerify;
+
+    use super::*;
+
+    #[test]
+    fn test_full_id_set() {
+        let container_a = ContainerId::default();
+        let container_b = ContainerId::commit(b"b");
+        let full_id = |container_id, byte: u8| ChunkFullId {
+            container_id,
+            chunk_id: ChunkId::hash(&[byte]),
+        };
+        let mut set = [
+            full_id(container_a, 1),
+            full_id(container_a, 2),
+            full_id(container_b, 1),
+        ]
+        .into_iter()
+        .collect::<ChunkFullIdSet>();
+        assert_eq!(set.len(), 3);
+        assert!(!set.insert(full_id(container_a, 1)));
+        assert!(set.contains(full_id(container_b, 1)));
+        assert_eq!(set.chunk_ids(container_a).unwrap().len(), 2);
+
+        let pulls =
+            set.pulls(StormApp::Storage, MesgId::default()).collect::<Vec<_>>();
+        assert_eq!(pulls.len(), 2);
+        assert_eq!(
+            pulls.iter().map(|pull| pull.chunk_ids.len()).sum::<usize>(),
+            3
+        );
+
+        let compact = set
+            .chunk_ids(container_a)
+            .unwrap()
+            .iter()
+            .copied()
+            .collect::<CompactChunkIds>();
+        assert_eq!(
+            CompactChunkIds::strict_deserialize(
+                compact.strict_serialize().unwrap()
+            )
+            .unwrap(),
+            compact
+        );
+
+        assert!(set.remove(full_id(container_b, 1)));
+        assert_eq!(set.containers().collect::<Vec<_>>(), vec![container_a]);
+        let map = BTreeMap::from(set.clone());
+        assert_eq!(ChunkFullIdSet::from(map), set);
+    }
+
+    #[test]
+    fn test_compact_chunk_ids() {
+        let compact = (0u16..1000)
+            .map(|no| ChunkId::hash(&no.to_le_bytes()))
+            .collect::<CompactChunkIds>();
+        let data = compact.strict_serialize().unwrap();
+        let plain = compact.as_inner().strict_serialize().unwrap();
+        assert!(data.len() < plain.len());
+        assert_eq!(
+            CompactChunkIds::strict_deserialize(&data).unwrap(),
+            compact
+        );
+
+        let mut unsorted = vec![2u8, 0, 0, 0];
+        unsorted.extend([1u8; 32]);
+        unsorted.push(0);
+        unsorted.extend([0u8; 32]);
+        assert!(CompactChunkIds::strict_deserialize(&unsorted[..38]).is_err());
+        assert!(CompactChunkIds::strict_deserialize(&unsorted).is_err());
+    }
+}
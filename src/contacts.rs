@@ -0,0 +1,233 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Contact list of the chat app, backed up and synchronized between user
+//! devices as an encrypted storm container.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use secp256k1::PublicKey;
+use stens::AsciiString;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::chunk::TooLargeData;
+use crate::encryption::{ContainerKey, EncryptionError};
+use crate::{Chunk, Container, MesgId, StormIdentity};
+
+/// MIME type of containers holding contact lists.
+pub const CONTACTS_MIME: &str = "application/x-storm-contacts";
+
+/// Errors restoring contact list from a container.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ContactsError {
+    /// container is not a contact list.
+    NotContacts,
+
+    /// unable to decrypt contact list: {0}
+    #[from]
+    Encryption(EncryptionError),
+
+    /// decrypted data are not a valid contact list.
+    Decoding,
+}
+
+/// Single entry of a contact list.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Contact {
+    pub identity: StormIdentity,
+    /// Local alias assigned to the contact by the user, taking precedence
+    /// over the alias from the identity.
+    pub alias: Option<String>,
+    /// Topics shared with the contact.
+    pub topics: BTreeSet<MesgId>,
+    /// Unix timestamp of the last contact update.
+    pub updated: u64,
+}
+
+impl Contact {
+    /// Alias to be displayed for the contact.
+    pub fn display_alias(&self) -> Option<&str> {
+        self.alias.as_deref().or(self.identity.alias.as_deref())
+    }
+}
+
+/// Contact list with merge semantics allowing to synchronize it between
+/// devices.
+///
+/// Merge keeps, for each contact, the most recently updated record and the
+/// union of the shared topics. Removed contacts are kept as tombstones with
+/// the removal time, so removal propagates unless the contact is updated
+/// after it was removed.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ContactList {
+    contacts: BTreeMap<PublicKey, Contact>,
+    removed: BTreeMap<PublicKey, u64>,
+}
+
+impl ContactList {
+    pub fn new() -> ContactList { ContactList::default() }
+
+    pub fn len(&self) -> usize { self.contacts.len() }
+
+    pub fn is_empty(&self) -> bool { self.contacts.is_empty() }
+
+    pub fn contact(&self, node_id: PublicKey) -> Option<&Contact> {
+        self.contacts.get(&node_id)
+    }
+
+    pub fn contacts(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.values()
+    }
+
+    /// Adds or updates contact. The contact is ignored if it was removed
+    /// later than updated.
+    pub fn upsert(&mut self, contact: Contact) {
+        let node_id = contact.identity.node_id;
+        match self.removed.get(&node_id) {
+            Some(removed) if *removed >= contact.updated => return,
+            Some(_) => {
+                self.removed.remove(&node_id);
+            }
+            None => {}
+        }
+        match self.contacts.get_mut(&node_id) {
+            Some(existing) => {
+                let mut topics = contact.topics.clone();
+                topics.extend(existing.topics.iter().copied());
+                if contact.updated > existing.updated {
+                    *existing = contact;
+                }
+                existing.topics = topics;
+            }
+            None => {
+                self.contacts.insert(node_id, contact);
+            }
+        }
+    }
+
+    /// Removes contact at time `now`.
+    pub fn remove(&mut self, node_id: PublicKey, now: u64) -> Option<Contact> {
+        let removed = self.removed.entry(node_id).or_default();
+        *removed = (*removed).max(now);
+        self.contacts.remove(&node_id)
+    }
+
+    /// Merges contact list from another device.
+    pub fn merge(&mut self, other: &ContactList) {
+        for (node_id, removed) in &other.removed {
+            let updated = self
+                .contacts
+                .get(node_id)
+                .map(|contact| contact.updated)
+                .unwrap_or_default();
+            if *removed >= updated {
+                self.remove(*node_id, *removed);
+            }
+        }
+        for contact in other.contacts.values() {
+            self.upsert(contact.clone());
+        }
+    }
+
+    /// Encrypts contact list into a container.
+    pub fn to_container(
+        &self,
+        key: &ContainerKey,
+        chunk_size: u32,
+    ) -> Result<(Container, Vec<Chunk>), TooLargeData> {
+        let data = self.strict_serialize().map_err(|_| TooLargeData)?;
+        let mime = AsciiString::try_from(CONTACTS_MIME)
+            .expect("static MIME type is ASCII");
+        key.encrypt_container(mime, "", &data, chunk_size)
+    }
+
+    /// Decrypts contact list from the container chunks, provided in the
+    /// container order.
+    pub fn from_container(
+        key: &ContainerKey,
+        container: &Container,
+        chunks: &[Chunk],
+    ) -> Result<ContactList, ContactsError> {
+        if container.header.mime.as_str() != CONTACTS_MIME {
+            return Err(ContactsError::NotContacts);
+        }
+        let mut data = vec![];
+        for index in 0..container.chunks.len() {
+            let chunk = chunks
+                .get(index)
+                .ok_or(EncryptionError::UnknownChunk(index))?;
+            data.extend(key.decrypt_container_chunk(container, index, chunk)?);
+        }
+        ContactList::strict_deserialize(data)
+            .map_err(|_| ContactsError::Decoding)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::CommitVerify;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+
+    fn contact(secret: u8, topic: u8, updated: u64) -> Contact {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[secret; 32]).unwrap();
+        Contact {
+            identity: StormIdentity::new(&secp, &secret, None),
+            alias: Some(format!("contact {}", updated)),
+            topics: bset! { MesgId::commit(&[topic]) },
+            updated,
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let alice = contact(0x11, 1, 10);
+        let bob = contact(0x22, 2, 10);
+        let alice_node = alice.identity.node_id;
+
+        let mut laptop = ContactList::new();
+        laptop.upsert(alice.clone());
+        laptop.upsert(bob.clone());
+        let mut phone = laptop.clone();
+
+        phone.upsert(contact(0x11, 3, 20));
+        laptop.remove(bob.identity.node_id, 15);
+
+        laptop.merge(&phone);
+        phone.merge(&laptop);
+        assert_eq!(laptop, phone);
+        assert_eq!(laptop.len(), 1);
+        let merged = laptop.contact(alice_node).unwrap();
+        assert_eq!(merged.display_alias(), Some("contact 20"));
+        assert_eq!(merged.topics.len(), 2);
+
+        let key = ContainerKey::from([9u8; 32]);
+        let (container, chunks) = laptop.to_container(&key, 128).unwrap();
+        assert_eq!(
+            ContactList::from_container(&key, &container, &chunks),
+            Ok(laptop)
+        );
+    }
+}
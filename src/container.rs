@@ -8,17 +8,37 @@
 // You should have received a copy of the MIT License along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+//! Containers and their identifiers.
+//!
+//! Container id is a two-level commitment. [`ContainerHeaderId`] commits to
+//! the container metadata, which allows to verify and pull the header
+//! independently of the potentially huge chunk index. [`ContainerId`]
+//! commits to the header id and the Merkle root of the container chunk ids.
+//! The Merkle tree allows to
+//! prove that a chunk belongs to a container with a [`ChunkProof`] of size
+//! logarithmic in the number of chunks.
+//!
+//! Containers and messages created before the two-level commitment refer to
+//! containers by the hash of the whole serialized container. Such ids can be
+//! still computed with [`Container::legacy_container_id`] and mapped to the
+//! current ones with [`Container::id_migration_map`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
 use std::str::FromStr;
 
-use bitcoin_hashes::{sha256, sha256t};
+use amplify::Wrapper;
+use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine};
+use commit_verify::commit_encode::CommitEncode;
 use commit_verify::{
     commit_encode, CommitVerify, ConsensusCommit, PrehashedProtocol, TaggedHash,
 };
 use lnpbp_bech32::{FromBech32Str, ToBech32String};
 use stens::AsciiString;
-use strict_encoding::{MediumVec, StrictEncode};
+use strict_encoding::{MediumVec, StrictDecode, StrictEncode};
 
-use crate::{ChunkId, MesgId};
+use crate::chunk::{self, TooLargeData, MAX_CHUNK_SIZE};
+use crate::{Chunk, ChunkId, MesgId, Tag};
 
 // "storm:container"
 static MIDSTATE_CONTAINER_ID: [u8; 32] = [
@@ -26,6 +46,19 @@ static MIDSTATE_CONTAINER_ID: [u8; 32] = [
     137, 2, 113, 215, 156, 29, 193, 183, 154, 55, 48, 250, 208, 149, 206, 14,
 ];
 
+// "storm:container-header"
+static MIDSTATE_CONTAINER_HEADER_ID: [u8; 32] = [
+    80, 204, 223, 182, 177, 236, 230, 164, 94, 189, 205, 193, 49, 215, 179,
+    168, 131, 195, 203, 229, 26, 97, 190, 174, 132, 130, 25, 197, 82, 255, 249,
+    132,
+];
+
+// "storm:chunk-merkle"
+static MIDSTATE_CHUNK_MERKLE: [u8; 32] = [
+    190, 58, 114, 93, 155, 95, 6, 91, 111, 216, 113, 65, 92, 161, 91, 162, 74,
+    245, 127, 67, 25, 131, 219, 29, 138, 235, 76, 158, 138, 8, 213, 123,
+];
+
 pub const STORM_CONTAINER_ID_HRP: &str = "storm";
 
 /// Tag used for [`ContainerId`] hash type
@@ -39,6 +72,205 @@ impl sha256t::Tag for ContainerIdTag {
     }
 }
 
+/// Tag used for [`ContainerHeaderId`] hash type
+pub struct ContainerHeaderIdTag;
+
+impl sha256t::Tag for ContainerHeaderIdTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate =
+            sha256::Midstate::from_inner(MIDSTATE_CONTAINER_HEADER_ID);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Identifier of the container metadata
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(
+    Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, From
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[wrapper(Debug, Display)]
+pub struct ContainerHeaderId(sha256t::Hash<ContainerHeaderIdTag>);
+
+impl<Msg> CommitVerify<Msg, PrehashedProtocol> for ContainerHeaderId
+where Msg: AsRef<[u8]>
+{
+    #[inline]
+    fn commit(msg: &Msg) -> ContainerHeaderId { ContainerHeaderId::hash(msg) }
+}
+
+/// Tag used for the nodes of the Merkle tree over container chunk ids
+pub struct ChunkMerkleTag;
+
+impl sha256t::Tag for ChunkMerkleTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_CHUNK_MERKLE);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Node of the Merkle tree over container chunk ids.
+pub type ChunkMerkleNode = sha256t::Hash<ChunkMerkleTag>;
+
+fn merkle_leaf(chunk_id: &ChunkId) -> ChunkMerkleNode {
+    let mut engine = ChunkMerkleNode::engine();
+    engine.input(&[0u8]);
+    engine.input(&chunk_id[..]);
+    ChunkMerkleNode::from_engine(engine)
+}
+
+fn merkle_branch(
+    left: &ChunkMerkleNode,
+    right: &ChunkMerkleNode,
+) -> ChunkMerkleNode {
+    let mut engine = ChunkMerkleNode::engine();
+    engine.input(&[1u8]);
+    engine.input(&left[..]);
+    engine.input(&right[..]);
+    ChunkMerkleNode::from_engine(engine)
+}
+
+fn merkle_level(nodes: &[ChunkMerkleNode]) -> Vec<ChunkMerkleNode> {
+    nodes
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => merkle_branch(left, right),
+            [single] => *single,
+            _ => unreachable!("chunks of two elements"),
+        })
+        .collect()
+}
+
+/// Levels of the Merkle tree over the chunk ids, from the leaves up to the
+/// root.
+fn merkle_levels(chunk_ids: &[ChunkId]) -> Vec<Vec<ChunkMerkleNode>> {
+    let mut levels =
+        vec![chunk_ids.iter().map(merkle_leaf).collect::<Vec<_>>()];
+    while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+        let next = merkle_level(level);
+        levels.push(next);
+    }
+    levels
+}
+
+/// Extracts proof for the leaf at `index` from the Merkle tree levels.
+fn merkle_proof(levels: &[Vec<ChunkMerkleNode>], index: usize) -> ChunkProof {
+    let mut pos = index;
+    let mut path = vec![];
+    for level in levels.iter().take_while(|level| level.len() > 1) {
+        if let Some(sibling) = level.get(pos ^ 1) {
+            path.push(*sibling);
+        }
+        pos /= 2;
+    }
+    ChunkProof {
+        index: index as u32,
+        path,
+    }
+}
+
+/// Computes Merkle root of the chunk ids. Leaves and branches are hashed
+/// with distinct prefixes; a node without a pair is moved to the next level
+/// unchanged. Root of an empty list is the hash of an empty string.
+pub fn chunk_merkle_root(chunk_ids: &[ChunkId]) -> ChunkMerkleNode {
+    if chunk_ids.is_empty() {
+        return ChunkMerkleNode::hash(&[]);
+    }
+    let mut level = chunk_ids.iter().map(merkle_leaf).collect::<Vec<_>>();
+    while level.len() > 1 {
+        level = merkle_level(&level);
+    }
+    level[0]
+}
+
+/// Data committed to by the container id.
+fn container_commitment(
+    header_id: ContainerHeaderId,
+    merkle_root: ChunkMerkleNode,
+) -> [u8; 64] {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(&header_id.as_inner()[..]);
+    data[32..].copy_from_slice(&merkle_root[..]);
+    data
+}
+
+/// Proof that a chunk belongs to a container at a given index, consisting
+/// of the sibling nodes on the path from the chunk leaf to the Merkle root.
+/// Proof size is logarithmic in the number of the container chunks, so
+/// chunks can be verified without the container chunk index, for instance
+/// when a part of a container is retrieved or audited.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ChunkProof {
+    /// Index of the chunk in the container.
+    pub index: u32,
+    pub path: Vec<ChunkMerkleNode>,
+}
+
+impl ChunkProof {
+    /// Computes Merkle root from the chunk id, given the total number of
+    /// the container chunks. Returns `None` if the proof is malformed.
+    pub fn merkle_root(
+        &self,
+        chunk_id: ChunkId,
+        count: u64,
+    ) -> Option<ChunkMerkleNode> {
+        let mut pos = self.index as u64;
+        if pos >= count {
+            return None;
+        }
+        let mut len = count;
+        let mut node = merkle_leaf(&chunk_id);
+        let mut path = self.path.iter();
+        while len > 1 {
+            if pos % 2 == 1 {
+                node = merkle_branch(path.next()?, &node);
+            } else if pos + 1 < len {
+                node = merkle_branch(&node, path.next()?);
+            }
+            pos /= 2;
+            len = (len + 1) / 2;
+        }
+        match path.next() {
+            None => Some(node),
+            Some(_) => None,
+        }
+    }
+
+    /// Verifies that the chunk belongs to the container with the given id
+    /// and header at the index specified by the proof.
+    pub fn verify(
+        &self,
+        chunk_id: ChunkId,
+        container_id: ContainerId,
+        header: &ContainerHeader,
+    ) -> bool {
+        let count = match header.chunk_count() {
+            Some(count) => count,
+            None => return false,
+        };
+        self.merkle_root(chunk_id, count)
+            .map(|root| {
+                ContainerId::commit(&container_commitment(
+                    header.header_id(),
+                    root,
+                )) == container_id
+            })
+            .unwrap_or_default()
+    }
+}
+
 /// Unique data container identifier
 #[derive(
     Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
@@ -145,15 +377,19 @@ pub struct ContainerFullId {
     pub container_id: ContainerId,
 }
 
+/// Version of the container headers produced by this library. Headers of
+/// version 0 do not commit to the chunk size, so their chunks can't be
+/// mapped to the byte ranges of the container data.
+pub const CONTAINER_VERSION: u16 = 1;
+
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, AsAny)]
-#[derive(StrictEncode, StrictDecode)]
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
     serde(crate = "serde_crate")
 )]
 pub struct ContainerHeader {
-    /// Version of the container. Always 0 for now.
+    /// Version of the container, up to [`CONTAINER_VERSION`].
     pub version: u16,
     /// MIME type of the file.
     pub mime: AsciiString, // TODO: Create a dedicated MIME type
@@ -162,24 +398,250 @@ pub struct ContainerHeader {
     /// Container size, which is the sum of sizes of the individual chunks.
     ///
     /// Consensus limitation of the container size is 43 bits: 19 bits for the
-    /// number of chunks and up to 24 bits for chunk size. 19 bits for the max
-    /// number of chunks comes from the fact that the total size of the
-    /// container index must be below 2^24 bytes (to fit into a LN packet);
-    /// since the size of the chunk id is 2^5 (32) bits, and the maximum
-    /// Bifrost packet size is 2^24, we have only 24-5=19 bits to store the
-    /// chunk index.
+    /// number of chunks and 24 bits for the chunk size, which can't exceed
+    /// [`MAX_CHUNK_SIZE`]. 19 bits for the max number of chunks comes from
+    /// the fact that the total size of the container index must be below
+    /// 2^24 bytes (to fit into a LN packet); since the size of the chunk id
+    /// is 2^5 (32) bytes, we have only 24-5=19 bits to store the chunk index.
     pub size: u64,
+    /// Size of each of the container chunks, except the last one, which may
+    /// be smaller. Encoded only by the headers of version 1 and above; zero
+    /// for the headers of version 0.
+    pub chunk_size: u32,
+}
+
+// Chunk size was added to the header in version 1; version 0 headers keep
+// their original layout, so the ids committing to them do not change
+impl StrictEncode for ContainerHeader {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let mut len = self.version.strict_encode(&mut e)?
+            + self.mime.strict_encode(&mut e)?
+            + self.info.strict_encode(&mut e)?
+            + self.size.strict_encode(&mut e)?;
+        if self.version > 0 {
+            len += self.chunk_size.strict_encode(&mut e)?;
+        }
+        Ok(len)
+    }
+}
+
+impl StrictDecode for ContainerHeader {
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let version = u16::strict_decode(&mut d)?;
+        if version > CONTAINER_VERSION {
+            return Err(strict_encoding::Error::DataIntegrityError(format!(
+                "unsupported container version {}",
+                version
+            )));
+        }
+        Ok(ContainerHeader {
+            version,
+            mime: AsciiString::strict_decode(&mut d)?,
+            info: String::strict_decode(&mut d)?,
+            size: u64::strict_decode(&mut d)?,
+            chunk_size: match version {
+                0 => 0,
+                _ => u32::strict_decode(&mut d)?,
+            },
+        })
+    }
 }
 
 // TODO: Add convenience constructors for ContainerHeader constructing from
 //       a given mime type const string
 
+impl commit_encode::Strategy for ContainerHeader {
+    type Strategy = commit_encode::strategies::UsingStrict;
+}
+
+impl ConsensusCommit for ContainerHeader {
+    type Commitment = ContainerHeaderId;
+}
+
+impl ContainerHeader {
+    pub fn header_id(&self) -> ContainerHeaderId { self.consensus_commit() }
+
+    /// Number of chunks in a container with this header, or `None` if the
+    /// chunk size is zero while the container is not empty.
+    pub fn chunk_count(&self) -> Option<u64> {
+        match (self.size, self.chunk_size as u64) {
+            (0, _) => Some(0),
+            (_, 0) => None,
+            (size, chunk_size) => Some((size + chunk_size - 1) / chunk_size),
+        }
+    }
+
+    /// Detects whether the container MIME type is audio or video.
+    pub fn is_media(&self) -> bool {
+        let mime = self.mime.as_str();
+        mime.starts_with("audio/") || mime.starts_with("video/")
+    }
+}
+
 #[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
 #[derive(NetworkEncode, NetworkDecode)]
 #[display("{id}")]
 pub struct ContainerInfo {
     pub header: ContainerHeader,
     pub id: ContainerFullId,
+    /// Tags allowing to filter and search announced containers without
+    /// pulling their metadata, up to [`crate::MAX_TAGS`]. May be empty.
+    pub tags: BTreeSet<Tag>,
+}
+
+impl ContainerInfo {
+    /// Checks whether the container is tagged with all the given tags.
+    pub fn has_tags(&self, tags: &BTreeSet<Tag>) -> bool {
+        tags.is_subset(&self.tags)
+    }
+}
+
+/// Part of a container chunk covering some byte range of the container data.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{index}:{offset}+{len}")]
+pub struct ChunkSlice {
+    /// Index of the chunk in the container.
+    pub index: usize,
+    /// Offset of the range start within the chunk.
+    pub offset: u32,
+    /// Length of the range within the chunk.
+    pub len: u32,
+}
+
+/// Selection of container chunks by their indexes: a range of indexes,
+/// optionally filtered with a bitmap.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{start}..{end}")]
+pub struct ChunkSelection {
+    /// Index of the first chunk in the range.
+    pub start: u32,
+    /// Index following the last chunk in the range.
+    pub end: u32,
+    /// Bitmap of the selected chunks within the range: the least significant
+    /// bit of the first byte corresponds to the chunk at `start`. Chunks
+    /// not covered by the bitmap are not selected.
+    pub bitmap: Option<Vec<u8>>,
+}
+
+impl ChunkSelection {
+    /// Selects all chunks within the range.
+    pub fn with_range(start: u32, end: u32) -> ChunkSelection {
+        ChunkSelection {
+            start,
+            end,
+            bitmap: None,
+        }
+    }
+
+    /// Selects chunks with the given indexes.
+    pub fn with_indexes(indexes: impl IntoIterator<Item = u32>) -> Self {
+        let indexes = indexes.into_iter().collect::<Vec<_>>();
+        let start = indexes.iter().copied().min().unwrap_or_default();
+        let end = indexes.iter().copied().max().map(|max| max + 1);
+        let end = end.unwrap_or_default();
+        let mut bitmap = vec![0u8; ((end - start + 7) / 8) as usize];
+        for index in indexes {
+            let offset = (index - start) as usize;
+            bitmap[offset / 8] |= 1 << (offset % 8);
+        }
+        ChunkSelection {
+            start,
+            end,
+            bitmap: Some(bitmap),
+        }
+    }
+
+    pub fn contains(&self, index: u32) -> bool {
+        if index < self.start || index >= self.end {
+            return false;
+        }
+        let offset = (index - self.start) as usize;
+        match &self.bitmap {
+            None => true,
+            Some(bitmap) => bitmap
+                .get(offset / 8)
+                .map(|byte| byte & (1 << (offset % 8)) != 0)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Selected indexes of a container having `count` chunks.
+    pub fn indexes(&self, count: u32) -> impl Iterator<Item = u32> + '_ {
+        (self.start..self.end.min(count)).filter(|index| self.contains(*index))
+    }
+}
+
+/// Maximal number of chunks in a [`ContainerPart`], limited by the length
+/// prefix of its chunk list.
+pub const MAX_PART_CHUNKS: usize = u16::MAX as usize;
+
+/// Part of the container index covering selected chunks. Each chunk id is
+/// provided with the proof of its inclusion into the container, so the part
+/// can be verified against the container id without the full index.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ContainerPart {
+    pub container_id: ContainerId,
+    pub header: ContainerHeader,
+    pub chunks: Vec<(ChunkId, ChunkProof)>,
+}
+
+impl ContainerPart {
+    /// Verifies that all chunks belong to the container.
+    pub fn verify(&self) -> bool {
+        self.chunks.iter().all(|(chunk_id, proof)| {
+            proof.verify(*chunk_id, self.container_id, &self.header)
+        })
+    }
+}
+
+/// First divergence of the chunks from the container index, found by
+/// [`Container::verify_all`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ContainerVerifyError {
+    /// container chunk size or number of chunks is inconsistent with the
+    /// container size.
+    InvalidIndex,
+
+    /// chunk #{index} has {actual} bytes instead of {expected}.
+    ChunkSize {
+        index: usize,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// chunk #{index} is expected at position #{expected_at}.
+    Misplaced { index: usize, expected_at: usize },
+
+    /// chunk #{index} has id {actual} instead of {expected}.
+    IdMismatch {
+        index: usize,
+        expected: ChunkId,
+        actual: ChunkId,
+    },
+
+    /// only {0} chunks are provided out of {1}.
+    MissingChunks(usize, usize),
+
+    /// more than {0} chunks are provided.
+    ExtraChunks(usize),
 }
 
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, AsAny)]
@@ -194,8 +656,15 @@ pub struct Container {
     pub chunks: MediumVec<ChunkId>,
 }
 
-impl commit_encode::Strategy for Container {
-    type Strategy = commit_encode::strategies::UsingStrict;
+/// Container commits to its header id followed by the Merkle root of its
+/// chunk ids.
+impl CommitEncode for Container {
+    fn commit_encode<E: io::Write>(&self, mut e: E) -> usize {
+        let data =
+            container_commitment(self.header.header_id(), self.merkle_root());
+        e.write_all(&data).expect("in-memory encoders do not error");
+        data.len()
+    }
 }
 
 impl ConsensusCommit for Container {
@@ -203,19 +672,577 @@ impl ConsensusCommit for Container {
 }
 
 impl Container {
+    /// Splits data into chunks of `chunk_size` bytes (the last chunk may be
+    /// smaller), producing container and its chunks. The chunk size is
+    /// clamped to the `1..=MAX_CHUNK_SIZE` range; to produce a container for
+    /// a specific recipient use chunk size returned by
+    /// [`chunk::negotiate_chunk_size`].
+    ///
+    /// Fails if the data produce too many chunks to fit into the container
+    /// index.
+    pub fn chunkify(
+        mime: AsciiString,
+        info: impl ToString,
+        data: &[u8],
+        chunk_size: u32,
+    ) -> Result<(Container, Vec<Chunk>), TooLargeData> {
+        let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        let chunks = data
+            .chunks(chunk_size as usize)
+            .map(Chunk::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("chunk size does not exceed maximum chunk size");
+        let ids = chunks.iter().map(Chunk::chunk_id).collect::<Vec<_>>();
+        let container = Container {
+            header: ContainerHeader {
+                version: CONTAINER_VERSION,
+                mime,
+                info: info.to_string(),
+                size: data.len() as u64,
+                chunk_size,
+            },
+            chunks: MediumVec::try_from(ids).map_err(|_| TooLargeData)?,
+        };
+        Ok((container, chunks))
+    }
+
     pub fn container_id(&self) -> ContainerId { self.consensus_commit() }
+
+    /// Merkle root of the container chunk ids.
+    pub fn merkle_root(&self) -> ChunkMerkleNode {
+        chunk_merkle_root(&self.chunks)
+    }
+
+    /// Constructs proof that the chunk at `index` belongs to the container.
+    pub fn chunk_proof(&self, index: usize) -> Option<ChunkProof> {
+        if index >= self.chunks.len() {
+            return None;
+        }
+        Some(merkle_proof(&merkle_levels(&self.chunks), index))
+    }
+
+    /// Extracts part of the container index covering the selected chunks.
+    /// The part is truncated to the first [`MAX_PART_CHUNKS`] selected
+    /// chunks; the remaining ones have to be requested separately.
+    pub fn part(&self, selection: &ChunkSelection) -> ContainerPart {
+        let levels = merkle_levels(&self.chunks);
+        let merkle_root = match levels.last().and_then(|level| level.first()) {
+            Some(root) => *root,
+            None => chunk_merkle_root(&[]),
+        };
+        let chunks = selection
+            .indexes(self.chunks.len() as u32)
+            .take(MAX_PART_CHUNKS)
+            .map(|index| {
+                let index = index as usize;
+                (self.chunks[index], merkle_proof(&levels, index))
+            })
+            .collect();
+        ContainerPart {
+            container_id: ContainerId::commit(&container_commitment(
+                self.header.header_id(),
+                merkle_root,
+            )),
+            header: self.header.clone(),
+            chunks,
+        }
+    }
+
+    /// Container id as computed before the introduction of header ids: a
+    /// hash of the container serialized with version 0 header, which did
+    /// not include the chunk size.
+    pub fn legacy_container_id(&self) -> ContainerId {
+        let mut data = vec![];
+        self.legacy_encode(&mut data).expect("in-memory encoders do not error");
+        ContainerId::commit(&data)
+    }
+
+    fn legacy_encode(
+        &self,
+        mut e: impl io::Write,
+    ) -> Result<usize, strict_encoding::Error> {
+        Ok(0u16.strict_encode(&mut e)?
+            + self.header.mime.strict_encode(&mut e)?
+            + self.header.info.strict_encode(&mut e)?
+            + self.header.size.strict_encode(&mut e)?
+            + self.chunks.strict_encode(&mut e)?)
+    }
+
+    /// Maps legacy ids of the containers to their current ids, for
+    /// migrating stored references.
+    pub fn id_migration_map<'container>(
+        containers: impl IntoIterator<Item = &'container Container>,
+    ) -> BTreeMap<ContainerId, ContainerId> {
+        containers
+            .into_iter()
+            .map(|container| {
+                (container.legacy_container_id(), container.container_id())
+            })
+            .collect()
+    }
+
+    /// Checks that the chunk size and the number of chunks are consistent
+    /// with the container size.
+    pub fn is_chunk_size_valid(&self) -> bool {
+        let count = self.chunks.len() as u64;
+        let chunk_size = self.header.chunk_size as u64;
+        if count == 0 {
+            return self.header.size == 0;
+        }
+        chunk_size > 0
+            && (count - 1) * chunk_size < self.header.size
+            && self.header.size <= count * chunk_size
+    }
+
+    /// Verifies in a single pass over the chunks that they constitute the
+    /// container data: that the chunks follow in the container order, have
+    /// ids listed in the container index and sizes matching the container
+    /// chunk size, adding up to the container size. Returns the first found
+    /// divergence, reporting chunks with a valid id at a wrong position as
+    /// [`ContainerVerifyError::Misplaced`].
+    pub fn verify_all(
+        &self,
+        chunks: impl Iterator<Item = Chunk>,
+    ) -> Result<(), ContainerVerifyError> {
+        if !self.is_chunk_size_valid() {
+            return Err(ContainerVerifyError::InvalidIndex);
+        }
+        let count = self.chunks.len();
+        let chunk_size = self.header.chunk_size as u64;
+        let mut provided = 0usize;
+        for (index, chunk) in chunks.enumerate() {
+            if index >= count {
+                return Err(ContainerVerifyError::ExtraChunks(count));
+            }
+            let start = index as u64 * chunk_size;
+            let expected = (start + chunk_size).min(self.header.size) - start;
+            let actual = chunk.len() as u64;
+            if actual != expected {
+                return Err(ContainerVerifyError::ChunkSize {
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+            let actual = chunk.chunk_id();
+            let expected = self.chunks[index];
+            if actual != expected {
+                return Err(
+                    match self.chunks.iter().position(|id| *id == actual) {
+                        Some(expected_at) => ContainerVerifyError::Misplaced {
+                            index,
+                            expected_at,
+                        },
+                        None => ContainerVerifyError::IdMismatch {
+                            index,
+                            expected,
+                            actual,
+                        },
+                    },
+                );
+            }
+            provided += 1;
+        }
+        if provided < count {
+            return Err(ContainerVerifyError::MissingChunks(provided, count));
+        }
+        Ok(())
+    }
+
+    /// Returns chunks and intra-chunk ranges covering the byte range of
+    /// the container data starting at `offset` and of `len` bytes. The range
+    /// is truncated to the container size; if it lies outside of the
+    /// container data, or the container chunk size is inconsistent, an empty
+    /// list is returned.
+    pub fn chunks_for_range(&self, offset: u64, len: u64) -> Vec<ChunkSlice> {
+        if !self.is_chunk_size_valid() || offset >= self.header.size {
+            return vec![];
+        }
+        let chunk_size = self.header.chunk_size as u64;
+        let end = offset.saturating_add(len).min(self.header.size);
+        let mut slices = vec![];
+        let mut pos = offset;
+        while pos < end {
+            let index = pos / chunk_size;
+            let chunk_start = index * chunk_size;
+            let chunk_end = (chunk_start + chunk_size).min(end);
+            slices.push(ChunkSlice {
+                index: index as usize,
+                offset: (pos - chunk_start) as u32,
+                len: (chunk_end - pos) as u32,
+            });
+            pos = chunk_end;
+        }
+        slices
+    }
+
+    /// Returns chunk indexes in the order they should be retrieved: either
+    /// following the priority hint or, if the hint is absent or does not
+    /// match the container, in the natural container order.
+    pub fn fetch_order(&self, priority: Option<&ChunkPriority>) -> Vec<usize> {
+        match priority {
+            Some(priority) if priority.is_valid_for(self) => {
+                priority.order.iter().map(|index| *index as usize).collect()
+            }
+            _ => (0..self.chunks.len()).collect(),
+        }
+    }
+}
+
+/// Hint on the order in which chunks of a container should be retrieved
+/// (for instance, headers first for media files or metadata first for
+/// archives). The hint is not a part of the container and is not committed
+/// to by the container id; it is sent alongside the container with
+/// `ChunkPriority` message and may be ignored by the downloader.
+///
+/// Keeping the hint inside [`Container`] would make it a part of the
+/// container encoding committed to by the container id: changing the
+/// preferred order would change the id, and the ids of all existing
+/// containers would change with the new field. Thus the hint is carried
+/// separately, with the same effect on the downloads.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, ...")]
+pub struct ChunkPriority {
+    pub container_id: ContainerId,
+    /// Permutation of the container chunk indexes.
+    pub order: MediumVec<u32>,
+}
+
+impl ChunkPriority {
+    /// Checks that the hint refers to the container and is a valid
+    /// permutation of its chunk indexes.
+    pub fn is_valid_for(&self, container: &Container) -> bool {
+        if self.container_id != container.container_id()
+            || self.order.len() != container.chunks.len()
+        {
+            return false;
+        }
+        let mut seen = vec![false; container.chunks.len()];
+        for index in self.order.iter() {
+            match seen.get_mut(*index as usize) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return false,
+            }
+        }
+        true
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use amplify::Wrapper;
     use commit_verify::tagged_hash;
 
     use super::*;
+    use crate::fixtures::sample;
 
     #[test]
     fn test_container_id_midstate() {
         let midstate = tagged_hash::Midstate::with(b"storm:container");
         assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_CONTAINER_ID);
+        let midstate = tagged_hash::Midstate::with(b"storm:container-header");
+        assert_eq!(
+            midstate.into_inner().into_inner(),
+            MIDSTATE_CONTAINER_HEADER_ID
+        );
+        let midstate = tagged_hash::Midstate::with(b"storm:chunk-merkle");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_CHUNK_MERKLE);
+    }
+
+    #[test]
+    fn test_two_level_id() {
+        let (container, _) = Container::chunkify(
+            AsciiString::new(),
+            "file",
+            &[0x5Au8; 1000],
+            100,
+        )
+        .unwrap();
+        let mut renamed = container.clone();
+        renamed.header.info = s!("renamed");
+        assert_ne!(renamed.header.header_id(), container.header.header_id());
+        assert_eq!(renamed.merkle_root(), container.merkle_root());
+        assert_ne!(renamed.container_id(), container.container_id());
+
+        let map = Container::id_migration_map([&container]);
+        assert_eq!(
+            map[&container.legacy_container_id()],
+            container.container_id()
+        );
+    }
+
+    #[test]
+    fn test_legacy_id() {
+        let container = Container {
+            header: ContainerHeader {
+                version: CONTAINER_VERSION,
+                mime: AsciiString::new(),
+                info: s!("file"),
+                size: 250,
+                chunk_size: 100,
+            },
+            chunks: MediumVec::try_from(vec![ChunkId::default(); 3]).unwrap(),
+        };
+        // Id of the container as computed before the two-level commitment,
+        // from its encoding with version 0 header without the chunk size
+        let legacy_id = [
+            19, 156, 49, 10, 192, 139, 253, 153, 191, 65, 204, 125, 198, 12,
+            36, 79, 202, 115, 51, 115, 99, 92, 37, 109, 114, 56, 52, 132, 226,
+            30, 94, 89,
+        ];
+        assert_eq!(container.legacy_container_id().as_inner()[..], legacy_id);
+
+        // Legacy id does not depend on the chunk size
+        let mut resized = container.clone();
+        resized.header.chunk_size = 125;
+        assert_eq!(
+            resized.legacy_container_id(),
+            container.legacy_container_id()
+        );
+        assert_ne!(resized.container_id(), container.container_id());
+    }
+
+    #[test]
+    fn test_header_versions() {
+        // Version 0 header has the layout preceding the chunk size
+        let data = [
+            0u8, 0, 0, 0, 4, 0, b'f', b'i', b'l', b'e', 250, 0, 0, 0, 0, 0, 0,
+            0,
+        ];
+        let legacy = ContainerHeader::strict_deserialize(&data).unwrap();
+        assert_eq!(legacy, ContainerHeader {
+            version: 0,
+            mime: AsciiString::new(),
+            info: s!("file"),
+            size: 250,
+            chunk_size: 0,
+        });
+        assert_eq!(legacy.chunk_count(), None);
+        assert_eq!(legacy.strict_serialize().unwrap(), data);
+
+        let header = ContainerHeader {
+            version: CONTAINER_VERSION,
+            chunk_size: 100,
+            ..legacy
+        };
+        let data = header.strict_serialize().unwrap();
+        assert_eq!(data[..2], [1, 0]);
+        assert_eq!(data[18..], [100, 0, 0, 0]);
+        assert_eq!(ContainerHeader::strict_deserialize(&data).unwrap(), header);
+        assert_eq!(header.chunk_count(), Some(3));
+
+        let mut data = data;
+        data[0] = 2;
+        assert!(ContainerHeader::strict_deserialize(&data).is_err());
+    }
+
+    #[test]
+    fn test_container_encoding() {
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "file", &[1u8; 250], 100)
+                .unwrap();
+        let mut expected = container.header.strict_serialize().unwrap();
+        expected.extend([3u8, 0, 0]);
+        for chunk in &chunks {
+            expected.extend(chunk.chunk_id().strict_serialize().unwrap());
+        }
+        let data = container.strict_serialize().unwrap();
+        assert_eq!(data, expected);
+        assert_eq!(Container::strict_deserialize(&data).unwrap(), container);
+    }
+
+    #[test]
+    fn test_chunk_priority() {
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &[1u8; 250], 100)
+                .unwrap();
+        let priority = ChunkPriority {
+            container_id: container.container_id(),
+            order: MediumVec::try_from(vec![2u32, 0, 1]).unwrap(),
+        };
+        let data = priority.strict_serialize().unwrap();
+        assert_eq!(data.len(), 32 + 3 + 3 * 4);
+        assert_eq!(ChunkPriority::strict_deserialize(&data).unwrap(), priority);
+
+        assert!(priority.is_valid_for(&container));
+        assert_eq!(container.fetch_order(Some(&priority)), vec![2, 0, 1]);
+        assert_eq!(container.fetch_order(None), vec![0, 1, 2]);
+
+        let mut duplicate = priority.clone();
+        duplicate.order = MediumVec::try_from(vec![2u32, 2, 1]).unwrap();
+        assert!(!duplicate.is_valid_for(&container));
+        assert_eq!(container.fetch_order(Some(&duplicate)), vec![0, 1, 2]);
+        let mut other = priority;
+        other.container_id = ContainerId::default();
+        assert!(!other.is_valid_for(&container));
+    }
+
+    #[test]
+    fn test_chunks_for_range() {
+        let container = Container {
+            header: ContainerHeader {
+                version: CONTAINER_VERSION,
+                mime: AsciiString::new(),
+                info: s!(""),
+                size: 250,
+                chunk_size: 100,
+            },
+            chunks: MediumVec::try_from(vec![ChunkId::default(); 3]).unwrap(),
+        };
+        assert!(container.is_chunk_size_valid());
+        assert_eq!(container.chunks_for_range(0, 10), vec![ChunkSlice {
+            index: 0,
+            offset: 0,
+            len: 10
+        }]);
+        assert_eq!(container.chunks_for_range(150, 200), vec![
+            ChunkSlice {
+                index: 1,
+                offset: 50,
+                len: 50
+            },
+            ChunkSlice {
+                index: 2,
+                offset: 0,
+                len: 50
+            }
+        ]);
+        assert_eq!(container.chunks_for_range(250, 1), vec![]);
+    }
+
+    #[test]
+    fn test_chunk_proof() {
+        for count in [1usize, 2, 5, 8, 13] {
+            let (container, chunks) = Container::chunkify(
+                AsciiString::new(),
+                "",
+                &(0..count as u8 * 10).collect::<Vec<_>>(),
+                10,
+            )
+            .unwrap();
+            let container_id = container.container_id();
+            for (index, chunk) in chunks.iter().enumerate() {
+                let proof = container.chunk_proof(index).unwrap();
+                assert!(proof.path.len() <= 4);
+                assert_eq!(
+                    proof.merkle_root(chunk.chunk_id(), count as u64),
+                    Some(container.merkle_root())
+                );
+                assert!(proof.verify(
+                    chunk.chunk_id(),
+                    container_id,
+                    &container.header
+                ));
+                let other = &chunks[(index + 1) % count];
+                assert_eq!(
+                    proof.verify(
+                        other.chunk_id(),
+                        container_id,
+                        &container.header
+                    ),
+                    count == 1
+                );
+            }
+            assert_eq!(container.chunk_proof(count), None);
+        }
+    }
+
+    #[test]
+    fn test_verify_all() {
+        let data = (0..=255u8).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 100).unwrap();
+        assert_eq!(container.verify_all(chunks.clone().into_iter()), Ok(()));
+
+        let mut swapped = chunks.clone();
+        swapped.swap(0, 1);
+        assert_eq!(
+            container.verify_all(swapped.into_iter()),
+            Err(ContainerVerifyError::Misplaced {
+                index: 0,
+                expected_at: 1
+            })
+        );
+        assert_eq!(
+            container.verify_all(chunks.iter().take(2).cloned()),
+            Err(ContainerVerifyError::MissingChunks(2, 3))
+        );
+        assert_eq!(
+            container.verify_all(chunks.iter().chain(&chunks[..1]).cloned()),
+            Err(ContainerVerifyError::ExtraChunks(3))
+        );
+        let forged_chunk = Chunk::try_from(vec![0u8; 100]).unwrap();
+        let mut forged = chunks.clone();
+        forged[1] = forged_chunk.clone();
+        assert_eq!(
+            container.verify_all(forged.into_iter()),
+            Err(ContainerVerifyError::IdMismatch {
+                index: 1,
+                expected: chunks[1].chunk_id(),
+                actual: forged_chunk.chunk_id(),
+            })
+        );
+        let mut truncated = chunks;
+        truncated[2] = Chunk::try_from(vec![0u8; 10]).unwrap();
+        assert_eq!(
+            container.verify_all(truncated.into_iter()),
+            Err(ContainerVerifyError::ChunkSize {
+                index: 2,
+                expected: 56,
+                actual: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_container_part() {
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &[7u8; 1000], 100)
+                .unwrap();
+        let selection = ChunkSelection::with_indexes([2, 5, 6]);
+        assert_eq!((selection.start, selection.end), (2, 7));
+        assert_eq!(selection.indexes(10).collect::<Vec<_>>(), vec![2, 5, 6]);
+        let part = container.part(&selection);
+        assert_eq!(part.chunks.len(), 3);
+        assert!(part.verify());
+
+        let part = container.part(&ChunkSelection::with_range(8, 20));
+        assert_eq!(part.chunks.len(), 2);
+        let mut forged = part.clone();
+        forged.chunks[0].1.index = 0;
+        assert!(!forged.verify());
+
+        let empty = Container {
+            header: ContainerHeader {
+                size: 0,
+                ..container.header
+            },
+            chunks: MediumVec::try_from(vec![]).unwrap(),
+        };
+        let part = empty.part(&ChunkSelection::with_range(0, 10));
+        assert!(part.chunks.is_empty());
+        assert_eq!(part.container_id, empty.container_id());
+    }
+
+    #[test]
+    fn test_container_part_limit() {
+        let data = sample(MAX_PART_CHUNKS + 10, 1);
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data, 1).unwrap();
+        let count = container.chunks.len() as u32;
+        let part = container.part(&ChunkSelection::with_range(0, count));
+        assert_eq!(part.chunks.len(), MAX_PART_CHUNKS);
+        assert_eq!(part.container_id, container.container_id());
+        let (chunk_id, proof) = &part.chunks[MAX_PART_CHUNKS - 1];
+        assert_eq!(
+            Some(proof),
+            container.chunk_proof(MAX_PART_CHUNKS - 1).as_ref()
+        );
+        assert!(proof.verify(*chunk_id, part.container_id, &part.header));
     }
 }
@@ -0,0 +1,330 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Accounting of data relayed and stored by peers on behalf of each other.
+//!
+//! Each node tracks the amount of data it served to and consumed from the
+//! remote peers. Periodically the node which consumed the service issues a
+//! signed IOU acknowledging cumulative amounts served by the other peer.
+//! Since the amounts are cumulative, only the latest IOU matters, and it can
+//! be later settled over lightning.
+
+use std::collections::BTreeMap;
+use std::ops::Sub;
+
+use bitcoin_hashes::{sha256, sha256t, Hash};
+use secp256k1::{
+    ecdsa, Message, PublicKey, Secp256k1, SecretKey, Signing, Verification,
+};
+use strict_encoding::StrictEncode;
+
+// "storm:iou"
+static MIDSTATE_IOU: [u8; 32] = [
+    229, 255, 127, 74, 203, 100, 41, 104, 146, 45, 23, 220, 215, 34, 38, 149,
+    208, 4, 182, 130, 37, 110, 238, 164, 217, 81, 223, 219, 175, 202, 106, 255,
+];
+
+/// Tag used for IOU signature hashes.
+pub struct IouTag;
+
+impl sha256t::Tag for IouTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_IOU);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Errors processing IOUs received from remote peers.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CreditError {
+    /// IOU is issued to {0} and not to the local node.
+    WrongBeneficiary(PublicKey),
+
+    /// IOU signature is invalid.
+    InvalidSignature,
+
+    /// IOU sequence number {0} is not greater than the one of the previously
+    /// accepted IOU.
+    Outdated(u64),
+
+    /// IOU acknowledges less data than the previously accepted IOU.
+    Decreased,
+}
+
+/// Cumulative amounts of data served by a peer.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("relayed {bytes_relayed} bytes, stored {bytes_stored} bytes")]
+pub struct Credit {
+    pub bytes_relayed: u64,
+    pub bytes_stored: u64,
+}
+
+impl Sub for Credit {
+    type Output = Credit;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Credit {
+            bytes_relayed: self.bytes_relayed.saturating_sub(rhs.bytes_relayed),
+            bytes_stored: self.bytes_stored.saturating_sub(rhs.bytes_stored),
+        }
+    }
+}
+
+impl Credit {
+    /// Detects whether each of the amounts is not less than in the other
+    /// credit.
+    pub fn covers(&self, other: &Credit) -> bool {
+        self.bytes_relayed >= other.bytes_relayed
+            && self.bytes_stored >= other.bytes_stored
+    }
+}
+
+/// Acknowledgement of the service provided by `beneficiary` to `issuer`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{issuer} owes {beneficiary} for {credit} (#{seq})")]
+pub struct Iou {
+    pub issuer: PublicKey,
+    pub beneficiary: PublicKey,
+    /// Sequence number of the IOU, growing with each new IOU issued to the
+    /// same beneficiary.
+    pub seq: u64,
+    /// Cumulative amounts of data served by the beneficiary.
+    pub credit: Credit,
+    /// Unix timestamp of the IOU issue.
+    pub timestamp: u64,
+}
+
+impl Iou {
+    /// Hash committed to by the IOU signature.
+    pub fn sig_hash(&self) -> sha256t::Hash<IouTag> {
+        let mut engine = sha256t::Hash::<IouTag>::engine();
+        self.strict_encode(&mut engine).expect("memory encoders do not fail");
+        sha256t::Hash::from_engine(engine)
+    }
+
+    fn message(&self) -> Message {
+        Message::from_slice(&self.sig_hash()[..])
+            .expect("hash has the size of a signed message")
+    }
+
+    /// Signs IOU with the issuer key.
+    pub fn sign<C: Signing>(
+        self,
+        secp: &Secp256k1<C>,
+        key: &SecretKey,
+    ) -> SignedIou {
+        SignedIou {
+            signature: secp.sign_ecdsa(&self.message(), key),
+            iou: self,
+        }
+    }
+}
+
+/// IOU signed by its issuer.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{iou}")]
+pub struct SignedIou {
+    pub iou: Iou,
+    pub signature: ecdsa::Signature,
+}
+
+impl SignedIou {
+    /// Verifies that the IOU is signed by the issuer.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), CreditError> {
+        secp.verify_ecdsa(
+            &self.iou.message(),
+            &self.signature,
+            &self.iou.issuer,
+        )
+        .map_err(|_| CreditError::InvalidSignature)
+    }
+}
+
+/// Ledger of data served to and consumed from remote peers.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct CreditLedger {
+    /// Service provided by the local node to peers.
+    provided: BTreeMap<PublicKey, Credit>,
+    /// Service consumed by the local node from peers.
+    consumed: BTreeMap<PublicKey, Credit>,
+    /// Latest IOUs received from the peers.
+    received: BTreeMap<PublicKey, SignedIou>,
+    /// Sequence numbers of the latest IOUs issued to the peers.
+    issued: BTreeMap<PublicKey, u64>,
+}
+
+impl CreditLedger {
+    pub fn new() -> CreditLedger { CreditLedger::default() }
+
+    /// Records data relayed by the local node on behalf of the peer.
+    pub fn record_relayed(&mut self, peer: PublicKey, bytes: u64) {
+        let credit = self.provided.entry(peer).or_default();
+        credit.bytes_relayed = credit.bytes_relayed.saturating_add(bytes);
+    }
+
+    /// Records data stored by the local node on behalf of the peer.
+    pub fn record_stored(&mut self, peer: PublicKey, bytes: u64) {
+        let credit = self.provided.entry(peer).or_default();
+        credit.bytes_stored = credit.bytes_stored.saturating_add(bytes);
+    }
+
+    /// Records data relayed by the peer on behalf of the local node.
+    pub fn record_relayed_by(&mut self, peer: PublicKey, bytes: u64) {
+        let credit = self.consumed.entry(peer).or_default();
+        credit.bytes_relayed = credit.bytes_relayed.saturating_add(bytes);
+    }
+
+    /// Records data stored by the peer on behalf of the local node.
+    pub fn record_stored_by(&mut self, peer: PublicKey, bytes: u64) {
+        let credit = self.consumed.entry(peer).or_default();
+        credit.bytes_stored = credit.bytes_stored.saturating_add(bytes);
+    }
+
+    /// Total service provided to the peer.
+    pub fn provided(&self, peer: PublicKey) -> Credit {
+        self.provided.get(&peer).copied().unwrap_or_default()
+    }
+
+    /// Total service consumed from the peer.
+    pub fn consumed(&self, peer: PublicKey) -> Credit {
+        self.consumed.get(&peer).copied().unwrap_or_default()
+    }
+
+    /// Latest IOU received from the peer.
+    pub fn latest_iou(&self, peer: PublicKey) -> Option<&SignedIou> {
+        self.received.get(&peer)
+    }
+
+    /// Service provided to the peer which is not yet acknowledged by an IOU.
+    pub fn unacknowledged(&self, peer: PublicKey) -> Credit {
+        let acknowledged = self
+            .received
+            .get(&peer)
+            .map(|signed| signed.iou.credit)
+            .unwrap_or_default();
+        self.provided(peer) - acknowledged
+    }
+
+    /// Issues IOU acknowledging all service consumed from the peer.
+    pub fn issue_iou<C: Signing>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        key: &SecretKey,
+        peer: PublicKey,
+        timestamp: u64,
+    ) -> SignedIou {
+        let seq = self.issued.entry(peer).or_default();
+        *seq += 1;
+        Iou {
+            issuer: PublicKey::from_secret_key(secp, key),
+            beneficiary: peer,
+            seq: *seq,
+            credit: self.consumed(peer),
+            timestamp,
+        }
+        .sign(secp, key)
+    }
+
+    /// Accepts IOU issued by a peer to the local node, returning the amount
+    /// of service newly acknowledged by it.
+    pub fn accept_iou<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        local_id: PublicKey,
+        signed: SignedIou,
+    ) -> Result<Credit, CreditError> {
+        let iou = signed.iou;
+        if iou.beneficiary != local_id {
+            return Err(CreditError::WrongBeneficiary(iou.beneficiary));
+        }
+        signed.verify(secp)?;
+        let prev = self.received.get(&iou.issuer).map(|signed| signed.iou);
+        if let Some(prev) = prev {
+            if iou.seq <= prev.seq {
+                return Err(CreditError::Outdated(iou.seq));
+            }
+            if !iou.credit.covers(&prev.credit) {
+                return Err(CreditError::Decreased);
+            }
+        }
+        self.received.insert(iou.issuer, signed);
+        Ok(iou.credit - prev.map(|prev| prev.credit).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use commit_verify::tagged_hash;
+
+    use super::*;
+
+    #[test]
+    fn test_iou_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:iou");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_IOU);
+    }
+
+    #[test]
+    fn test_iou_exchange() {
+        let secp = Secp256k1::new();
+        let alice_key = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let bob_key = SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let alice = PublicKey::from_secret_key(&secp, &alice_key);
+        let bob = PublicKey::from_secret_key(&secp, &bob_key);
+
+        let mut alice_ledger = CreditLedger::new();
+        let mut bob_ledger = CreditLedger::new();
+        alice_ledger.record_relayed(bob, 1000);
+        bob_ledger.record_relayed_by(alice, 1000);
+        assert_eq!(alice_ledger.unacknowledged(bob).bytes_relayed, 1000);
+
+        let iou = bob_ledger.issue_iou(&secp, &bob_key, alice, 0);
+        assert_eq!(
+            alice_ledger.accept_iou(&secp, alice, iou).unwrap().bytes_relayed,
+            1000
+        );
+        assert_eq!(alice_ledger.unacknowledged(bob), Credit::default());
+        assert_eq!(
+            alice_ledger.accept_iou(&secp, alice, iou),
+            Err(CreditError::Outdated(1))
+        );
+        assert_eq!(
+            alice_ledger.accept_iou(&secp, bob, iou),
+            Err(CreditError::WrongBeneficiary(alice))
+        );
+    }
+}
@@ -0,0 +1,380 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Binary deltas between container versions.
+//!
+//! A [`Delta`] encodes a new version of the container data as a sequence of
+//! operations over the data of an older (base) version: each operation
+//! inserts literal bytes and then copies a range of the base data. The delta
+//! is itself stored as a container with [`DELTA_MIME`] type, so its chunks
+//! are transferred as any other chunks. A node mirroring the base container
+//! downloads the delta only, applies it to the base data and verifies that
+//! the result produces exactly the target container id.
+//!
+//! Deltas are computed rsync-style: the base data are indexed by
+//! [`RollingChecksum`] of fixed-size blocks, and the target data are scanned
+//! with the rolling checksum for matching blocks, which are then extended
+//! byte by byte.
+
+use std::collections::BTreeMap;
+
+use stens::AsciiString;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::chunk::TooLargeData;
+use crate::{Chunk, Container, ContainerHeader, ContainerId};
+
+/// MIME type of containers holding binary deltas.
+pub const DELTA_MIME: &str = "application/x-storm-delta";
+
+/// Default size of the base data blocks matched when computing deltas.
+pub const DEFAULT_DELTA_BLOCK_SIZE: usize = 64;
+
+// Literal data of a single operation are limited by the strict encoding of
+// byte vectors.
+const MAX_INSERT_LEN: usize = u16::MAX as usize;
+
+/// Errors computing and applying binary deltas.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DeltaError {
+    /// delta or the data it produces are too large.
+    TooLarge,
+
+    /// container is not a delta or has malformed data.
+    Decoding,
+
+    /// delta copies {len} bytes at offset {offset} outside of the base data.
+    InvalidCopy { offset: u64, len: u32 },
+
+    /// delta produces {actual} bytes instead of {expected}.
+    SizeMismatch { expected: u64, actual: u64 },
+
+    /// delta produces container {0} instead of the target one.
+    TargetMismatch(ContainerId),
+}
+
+/// Adler-32 style checksum which can be rolled over the data byte by byte,
+/// as used by rsync.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingChecksum {
+    /// Computes checksum of the window.
+    pub fn new(window: &[u8]) -> RollingChecksum {
+        let len = window.len() as u32;
+        let mut a = 0u32;
+        let mut b = 0u32;
+        for (pos, byte) in window.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((len - pos as u32).wrapping_mul(*byte as u32));
+        }
+        RollingChecksum { a, b, len }
+    }
+
+    /// Moves the window by one byte, removing `out` from its start and
+    /// appending `inp` to its end.
+    pub fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    pub fn value(&self) -> u32 { (self.a & 0xFFFF) | (self.b << 16) }
+}
+
+/// Delta operation: inserts literal data and then copies range of the base
+/// data.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct DeltaOp {
+    pub insert: Vec<u8>,
+    pub copy_offset: u64,
+    pub copy_len: u32,
+}
+
+/// Binary delta producing target container data from the base ones.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{base_id} -> {target_id}")]
+pub struct Delta {
+    pub base_id: ContainerId,
+    pub target_id: ContainerId,
+    /// Header of the target container, used to re-create it after the delta
+    /// is applied.
+    pub target: ContainerHeader,
+    pub ops: Vec<DeltaOp>,
+}
+
+impl Delta {
+    /// Computes delta producing data of the target container from the data
+    /// of the base one, matching base blocks of `block_size` bytes.
+    pub fn diff(
+        base_id: ContainerId,
+        base: &[u8],
+        target: &Container,
+        target_data: &[u8],
+        block_size: usize,
+    ) -> Result<Delta, DeltaError> {
+        let block_size = block_size.max(1);
+        let mut blocks = BTreeMap::<u32, Vec<usize>>::new();
+        for (no, block) in base.chunks_exact(block_size).enumerate() {
+            blocks
+                .entry(RollingChecksum::new(block).value())
+                .or_default()
+                .push(no * block_size);
+        }
+
+        let mut ops = vec![];
+        let mut op = DeltaOp::default();
+        let mut pos = 0usize;
+        let mut checksum = None;
+        while pos + block_size <= target_data.len() {
+            let window = &target_data[pos..pos + block_size];
+            let sum =
+                *checksum.get_or_insert_with(|| RollingChecksum::new(window));
+            let found = blocks.get(&sum.value()).and_then(|offsets| {
+                offsets.iter().find(|offset| {
+                    &base[**offset..**offset + block_size] == window
+                })
+            });
+            if let Some(&offset) = found {
+                let mut len = block_size;
+                while offset + len < base.len()
+                    && pos + len < target_data.len()
+                    && len < u32::MAX as usize
+                    && base[offset + len] == target_data[pos + len]
+                {
+                    len += 1;
+                }
+                op.copy_offset = offset as u64;
+                op.copy_len = len as u32;
+                ops.push(std::mem::take(&mut op));
+                pos += len;
+                checksum = None;
+                continue;
+            }
+            if op.insert.len() == MAX_INSERT_LEN {
+                ops.push(std::mem::take(&mut op));
+            }
+            op.insert.push(target_data[pos]);
+            if let (Some(sum), Some(next)) =
+                (checksum.as_mut(), target_data.get(pos + block_size))
+            {
+                sum.roll(target_data[pos], *next);
+            }
+            pos += 1;
+        }
+        for byte in &target_data[pos..] {
+            if op.insert.len() == MAX_INSERT_LEN {
+                ops.push(std::mem::take(&mut op));
+            }
+            op.insert.push(*byte);
+        }
+        if !op.insert.is_empty() {
+            ops.push(op);
+        }
+        if ops.len() > u16::MAX as usize {
+            return Err(DeltaError::TooLarge);
+        }
+
+        Ok(Delta {
+            base_id,
+            target_id: target.container_id(),
+            target: target.header.clone(),
+            ops,
+        })
+    }
+
+    /// Total length of the literal data carried by the delta.
+    pub fn literal_len(&self) -> usize {
+        self.ops.iter().map(|op| op.insert.len()).sum()
+    }
+
+    /// Applies delta to the base data, producing target data. The copied
+    /// ranges and the resulting size are checked before any data are
+    /// produced, so a forged delta can't make the node allocate more than
+    /// its operations actually produce.
+    pub fn apply_data(&self, base: &[u8]) -> Result<Vec<u8>, DeltaError> {
+        let mut size = 0u64;
+        for op in &self.ops {
+            if op.copy_len > 0
+                && op
+                    .copy_offset
+                    .checked_add(op.copy_len as u64)
+                    .filter(|end| *end <= base.len() as u64)
+                    .is_none()
+            {
+                return Err(DeltaError::InvalidCopy {
+                    offset: op.copy_offset,
+                    len: op.copy_len,
+                });
+            }
+            size = size
+                .saturating_add(op.insert.len() as u64)
+                .saturating_add(op.copy_len as u64);
+        }
+        if size != self.target.size {
+            return Err(DeltaError::SizeMismatch {
+                expected: self.target.size,
+                actual: size,
+            });
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+        for op in &self.ops {
+            data.extend_from_slice(&op.insert);
+            if op.copy_len > 0 {
+                let offset = op.copy_offset as usize;
+                data.extend_from_slice(
+                    &base[offset..offset + op.copy_len as usize],
+                );
+            }
+        }
+        Ok(data)
+    }
+
+    /// Applies delta to the base data and verifies that the result produces
+    /// the target container, returning it together with its chunks.
+    pub fn apply(
+        &self,
+        base: &[u8],
+    ) -> Result<(Container, Vec<Chunk>), DeltaError> {
+        let data = self.apply_data(base)?;
+        let (container, chunks) = Container::chunkify(
+            self.target.mime.clone(),
+            &self.target.info,
+            &data,
+            self.target.chunk_size,
+        )
+        .map_err(|_| DeltaError::TooLarge)?;
+        let container_id = container.container_id();
+        if container_id != self.target_id {
+            return Err(DeltaError::TargetMismatch(container_id));
+        }
+        Ok((container, chunks))
+    }
+
+    /// Stores delta as a container, producing its chunks.
+    pub fn chunkify(
+        &self,
+        chunk_size: u32,
+    ) -> Result<(Container, Vec<Chunk>), TooLargeData> {
+        let data = self.strict_serialize().map_err(|_| TooLargeData)?;
+        let mime = AsciiString::try_from(DELTA_MIME)
+            .expect("static MIME type is ASCII");
+        Container::chunkify(mime, self, &data, chunk_size)
+    }
+
+    /// Restores delta from the data of a delta container.
+    pub fn from_container_data(data: &[u8]) -> Result<Delta, DeltaError> {
+        Delta::strict_deserialize(data).map_err(|_| DeltaError::Decoding)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::sample;
+
+    #[test]
+    fn test_rolling_checksum() {
+        let data = sample(100, 1);
+        let mut sum = RollingChecksum::new(&data[..16]);
+        for pos in 0..data.len() - 16 {
+            sum.roll(data[pos], data[pos + 16]);
+            assert_eq!(sum, RollingChecksum::new(&data[pos + 1..pos + 17]));
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        let mime = AsciiString::try_from("text/plain").unwrap();
+        let base = sample(10_000, 1);
+        let mut target = base.clone();
+        target.splice(3000..3000, b"inserted".iter().copied());
+        target[7000] ^= 0xFF;
+        target.truncate(9500);
+        target.extend(b"appended");
+
+        let (base_container, _) =
+            Container::chunkify(mime.clone(), "v1", &base, 1024).unwrap();
+        let (target_container, target_chunks) =
+            Container::chunkify(mime, "v2", &target, 1024).unwrap();
+        let delta = Delta::diff(
+            base_container.container_id(),
+            &base,
+            &target_container,
+            &target,
+            DEFAULT_DELTA_BLOCK_SIZE,
+        )
+        .unwrap();
+        assert!(delta.literal_len() < 3 * DEFAULT_DELTA_BLOCK_SIZE);
+        assert_eq!(
+            delta.apply(&base).unwrap(),
+            (target_container.clone(), target_chunks)
+        );
+
+        let mut other = base.clone();
+        other[10] ^= 0xFF;
+        assert!(matches!(
+            delta.apply(&other),
+            Err(DeltaError::TargetMismatch(_))
+        ));
+        assert_eq!(
+            delta.apply(&base[..5000]),
+            Err(DeltaError::InvalidCopy {
+                offset: delta.ops[1].copy_offset,
+                len: delta.ops[1].copy_len,
+            })
+        );
+
+        let mut forged = delta.clone();
+        forged.target.size = u64::MAX;
+        assert_eq!(
+            forged.apply_data(&base),
+            Err(DeltaError::SizeMismatch {
+                expected: u64::MAX,
+                actual: target.len() as u64,
+            })
+        );
+        let mut forged = delta.clone();
+        forged.ops[1].copy_offset = u64::MAX;
+        assert_eq!(
+            forged.apply_data(&base),
+            Err(DeltaError::InvalidCopy {
+                offset: u64::MAX,
+                len: delta.ops[1].copy_len,
+            })
+        );
+
+        let (container, chunks) = delta.chunkify(256).unwrap();
+        assert_eq!(container.header.mime.as_str(), DELTA_MIME);
+        let data =
+            chunks.iter().flat_map(|chunk| chunk.to_vec()).collect::<Vec<_>>();
+        assert_eq!(Delta::from_container_data(&data).unwrap(), delta);
+    }
+}
@@ -0,0 +1,372 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Negotiation of compression dictionaries for small message bodies.
+//!
+//! Small messages of the same app (like chat posts) are highly repetitive,
+//! but too short to be compressed efficiently on their own. Peers may agree
+//! on a shared zstd dictionary for an app and compress bodies against it.
+//! Dictionaries are distributed as regular containers with
+//! [`DICTIONARY_MIME`] type and are identified by their container id, so a
+//! peer missing a dictionary retrieves it as any other container.
+//!
+//! Negotiation happens per app: a peer sends `OfferDictionaries` listing the
+//! dictionaries it holds in the order of preference, and the remote peer
+//! replies with `SelectDictionary` naming the first offered dictionary it
+//! also holds, or none. Both peers remember the agreed dictionary in their
+//! [`DictionaryBook`] and send bodies as [`CompressedBody`] from then on.
+//!
+//! The library does not depend on a particular zstd implementation: the
+//! node provides it by implementing [`DictionaryCodec`].
+
+use std::collections::BTreeMap;
+
+use secp256k1::PublicKey;
+
+use crate::chunk::encoding::ApplyStrictEncoding;
+use crate::chunk::MAX_CHUNK_SIZE;
+use crate::p2p::AppMsg;
+use crate::{ContainerId, StormApp};
+
+/// MIME type of containers holding zstd dictionaries.
+pub const DICTIONARY_MIME: &str = "application/x-zstd-dictionary";
+
+/// Maximal number of dictionaries offered with a single `OfferDictionaries`
+/// message.
+pub const MAX_OFFERED_DICTIONARIES: usize = 16;
+
+/// Errors compressing and decompressing message bodies.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CompressionError {
+    /// body is compressed with dictionary {0}, while {1} is provided.
+    DictionaryMismatch(ContainerId, ContainerId),
+
+    /// decompressed body size {0} exceeds the maximal chunk size.
+    TooLarge(u32),
+
+    /// decompressed body has {actual} bytes instead of {expected}.
+    SizeMismatch { expected: u32, actual: usize },
+
+    /// codec failure: {0}.
+    Codec(String),
+}
+
+/// Dictionary compression algorithm, implemented by the node.
+pub trait DictionaryCodec {
+    /// Compresses data against the dictionary.
+    fn compress(
+        &self,
+        dictionary: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, String>;
+
+    /// Decompresses data against the dictionary, producing at most
+    /// `max_size` bytes.
+    fn decompress(
+        &self,
+        dictionary: &[u8],
+        data: &[u8],
+        max_size: usize,
+    ) -> Result<Vec<u8>, String>;
+}
+
+/// Message body compressed with a shared dictionary.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{dictionary_id}, {size} bytes")]
+pub struct CompressedBody {
+    /// Container id of the dictionary.
+    pub dictionary_id: ContainerId,
+    /// Size of the decompressed body.
+    pub size: u32,
+    pub data: Vec<u8>,
+}
+
+impl ApplyStrictEncoding for CompressedBody {}
+
+impl CompressedBody {
+    /// Compresses body with the dictionary stored in the container with the
+    /// given id.
+    pub fn compress(
+        codec: &impl DictionaryCodec,
+        dictionary_id: ContainerId,
+        dictionary: &[u8],
+        body: &[u8],
+    ) -> Result<CompressedBody, CompressionError> {
+        if body.len() > MAX_CHUNK_SIZE as usize {
+            return Err(CompressionError::TooLarge(body.len() as u32));
+        }
+        let data = codec
+            .compress(dictionary, body)
+            .map_err(CompressionError::Codec)?;
+        Ok(CompressedBody {
+            dictionary_id,
+            size: body.len() as u32,
+            data,
+        })
+    }
+
+    /// Decompresses body, checking that it was compressed with the provided
+    /// dictionary and has the declared size.
+    pub fn decompress(
+        &self,
+        codec: &impl DictionaryCodec,
+        dictionary_id: ContainerId,
+        dictionary: &[u8],
+    ) -> Result<Vec<u8>, CompressionError> {
+        if self.dictionary_id != dictionary_id {
+            return Err(CompressionError::DictionaryMismatch(
+                self.dictionary_id,
+                dictionary_id,
+            ));
+        }
+        if self.size > MAX_CHUNK_SIZE {
+            return Err(CompressionError::TooLarge(self.size));
+        }
+        let body = codec
+            .decompress(dictionary, &self.data, self.size as usize)
+            .map_err(CompressionError::Codec)?;
+        if body.len() != self.size as usize {
+            return Err(CompressionError::SizeMismatch {
+                expected: self.size,
+                actual: body.len(),
+            });
+        }
+        Ok(body)
+    }
+}
+
+/// Dictionaries held by the node and the dictionaries agreed with the peers.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct DictionaryBook {
+    local: BTreeMap<StormApp, Vec<ContainerId>>,
+    agreed: BTreeMap<(PublicKey, StormApp), ContainerId>,
+}
+
+impl DictionaryBook {
+    pub fn new() -> DictionaryBook { DictionaryBook::default() }
+
+    /// Adds dictionary for the app with the lowest preference.
+    pub fn add_dictionary(
+        &mut self,
+        app: StormApp,
+        dictionary_id: ContainerId,
+    ) {
+        let dictionaries = self.local.entry(app).or_default();
+        if !dictionaries.contains(&dictionary_id) {
+            dictionaries.push(dictionary_id);
+        }
+    }
+
+    /// Removes dictionary, forgetting all agreements to use it.
+    pub fn remove_dictionary(
+        &mut self,
+        app: StormApp,
+        dictionary_id: ContainerId,
+    ) {
+        if let Some(dictionaries) = self.local.get_mut(&app) {
+            dictionaries.retain(|id| *id != dictionary_id);
+        }
+        self.agreed.retain(|(_, a), id| *a != app || *id != dictionary_id);
+    }
+
+    /// Dictionaries held for the app, in the order of preference.
+    pub fn dictionaries(&self, app: StormApp) -> &[ContainerId] {
+        self.local.get(&app).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Payload of `OfferDictionaries` message for the app.
+    pub fn offer(&self, app: StormApp) -> AppMsg<Vec<ContainerId>> {
+        let mut dictionaries = self.dictionaries(app).to_vec();
+        dictionaries.truncate(MAX_OFFERED_DICTIONARIES);
+        AppMsg::new(app, dictionaries)
+    }
+
+    /// Processes `OfferDictionaries` message from the peer, producing the
+    /// payload of `SelectDictionary` response. Offers with more than
+    /// [`MAX_OFFERED_DICTIONARIES`] dictionaries select none.
+    pub fn select(
+        &mut self,
+        peer: PublicKey,
+        offer: &AppMsg<Vec<ContainerId>>,
+    ) -> AppMsg<Option<ContainerId>> {
+        let local = self.dictionaries(offer.app);
+        let selected = if offer.data.len() > MAX_OFFERED_DICTIONARIES {
+            None
+        } else {
+            offer.data.iter().find(|id| local.contains(id)).copied()
+        };
+        self.set_agreed(peer, offer.app, selected);
+        AppMsg::new(offer.app, selected)
+    }
+
+    /// Processes `SelectDictionary` response from the peer. Selection of a
+    /// dictionary unknown to the node is treated as no selection.
+    pub fn confirm(
+        &mut self,
+        peer: PublicKey,
+        selection: &AppMsg<Option<ContainerId>>,
+    ) -> Option<ContainerId> {
+        let selected = selection
+            .data
+            .filter(|id| self.dictionaries(selection.app).contains(id));
+        self.set_agreed(peer, selection.app, selected);
+        selected
+    }
+
+    fn set_agreed(
+        &mut self,
+        peer: PublicKey,
+        app: StormApp,
+        dictionary_id: Option<ContainerId>,
+    ) {
+        match dictionary_id {
+            Some(id) => self.agreed.insert((peer, app), id),
+            None => self.agreed.remove(&(peer, app)),
+        };
+    }
+
+    /// Dictionary agreed with the peer for the app.
+    pub fn agreed(
+        &self,
+        peer: PublicKey,
+        app: StormApp,
+    ) -> Option<ContainerId> {
+        self.agreed.get(&(peer, app)).copied()
+    }
+
+    /// Forgets all agreements with the disconnected peer.
+    pub fn remove_peer(&mut self, peer: PublicKey) {
+        self.agreed.retain(|(node_id, _), _| *node_id != peer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::{Secp256k1, SecretKey};
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::Container;
+
+    fn dictionary_id(dictionary: &[u8]) -> ContainerId {
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", dictionary, 64)
+                .unwrap();
+        container.container_id()
+    }
+
+    /// Codec replacing dictionary prefix of the data with its length.
+    struct PrefixCodec;
+
+    impl DictionaryCodec for PrefixCodec {
+        fn compress(
+            &self,
+            dictionary: &[u8],
+            data: &[u8],
+        ) -> Result<Vec<u8>, String> {
+            let common =
+                dictionary.iter().zip(data).take_while(|(a, b)| a == b).count();
+            let mut compressed = vec![common as u8];
+            compressed.extend_from_slice(&data[common..]);
+            Ok(compressed)
+        }
+
+        fn decompress(
+            &self,
+            dictionary: &[u8],
+            data: &[u8],
+            max_size: usize,
+        ) -> Result<Vec<u8>, String> {
+            let (common, rest) = data.split_first().ok_or("empty data")?;
+            let mut body = dictionary[..*common as usize].to_vec();
+            body.extend_from_slice(rest);
+            body.truncate(max_size);
+            Ok(body)
+        }
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let secp = Secp256k1::new();
+        let alice = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x11; 32]).unwrap(),
+        );
+        let bob = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x22; 32]).unwrap(),
+        );
+        let old = dictionary_id(b"old");
+        let new = dictionary_id(b"new");
+
+        let mut alice_book = DictionaryBook::new();
+        alice_book.add_dictionary(StormApp::Chat, new);
+        alice_book.add_dictionary(StormApp::Chat, old);
+        let mut bob_book = DictionaryBook::new();
+        bob_book.add_dictionary(StormApp::Chat, old);
+
+        let offer = alice_book.offer(StormApp::Chat);
+        assert_eq!(offer.data, vec![new, old]);
+        let selection = bob_book.select(alice, &offer);
+        assert_eq!(selection.data, Some(old));
+        assert_eq!(alice_book.confirm(bob, &selection), Some(old));
+        assert_eq!(alice_book.agreed(bob, StormApp::Chat), Some(old));
+        assert_eq!(bob_book.agreed(alice, StormApp::Chat), Some(old));
+        assert_eq!(alice_book.agreed(bob, StormApp::Storage), None);
+
+        bob_book.remove_dictionary(StormApp::Chat, old);
+        assert_eq!(bob_book.agreed(alice, StormApp::Chat), None);
+        alice_book.remove_peer(bob);
+        assert_eq!(alice_book.agreed(bob, StormApp::Chat), None);
+    }
+
+    #[test]
+    fn test_compressed_body() {
+        let dictionary = b"hello, how are you";
+        let body = b"hello, how is it going";
+        let dictionary_id = dictionary_id(dictionary);
+        let compressed = CompressedBody::compress(
+            &PrefixCodec,
+            dictionary_id,
+            dictionary,
+            body,
+        )
+        .unwrap();
+        assert!(compressed.data.len() < body.len());
+        assert_eq!(
+            compressed
+                .decompress(&PrefixCodec, dictionary_id, dictionary)
+                .unwrap(),
+            body
+        );
+
+        let other = ContainerId::default();
+        assert_eq!(
+            compressed.decompress(&PrefixCodec, other, dictionary),
+            Err(CompressionError::DictionaryMismatch(dictionary_id, other))
+        );
+        let mut forged = compressed;
+        forged.size = 100;
+        assert_eq!(
+            forged.decompress(&PrefixCodec, dictionary_id, dictionary),
+            Err(CompressionError::SizeMismatch {
+                expected: 100,
+                actual: body.len()
+            })
+        );
+    }
+}
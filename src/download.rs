@@ -0,0 +1,1137 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use amplify::Wrapper;
+use secp256k1::{PublicKey, Secp256k1, Verification};
+use strict_encoding::MediumVec;
+
+use crate::p2p::{ChunkMismatch, ChunkPull, ChunkPush, PrefetchHint};
+use crate::signed::{Signable, Signed};
+use crate::varint::VarInt;
+use crate::{
+    BandwidthLimit, Chunk, ChunkId, ChunkPriority, ChunkSink, Container,
+    ContainerId, MesgId, PeerBook, RequestKey, RequestTracker, ResponseMatch,
+    SessionError, SinkPoll, StormApp, TokenBucket, TransferKey,
+};
+
+/// Default maximum number of chunks requested simultaneously by a download.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+/// Errors processing chunks received during a download.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DownloadError {
+    /// chunk belongs to container {0} which is not downloaded.
+    ContainerMismatch(ContainerId),
+
+    /// chunk {0} is not part of the downloaded container.
+    UnknownChunk(ChunkId),
+
+    /// chunk data hash to {actual} instead of the requested {expected}.
+    ChunkMismatch { expected: ChunkId, actual: ChunkId },
+
+    /// chunk {0} was not requested from the peer.
+    Unsolicited(ChunkId),
+
+    /// {0}
+    Session(SessionError),
+
+    /// retrieval receipt is not signed by the downloader or is issued to a
+    /// different provider.
+    InvalidReceipt,
+}
+
+/// Errors passing chunks received during a download to a [`ChunkSink`].
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ReceiveError<E: std::error::Error> {
+    /// {0}
+    Download(DownloadError),
+
+    /// chunk sink failure: {0}
+    Sink(E),
+}
+
+/// Errors restoring download from a [`TransferCheckpoint`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CheckpointError {
+    /// checkpoint is made for container {0} which does not match the
+    /// downloaded container.
+    ContainerMismatch(ContainerId),
+
+    /// checkpoint chunk bitmap does not match the number of container chunks.
+    BitmapMismatch,
+
+    /// checkpoint verified byte ranges do not match its chunk bitmap.
+    RangeMismatch,
+
+    /// checkpoint data are too large to be encoded.
+    TooLarge,
+}
+
+/// Range of container data bytes.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{offset}+{len}")]
+pub struct ByteRange {
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Persistent state of a partially completed download, allowing to resume it
+/// after the node restart.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct TransferCheckpoint {
+    pub app: StormApp,
+    pub message_id: MesgId,
+    pub container_id: ContainerId,
+    /// Bitmap of the container chunks already received and verified, where
+    /// bit `i % 8` of byte `i / 8` corresponds to the chunk with index `i`.
+    pub have: MediumVec<u8>,
+    /// Peers known to have some of the missing chunks, which should be
+    /// asked for the chunk availability once the download is resumed.
+    pub peers: BTreeSet<PublicKey>,
+    /// Byte ranges of the container data covered by the received chunks.
+    pub verified: Vec<ByteRange>,
+}
+
+impl TransferCheckpoint {
+    /// Checks whether the chunk with the given index was received.
+    pub fn has_chunk(&self, index: usize) -> bool {
+        self.have
+            .get(index / 8)
+            .map(|byte| byte & (1 << (index % 8)) != 0)
+            .unwrap_or_default()
+    }
+
+    fn ranges(container: &Container, have: &[bool]) -> Vec<ByteRange> {
+        let chunk_size = container.header.chunk_size as u64;
+        let size = container.header.size;
+        let mut ranges = Vec::<ByteRange>::new();
+        for (index, _) in have.iter().enumerate().filter(|(_, have)| **have) {
+            let offset = index as u64 * chunk_size;
+            let len = chunk_size.min(size.saturating_sub(offset));
+            match ranges.last_mut() {
+                Some(last) if last.offset + last.len == offset => {
+                    last.len += len
+                }
+                _ => ranges.push(ByteRange { offset, len }),
+            }
+        }
+        ranges
+    }
+}
+
+/// Receipt of a successful container retrieval, signed by the downloader
+/// after it verified the complete container. Providers may use receipts to
+/// prove service delivery for payment settlement or reputation.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {chunks} chunks from {provider}")]
+pub struct RetrievalReceipt {
+    /// Node which served the container chunks.
+    pub provider: PublicKey,
+    pub container_id: ContainerId,
+    /// Size of the container data, in bytes.
+    pub size: VarInt,
+    /// Number of the container chunks delivered by the provider.
+    pub chunks: VarInt,
+    /// Unix timestamp when the download was completed.
+    pub timestamp: u64,
+}
+
+impl Signable for RetrievalReceipt {
+    const SIGNED_TYPE: u8 = 14;
+}
+
+impl RetrievalReceipt {
+    /// Checks that the receipt is signed by the `downloader` and is issued
+    /// to the `provider`. Returns the number of chunks the receipt
+    /// acknowledges.
+    pub fn check<C: Verification>(
+        secp: &Secp256k1<C>,
+        receipt: &Signed<RetrievalReceipt>,
+        downloader: PublicKey,
+        provider: PublicKey,
+    ) -> Result<u64, DownloadError> {
+        if receipt.signer != downloader || receipt.verify(secp).is_err() {
+            return Err(DownloadError::InvalidReceipt);
+        }
+        if receipt.data.provider != provider {
+            return Err(DownloadError::InvalidReceipt);
+        }
+        Ok(receipt.data.chunks.into_inner())
+    }
+}
+
+/// Download of container chunks from multiple peers.
+///
+/// The download keeps track of which peers have which chunks, picks the most
+/// reliable peer (according to the [`PeerBook`]) for each missing chunk and
+/// retries requests which time out against the next-best peer, penalizing
+/// the peer which failed to respond.
+#[derive(Clone, Debug)]
+pub struct Download {
+    app: StormApp,
+    message_id: MesgId,
+    container_id: ContainerId,
+    container: Container,
+    /// Order in which chunks are requested, following the priority hint.
+    order: Vec<usize>,
+    missing: BTreeSet<ChunkId>,
+    availability: BTreeMap<ChunkId, BTreeSet<PublicKey>>,
+    failed: BTreeMap<ChunkId, BTreeSet<PublicKey>>,
+    tracker: RequestTracker,
+    max_in_flight: usize,
+    bandwidth: Option<TokenBucket>,
+    /// Number of chunks delivered by each of the peers.
+    delivered: BTreeMap<PublicKey, u32>,
+    /// Received chunks which the sink was too busy to take.
+    backlog: Vec<Chunk>,
+    /// Keys decrypting chunks pushed by each of the peers.
+    keys: BTreeMap<PublicKey, TransferKey>,
+}
+
+impl Download {
+    pub fn new(
+        app: StormApp,
+        message_id: MesgId,
+        container: Container,
+        timeout_ms: u64,
+    ) -> Download {
+        Download {
+            app,
+            message_id,
+            container_id: container.container_id(),
+            missing: container.chunks.iter().copied().collect(),
+            order: container.fetch_order(None),
+            container,
+            availability: empty!(),
+            failed: empty!(),
+            tracker: RequestTracker::new(timeout_ms),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            bandwidth: None,
+            delivered: empty!(),
+            backlog: vec![],
+            keys: empty!(),
+        }
+    }
+
+    /// Decrypts further chunks pushed by the peer with the transfer key,
+    /// derived for the peer as the sender.
+    pub fn set_transfer_key(&mut self, peer: PublicKey, key: TransferKey) {
+        self.keys.insert(peer, key);
+    }
+
+    /// Sets hint on the order in which the chunks are requested, received
+    /// with `ChunkPriority` message. Returns `false` and ignores the hint if
+    /// it does not match the container.
+    pub fn set_priority(&mut self, priority: ChunkPriority) -> bool {
+        if !priority.is_valid_for(&self.container) {
+            return false;
+        }
+        self.order = self.container.fetch_order(Some(&priority));
+        true
+    }
+
+    /// Marks chunks as already present locally, so they will not be
+    /// requested.
+    pub fn mark_present(
+        &mut self,
+        chunk_ids: impl IntoIterator<Item = ChunkId>,
+    ) {
+        for chunk_id in chunk_ids {
+            self.missing.remove(&chunk_id);
+            self.availability.remove(&chunk_id);
+            self.failed.remove(&chunk_id);
+        }
+    }
+
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.max_in_flight = max_in_flight.max(1);
+    }
+
+    /// Limits the rate at which chunks are requested, such that the data
+    /// requested from all peers does not exceed the bandwidth limit.
+    pub fn set_bandwidth_limit(&mut self, limit: BandwidthLimit, now_ms: u64) {
+        self.bandwidth = Some(limit.token_bucket(now_ms));
+    }
+
+    pub fn remove_bandwidth_limit(&mut self) { self.bandwidth = None; }
+
+    /// Number of milliseconds after `now_ms` when the bandwidth limit will
+    /// allow requesting next chunk, or zero if there is no limit.
+    pub fn throttled_ms(&mut self, now_ms: u64) -> u64 {
+        let amount = self.chunk_len();
+        self.bandwidth
+            .as_mut()
+            .and_then(|bucket| {
+                bucket.wait_ms(amount.min(bucket.burst()), now_ms)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Expected size of a single chunk, used for the bandwidth accounting.
+    fn chunk_len(&self) -> u64 {
+        let header = &self.container.header;
+        if self.container.chunks.len() > 1 {
+            header.chunk_size as u64
+        } else {
+            header.size
+        }
+    }
+
+    pub fn app(&self) -> StormApp { self.app }
+
+    pub fn container_id(&self) -> ContainerId { self.container_id }
+
+    pub fn container(&self) -> &Container { &self.container }
+
+    pub fn is_complete(&self) -> bool { self.missing.is_empty() }
+
+    pub fn missing(&self) -> &BTreeSet<ChunkId> { &self.missing }
+
+    /// Received chunks which are not yet taken by the sink passed to
+    /// [`Download::receive_into`].
+    pub fn backlog(&self) -> &[Chunk] { &self.backlog }
+
+    /// Chunks which are missing and can't be requested from any of the known
+    /// peers, since they are either unavailable or have already failed.
+    pub fn stalled(&self) -> BTreeSet<ChunkId> {
+        self.missing
+            .iter()
+            .filter(|chunk_id| {
+                !self.tracker.is_pending(self.key(**chunk_id))
+                    && self.candidates(**chunk_id).next().is_none()
+            })
+            .copied()
+            .collect()
+    }
+
+    fn key(&self, chunk_id: ChunkId) -> RequestKey {
+        RequestKey::Chunk(self.container_id, chunk_id)
+    }
+
+    /// Penalizes the peer which delivered invalid chunk data and completes
+    /// its request, such that it is not penalized again on expiry.
+    fn reject(
+        &mut self,
+        peer: PublicKey,
+        chunk_id: ChunkId,
+        peers: &mut PeerBook,
+    ) {
+        peers.record_mismatch(peer);
+        let key = self.key(chunk_id);
+        self.tracker.complete(peer, key);
+        self.failed.entry(chunk_id).or_default().insert(peer);
+    }
+
+    fn candidates(
+        &self,
+        chunk_id: ChunkId,
+    ) -> impl Iterator<Item = PublicKey> + '_ {
+        let failed = self.failed.get(&chunk_id);
+        self.availability.get(&chunk_id).into_iter().flatten().copied().filter(
+            move |peer| {
+                failed.map(|failed| !failed.contains(peer)).unwrap_or(true)
+            },
+        )
+    }
+
+    /// Picks the most reliable of the peers having the chunk, skipping the
+    /// peers which advertised limits do not allow to serve the container or
+    /// to take one more request.
+    fn best_candidate(
+        &self,
+        chunk_id: ChunkId,
+        peers: &PeerBook,
+    ) -> Option<PublicKey> {
+        self.candidates(chunk_id)
+            .filter(|peer| {
+                let limits = peers
+                    .peer(*peer)
+                    .map(|info| info.advert.limits)
+                    .unwrap_or_default();
+                limits.check_container(&self.container).is_ok()
+                    && limits
+                        .check_transfers(self.tracker.outstanding(*peer))
+                        .is_ok()
+            })
+            .max_by_key(|peer| {
+                peers.peer(*peer).map(|info| info.reliability()).unwrap_or(500)
+            })
+    }
+
+    /// Registers chunks known to be available at the peer.
+    pub fn add_availability(
+        &mut self,
+        peer: PublicKey,
+        chunk_ids: impl IntoIterator<Item = ChunkId>,
+    ) {
+        for chunk_id in chunk_ids {
+            if self.missing.contains(&chunk_id) {
+                self.availability.entry(chunk_id).or_default().insert(peer);
+            }
+        }
+    }
+
+    /// Registers peer as having all of the container chunks.
+    pub fn add_seeder(&mut self, peer: PublicKey) {
+        let missing = self.missing.clone();
+        self.add_availability(peer, missing);
+    }
+
+    /// Removes peer from the download, e.g. when it disconnects. Chunks
+    /// requested from the peer will be re-requested from other peers.
+    pub fn remove_peer(&mut self, peer: PublicKey) {
+        self.tracker.cancel_peer(peer);
+        for peers in self.availability.values_mut() {
+            peers.remove(&peer);
+        }
+    }
+
+    /// Schedules requests for the missing chunks, choosing the most reliable
+    /// of the peers having each chunk.
+    pub fn next_requests(
+        &mut self,
+        peers: &PeerBook,
+        now_ms: u64,
+    ) -> Vec<(PublicKey, ChunkPull)> {
+        let in_flight = self
+            .missing
+            .iter()
+            .filter(|chunk_id| self.tracker.is_pending(self.key(**chunk_id)))
+            .count();
+        let mut budget = self.max_in_flight.saturating_sub(in_flight);
+
+        let chunk_len = self.chunk_len();
+        let mut scheduled = BTreeSet::new();
+        let mut requests = BTreeMap::<PublicKey, BTreeSet<ChunkId>>::new();
+        for index in self.order.clone() {
+            if budget == 0 {
+                break;
+            }
+            let chunk_id = self.container.chunks[index];
+            if !self.missing.contains(&chunk_id)
+                || !scheduled.insert(chunk_id)
+                || self.tracker.is_pending(self.key(chunk_id))
+            {
+                continue;
+            }
+            let peer = match self.best_candidate(chunk_id, peers) {
+                Some(peer) => peer,
+                None => continue,
+            };
+            if let Some(bucket) = self.bandwidth.as_mut() {
+                let amount = chunk_len.min(bucket.burst());
+                if !bucket.try_consume(amount, now_ms) {
+                    break;
+                }
+            }
+            let key = self.key(chunk_id);
+            if self.tracker.register(peer, key, now_ms) {
+                requests.entry(peer).or_default().insert(chunk_id);
+                budget -= 1;
+            }
+        }
+
+        requests
+            .into_iter()
+            .map(|(peer, chunk_ids)| {
+                (peer, ChunkPull {
+                    app: self.app,
+                    message_id: self.message_id,
+                    container_id: self.container_id,
+                    chunk_ids,
+                })
+            })
+            .collect()
+    }
+
+    /// Schedules requests for the missing chunks like
+    /// [`Download::next_requests`], unless the sink consuming the received
+    /// chunks is busy.
+    ///
+    /// The chunks from the [`Download::backlog`] are offered to the sink
+    /// again and the sink is polled to make progress; no new chunks are
+    /// requested while the sink is not ready or some of the received chunks
+    /// are still waiting for it.
+    pub fn next_requests_for<S: ChunkSink>(
+        &mut self,
+        peers: &PeerBook,
+        sink: &mut S,
+        now_ms: u64,
+    ) -> Result<Vec<(PublicKey, ChunkPull)>, S::Error> {
+        let mut backlog = vec![];
+        for chunk in std::mem::take(&mut self.backlog) {
+            match sink.offer(chunk)? {
+                SinkPoll::Accepted => {}
+                SinkPoll::Busy(chunk) => backlog.push(chunk),
+            }
+        }
+        self.backlog = backlog;
+        if !sink.poll_ready()? || !self.backlog.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(self.next_requests(peers, now_ms))
+    }
+
+    /// Produces hints for the peers about up to `lookahead` missing chunks
+    /// which are not requested yet, but will be requested next. Each chunk is
+    /// hinted to the peer it will most likely be requested from.
+    pub fn prefetch_hints(
+        &self,
+        peers: &PeerBook,
+        lookahead: usize,
+    ) -> Vec<(PublicKey, PrefetchHint)> {
+        let mut hints = BTreeMap::<PublicKey, BTreeSet<ChunkId>>::new();
+        let mut scheduled = BTreeSet::new();
+        for index in self.order.iter().copied() {
+            if scheduled.len() >= lookahead {
+                break;
+            }
+            let chunk_id = self.container.chunks[index];
+            if !self.missing.contains(&chunk_id)
+                || self.tracker.is_pending(self.key(chunk_id))
+                || !scheduled.insert(chunk_id)
+            {
+                continue;
+            }
+            if let Some(peer) = self.best_candidate(chunk_id, peers) {
+                hints.entry(peer).or_default().insert(chunk_id);
+            }
+        }
+
+        hints
+            .into_iter()
+            .map(|(peer, chunk_ids)| {
+                (peer, PrefetchHint {
+                    app: self.app,
+                    container_id: self.container_id,
+                    chunk_ids,
+                })
+            })
+            .collect()
+    }
+
+    /// Exports download progress into a checkpoint which can be persisted
+    /// and used to resume the download with [`Download::restore`].
+    pub fn checkpoint(&self) -> Result<TransferCheckpoint, CheckpointError> {
+        let have = self
+            .container
+            .chunks
+            .iter()
+            .map(|chunk_id| !self.missing.contains(chunk_id))
+            .collect::<Vec<_>>();
+        let mut bitmap = vec![0u8; (have.len() + 7) / 8];
+        for (index, _) in have.iter().enumerate().filter(|(_, have)| **have) {
+            bitmap[index / 8] |= 1 << (index % 8);
+        }
+        Ok(TransferCheckpoint {
+            app: self.app,
+            message_id: self.message_id,
+            container_id: self.container_id,
+            have: MediumVec::try_from(bitmap)
+                .map_err(|_| CheckpointError::TooLarge)?,
+            peers: self.availability.values().flatten().copied().collect(),
+            verified: TransferCheckpoint::ranges(&self.container, &have),
+        })
+    }
+
+    /// Resumes download from the checkpoint. Chunks marked as received in
+    /// the checkpoint are not requested again; the caller is responsible
+    /// for making sure that their data are still present in the local
+    /// store.
+    pub fn restore(
+        container: Container,
+        checkpoint: &TransferCheckpoint,
+        timeout_ms: u64,
+    ) -> Result<Download, CheckpointError> {
+        if container.container_id() != checkpoint.container_id {
+            return Err(CheckpointError::ContainerMismatch(
+                checkpoint.container_id,
+            ));
+        }
+        let count = container.chunks.len();
+        if checkpoint.have.len() != (count + 7) / 8
+            || (count..checkpoint.have.len() * 8)
+                .any(|index| checkpoint.has_chunk(index))
+        {
+            return Err(CheckpointError::BitmapMismatch);
+        }
+        let have = (0..count)
+            .map(|index| checkpoint.has_chunk(index))
+            .collect::<Vec<_>>();
+        if TransferCheckpoint::ranges(&container, &have) != checkpoint.verified
+        {
+            return Err(CheckpointError::RangeMismatch);
+        }
+        let present = container
+            .chunks
+            .iter()
+            .zip(&have)
+            .filter(|(_, have)| **have)
+            .map(|(chunk_id, _)| *chunk_id)
+            .collect::<Vec<_>>();
+        let mut download = Download::new(
+            checkpoint.app,
+            checkpoint.message_id,
+            container,
+            timeout_ms,
+        );
+        download.mark_present(present);
+        Ok(download)
+    }
+
+    /// Processes chunk received from the peer, returning the chunk if it was
+    /// missing and `None` if it was already received.
+    pub fn receive(
+        &mut self,
+        peer: PublicKey,
+        push: ChunkPush,
+        peers: &mut PeerBook,
+    ) -> Result<Option<Chunk>, DownloadError> {
+        if push.container_id != self.container_id {
+            return Err(DownloadError::ContainerMismatch(push.container_id));
+        }
+        if !self.container.chunks.contains(&push.chunk_id) {
+            return Err(DownloadError::UnknownChunk(push.chunk_id));
+        }
+        let chunk_id = push.chunk_id;
+        let push = match self.keys.get(&peer) {
+            Some(key) => match key.open_push(push) {
+                Ok(push) => push,
+                Err(err) => {
+                    self.reject(peer, chunk_id, peers);
+                    return Err(DownloadError::Session(err));
+                }
+            },
+            None => push,
+        };
+        let actual = push.chunk.chunk_id();
+        if actual != push.chunk_id {
+            self.reject(peer, push.chunk_id, peers);
+            return Err(DownloadError::ChunkMismatch {
+                expected: push.chunk_id,
+                actual,
+            });
+        }
+        let key = self.key(push.chunk_id);
+        if self.tracker.complete(peer, key) == ResponseMatch::Unsolicited
+            && self.missing.contains(&push.chunk_id)
+        {
+            return Err(DownloadError::Unsolicited(push.chunk_id));
+        }
+        peers.record_success(peer);
+        if !self.missing.remove(&push.chunk_id) {
+            return Ok(None);
+        }
+        self.availability.remove(&push.chunk_id);
+        self.failed.remove(&push.chunk_id);
+        *self.delivered.entry(peer).or_default() += 1;
+        Ok(Some(push.chunk))
+    }
+
+    /// Processes chunk received from the peer like [`Download::receive`] and
+    /// offers the missing chunk to the `sink`. Chunks which the sink is too
+    /// busy to take are kept in the [`Download::backlog`] and offered again
+    /// by [`Download::next_requests_for`]. Returns whether the chunk was
+    /// missing.
+    pub fn receive_into<S: ChunkSink>(
+        &mut self,
+        peer: PublicKey,
+        push: ChunkPush,
+        peers: &mut PeerBook,
+        sink: &mut S,
+    ) -> Result<bool, ReceiveError<S::Error>> {
+        let chunk = match self
+            .receive(peer, push, peers)
+            .map_err(ReceiveError::Download)?
+        {
+            Some(chunk) => chunk,
+            None => return Ok(false),
+        };
+        if let SinkPoll::Busy(chunk) =
+            sink.offer(chunk).map_err(ReceiveError::Sink)?
+        {
+            self.backlog.push(chunk);
+        }
+        Ok(true)
+    }
+
+    /// Produces `ChunkMismatch` report to be sent to the peer for which
+    /// [`Download::receive`] failed with [`DownloadError::ChunkMismatch`].
+    pub fn mismatch_report(&self, err: DownloadError) -> Option<ChunkMismatch> {
+        match err {
+            DownloadError::ChunkMismatch { expected, actual } => {
+                Some(ChunkMismatch {
+                    app: self.app,
+                    container_id: self.container_id,
+                    expected,
+                    actual,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Produces receipts for each of the peers which delivered chunks of the
+    /// container, to be signed by the downloader. Returns no receipts until
+    /// the download is complete.
+    pub fn retrieval_receipts(&self, now: u64) -> Vec<RetrievalReceipt> {
+        if !self.is_complete() {
+            return vec![];
+        }
+        self.delivered
+            .iter()
+            .map(|(provider, chunks)| RetrievalReceipt {
+                provider: *provider,
+                container_id: self.container_id,
+                size: self.container.header.size.into(),
+                chunks: (*chunks).into(),
+                timestamp: now,
+            })
+            .collect()
+    }
+
+    /// Expires requests which were not answered in time, penalizing the
+    /// peers which failed to respond, and retries them against the next-best
+    /// peers. Chunks which can't be retried are reported by
+    /// [`Download::stalled`].
+    pub fn expire(
+        &mut self,
+        peers: &mut PeerBook,
+        now_ms: u64,
+    ) -> Vec<(PublicKey, ChunkPull)> {
+        for (key, peer) in self.tracker.expire(now_ms) {
+            if let RequestKey::Chunk(_, chunk_id) = key {
+                peers.record_failure(peer);
+                self.failed.entry(chunk_id).or_default().insert(peer);
+            }
+        }
+        self.next_requests(peers, now_ms)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use stens::AsciiString;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+    use crate::fixtures::{alice, bob};
+    use crate::limits::AppsAdvert;
+    use crate::{ContainerAssembler, UploadQueue};
+
+    #[test]
+    fn test_failover() {
+        let alice = alice();
+        let bob = bob();
+        let data = (0u8..100).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        peers.update_advert(alice, AppsAdvert::default(), 0);
+        peers.update_advert(bob, AppsAdvert::default(), 0);
+        peers.record_success(alice);
+
+        let mut download =
+            Download::new(StormApp::Storage, MesgId::default(), container, 100);
+        download.add_seeder(alice);
+        download.add_seeder(bob);
+        let hints = download.prefetch_hints(&peers, 4);
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].0, alice);
+        let requests = download.next_requests(&peers, 0);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, alice);
+        assert!(download.prefetch_hints(&peers, 4).is_empty());
+
+        let retries = download.expire(&mut peers, 100);
+        assert_eq!(retries.len(), 1);
+        assert_eq!(retries[0].0, bob);
+
+        assert!(download.retrieval_receipts(0).is_empty());
+        for chunk in &chunks {
+            let push = ChunkPush {
+                app: StormApp::Storage,
+                container_id: download.container_id(),
+                chunk_id: chunk.chunk_id(),
+                chunk: chunk.clone(),
+            };
+            assert!(!download.is_complete());
+            assert!(download.receive(bob, push, &mut peers).unwrap().is_some());
+        }
+        assert!(download.is_complete());
+        assert!(download.stalled().is_empty());
+
+        let receipts = download.retrieval_receipts(1000);
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].provider, bob);
+        // Small sizes and chunk counts take a single byte each
+        assert_eq!(receipts[0].strict_serialize().unwrap().len(), 33 + 32 + 10);
+        let secp = Secp256k1::new();
+        let key = secp256k1::SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let downloader = PublicKey::from_secret_key(&secp, &key);
+        let receipt = Signed::sign(&secp, receipts[0], &key);
+        assert_eq!(
+            RetrievalReceipt::check(&secp, &receipt, downloader, bob),
+            Ok(2)
+        );
+        assert_eq!(
+            RetrievalReceipt::check(&secp, &receipt, downloader, alice),
+            Err(DownloadError::InvalidReceipt)
+        );
+    }
+
+    #[test]
+    fn test_chunk_mismatch() {
+        let alice = alice();
+        let data = (0u8..100).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        peers.update_advert(alice, AppsAdvert::default(), 0);
+
+        let mut download =
+            Download::new(StormApp::Storage, MesgId::default(), container, 100);
+        download.add_seeder(alice);
+        download.set_max_in_flight(1);
+        assert_eq!(download.next_requests(&peers, 0).len(), 1);
+        let expected = chunks[0].chunk_id();
+        let forged = Chunk::try_from(vec![8u8; 50]).unwrap();
+        let actual = forged.chunk_id();
+        let push = ChunkPush {
+            app: StormApp::Storage,
+            container_id: download.container_id(),
+            chunk_id: expected,
+            chunk: forged,
+        };
+        let err = download.receive(alice, push, &mut peers).unwrap_err();
+        assert_eq!(err, DownloadError::ChunkMismatch { expected, actual });
+        assert_eq!(
+            peers.peer(alice).unwrap().failures,
+            crate::CHUNK_MISMATCH_PENALTY
+        );
+        // The rejected request does not time out and is not penalized twice
+        download.expire(&mut peers, 1000);
+        assert_eq!(
+            peers.peer(alice).unwrap().failures,
+            crate::CHUNK_MISMATCH_PENALTY
+        );
+        assert_eq!(
+            download.mismatch_report(err),
+            Some(ChunkMismatch {
+                app: StormApp::Storage,
+                container_id: download.container_id(),
+                expected,
+                actual,
+            })
+        );
+    }
+
+    #[test]
+    fn test_transfer_encryption() {
+        let alice = alice();
+        let bob = bob();
+        let data = (0u8..100).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        peers.update_advert(alice, AppsAdvert::default(), 0);
+
+        let mut download =
+            Download::new(StormApp::Storage, MesgId::default(), container, 100);
+        download.add_seeder(alice);
+        assert_eq!(download.next_requests(&peers, 0).len(), 1);
+
+        // Alice uploads chunks to Bob, both derive the key for Alice as sender
+        let container_id = download.container_id();
+        let key = TransferKey::derive(&[0x42; 32], container_id, alice);
+        download.set_transfer_key(alice, key);
+        let mut uploads = UploadQueue::new();
+        uploads.set_transfer_key(bob, container_id, key);
+        for chunk in &chunks {
+            let push = ChunkPush {
+                app: StormApp::Storage,
+                container_id,
+                chunk_id: chunk.chunk_id(),
+                chunk: chunk.clone(),
+            };
+            uploads.enqueue(bob, push, 0).unwrap();
+        }
+        let mut sealed =
+            uploads.drain_ready(0).into_iter().map(|(_, push)| push);
+
+        let push = sealed.next().unwrap();
+        assert_ne!(push.chunk, chunks[0]);
+        assert_eq!(
+            download.receive(alice, push, &mut peers).unwrap(),
+            Some(chunks[0].clone())
+        );
+
+        download.set_transfer_key(
+            alice,
+            TransferKey::derive(&[0x43; 32], container_id, alice),
+        );
+        let push = sealed.next().unwrap();
+        assert_eq!(
+            download.receive(alice, push, &mut peers),
+            Err(DownloadError::Session(SessionError::Decryption(
+                chunks[1].chunk_id()
+            )))
+        );
+        assert_eq!(
+            peers.peer(alice).unwrap().failures,
+            crate::CHUNK_MISMATCH_PENALTY
+        );
+    }
+
+    #[test]
+    fn test_peer_limits() {
+        let alice = alice();
+        let bob = bob();
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        let mut advert = AppsAdvert::default();
+        advert.limits.max_transfers = 1;
+        peers.update_advert(alice, advert.clone(), 0);
+        advert.limits.max_transfers = u16::MAX;
+        advert.limits.max_container_size = 100;
+        peers.update_advert(bob, advert, 0);
+        peers.record_success(alice);
+
+        let mut download =
+            Download::new(StormApp::Storage, MesgId::default(), container, 100);
+        download.add_seeder(alice);
+        download.add_seeder(bob);
+        // Bob does not serve containers of this size and Alice takes a single
+        // request at a time
+        let requests = download.next_requests(&peers, 0);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, alice);
+        assert_eq!(requests[0].1.chunk_ids.len(), 1);
+        assert!(download.next_requests(&peers, 0).is_empty());
+    }
+
+    #[test]
+    fn test_bandwidth_limit() {
+        let alice = alice();
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        peers.update_advert(alice, AppsAdvert::default(), 0);
+
+        let mut download = Download::new(
+            StormApp::Storage,
+            MesgId::default(),
+            container,
+            1000,
+        );
+        download.add_seeder(alice);
+        assert_eq!(download.throttled_ms(0), 0);
+        download.set_bandwidth_limit(
+            BandwidthLimit {
+                bytes_per_sec: 100,
+                burst: 100,
+            },
+            0,
+        );
+        // Burst allows requesting two chunks at once, and then a chunk per
+        // half a second
+        let requests = download.next_requests(&peers, 0);
+        assert_eq!(requests[0].1.chunk_ids.len(), 2);
+        assert_eq!(download.throttled_ms(0), 500);
+        assert!(download.next_requests(&peers, 499).is_empty());
+        assert_eq!(download.throttled_ms(499), 1);
+        let requests = download.next_requests(&peers, 500);
+        assert_eq!(requests[0].1.chunk_ids.len(), 1);
+
+        download.remove_bandwidth_limit();
+        assert_eq!(download.throttled_ms(500), 0);
+        let requests = download.next_requests(&peers, 500);
+        assert_eq!(requests[0].1.chunk_ids.len(), 1);
+        assert!(download.next_requests(&peers, 500).is_empty());
+    }
+
+    #[test]
+    fn test_priority() {
+        let alice = alice();
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        peers.update_advert(alice, AppsAdvert::default(), 0);
+
+        let mut download = Download::new(
+            StormApp::Storage,
+            MesgId::default(),
+            container.clone(),
+            100,
+        );
+        download.add_seeder(alice);
+        download.set_max_in_flight(1);
+        assert!(!download.set_priority(ChunkPriority {
+            container_id: container.container_id(),
+            order: MediumVec::try_from(vec![3u32, 3, 1, 0]).unwrap(),
+        }));
+        assert!(download.set_priority(ChunkPriority {
+            container_id: container.container_id(),
+            order: MediumVec::try_from(vec![3u32, 2, 1, 0]).unwrap(),
+        }));
+        let requests = download.next_requests(&peers, 0);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].1.chunk_ids, bset! { chunks[3].chunk_id() });
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let alice = alice();
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut peers = PeerBook::new();
+        peers.update_advert(alice, AppsAdvert::default(), 0);
+        let push = |index: usize| ChunkPush {
+            app: StormApp::Storage,
+            container_id: container.container_id(),
+            chunk_id: chunks[index].chunk_id(),
+            chunk: chunks[index].clone(),
+        };
+        let ids = |indexes: &[usize]| {
+            indexes
+                .iter()
+                .map(|index| chunks[*index].chunk_id())
+                .collect::<BTreeSet<_>>()
+        };
+
+        let mut download = Download::new(
+            StormApp::Storage,
+            MesgId::default(),
+            container.clone(),
+            100,
+        );
+        download.add_seeder(alice);
+        download.set_max_in_flight(2);
+        // Request non-adjacent chunks first, so they can't be written at once
+        assert!(download.set_priority(ChunkPriority {
+            container_id: container.container_id(),
+            order: MediumVec::try_from(vec![0u32, 2, 1, 3]).unwrap(),
+        }));
+        // Assembler buffering just a single chunk
+        let mut sink =
+            ContainerAssembler::new(container.clone(), Cursor::new(vec![]), 50)
+                .unwrap();
+
+        let requests =
+            download.next_requests_for(&peers, &mut sink, 0).unwrap();
+        assert_eq!(requests[0].1.chunk_ids, ids(&[0, 2]));
+        for index in [0usize, 2] {
+            assert!(download
+                .receive_into(alice, push(index), &mut peers, &mut sink)
+                .unwrap());
+        }
+        assert_eq!(download.backlog(), &[chunks[2].clone()]);
+
+        // Nothing is in flight, but the received chunk is still waiting for
+        // the sink, so no new requests are made
+        assert!(!download.clone().next_requests(&peers, 10).is_empty());
+        let requests =
+            download.next_requests_for(&peers, &mut sink, 10).unwrap();
+        assert!(requests.is_empty());
+        assert_eq!(download.backlog(), &[chunks[2].clone()]);
+
+        // Once the sink takes the chunk and writes it out requests resume
+        let requests =
+            download.next_requests_for(&peers, &mut sink, 20).unwrap();
+        assert!(download.backlog().is_empty());
+        assert_eq!(requests[0].1.chunk_ids, ids(&[1, 3]));
+
+        for index in [1usize, 3] {
+            download
+                .receive_into(alice, push(index), &mut peers, &mut sink)
+                .unwrap();
+        }
+        assert!(download.is_complete());
+        while !download.backlog().is_empty() {
+            download.next_requests_for(&peers, &mut sink, 30).unwrap();
+        }
+        assert!(!download
+            .receive_into(alice, push(1), &mut peers, &mut sink)
+            .unwrap());
+        assert_eq!(sink.finish().unwrap().into_inner(), data);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let data = (0u8..250).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        let mut download = Download::new(
+            StormApp::Storage,
+            MesgId::default(),
+            container.clone(),
+            100,
+        );
+        download.mark_present(
+            [0, 1, 3].into_iter().map(|no| chunks[no].chunk_id()),
+        );
+        let checkpoint = download.checkpoint().unwrap();
+        assert_eq!(checkpoint.verified, vec![
+            ByteRange {
+                offset: 0,
+                len: 100
+            },
+            ByteRange {
+                offset: 150,
+                len: 50
+            }
+        ]);
+
+        let data = checkpoint.strict_serialize().unwrap();
+        let checkpoint = TransferCheckpoint::strict_deserialize(data).unwrap();
+        let restored =
+            Download::restore(container.clone(), &checkpoint, 100).unwrap();
+        assert_eq!(restored.missing(), download.missing());
+
+        let mut other = checkpoint;
+        other.verified.pop();
+        assert_eq!(
+            Download::restore(container, &other, 100).unwrap_err(),
+            CheckpointError::RangeMismatch
+        );
+    }
+}
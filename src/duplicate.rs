@@ -0,0 +1,154 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Suppression of duplicate gossiped and relayed messages.
+//!
+//! Nodes consult [`DuplicateCache`] before processing a gossiped or relayed
+//! message: messages with the same hash seen within the time-to-live of
+//! their type are dropped, which prevents amplification loops in the relay
+//! network. The cache holds a bounded number of hashes; once the limit is
+//! reached, hashes closest to expiration are evicted first.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin_hashes::{sha256, Hash};
+use internet2::TypedEnum;
+
+use crate::p2p::Messages;
+
+/// Default time during which a message is considered duplicate, in seconds.
+pub const DEFAULT_DUPLICATE_TTL: u64 = 10 * 60;
+
+/// Default maximal number of message hashes held by the cache.
+pub const DEFAULT_DUPLICATE_CAPACITY: usize = 1 << 16;
+
+/// Rolling cache of the hashes of recently seen messages.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DuplicateCache {
+    default_ttl: u64,
+    ttls: BTreeMap<u16, u64>,
+    capacity: usize,
+    seen: BTreeMap<sha256::Hash, u64>,
+    by_expiry: BTreeSet<(u64, sha256::Hash)>,
+}
+
+impl Default for DuplicateCache {
+    fn default() -> Self {
+        DuplicateCache::with_capacity(DEFAULT_DUPLICATE_CAPACITY)
+    }
+}
+
+impl DuplicateCache {
+    pub fn new() -> DuplicateCache { DuplicateCache::default() }
+
+    /// Constructs cache holding at most `capacity` message hashes.
+    pub fn with_capacity(capacity: usize) -> DuplicateCache {
+        DuplicateCache {
+            default_ttl: DEFAULT_DUPLICATE_TTL,
+            ttls: empty!(),
+            capacity: capacity.max(1),
+            seen: empty!(),
+            by_expiry: empty!(),
+        }
+    }
+
+    /// Sets time-to-live for the messages of types without specific
+    /// time-to-live.
+    pub fn set_default_ttl(&mut self, ttl: u64) { self.default_ttl = ttl; }
+
+    /// Sets time-to-live for the messages of the given type.
+    pub fn set_ttl(&mut self, mesg_type: u16, ttl: u64) {
+        self.ttls.insert(mesg_type, ttl);
+    }
+
+    /// Time-to-live of the messages of the given type.
+    pub fn ttl(&self, mesg_type: u16) -> u64 {
+        self.ttls.get(&mesg_type).copied().unwrap_or(self.default_ttl)
+    }
+
+    pub fn len(&self) -> usize { self.seen.len() }
+
+    pub fn is_empty(&self) -> bool { self.seen.is_empty() }
+
+    /// Checks whether the message with the given hash was seen and has not
+    /// expired at `now`.
+    pub fn contains(&self, hash: sha256::Hash, now: u64) -> bool {
+        self.seen.get(&hash).map(|expiry| *expiry > now).unwrap_or_default()
+    }
+
+    /// Registers serialized message of the given type received at time
+    /// `now`. Returns `false` if the message is a duplicate which must not
+    /// be processed.
+    pub fn check_raw(&mut self, mesg_type: u16, data: &[u8], now: u64) -> bool {
+        let hash = sha256::Hash::hash(data);
+        if self.contains(hash, now) {
+            return false;
+        }
+        if let Some(expiry) = self.seen.remove(&hash) {
+            self.by_expiry.remove(&(expiry, hash));
+        }
+        let expiry = now.saturating_add(self.ttl(mesg_type));
+        self.seen.insert(hash, expiry);
+        self.by_expiry.insert((expiry, hash));
+        while self.seen.len() > self.capacity {
+            let oldest = *self.by_expiry.iter().next().expect("cache is full");
+            self.by_expiry.remove(&oldest);
+            self.seen.remove(&oldest.1);
+        }
+        true
+    }
+
+    /// Registers message received at time `now`. Returns `false` if the
+    /// message is a duplicate which must not be processed.
+    pub fn check(&mut self, mesg: &Messages, now: u64) -> bool {
+        self.check_raw(mesg.get_type(), &mesg.serialize(), now)
+    }
+
+    /// Forgets messages which have expired at `now`.
+    pub fn prune(&mut self, now: u64) {
+        while let Some(&(expiry, hash)) = self.by_expiry.iter().next() {
+            if expiry > now {
+                break;
+            }
+            self.by_expiry.remove(&(expiry, hash));
+            self.seen.remove(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::p2p::AppMsg;
+    use crate::StormApp;
+
+    #[test]
+    fn test_duplicates() {
+        let ping = Messages::AppPing(AppMsg::new(StormApp::Chat, 1));
+        let other = Messages::AppPing(AppMsg::new(StormApp::Chat, 2));
+        let mut cache = DuplicateCache::with_capacity(2);
+        cache.set_ttl(ping.get_type(), 100);
+        assert_eq!(cache.ttl(ping.get_type()), 100);
+        assert_eq!(cache.ttl(0x0002), DEFAULT_DUPLICATE_TTL);
+
+        assert!(cache.check(&ping, 10));
+        assert!(!cache.check(&ping, 20));
+        assert!(cache.check(&ping, 110));
+        assert!(cache.check(&other, 120));
+
+        // The oldest hash is evicted when the capacity is exceeded
+        cache.check_raw(0x0002, b"data", 130);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.check(&ping, 130));
+
+        cache.prune(10_000);
+        assert!(cache.is_empty());
+    }
+}
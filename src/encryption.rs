@@ -0,0 +1,1015 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Encrypted containers.
+//!
+//! Container data are split into plaintext pieces of `chunk_size - 16` bytes,
+//! each of which is encrypted with ChaCha20-Poly1305 under the container key
+//! into a separate chunk of at most `chunk_size` bytes. This allows chunks to
+//! be encrypted and decrypted independently and in any order, as they arrive
+//! from the swarm.
+//!
+//! # Nonce derivation
+//!
+//! The nonce of a chunk is derived from its index within the container:
+//! bytes `0..8` hold the chunk index as a little-endian 64-bit integer,
+//! bytes `8..11` are zero and byte `11` is set to `1` for the last chunk of
+//! the container and to `0` otherwise. The last-chunk flag prevents
+//! truncation of the container data and chunk reordering is prevented by
+//! the index. Since the nonce is unique for each chunk under the same key,
+//! the container key must never be reused for different containers; a fresh
+//! random key has to be generated for each encrypted container.
+//!
+//! # Key wrapping
+//!
+//! Container keys are delivered to the recipients wrapped to their node
+//! identity keys. Since node identities are secp256k1 keys, key agreement
+//! uses ECDH over secp256k1 between a one-time ephemeral key of the sender
+//! and the recipient node key. The wrapping key is
+//! `SHA256("storm:keywrap" || ecdh || ephemeral_pubkey || recipient_pubkey)`
+//! where `ecdh` is the SHA256 of the compressed shared point, and the
+//! container key is encrypted with it using ChaCha20-Poly1305 with zero nonce
+//! (which is safe since the wrapping key is never reused).
+//!
+//! # Key rotation
+//!
+//! Long-lived containers are accessed through key epochs: the container key
+//! is wrapped with a key of each epoch and published as a [`KeyRotation`]
+//! record. Since the same epoch key (like a group key) may wrap keys of many
+//! containers, the container key is not encrypted with the epoch key
+//! directly, but with a key derived from it as a [`KeyRotationTag`] tagged
+//! hash of the epoch key, container id and epoch number. Rotation introduces a
+//! new epoch key without re-encrypting chunk data; readers holding older epoch
+//! keys keep access to the data, but can detect that their epoch is outdated
+//! and request the new epoch key.
+//!
+//! # Group sharing
+//!
+//! Topics and containers shared within a group use [`KeyEnvelope`], carrying
+//! the group key wrapped to each of the members. Any change of the member
+//! list produces a new envelope with a fresh key in the next epoch, so
+//! removed members can't read new data and new members can't read the data
+//! encrypted before they have joined.
+//!
+//! # Multi-recipient posts
+//!
+//! A post addressed to several nodes is sent as a [`SealedPost`]: the post
+//! is encrypted once with a fresh post key, which is wrapped to each of the
+//! recipients. The sealed payload also carries the keys of the encrypted
+//! attachment containers, so the attachments are stored and transferred
+//! only once for all of the recipients.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing};
+use stens::AsciiString;
+use strict_encoding::{MediumVec, StrictDecode, StrictEncode};
+
+use crate::chunk::{TooLargeData, MAX_CHUNK_SIZE};
+use crate::posting::Post;
+use crate::{
+    Chunk, ChunkId, Container, ContainerHeader, ContainerId, MesgId,
+    CONTAINER_VERSION,
+};
+
+// "storm:keyrotation"
+static MIDSTATE_KEY_ROTATION: [u8; 32] = [
+    250, 229, 20, 16, 36, 190, 189, 114, 154, 240, 253, 190, 199, 92, 208, 21,
+    173, 88, 78, 244, 29, 253, 215, 4, 188, 121, 153, 29, 86, 41, 167, 78,
+];
+
+/// Tag used for deriving keys wrapping container keys in key rotations.
+pub struct KeyRotationTag;
+
+impl sha256t::Tag for KeyRotationTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_KEY_ROTATION);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Size of the authentication tag appended to each encrypted chunk.
+pub const CHUNK_TAG_LEN: u32 = 16;
+
+/// Errors decrypting container chunks.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum EncryptionError {
+    /// chunk #{0} is not part of the container.
+    UnknownChunk(usize),
+
+    /// chunk data do not match chunk id {0}.
+    ChunkMismatch(ChunkId),
+
+    /// unable to decrypt chunk #{0} with the provided key.
+    Decryption(usize),
+
+    /// wrapped key is addressed to a different recipient.
+    RecipientMismatch,
+
+    /// unable to unwrap container key with the provided secret key.
+    Unwrap,
+
+    /// no key rotation record for epoch {0}.
+    UnknownEpoch(u32),
+
+    /// key rotation record belongs to container {0}.
+    ContainerMismatch(ContainerId),
+
+    /// a different key rotation record for epoch {0} is already known.
+    EpochConflict(u32),
+
+    /// key epoch {0} is the last one and can't be advanced.
+    EpochOverflow(u32),
+
+    /// node {0} is not a member of the group.
+    NotMember(PublicKey),
+
+    /// post is not addressed to node {0}.
+    NotRecipient(PublicKey),
+
+    /// unable to decrypt sealed post with the provided key.
+    PostDecryption,
+}
+
+/// Symmetric key encrypting container data.
+#[derive(Wrapper, Copy, Clone, PartialEq, Eq, Hash, From)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct ContainerKey([u8; 32]);
+
+// We do not want the key material to end up in logs
+impl std::fmt::Debug for ContainerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ContainerKey(..)")
+    }
+}
+
+impl ContainerKey {
+    /// Derives nonce for the chunk with the given index, as defined in the
+    /// [module documentation](self).
+    pub fn chunk_nonce(index: u64, last: bool) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&index.to_le_bytes());
+        nonce[11] = last as u8;
+        nonce
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+
+    /// Encrypts single piece of container data into a chunk.
+    pub fn encrypt_chunk(
+        &self,
+        index: u64,
+        last: bool,
+        data: &[u8],
+    ) -> Result<Chunk, TooLargeData> {
+        let nonce = Self::chunk_nonce(index, last);
+        let data = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), data)
+            .expect("chacha20poly1305 encryption of in-memory data");
+        MediumVec::try_from(data).map(Chunk::from).map_err(|_| TooLargeData)
+    }
+
+    /// Decrypts single chunk of the container data.
+    pub fn decrypt_chunk(
+        &self,
+        index: u64,
+        last: bool,
+        chunk: &Chunk,
+    ) -> Option<Vec<u8>> {
+        let nonce = Self::chunk_nonce(index, last);
+        self.cipher().decrypt(Nonce::from_slice(&nonce), chunk.as_ref()).ok()
+    }
+
+    /// Encrypts data producing container and its encrypted chunks. Each
+    /// chunk is no larger than `chunk_size`, which is clamped to the
+    /// `CHUNK_TAG_LEN + 1..=MAX_CHUNK_SIZE` range.
+    pub fn encrypt_container(
+        &self,
+        mime: AsciiString,
+        info: impl ToString,
+        data: &[u8],
+        chunk_size: u32,
+    ) -> Result<(Container, Vec<Chunk>), TooLargeData> {
+        let chunk_size = chunk_size.clamp(CHUNK_TAG_LEN + 1, MAX_CHUNK_SIZE);
+        let piece_size = (chunk_size - CHUNK_TAG_LEN) as usize;
+        let count = (data.len() + piece_size - 1) / piece_size;
+        let chunks = data
+            .chunks(piece_size)
+            .enumerate()
+            .map(|(index, piece)| {
+                self.encrypt_chunk(index as u64, index + 1 == count, piece)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let ids = chunks.iter().map(Chunk::chunk_id).collect::<Vec<_>>();
+        let container = Container {
+            header: ContainerHeader {
+                version: CONTAINER_VERSION,
+                mime,
+                info: info.to_string(),
+                size: chunks.iter().map(|chunk| chunk.len() as u64).sum(),
+                chunk_size,
+            },
+            chunks: MediumVec::try_from(ids).map_err(|_| TooLargeData)?,
+        };
+        Ok((container, chunks))
+    }
+
+    /// Verifies and decrypts chunk with the given index within the
+    /// container, independently of the other container chunks.
+    pub fn decrypt_container_chunk(
+        &self,
+        container: &Container,
+        index: usize,
+        chunk: &Chunk,
+    ) -> Result<Vec<u8>, EncryptionError> {
+        let chunk_id = *container
+            .chunks
+            .get(index)
+            .ok_or(EncryptionError::UnknownChunk(index))?;
+        if chunk.chunk_id() != chunk_id {
+            return Err(EncryptionError::ChunkMismatch(chunk_id));
+        }
+        let last = index + 1 == container.chunks.len();
+        self.decrypt_chunk(index as u64, last, chunk)
+            .ok_or(EncryptionError::Decryption(index))
+    }
+}
+
+/// Key wrapping the container key to a specific recipient, derived with ECDH
+/// between ephemeral and recipient keys.
+#[derive(Wrapper, Copy, Clone, PartialEq, Eq, Hash, From)]
+pub struct WrappingKey([u8; 32]);
+
+impl std::fmt::Debug for WrappingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WrappingKey(..)")
+    }
+}
+
+impl WrappingKey {
+    /// Derives wrapping key from a local secret key and the remote public
+    /// key. Both sides of the key agreement derive the same key: the sender
+    /// from the ephemeral secret and the recipient public key, and the
+    /// recipient from its node secret and the ephemeral public key.
+    pub fn derive(
+        local: &SecretKey,
+        remote: &PublicKey,
+        ephemeral: &PublicKey,
+        recipient: &PublicKey,
+    ) -> WrappingKey {
+        let ecdh = SharedSecret::new(remote, local);
+        let mut engine = sha256::Hash::engine();
+        engine.input(b"storm:keywrap");
+        engine.input(&ecdh.secret_bytes());
+        engine.input(&ephemeral.serialize());
+        engine.input(&recipient.serialize());
+        WrappingKey(sha256::Hash::from_engine(engine).into_inner())
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+/// Container key wrapped to a recipient node key.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct WrappedKey {
+    /// Ephemeral public key of the sender used for the key agreement.
+    pub ephemeral: PublicKey,
+    /// Node key of the recipient.
+    pub recipient: PublicKey,
+    /// Encrypted container key with the authentication tag.
+    pub ciphertext: Vec<u8>,
+}
+
+impl WrappedKey {
+    /// Unwraps container key using the recipient node secret key.
+    pub fn unwrap<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        node_secret: &SecretKey,
+    ) -> Result<ContainerKey, EncryptionError> {
+        if PublicKey::from_secret_key(secp, node_secret) != self.recipient {
+            return Err(EncryptionError::RecipientMismatch);
+        }
+        let wrapping_key = WrappingKey::derive(
+            node_secret,
+            &self.ephemeral,
+            &self.ephemeral,
+            &self.recipient,
+        );
+        let data = wrapping_key
+            .cipher()
+            .decrypt(Nonce::from_slice(&[0u8; 12]), self.ciphertext.as_ref())
+            .map_err(|_| EncryptionError::Unwrap)?;
+        let mut key = [0u8; 32];
+        if data.len() != key.len() {
+            return Err(EncryptionError::Unwrap);
+        }
+        key.copy_from_slice(&data);
+        Ok(ContainerKey(key))
+    }
+}
+
+impl ContainerKey {
+    /// Wraps the key to the recipient node key. The ephemeral secret key
+    /// must be freshly generated and never reused.
+    pub fn wrap<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        recipient: PublicKey,
+        ephemeral_secret: &SecretKey,
+    ) -> WrappedKey {
+        let ephemeral = PublicKey::from_secret_key(secp, ephemeral_secret);
+        let wrapping_key = WrappingKey::derive(
+            ephemeral_secret,
+            &recipient,
+            &ephemeral,
+            &recipient,
+        );
+        let ciphertext = wrapping_key
+            .cipher()
+            .encrypt(Nonce::from_slice(&[0u8; 12]), self.0.as_ref())
+            .expect("chacha20poly1305 encryption of in-memory data");
+        WrappedKey {
+            ephemeral,
+            recipient,
+            ciphertext,
+        }
+    }
+}
+
+/// Container key wrapped with the key of a specific epoch.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct KeyRotation {
+    pub container_id: ContainerId,
+    pub epoch: u32,
+    /// Container key encrypted with the epoch key.
+    pub wrapped: Vec<u8>,
+}
+
+impl KeyRotation {
+    /// Derives key wrapping the container key in the given epoch. The
+    /// derived key is unique for each container and epoch, so it is used
+    /// with zero nonce.
+    fn wrapping_key(
+        epoch_key: &ContainerKey,
+        container_id: ContainerId,
+        epoch: u32,
+    ) -> ContainerKey {
+        let mut engine = sha256t::Hash::<KeyRotationTag>::engine();
+        engine.input(&epoch_key.0);
+        container_id
+            .strict_encode(&mut engine)
+            .expect("memory encoders do not fail");
+        epoch.strict_encode(&mut engine).expect("memory encoders do not fail");
+        ContainerKey(
+            sha256t::Hash::<KeyRotationTag>::from_engine(engine).into_inner(),
+        )
+    }
+
+    /// Wraps container key with the epoch key.
+    pub fn with(
+        container_id: ContainerId,
+        epoch: u32,
+        container_key: &ContainerKey,
+        epoch_key: &ContainerKey,
+    ) -> KeyRotation {
+        let wrapped = Self::wrapping_key(epoch_key, container_id, epoch)
+            .cipher()
+            .encrypt(Nonce::from_slice(&[0u8; 12]), container_key.0.as_ref())
+            .expect("chacha20poly1305 encryption of in-memory data");
+        KeyRotation {
+            container_id,
+            epoch,
+            wrapped,
+        }
+    }
+
+    /// Unwraps container key with the epoch key.
+    pub fn unwrap(
+        &self,
+        epoch_key: &ContainerKey,
+    ) -> Result<ContainerKey, EncryptionError> {
+        let data = Self::wrapping_key(epoch_key, self.container_id, self.epoch)
+            .cipher()
+            .decrypt(Nonce::from_slice(&[0u8; 12]), self.wrapped.as_ref())
+            .map_err(|_| EncryptionError::Unwrap)?;
+        let mut key = [0u8; 32];
+        if data.len() != key.len() {
+            return Err(EncryptionError::Unwrap);
+        }
+        key.copy_from_slice(&data);
+        Ok(ContainerKey(key))
+    }
+}
+
+/// History of key rotations of a container.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct KeyRotations {
+    container_id: ContainerId,
+    rotations: BTreeMap<u32, KeyRotation>,
+}
+
+impl KeyRotations {
+    pub fn new(container_id: ContainerId) -> KeyRotations {
+        KeyRotations {
+            container_id,
+            rotations: empty!(),
+        }
+    }
+
+    pub fn container_id(&self) -> ContainerId { self.container_id }
+
+    /// Latest known epoch, if any.
+    pub fn latest_epoch(&self) -> Option<u32> {
+        self.rotations.keys().next_back().copied()
+    }
+
+    /// Adds rotation record received from the network. Records are not
+    /// authenticated, so a known record is never replaced with a different
+    /// one for the same epoch.
+    pub fn insert(
+        &mut self,
+        rotation: KeyRotation,
+    ) -> Result<(), EncryptionError> {
+        if rotation.container_id != self.container_id {
+            return Err(EncryptionError::ContainerMismatch(
+                rotation.container_id,
+            ));
+        }
+        match self.rotations.get(&rotation.epoch) {
+            Some(known) if *known != rotation => {
+                Err(EncryptionError::EpochConflict(rotation.epoch))
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.rotations.insert(rotation.epoch, rotation);
+                Ok(())
+            }
+        }
+    }
+
+    /// Starts a new key epoch, wrapping container key with the new epoch key.
+    /// The returned record has to be published to the readers.
+    ///
+    /// Fails if the latest epoch is [`u32::MAX`].
+    pub fn rotate(
+        &mut self,
+        container_key: &ContainerKey,
+        epoch_key: &ContainerKey,
+    ) -> Result<KeyRotation, EncryptionError> {
+        let epoch = match self.latest_epoch() {
+            None => 0,
+            Some(latest) => latest
+                .checked_add(1)
+                .ok_or(EncryptionError::EpochOverflow(latest))?,
+        };
+        let rotation = KeyRotation::with(
+            self.container_id,
+            epoch,
+            container_key,
+            epoch_key,
+        );
+        self.rotations.insert(epoch, rotation.clone());
+        Ok(rotation)
+    }
+
+    /// Checks whether a reader holding key of the given epoch has to obtain
+    /// a newer epoch key.
+    pub fn is_stale(&self, epoch: u32) -> bool {
+        self.latest_epoch().map(|latest| latest > epoch).unwrap_or_default()
+    }
+
+    /// Resolves container key using the most recent of the epoch keys held by
+    /// the reader.
+    pub fn resolve(
+        &self,
+        epoch_keys: &BTreeMap<u32, ContainerKey>,
+    ) -> Result<ContainerKey, EncryptionError> {
+        let mut err = EncryptionError::UnknownEpoch(
+            epoch_keys.keys().next_back().copied().unwrap_or_default(),
+        );
+        for (epoch, epoch_key) in epoch_keys.iter().rev() {
+            match self.rotations.get(epoch) {
+                None => err = EncryptionError::UnknownEpoch(*epoch),
+                Some(rotation) => match rotation.unwrap(epoch_key) {
+                    Ok(key) => return Ok(key),
+                    Err(e) => err = e,
+                },
+            }
+        }
+        Err(err)
+    }
+}
+
+/// Group key of a topic wrapped to each of the group members.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct KeyEnvelope {
+    pub topic_id: MesgId,
+    /// Key epoch, incremented with each change of the members.
+    pub epoch: u32,
+    pub keys: BTreeMap<PublicKey, WrappedKey>,
+}
+
+impl KeyEnvelope {
+    /// Wraps group key to each of the members. Since wrapping keys differ
+    /// for each of the members, the same ephemeral key may be used for all
+    /// of them; it must not be reused for other envelopes.
+    pub fn seal<C: Signing>(
+        secp: &Secp256k1<C>,
+        topic_id: MesgId,
+        epoch: u32,
+        group_key: &ContainerKey,
+        members: impl IntoIterator<Item = PublicKey>,
+        ephemeral_secret: &SecretKey,
+    ) -> KeyEnvelope {
+        let keys = members
+            .into_iter()
+            .map(|member| {
+                (member, group_key.wrap(secp, member, ephemeral_secret))
+            })
+            .collect();
+        KeyEnvelope {
+            topic_id,
+            epoch,
+            keys,
+        }
+    }
+
+    pub fn members(&self) -> BTreeSet<PublicKey> {
+        self.keys.keys().copied().collect()
+    }
+
+    pub fn is_member(&self, node_id: PublicKey) -> bool {
+        self.keys.contains_key(&node_id)
+    }
+
+    /// Extracts group key using the member node secret key.
+    pub fn open<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        node_secret: &SecretKey,
+    ) -> Result<ContainerKey, EncryptionError> {
+        let node_id = PublicKey::from_secret_key(secp, node_secret);
+        self.keys
+            .get(&node_id)
+            .ok_or(EncryptionError::NotMember(node_id))?
+            .unwrap(secp, node_secret)
+    }
+
+    /// Re-keys the group adding new member, producing envelope for the next
+    /// epoch with the new group key.
+    pub fn add_member<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        member: PublicKey,
+        new_key: &ContainerKey,
+        ephemeral_secret: &SecretKey,
+    ) -> Result<KeyEnvelope, EncryptionError> {
+        let mut members = self.members();
+        members.insert(member);
+        self.rekey(secp, members, new_key, ephemeral_secret)
+    }
+
+    /// Re-keys the group removing the member, producing envelope for the
+    /// next epoch with the new group key.
+    pub fn remove_member<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        member: PublicKey,
+        new_key: &ContainerKey,
+        ephemeral_secret: &SecretKey,
+    ) -> Result<KeyEnvelope, EncryptionError> {
+        let mut members = self.members();
+        if !members.remove(&member) {
+            return Err(EncryptionError::NotMember(member));
+        }
+        self.rekey(secp, members, new_key, ephemeral_secret)
+    }
+
+    /// Produces envelope for the next epoch wrapping new group key to the
+    /// members. Fails if the envelope epoch is [`u32::MAX`].
+    pub fn rekey<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        members: impl IntoIterator<Item = PublicKey>,
+        new_key: &ContainerKey,
+        ephemeral_secret: &SecretKey,
+    ) -> Result<KeyEnvelope, EncryptionError> {
+        let epoch = self
+            .epoch
+            .checked_add(1)
+            .ok_or(EncryptionError::EpochOverflow(self.epoch))?;
+        Ok(KeyEnvelope::seal(
+            secp,
+            self.topic_id,
+            epoch,
+            new_key,
+            members,
+            ephemeral_secret,
+        ))
+    }
+}
+
+/// Plaintext of a sealed post.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+struct SealedPayload {
+    post: Post,
+    attachment_keys: BTreeMap<ContainerId, ContainerKey>,
+}
+
+/// Post encrypted once and addressed to multiple recipients, each of which
+/// receives the post key wrapped to its node key.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SealedPost {
+    pub recipients: BTreeMap<PublicKey, WrappedKey>,
+    /// Post and attachment keys encrypted with the post key.
+    pub ciphertext: MediumVec<u8>,
+}
+
+impl SealedPost {
+    /// Encrypts post together with the keys of its encrypted attachments
+    /// and wraps the post key to each of the recipients. Both the post key
+    /// and the ephemeral key must be freshly generated and never reused.
+    ///
+    /// Fails if the encrypted post does not fit into [`MAX_CHUNK_SIZE`]
+    /// bytes.
+    pub fn seal<C: Signing>(
+        secp: &Secp256k1<C>,
+        post: Post,
+        attachment_keys: BTreeMap<ContainerId, ContainerKey>,
+        post_key: &ContainerKey,
+        recipients: impl IntoIterator<Item = PublicKey>,
+        ephemeral_secret: &SecretKey,
+    ) -> Result<SealedPost, TooLargeData> {
+        let payload = SealedPayload {
+            post,
+            attachment_keys,
+        };
+        let plaintext = payload
+            .strict_serialize()
+            .expect("strict encoding of in-memory data");
+        let ciphertext = post_key
+            .cipher()
+            .encrypt(Nonce::from_slice(&[0u8; 12]), plaintext.as_ref())
+            .expect("chacha20poly1305 encryption of in-memory data");
+        let ciphertext =
+            MediumVec::try_from(ciphertext).map_err(|_| TooLargeData)?;
+        let recipients = recipients
+            .into_iter()
+            .map(|recipient| {
+                (recipient, post_key.wrap(secp, recipient, ephemeral_secret))
+            })
+            .collect();
+        Ok(SealedPost {
+            recipients,
+            ciphertext,
+        })
+    }
+
+    pub fn recipients(&self) -> BTreeSet<PublicKey> {
+        self.recipients.keys().copied().collect()
+    }
+
+    pub fn is_recipient(&self, node_id: PublicKey) -> bool {
+        self.recipients.contains_key(&node_id)
+    }
+
+    /// Decrypts post using the recipient node secret key, returning the post
+    /// and the keys of its encrypted attachments.
+    pub fn open<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        node_secret: &SecretKey,
+    ) -> Result<(Post, BTreeMap<ContainerId, ContainerKey>), EncryptionError>
+    {
+        let node_id = PublicKey::from_secret_key(secp, node_secret);
+        let post_key = self
+            .recipients
+            .get(&node_id)
+            .ok_or(EncryptionError::NotRecipient(node_id))?
+            .unwrap(secp, node_secret)?;
+        let plaintext = post_key
+            .cipher()
+            .decrypt(Nonce::from_slice(&[0u8; 12]), self.ciphertext.as_ref())
+            .map_err(|_| EncryptionError::PostDecryption)?;
+        let payload = SealedPayload::strict_deserialize(plaintext)
+            .map_err(|_| EncryptionError::PostDecryption)?;
+        Ok((payload.post, payload.attachment_keys))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::tagged_hash;
+
+    use super::*;
+
+    #[test]
+    fn test_key_rotation_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:keyrotation");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_KEY_ROTATION);
+    }
+
+    #[test]
+    fn test_encrypt_container() {
+        let key = ContainerKey::from([3u8; 32]);
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, chunks) =
+            key.encrypt_container(AsciiString::new(), "", &data, 64).unwrap();
+        assert_eq!(chunks.len(), 5);
+        assert!(container.is_chunk_size_valid());
+
+        // Chunks are decrypted out of order
+        let mut decrypted = vec![vec![]; chunks.len()];
+        for index in (0..chunks.len()).rev() {
+            decrypted[index] = key
+                .decrypt_container_chunk(&container, index, &chunks[index])
+                .unwrap();
+        }
+        assert_eq!(decrypted.concat(), data);
+
+        // Truncated container does not decrypt
+        assert_eq!(key.decrypt_chunk(3, true, &chunks[3]), None);
+        assert_eq!(
+            ContainerKey::from([4u8; 32])
+                .decrypt_container_chunk(&container, 0, &chunks[0]),
+            Err(EncryptionError::Decryption(0))
+        );
+    }
+
+    #[test]
+    fn test_key_wrapping() {
+        let secp = Secp256k1::new();
+        let node_secret = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let other_secret = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let ephemeral = SecretKey::from_slice(&[0x33; 32]).unwrap();
+        let recipient = PublicKey::from_secret_key(&secp, &node_secret);
+
+        let key = ContainerKey::from([5u8; 32]);
+        let wrapped = key.wrap(&secp, recipient, &ephemeral);
+        assert_eq!(wrapped.unwrap(&secp, &node_secret), Ok(key));
+        assert_eq!(
+            wrapped.unwrap(&secp, &other_secret),
+            Err(EncryptionError::RecipientMismatch)
+        );
+
+        let mut forged = wrapped;
+        forged.ephemeral = recipient;
+        assert_eq!(
+            forged.unwrap(&secp, &node_secret),
+            Err(EncryptionError::Unwrap)
+        );
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let key = ContainerKey::from([5u8; 32]);
+        let (container, _) =
+            key.encrypt_container(AsciiString::new(), "", b"data", 64).unwrap();
+        let old_epoch = ContainerKey::from([6u8; 32]);
+        let new_epoch = ContainerKey::from([7u8; 32]);
+
+        let mut rotations = KeyRotations::new(container.container_id());
+        rotations.rotate(&key, &old_epoch).unwrap();
+        let rotation = rotations.rotate(&key, &new_epoch).unwrap();
+        assert_eq!(rotation.epoch, 1);
+        assert_eq!(rotation.unwrap(&new_epoch), Ok(key));
+
+        let mut reader = KeyRotations::new(container.container_id());
+        reader.insert(rotations.rotations[&0].clone()).unwrap();
+        let old_keys = bmap! { 0 => old_epoch };
+        assert_eq!(reader.resolve(&old_keys), Ok(key));
+        reader.insert(rotation).unwrap();
+        assert!(reader.is_stale(0));
+        assert!(!reader.is_stale(1));
+        assert_eq!(
+            reader.resolve(&bmap! { 2 => new_epoch }),
+            Err(EncryptionError::UnknownEpoch(2))
+        );
+
+        // Known record is not replaced with a forged one
+        let forged = KeyRotation::with(
+            container.container_id(),
+            1,
+            &ContainerKey::from([8u8; 32]),
+            &new_epoch,
+        );
+        assert_eq!(
+            reader.insert(forged),
+            Err(EncryptionError::EpochConflict(1))
+        );
+        assert_eq!(reader.resolve(&bmap! { 1 => new_epoch }), Ok(key));
+        reader.insert(rotations.rotations[&1].clone()).unwrap();
+
+        // Epoch counter does not wrap around
+        let last = KeyRotation::with(
+            container.container_id(),
+            u32::MAX,
+            &key,
+            &new_epoch,
+        );
+        reader.insert(last).unwrap();
+        assert_eq!(
+            reader.rotate(&key, &new_epoch),
+            Err(EncryptionError::EpochOverflow(u32::MAX))
+        );
+        assert_eq!(reader.rotations.len(), 3);
+        assert_eq!(reader.resolve(&bmap! { 0 => old_epoch }), Ok(key));
+    }
+
+    #[test]
+    fn test_shared_epoch_key() {
+        let epoch_key = ContainerKey::from([9u8; 32]);
+        let key1 = ContainerKey::from([1u8; 32]);
+        let key2 = ContainerKey::from([2u8; 32]);
+        let (container1, _) =
+            key1.encrypt_container(AsciiString::new(), "", b"one", 64).unwrap();
+        let (container2, _) =
+            key2.encrypt_container(AsciiString::new(), "", b"two", 64).unwrap();
+
+        let rotation1 =
+            KeyRotation::with(container1.container_id(), 0, &key1, &epoch_key);
+        let rotation2 =
+            KeyRotation::with(container2.container_id(), 0, &key2, &epoch_key);
+        assert_eq!(rotation1.unwrap(&epoch_key), Ok(key1));
+        assert_eq!(rotation2.unwrap(&epoch_key), Ok(key2));
+
+        // Keys of different containers are wrapped with different keys, so
+        // XOR of the ciphertexts does not reveal XOR of the container keys
+        let xor = rotation1
+            .wrapped
+            .iter()
+            .zip(&rotation2.wrapped)
+            .map(|(a, b)| a ^ b)
+            .take(32)
+            .collect::<Vec<_>>();
+        assert_ne!(xor, vec![1u8 ^ 2; 32]);
+
+        // Record can't be re-bound to another container or epoch
+        let mut forged = rotation1.clone();
+        forged.container_id = container2.container_id();
+        assert_eq!(forged.unwrap(&epoch_key), Err(EncryptionError::Unwrap));
+        let mut forged = rotation1;
+        forged.epoch = 1;
+        assert_eq!(forged.unwrap(&epoch_key), Err(EncryptionError::Unwrap));
+    }
+
+    #[test]
+    fn test_key_envelope() {
+        let secp = Secp256k1::new();
+        let alice_secret = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let bob_secret = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let alice = PublicKey::from_secret_key(&secp, &alice_secret);
+        let bob = PublicKey::from_secret_key(&secp, &bob_secret);
+        let ephemeral = SecretKey::from_slice(&[0x33; 32]).unwrap();
+
+        let key = ContainerKey::from([1u8; 32]);
+        let envelope = KeyEnvelope::seal(
+            &secp,
+            MesgId::default(),
+            0,
+            &key,
+            [alice, bob],
+            &ephemeral,
+        );
+        assert_eq!(envelope.open(&secp, &bob_secret), Ok(key));
+
+        let new_key = ContainerKey::from([2u8; 32]);
+        let ephemeral = SecretKey::from_slice(&[0x44; 32]).unwrap();
+        let envelope =
+            envelope.remove_member(&secp, bob, &new_key, &ephemeral).unwrap();
+        assert_eq!(envelope.epoch, 1);
+        assert_eq!(envelope.open(&secp, &alice_secret), Ok(new_key));
+        assert_eq!(
+            envelope.open(&secp, &bob_secret),
+            Err(EncryptionError::NotMember(bob))
+        );
+
+        let mut last = envelope;
+        last.epoch = u32::MAX;
+        assert_eq!(
+            last.add_member(&secp, bob, &key, &ephemeral),
+            Err(EncryptionError::EpochOverflow(u32::MAX))
+        );
+    }
+
+    #[test]
+    fn test_sealed_post() {
+        let secp = Secp256k1::new();
+        let alice_secret = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let bob_secret = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let carol_secret = SecretKey::from_slice(&[0x33; 32]).unwrap();
+        let alice = PublicKey::from_secret_key(&secp, &alice_secret);
+        let bob = PublicKey::from_secret_key(&secp, &bob_secret);
+        let carol = PublicKey::from_secret_key(&secp, &carol_secret);
+        let ephemeral = SecretKey::from_slice(&[0x44; 32]).unwrap();
+
+        let attachment_key = ContainerKey::from([1u8; 32]);
+        let (container, _) = attachment_key
+            .encrypt_container(AsciiString::new(), "", b"photo", 64)
+            .unwrap();
+        let post = Post::from(crate::Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"hello".to_vec()).unwrap(),
+            container_ids: vec![container.container_id()],
+        });
+        let attachment_keys =
+            bmap! { container.container_id() => attachment_key };
+        let sealed = SealedPost::seal(
+            &secp,
+            post.clone(),
+            attachment_keys.clone(),
+            &ContainerKey::from([2u8; 32]),
+            [alice, bob],
+            &ephemeral,
+        )
+        .unwrap();
+        assert_eq!(sealed.recipients(), bset! { alice, bob });
+        assert_eq!(
+            sealed.open(&secp, &alice_secret),
+            Ok((post.clone(), attachment_keys.clone()))
+        );
+        assert_eq!(
+            sealed.open(&secp, &bob_secret),
+            Ok((post, attachment_keys))
+        );
+        assert_eq!(
+            sealed.open(&secp, &carol_secret),
+            Err(EncryptionError::NotRecipient(carol))
+        );
+    }
+
+    #[test]
+    fn test_large_sealed_post() {
+        let secp = Secp256k1::new();
+        let alice_secret = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let alice = PublicKey::from_secret_key(&secp, &alice_secret);
+        let ephemeral = SecretKey::from_slice(&[0x44; 32]).unwrap();
+
+        let body = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let post = Post::from(crate::Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(body).unwrap(),
+            container_ids: vec![],
+        });
+        let sealed = SealedPost::seal(
+            &secp,
+            post.clone(),
+            empty!(),
+            &ContainerKey::from([2u8; 32]),
+            [alice],
+            &ephemeral,
+        )
+        .unwrap();
+        assert!(sealed.ciphertext.len() > 1 << 16);
+
+        let data = sealed.strict_serialize().unwrap();
+        let decoded = SealedPost::strict_deserialize(&data).unwrap();
+        assert_eq!(decoded, sealed);
+        assert_eq!(decoded.open(&secp, &alice_secret), Ok((post, empty!())));
+    }
+}
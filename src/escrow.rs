@@ -0,0 +1,246 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! References to on-chain or lightning funds collateralizing storage leases.
+//!
+//! Storm does not track blockchain or channel state: the types here only
+//! link a lease to its collateral and check that the reference is
+//! consistent with the lease terms. Whether the funds actually exist must be
+//! verified by the higher layers.
+
+use bitcoin_hashes::sha256d;
+use secp256k1::PublicKey;
+
+use crate::signed::{Signable, Signed};
+use crate::storage::{LeaseTerms, StorageReceipt};
+use crate::{ContainerId, PaymentHash};
+
+/// Errors in escrow references.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum EscrowError {
+    /// escrow refers to a different lease.
+    LeaseMismatch,
+
+    /// escrow funds of {funded_msat} msat do not cover lease fee of
+    /// {required_msat} msat.
+    InsufficientFunding {
+        required_msat: u64,
+        funded_msat: u64,
+    },
+
+    /// funding script descriptor is not a 2-of-2 multisig descriptor.
+    InvalidDescriptor,
+
+    /// funding script descriptor does not contain key {0}.
+    MissingKey(PublicKey),
+}
+
+/// Reference to a bitcoin transaction output.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{txid}:{vout}")]
+pub struct OutPoint {
+    pub txid: sha256d::Hash,
+    pub vout: u32,
+}
+
+/// Funds collateralizing a storage lease.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum EscrowFunding {
+    /// Output locked with 2-of-2 multisig of the client and the provider.
+    #[display("multisig({outpoint}, {amount_sat} sat)")]
+    Multisig {
+        outpoint: OutPoint,
+        amount_sat: u64,
+        /// Output script descriptor, like `wsh(multi(2,<key1>,<key2>))`.
+        descriptor: String,
+    },
+
+    /// HTLC in a lightning channel between the client and the provider.
+    #[display("htlc({short_channel_id}, {amount_msat} msat)")]
+    Htlc {
+        short_channel_id: u64,
+        payment_hash: PaymentHash,
+        amount_msat: u64,
+        /// Absolute block height at which the HTLC times out.
+        cltv_expiry: u32,
+    },
+}
+
+impl EscrowFunding {
+    /// Amount of the escrowed funds, in millisatoshis.
+    pub fn amount_msat(&self) -> u64 {
+        match self {
+            EscrowFunding::Multisig { amount_sat, .. } => {
+                amount_sat.saturating_mul(1000)
+            }
+            EscrowFunding::Htlc { amount_msat, .. } => *amount_msat,
+        }
+    }
+
+    fn check_descriptor(
+        descriptor: &str,
+        keys: [PublicKey; 2],
+    ) -> Result<(), EscrowError> {
+        let args = ["wsh(multi(2,", "wsh(sortedmulti(2,"]
+            .iter()
+            .find_map(|prefix| descriptor.strip_prefix(prefix))
+            .and_then(|rest| rest.strip_suffix("))"))
+            .ok_or(EscrowError::InvalidDescriptor)?;
+        let descriptor_keys = args.split(',').collect::<Vec<_>>();
+        if descriptor_keys.len() != 2 {
+            return Err(EscrowError::InvalidDescriptor);
+        }
+        for key in keys {
+            if !descriptor_keys.contains(&key.to_string().as_str()) {
+                return Err(EscrowError::MissingKey(key));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reference linking a storage lease to the funds collateralizing it.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {lease}, {funding}")]
+pub struct EscrowRef {
+    pub container_id: ContainerId,
+    pub client: PublicKey,
+    pub provider: PublicKey,
+    pub lease: LeaseTerms,
+    pub funding: EscrowFunding,
+}
+
+impl Signable for EscrowRef {
+    const SIGNED_TYPE: u8 = 19;
+}
+
+impl EscrowRef {
+    /// Constructs escrow reference for the lease from the provider receipt.
+    pub fn with(
+        receipt: &Signed<StorageReceipt>,
+        funding: EscrowFunding,
+    ) -> Self {
+        EscrowRef {
+            container_id: receipt.data.container_id,
+            client: receipt.data.client,
+            provider: receipt.signer,
+            lease: receipt.data.lease,
+            funding,
+        }
+    }
+
+    /// Checks that the escrow refers to the lease from the provider receipt,
+    /// that the escrowed funds cover the lease fee and that multisig funds
+    /// are locked to the keys of both parties.
+    pub fn check(
+        &self,
+        receipt: &Signed<StorageReceipt>,
+    ) -> Result<(), EscrowError> {
+        if self.container_id != receipt.data.container_id
+            || self.client != receipt.data.client
+            || self.provider != receipt.signer
+            || self.lease != receipt.data.lease
+        {
+            return Err(EscrowError::LeaseMismatch);
+        }
+        let funded_msat = self.funding.amount_msat();
+        if funded_msat < self.lease.fee_msat {
+            return Err(EscrowError::InsufficientFunding {
+                required_msat: self.lease.fee_msat,
+                funded_msat,
+            });
+        }
+        if let EscrowFunding::Multisig { descriptor, .. } = &self.funding {
+            EscrowFunding::check_descriptor(descriptor, [
+                self.client,
+                self.provider,
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+    use crate::varint::VarInt;
+
+    #[test]
+    fn test_escrow() {
+        let secp = Secp256k1::new();
+        let client_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let provider_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let client = PublicKey::from_secret_key(&secp, &client_key);
+        let provider = PublicKey::from_secret_key(&secp, &provider_key);
+        let receipt = StorageReceipt {
+            client,
+            container_id: ContainerId::default(),
+            size: VarInt::from(1024u64),
+            lease: LeaseTerms {
+                start: 1000,
+                expiry: 2000,
+                fee_msat: 10_000,
+                replicas: 1,
+            },
+            timestamp: 1000,
+        };
+        let receipt = Signed::sign(&secp, receipt, &provider_key);
+
+        let funding =
+            |descriptor: String, amount_sat| EscrowFunding::Multisig {
+                outpoint: OutPoint {
+                    txid: default!(),
+                    vout: 0,
+                },
+                amount_sat,
+                descriptor,
+            };
+        let descriptor = format!("wsh(multi(2,{},{}))", client, provider);
+        let escrow = EscrowRef::with(&receipt, funding(descriptor.clone(), 10));
+        assert_eq!(escrow.check(&receipt), Ok(()));
+
+        let escrow = EscrowRef::with(&receipt, funding(descriptor, 5));
+        assert_eq!(
+            escrow.check(&receipt),
+            Err(EscrowError::InsufficientFunding {
+                required_msat: 10_000,
+                funded_msat: 5_000
+            })
+        );
+
+        let descriptor = format!("wsh(multi(2,{},{}))", client, client);
+        let escrow = EscrowRef::with(&receipt, funding(descriptor, 10));
+        assert_eq!(
+            escrow.check(&receipt),
+            Err(EscrowError::MissingKey(provider))
+        );
+    }
+}
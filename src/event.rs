@@ -0,0 +1,106 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use crate::{ContainerId, PaymentHash, StormApp};
+
+/// Protocol state change, emitted by storm state machines and suitable for
+/// persisting as an audit trail.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum StormEvent {
+    /// Container was announced to remote peers.
+    #[display("container_announced({app}, {container_id})")]
+    ContainerAnnounced {
+        app: StormApp,
+        container_id: ContainerId,
+    },
+
+    /// Transfer of container chunks has started.
+    #[display("transfer_started({app}, {container_id}, {chunks} chunks)")]
+    TransferStarted {
+        app: StormApp,
+        container_id: ContainerId,
+        chunks: u32,
+    },
+
+    /// All container chunks were transferred.
+    #[display("transfer_completed({app}, {container_id})")]
+    TransferCompleted {
+        app: StormApp,
+        container_id: ContainerId,
+    },
+
+    /// Access to the container data was granted to the remote peer upon
+    /// payment.
+    #[display("access_granted({app}, {container_id}, {payment_hash})")]
+    AccessGranted {
+        app: StormApp,
+        container_id: ContainerId,
+        payment_hash: PaymentHash,
+    },
+}
+
+impl StormEvent {
+    pub fn storm_app(&self) -> StormApp {
+        match self {
+            StormEvent::ContainerAnnounced { app, .. }
+            | StormEvent::TransferStarted { app, .. }
+            | StormEvent::TransferCompleted { app, .. }
+            | StormEvent::AccessGranted { app, .. } => *app,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin_hashes::Hash;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let container_id = ContainerId::default();
+        let events = [
+            StormEvent::ContainerAnnounced {
+                app: StormApp::Chat,
+                container_id,
+            },
+            StormEvent::TransferStarted {
+                app: StormApp::Storage,
+                container_id,
+                chunks: 3,
+            },
+            StormEvent::TransferCompleted {
+                app: StormApp::Storage,
+                container_id,
+            },
+            StormEvent::AccessGranted {
+                app: StormApp::Storage,
+                container_id,
+                payment_hash: PaymentHash::hash(b"preimage"),
+            },
+        ];
+        assert_eq!(events[0].storm_app(), StormApp::Chat);
+        assert_eq!(
+            events[1].to_string(),
+            format!("transfer_started(storage, {}, 3 chunks)", container_id)
+        );
+        for event in events {
+            let data = event.strict_serialize().unwrap();
+            assert_eq!(StormEvent::strict_deserialize(data).unwrap(), event);
+        }
+    }
+}
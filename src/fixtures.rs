@@ -0,0 +1,42 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Fixtures shared by the unit tests.
+
+use std::str::FromStr;
+
+use secp256k1::PublicKey;
+
+/// Node id of the first test peer.
+pub fn alice() -> PublicKey {
+    PublicKey::from_str(
+        "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+    )
+    .unwrap()
+}
+
+/// Node id of the second test peer.
+pub fn bob() -> PublicKey {
+    PublicKey::from_str(
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+    )
+    .unwrap()
+}
+
+/// Pseudo-random data of the given length, deterministic for each `seed`.
+pub fn sample(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
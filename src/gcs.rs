@@ -0,0 +1,221 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Golomb-coded set filters (following BIP-158 construction) summarizing
+//! containers a node is willing to serve.
+
+use bitcoin_hashes::siphash24;
+use strict_encoding::MediumVec;
+
+use crate::chunk::TooLargeData;
+use crate::ContainerId;
+
+/// Golomb-Rice coding parameter (number of remainder bits).
+pub const GCS_P: u8 = 19;
+
+/// Inverse false-positive rate of the filter.
+pub const GCS_M: u64 = 784931;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: vec![],
+            used: 8,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.used == 8 {
+            self.bytes.push(0);
+            self.used = 0;
+        }
+        if bit {
+            *self.bytes.last_mut().expect("byte is always pushed") |=
+                0x80 >> self.used;
+        }
+        self.used += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, bits: u8) {
+        for i in (0..bits).rev() {
+            self.write_bit(value & (1 << i) != 0);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self { BitReader { bytes, pos: 0 } }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.pos / 8)?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, bits: u8) -> Option<u64> {
+        let mut value = 0u64;
+        for _ in 0..bits {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    fn read_golomb(&mut self) -> Option<u64> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let remainder = self.read_bits(GCS_P)?;
+        Some((quotient << GCS_P) + remainder)
+    }
+}
+
+/// Golomb-coded set filter over container ids.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ContainerFilter {
+    /// SipHash key used for hashing container ids.
+    key: [u8; 16],
+    /// Number of items in the filter.
+    count: u32,
+    /// Golomb-Rice encoded sorted hash deltas.
+    data: MediumVec<u8>,
+}
+
+impl ContainerFilter {
+    fn keys(&self) -> (u64, u64) {
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&self.key[..8]);
+        k1.copy_from_slice(&self.key[8..]);
+        (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+    }
+
+    fn hash(&self, container_id: ContainerId) -> u64 {
+        let (k0, k1) = self.keys();
+        let range = self.count as u128 * GCS_M as u128;
+        let hash =
+            siphash24::Hash::hash_to_u64_with_keys(k0, k1, &container_id[..]);
+        ((hash as u128 * range) >> 64) as u64
+    }
+
+    /// Constructs filter containing all of the provided container ids, using
+    /// the `key` for the hashing.
+    pub fn with(
+        key: [u8; 16],
+        container_ids: impl IntoIterator<Item = ContainerId>,
+    ) -> Result<ContainerFilter, TooLargeData> {
+        let container_ids = container_ids.into_iter().collect::<Vec<_>>();
+        let mut filter = ContainerFilter {
+            key,
+            count: container_ids.len() as u32,
+            data: default!(),
+        };
+        let mut hashes = container_ids
+            .into_iter()
+            .map(|id| filter.hash(id))
+            .collect::<Vec<_>>();
+        hashes.sort_unstable();
+        hashes.dedup();
+
+        let mut writer = BitWriter::new();
+        let mut last = 0u64;
+        for hash in hashes {
+            let delta = hash - last;
+            last = hash;
+            for _ in 0..(delta >> GCS_P) {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta, GCS_P);
+        }
+        filter.data =
+            MediumVec::try_from(writer.bytes).map_err(|_| TooLargeData)?;
+        Ok(filter)
+    }
+
+    /// Number of containers in the filter.
+    pub fn count(&self) -> u32 { self.count }
+
+    /// Checks whether container may be present in the filter. Returns
+    /// `false` only if the container is definitely absent.
+    pub fn matches(&self, container_id: ContainerId) -> bool {
+        self.matches_any([container_id])
+    }
+
+    /// Checks whether any of the containers may be present in the filter.
+    pub fn matches_any(
+        &self,
+        container_ids: impl IntoIterator<Item = ContainerId>,
+    ) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let mut queries = container_ids
+            .into_iter()
+            .map(|id| self.hash(id))
+            .collect::<Vec<_>>();
+        queries.sort_unstable();
+
+        let mut reader = BitReader::new(self.data.as_ref());
+        let mut value = match reader.read_golomb() {
+            Some(value) => value,
+            None => return false,
+        };
+        for query in queries {
+            while value < query {
+                match reader.read_golomb() {
+                    Some(delta) => value += delta,
+                    None => return false,
+                }
+            }
+            if value == query {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::CommitVerify;
+
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let ids = (0u32..100)
+            .map(|no| ContainerId::commit(&no.to_be_bytes()))
+            .collect::<Vec<_>>();
+        let filter = ContainerFilter::with([1; 16], ids.clone()).unwrap();
+        assert!(ids.iter().all(|id| filter.matches(*id)));
+        let others = (100u32..200)
+            .map(|no| ContainerId::commit(&no.to_be_bytes()))
+            .collect::<Vec<_>>();
+        assert!(!filter.matches_any(others));
+    }
+}
@@ -0,0 +1,154 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Hash-locked chunk delivery: chunk data are encrypted with a key which is
+//! the preimage of a lightning payment hash, such that the receiver is able
+//! to decrypt them only after the payment is settled.
+
+use bitcoin_hashes::{sha256, Hash};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use strict_encoding::MediumVec;
+
+use crate::chunk::TooLargeData;
+use crate::{Chunk, ChunkId};
+
+/// Lightning payment hash locking chunk data.
+pub type PaymentHash = sha256::Hash;
+
+/// Errors happening during unlocking of the hash-locked chunk data.
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum HashLockError {
+    /// preimage does not match payment hash {expected}.
+    PreimageMismatch { expected: PaymentHash },
+
+    /// unable to decrypt locked chunk with the provided preimage.
+    Decryption,
+
+    /// decrypted chunk id {actual} does not match expected {expected}.
+    ChunkIdMismatch { expected: ChunkId, actual: ChunkId },
+}
+
+/// Preimage of a lightning payment hash, which is used as a symmetric key
+/// for encrypting chunk data.
+#[derive(
+    Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, From
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct PaymentPreimage([u8; 32]);
+
+impl PaymentPreimage {
+    /// Computes lightning payment hash corresponding to the preimage.
+    pub fn payment_hash(&self) -> PaymentHash { sha256::Hash::hash(&self.0) }
+
+    /// Checks that the preimage matches the provided payment hash.
+    pub fn verify(
+        &self,
+        payment_hash: PaymentHash,
+    ) -> Result<(), HashLockError> {
+        if self.payment_hash() != payment_hash {
+            return Err(HashLockError::PreimageMismatch {
+                expected: payment_hash,
+            });
+        }
+        Ok(())
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+
+    // Each chunk under the same lock has a distinct id, so using the id
+    // prefix as a nonce never repeats a nonce for distinct plaintexts.
+    fn nonce(chunk_id: ChunkId) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&chunk_id[..12]);
+        nonce
+    }
+
+    /// Encrypts chunk data with the preimage as a key.
+    ///
+    /// Encrypted data are 16 bytes longer than the source chunk due to the
+    /// authentication tag; thus encryption of a chunk close to the maximum
+    /// chunk size may fail with [`TooLargeData`].
+    pub fn lock_chunk(&self, chunk: &Chunk) -> Result<Chunk, TooLargeData> {
+        let nonce = Self::nonce(chunk.chunk_id());
+        let data = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), chunk.as_ref())
+            .expect("chacha20poly1305 encryption of in-memory data");
+        MediumVec::try_from(data).map(Chunk::from).map_err(|_| TooLargeData)
+    }
+
+    /// Decrypts hash-locked chunk data and verifies that they match the
+    /// expected chunk id.
+    pub fn unlock_chunk(
+        &self,
+        chunk_id: ChunkId,
+        locked: &Chunk,
+    ) -> Result<Chunk, HashLockError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("unlock_chunk", chunk_id = %chunk_id)
+            .entered();
+        let nonce = Self::nonce(chunk_id);
+        let data = self
+            .cipher()
+            .decrypt(Nonce::from_slice(&nonce), locked.as_ref())
+            .map_err(|_| {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("unable to decrypt hash-locked chunk");
+                HashLockError::Decryption
+            })?;
+        // Decrypted data are always shorter than the locked chunk
+        let chunk = Chunk::try_from(data)
+            .expect("decrypted data are smaller than the locked chunk");
+        let actual = chunk.chunk_id();
+        if actual != chunk_id {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(actual = %actual, "unlocked chunk id mismatch");
+            return Err(HashLockError::ChunkIdMismatch {
+                expected: chunk_id,
+                actual,
+            });
+        }
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lock_unlock() {
+        let preimage = PaymentPreimage::from([7u8; 32]);
+        let chunk = Chunk::try_from(b"some chunk data".to_vec()).unwrap();
+        let locked = preimage.lock_chunk(&chunk).unwrap();
+        assert_ne!(locked, chunk);
+        assert_eq!(
+            preimage.unlock_chunk(chunk.chunk_id(), &locked).unwrap(),
+            chunk
+        );
+        assert_eq!(
+            PaymentPreimage::from([8u8; 32])
+                .unlock_chunk(chunk.chunk_id(), &locked),
+            Err(HashLockError::Decryption)
+        );
+        assert!(preimage.verify(preimage.payment_hash()).is_ok());
+    }
+}
@@ -0,0 +1,473 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+
+use amplify::Wrapper;
+use bitcoin_hashes::{sha256, sha256t, Hash};
+use secp256k1::{
+    ecdsa, Message, PublicKey, Secp256k1, SecretKey, Signing, Verification,
+};
+use strict_encoding::StrictEncode;
+
+use crate::{ContainerId, Mesg, MesgId};
+
+// "storm:identity"
+static MIDSTATE_IDENTITY: [u8; 32] = [
+    163, 128, 112, 21, 46, 65, 246, 196, 86, 238, 50, 213, 48, 254, 185, 1,
+    143, 163, 201, 150, 49, 210, 73, 54, 112, 137, 250, 143, 242, 46, 210, 146,
+];
+
+// "storm:envelope"
+static MIDSTATE_ENVELOPE: [u8; 32] = [
+    145, 51, 168, 148, 34, 82, 107, 226, 3, 77, 160, 56, 224, 195, 160, 19, 91,
+    130, 66, 235, 227, 80, 166, 68, 28, 111, 243, 228, 87, 104, 65, 170,
+];
+
+/// Size of the window of sequence numbers below the highest one seen from a
+/// sender, within which envelopes are still accepted out of order.
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// Tag used for identity signature hashes.
+pub struct IdentityTag;
+
+impl sha256t::Tag for IdentityTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_IDENTITY);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Tag used for hashes of signed envelopes.
+pub struct EnvelopeTag;
+
+impl sha256t::Tag for EnvelopeTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_ENVELOPE);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Errors verifying identities and data signed by them.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum IdentityError {
+    /// identity signature does not prove control over node key {0}.
+    InvalidProof(PublicKey),
+
+    /// data are not signed by {0}.
+    InvalidSignature(PublicKey),
+
+    /// secret key does not correspond to the identity node key {0}.
+    KeyMismatch(PublicKey),
+
+    /// envelope #{1} from {0} was already received or is too old.
+    Replayed(PublicKey, u64),
+}
+
+/// Author identity bound to a lightning node key.
+///
+/// The identity is the same across all storm apps, such that messages and
+/// containers produced by the same node are attributed to the same author.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{node_id}")]
+pub struct StormIdentity {
+    pub node_id: PublicKey,
+    /// Human-readable alias of the author. Aliases are not unique and are
+    /// informational only.
+    pub alias: Option<String>,
+    /// Signature of the node key over the identity data, proving control of
+    /// the node key.
+    pub proof: ecdsa::Signature,
+}
+
+impl StormIdentity {
+    fn proof_message(node_id: PublicKey, alias: &Option<String>) -> Message {
+        let mut engine = sha256t::Hash::<IdentityTag>::engine();
+        node_id
+            .strict_encode(&mut engine)
+            .expect("memory encoders do not fail");
+        alias.strict_encode(&mut engine).expect("memory encoders do not fail");
+        let hash = sha256t::Hash::<IdentityTag>::from_engine(engine);
+        Message::from_slice(&hash[..])
+            .expect("hash has the size of a signed message")
+    }
+
+    /// Constructs identity for the node key, signing it with the key.
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        node_secret: &SecretKey,
+        alias: Option<String>,
+    ) -> StormIdentity {
+        let node_id = PublicKey::from_secret_key(secp, node_secret);
+        let proof =
+            secp.sign_ecdsa(&Self::proof_message(node_id, &alias), node_secret);
+        StormIdentity {
+            node_id,
+            alias,
+            proof,
+        }
+    }
+
+    /// Verifies that the identity is signed by the node key.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), IdentityError> {
+        secp.verify_ecdsa(
+            &Self::proof_message(self.node_id, &self.alias),
+            &self.proof,
+            &self.node_id,
+        )
+        .map_err(|_| IdentityError::InvalidProof(self.node_id))
+    }
+
+    /// Signs data digest on behalf of the identity.
+    pub fn sign<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        node_secret: &SecretKey,
+        digest: [u8; 32],
+    ) -> Result<ecdsa::Signature, IdentityError> {
+        if PublicKey::from_secret_key(secp, node_secret) != self.node_id {
+            return Err(IdentityError::KeyMismatch(self.node_id));
+        }
+        let msg = Message::from_slice(&digest)
+            .expect("digest has the size of a signed message");
+        Ok(secp.sign_ecdsa(&msg, node_secret))
+    }
+
+    /// Verifies that the data digest is signed by the identity.
+    pub fn verify_signature<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        digest: [u8; 32],
+        signature: &ecdsa::Signature,
+    ) -> Result<(), IdentityError> {
+        let msg = Message::from_slice(&digest)
+            .expect("digest has the size of a signed message");
+        secp.verify_ecdsa(&msg, signature, &self.node_id)
+            .map_err(|_| IdentityError::InvalidSignature(self.node_id))
+    }
+
+    /// Verifies that the message with the given id is authored by the
+    /// identity.
+    pub fn verify_mesg<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        mesg_id: MesgId,
+        signature: &ecdsa::Signature,
+    ) -> Result<(), IdentityError> {
+        self.verify_signature(
+            secp,
+            mesg_id.into_inner().into_inner(),
+            signature,
+        )
+    }
+
+    /// Verifies that the container with the given id is authored by the
+    /// identity.
+    pub fn verify_container<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        container_id: ContainerId,
+        signature: &ecdsa::Signature,
+    ) -> Result<(), IdentityError> {
+        self.verify_signature(
+            secp,
+            container_id.into_inner().into_inner(),
+            signature,
+        )
+    }
+}
+
+/// Message signed by its author identity.
+///
+/// The signature commits to the message id and to the sequence number of
+/// the envelope, which the author increases with each sealed envelope.
+/// Receivers track sequence numbers with [`ReplayGuard`], such that a
+/// captured envelope can't be replayed to trigger the same action twice.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mesg} by {author} #{seq}")]
+pub struct SignedEnvelope {
+    pub author: StormIdentity,
+    pub mesg: Mesg,
+    /// Sequence number of the envelope among the envelopes sealed by the
+    /// author.
+    pub seq: u64,
+    /// Signature of the author over the message id and sequence number.
+    pub signature: ecdsa::Signature,
+}
+
+impl SignedEnvelope {
+    fn digest(mesg_id: MesgId, seq: u64) -> [u8; 32] {
+        let mut engine = sha256t::Hash::<EnvelopeTag>::engine();
+        mesg_id
+            .strict_encode(&mut engine)
+            .expect("memory encoders do not fail");
+        seq.strict_encode(&mut engine).expect("memory encoders do not fail");
+        sha256t::Hash::<EnvelopeTag>::from_engine(engine).into_inner()
+    }
+
+    /// Signs message on behalf of the author identity under the given
+    /// sequence number.
+    pub fn seal<C: Signing>(
+        secp: &Secp256k1<C>,
+        node_secret: &SecretKey,
+        author: StormIdentity,
+        mesg: Mesg,
+        seq: u64,
+    ) -> Result<SignedEnvelope, IdentityError> {
+        let digest = Self::digest(mesg.mesg_id(), seq);
+        let signature = author.sign(secp, node_secret, digest)?;
+        Ok(SignedEnvelope {
+            author,
+            mesg,
+            seq,
+            signature,
+        })
+    }
+
+    /// Verifies that the author identity is bound to the node key and that
+    /// the message is signed by it.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), IdentityError> {
+        self.author.verify(secp)?;
+        self.author.verify_signature(
+            secp,
+            Self::digest(self.mesg.mesg_id(), self.seq),
+            &self.signature,
+        )
+    }
+
+    /// Verifies the envelope and registers its sequence number with the
+    /// replay guard, failing if the envelope was already received.
+    pub fn verify_fresh<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        guard: &mut ReplayGuard,
+    ) -> Result<(), IdentityError> {
+        self.verify(secp)?;
+        guard.accept(self.author.node_id, self.seq)
+    }
+}
+
+/// Sequence numbers received from a single sender.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ReplayWindow {
+    /// Highest sequence number received.
+    pub highest: u64,
+    /// Bit `n` is set if sequence number `highest - n` was received.
+    pub seen: u64,
+}
+
+impl ReplayWindow {
+    /// Checks whether the sequence number was not received yet and is
+    /// within the window.
+    pub fn is_fresh(&self, seq: u64) -> bool {
+        if self.seen == 0 || seq > self.highest {
+            return true;
+        }
+        let offset = self.highest - seq;
+        offset < REPLAY_WINDOW && self.seen & (1 << offset) == 0
+    }
+
+    fn accept(&mut self, seq: u64) -> bool {
+        if !self.is_fresh(seq) {
+            return false;
+        }
+        if self.seen == 0 {
+            self.highest = seq;
+            self.seen = 1;
+        } else if seq > self.highest {
+            let shift = seq - self.highest;
+            self.seen =
+                if shift < REPLAY_WINDOW { self.seen << shift } else { 0 };
+            self.seen |= 1;
+            self.highest = seq;
+        } else {
+            self.seen |= 1 << (self.highest - seq);
+        }
+        true
+    }
+}
+
+/// Per-sender state of received sequence numbers, protecting against replay
+/// of signed data.
+///
+/// Sequence numbers up to [`REPLAY_WINDOW`] below the highest one received
+/// from the sender are accepted once, allowing for reordering in transit;
+/// older ones are rejected. The state must be persisted by the node, since
+/// a guard starting from scratch accepts any previously seen envelope.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ReplayGuard {
+    senders: BTreeMap<PublicKey, ReplayWindow>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> ReplayGuard { ReplayGuard::default() }
+
+    /// Sequence numbers received from the sender, if any.
+    pub fn window(&self, sender: PublicKey) -> Option<ReplayWindow> {
+        self.senders.get(&sender).copied()
+    }
+
+    /// Checks whether the sequence number from the sender would be accepted.
+    pub fn is_fresh(&self, sender: PublicKey, seq: u64) -> bool {
+        self.senders
+            .get(&sender)
+            .map(|window| window.is_fresh(seq))
+            .unwrap_or(true)
+    }
+
+    /// Registers sequence number received from the sender, failing if it
+    /// was already received or is too old.
+    pub fn accept(
+        &mut self,
+        sender: PublicKey,
+        seq: u64,
+    ) -> Result<(), IdentityError> {
+        if !self.senders.entry(sender).or_default().accept(seq) {
+            return Err(IdentityError::Replayed(sender, seq));
+        }
+        Ok(())
+    }
+
+    /// Forgets the state of the sender.
+    pub fn remove(&mut self, sender: PublicKey) -> Option<ReplayWindow> {
+        self.senders.remove(&sender)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::tagged_hash;
+    use strict_encoding::StrictDecode;
+
+    use super::*;
+    use crate::Chunk;
+
+    #[test]
+    fn test_identity_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:identity");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_IDENTITY);
+        let midstate = tagged_hash::Midstate::with(b"storm:envelope");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_ENVELOPE);
+    }
+
+    #[test]
+    fn test_identity() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let other = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let identity = StormIdentity::new(&secp, &secret, Some(s!("alice")));
+        assert_eq!(identity.verify(&secp), Ok(()));
+
+        let mut forged = identity.clone();
+        forged.alias = Some(s!("mallory"));
+        assert_eq!(
+            forged.verify(&secp),
+            Err(IdentityError::InvalidProof(identity.node_id))
+        );
+
+        let mesg_id = MesgId::default();
+        let digest = mesg_id.into_inner().into_inner();
+        let sig = identity.sign(&secp, &secret, digest).unwrap();
+        assert_eq!(identity.verify_mesg(&secp, mesg_id, &sig), Ok(()));
+        assert_eq!(
+            identity.sign(&secp, &other, digest),
+            Err(IdentityError::KeyMismatch(identity.node_id))
+        );
+    }
+
+    #[test]
+    fn test_signed_envelope() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let identity = StormIdentity::new(&secp, &secret, None);
+        let mesg = Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"hello".to_vec()).unwrap(),
+            container_ids: vec![],
+        };
+        let mut envelope =
+            SignedEnvelope::seal(&secp, &secret, identity, mesg, 1).unwrap();
+        assert_eq!(envelope.verify(&secp), Ok(()));
+
+        let mut guard = ReplayGuard::new();
+        let node_id = envelope.author.node_id;
+        assert_eq!(envelope.verify_fresh(&secp, &mut guard), Ok(()));
+        assert_eq!(
+            envelope.verify_fresh(&secp, &mut guard),
+            Err(IdentityError::Replayed(node_id, 1))
+        );
+
+        let mut forged = envelope.clone();
+        forged.seq = 2;
+        assert!(forged.verify(&secp).is_err());
+        envelope.mesg.body = Chunk::try_from(b"forged".to_vec()).unwrap();
+        assert!(envelope.verify(&secp).is_err());
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let secp = Secp256k1::new();
+        let sender = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x11; 32]).unwrap(),
+        );
+        let mut guard = ReplayGuard::new();
+        assert_eq!(guard.accept(sender, 100), Ok(()));
+        assert_eq!(guard.accept(sender, 98), Ok(()));
+        assert_eq!(guard.accept(sender, 101), Ok(()));
+        assert!(guard.is_fresh(sender, 99));
+        assert!(!guard.is_fresh(sender, 98));
+        assert!(!guard.is_fresh(sender, 101 - REPLAY_WINDOW));
+        assert_eq!(
+            guard.accept(sender, 100),
+            Err(IdentityError::Replayed(sender, 100))
+        );
+
+        assert_eq!(guard.accept(sender, 1000), Ok(()));
+        assert!(!guard.is_fresh(sender, 101));
+        assert!(guard.is_fresh(sender, 999));
+
+        let data = guard.strict_serialize().unwrap();
+        assert_eq!(ReplayGuard::strict_deserialize(data).unwrap(), guard);
+    }
+}
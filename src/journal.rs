@@ -0,0 +1,310 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Journal: container profile for continuously growing data.
+//!
+//! Journal entries are grouped into segments, each of which is stored as a
+//! separate immutable container. The current state of the journal is
+//! described by a head, which lists all of the segments and is published as
+//! a message under the journal topic; readers fetch the latest head and pull
+//! only the segments containing entries they have not seen yet.
+
+use stens::AsciiString;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::chunk::encoding::ApplyStrictEncoding;
+use crate::chunk::TooLargeData;
+use crate::{
+    Chunk, Container, ContainerId, Mesg, MesgId, TryFromChunk, TryToChunk,
+};
+
+/// MIME type of containers holding journal segments.
+pub const JOURNAL_SEGMENT_MIME: &str = "application/x-storm-journal-segment";
+
+/// Maximum number of segments referenced by a journal head, limited by the
+/// number of containers a single message can attach.
+pub const MAX_JOURNAL_SEGMENTS: usize = u16::MAX as usize;
+
+/// Errors working with journals.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum JournalError {
+    /// data can't be decoded as a journal head or segment.
+    Decoding,
+
+    /// segment {0} does not match the journal head.
+    SegmentMismatch(ContainerId),
+
+    /// journal head already references the maximum number of segments.
+    TooManySegments,
+
+    /// journal segment data are too large to be stored in a container.
+    TooLarge,
+}
+
+/// Reference to a journal segment from the journal head.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}:{first_seq}+{count}")]
+pub struct SegmentRef {
+    pub container_id: ContainerId,
+    /// Sequence number of the first entry in the segment.
+    pub first_seq: u64,
+    /// Number of entries in the segment.
+    pub count: u32,
+}
+
+impl SegmentRef {
+    /// Sequence number following the last entry of the segment.
+    pub fn end_seq(&self) -> u64 { self.first_seq + self.count as u64 }
+}
+
+/// Segment of journal entries, stored as a container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct JournalSegment {
+    /// Sequence number of the first entry in the segment.
+    pub first_seq: u64,
+    pub entries: Vec<Vec<u8>>,
+}
+
+impl JournalSegment {
+    /// Restores segment from the container data.
+    pub fn from_container_data(data: &[u8]) -> Result<Self, JournalError> {
+        JournalSegment::strict_deserialize(data)
+            .map_err(|_| JournalError::Decoding)
+    }
+
+    /// Entries with sequence numbers not less than `seq`, together with
+    /// their sequence numbers.
+    pub fn entries_since(
+        &self,
+        seq: u64,
+    ) -> impl Iterator<Item = (u64, &[u8])> + '_ {
+        (self.first_seq..)
+            .zip(&self.entries)
+            .filter(move |(no, _)| *no >= seq)
+            .map(|(no, entry)| (no, entry.as_slice()))
+    }
+}
+
+/// Head of a journal listing all of its segments.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct JournalHead {
+    pub segments: Vec<SegmentRef>,
+}
+
+impl ApplyStrictEncoding for JournalHead {}
+
+impl JournalHead {
+    /// Sequence number of the next entry to be appended.
+    pub fn next_seq(&self) -> u64 {
+        self.segments.last().map(SegmentRef::end_seq).unwrap_or_default()
+    }
+
+    /// Segments containing entries with sequence numbers not less than
+    /// `seq`.
+    pub fn segments_since(&self, seq: u64) -> &[SegmentRef] {
+        let start = self
+            .segments
+            .iter()
+            .position(|segment| segment.end_seq() > seq)
+            .unwrap_or(self.segments.len());
+        &self.segments[start..]
+    }
+
+    /// Produces message publishing the head under the journal topic. The
+    /// message attaches all segment containers, so they are retained by the
+    /// nodes storing the message. Fails if the head references more than
+    /// [`MAX_JOURNAL_SEGMENTS`] segments.
+    pub fn to_mesg(&self, topic_id: MesgId) -> Result<Mesg, TooLargeData> {
+        if self.segments.len() > MAX_JOURNAL_SEGMENTS {
+            return Err(TooLargeData);
+        }
+        Ok(Mesg {
+            parent_id: topic_id,
+            body: self.try_to_chunk()?,
+            container_ids: self
+                .segments
+                .iter()
+                .map(|segment| segment.container_id)
+                .collect(),
+        })
+    }
+
+    /// Restores head from the message published under the journal topic.
+    pub fn from_mesg(mesg: &Mesg) -> Result<JournalHead, JournalError> {
+        JournalHead::try_from_chunk(mesg.body.clone())
+            .map_err(|_| JournalError::Decoding)
+    }
+
+    /// Checks that the segment corresponds to the reference in the head.
+    pub fn verify_segment(
+        &self,
+        container: &Container,
+        segment: &JournalSegment,
+    ) -> Result<(), JournalError> {
+        let container_id = container.container_id();
+        match self
+            .segments
+            .iter()
+            .find(|segment| segment.container_id == container_id)
+        {
+            Some(seg_ref)
+                if seg_ref.first_seq == segment.first_seq
+                    && seg_ref.count as usize == segment.entries.len() =>
+            {
+                Ok(())
+            }
+            _ => Err(JournalError::SegmentMismatch(container_id)),
+        }
+    }
+}
+
+/// Writer side of a journal, accumulating entries and sealing them into
+/// segments.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Journal {
+    head: JournalHead,
+    pending: Vec<Vec<u8>>,
+}
+
+impl Journal {
+    pub fn new() -> Journal { Journal::default() }
+
+    /// Continues journal from the previously published head.
+    pub fn with(head: JournalHead) -> Journal {
+        Journal {
+            head,
+            pending: empty!(),
+        }
+    }
+
+    pub fn head(&self) -> &JournalHead { &self.head }
+
+    /// Number of entries not yet sealed into a segment.
+    pub fn pending(&self) -> usize { self.pending.len() }
+
+    /// Adds entry to the journal, returning its sequence number. The entry
+    /// becomes visible to readers once the segment is appended and the head
+    /// is published.
+    pub fn push(&mut self, entry: impl Into<Vec<u8>>) -> u64 {
+        self.pending.push(entry.into());
+        self.head.next_seq() + self.pending.len() as u64 - 1
+    }
+
+    /// Seals pending entries into a new segment container and adds it to the
+    /// head. Returns `None` if there are no pending entries. Fails, keeping
+    /// the entries pending, if the head already references
+    /// [`MAX_JOURNAL_SEGMENTS`] segments and could not be published anymore.
+    pub fn append_segment(
+        &mut self,
+        chunk_size: u32,
+    ) -> Result<Option<(Container, Vec<Chunk>)>, JournalError> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        if self.head.segments.len() >= MAX_JOURNAL_SEGMENTS {
+            return Err(JournalError::TooManySegments);
+        }
+        let segment = JournalSegment {
+            first_seq: self.head.next_seq(),
+            entries: self.pending.clone(),
+        };
+        let data =
+            segment.strict_serialize().map_err(|_| JournalError::TooLarge)?;
+        let mime = AsciiString::try_from(JOURNAL_SEGMENT_MIME)
+            .expect("static MIME type is ASCII");
+        let (container, chunks) =
+            Container::chunkify(mime, "", &data, chunk_size)
+                .map_err(|_| JournalError::TooLarge)?;
+        self.head.segments.push(SegmentRef {
+            container_id: container.container_id(),
+            first_seq: segment.first_seq,
+            count: segment.entries.len() as u32,
+        });
+        self.pending.clear();
+        Ok(Some((container, chunks)))
+    }
+
+    /// Produces message publishing current journal head.
+    pub fn publish_head(&self, topic_id: MesgId) -> Result<Mesg, TooLargeData> {
+        self.head.to_mesg(topic_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_journal() {
+        let mut journal = Journal::new();
+        journal.push(b"a".to_vec());
+        journal.push(b"b".to_vec());
+        let (first, chunks) = journal.append_segment(1024).unwrap().unwrap();
+        assert_eq!(journal.push(b"c".to_vec()), 2);
+        journal.append_segment(1024).unwrap().unwrap();
+        assert_eq!(journal.append_segment(1024), Ok(None));
+
+        let mesg = journal.publish_head(MesgId::default()).unwrap();
+        let head = JournalHead::from_mesg(&mesg).unwrap();
+        assert_eq!(head.next_seq(), 3);
+        assert_eq!(mesg.container_ids.len(), 2);
+        assert_eq!(head.segments_since(1).len(), 2);
+        assert_eq!(head.segments_since(2).len(), 1);
+        assert_eq!(head.segments_since(3).len(), 0);
+
+        let segment = JournalSegment::from_container_data(&chunks[0]).unwrap();
+        assert_eq!(head.verify_segment(&first, &segment), Ok(()));
+        let entries = segment.entries_since(1).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(1, &b"b"[..])]);
+    }
+
+    #[test]
+    fn test_segment_limit() {
+        let segment = SegmentRef {
+            container_id: ContainerId::default(),
+            first_seq: 0,
+            count: 0,
+        };
+        let mut head = JournalHead {
+            segments: vec![segment; MAX_JOURNAL_SEGMENTS],
+        };
+        let mut journal = Journal::with(head.clone());
+        journal.push(b"a".to_vec());
+        assert_eq!(
+            journal.append_segment(1024),
+            Err(JournalError::TooManySegments)
+        );
+        assert_eq!(journal.pending(), 1);
+        let mesg = journal.publish_head(MesgId::default()).unwrap();
+        assert_eq!(JournalHead::from_mesg(&mesg), Ok(head.clone()));
+
+        head.segments.push(segment);
+        assert_eq!(head.to_mesg(MesgId::default()), Err(TooLargeData));
+    }
+}
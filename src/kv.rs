@@ -0,0 +1,370 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Key-value container profile.
+//!
+//! Container data start with a key table: 4-byte little-endian length of the
+//! table followed by the strict-encoded list of keys sorted in lexicographic
+//! order, each with the offset and length of its value. The table is padded
+//! to the chunk boundary, so it occupies a whole number of leading chunks;
+//! values are concatenated in the key order after the table. This allows a
+//! reader to fetch the table chunks only and then pull just the chunks
+//! holding the values it needs.
+
+use std::collections::BTreeMap;
+
+use stens::AsciiString;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::{Chunk, ChunkSlice, Container};
+
+/// MIME type of key-value containers.
+pub const KV_CONTAINER_MIME: &str = "application/x-storm-kv";
+
+/// Errors working with key-value containers.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum KvError {
+    /// key-value data are too large to fit into a single container.
+    TooLarge,
+
+    /// container is not a key-value container or has a malformed key table.
+    Decoding,
+
+    /// container chunk #{0} required to read the data is not provided.
+    MissingChunk(usize),
+}
+
+/// Record in the key table of a key-value container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct KvEntry {
+    pub key: Vec<u8>,
+    /// Offset of the value from the start of the value area.
+    pub offset: u64,
+    /// Length of the value, in bytes.
+    pub len: u32,
+}
+
+/// Builder for key-value containers.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct KvBuilder {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl KvBuilder {
+    pub fn new() -> KvBuilder { KvBuilder::default() }
+
+    /// Adds value under the key, returning previous value for the same key,
+    /// if any.
+    pub fn insert(
+        &mut self,
+        key: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        self.entries.insert(key.into(), value.into())
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Produces key-value container and its chunks.
+    pub fn build(
+        &self,
+        info: impl ToString,
+        chunk_size: u32,
+    ) -> Result<(KvContainer, Vec<Chunk>), KvError> {
+        self.build_with_mime(KV_CONTAINER_MIME, info, chunk_size)
+    }
+
+    /// Produces container with the key-value layout and a custom MIME type,
+    /// which is used by container profiles built on top of key-value
+    /// containers.
+    pub fn build_with_mime(
+        &self,
+        mime: &str,
+        info: impl ToString,
+        chunk_size: u32,
+    ) -> Result<(KvContainer, Vec<Chunk>), KvError> {
+        let mut offset = 0u64;
+        let mut table = Vec::with_capacity(self.entries.len());
+        for (key, value) in &self.entries {
+            let len =
+                u32::try_from(value.len()).map_err(|_| KvError::TooLarge)?;
+            table.push(KvEntry {
+                key: key.clone(),
+                offset,
+                len,
+            });
+            offset += len as u64;
+        }
+        let table_data =
+            table.strict_serialize().map_err(|_| KvError::TooLarge)?;
+
+        let mut data =
+            Vec::with_capacity(4 + table_data.len() + offset as usize);
+        data.extend((table_data.len() as u32).to_le_bytes());
+        data.extend(table_data);
+        let chunk_size = chunk_size.clamp(1, crate::chunk::MAX_CHUNK_SIZE);
+        let table_chunks =
+            (data.len() + chunk_size as usize - 1) / chunk_size as usize;
+        data.resize(table_chunks * chunk_size as usize, 0);
+        for value in self.entries.values() {
+            data.extend(value);
+        }
+
+        let mime =
+            AsciiString::try_from(mime).map_err(|_| KvError::Decoding)?;
+        let (container, chunks) =
+            Container::chunkify(mime, info, &data, chunk_size)
+                .map_err(|_| KvError::TooLarge)?;
+        let kv = KvContainer {
+            container,
+            table,
+            table_chunks,
+        };
+        Ok((kv, chunks))
+    }
+}
+
+/// Key-value container with the decoded key table.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct KvContainer {
+    container: Container,
+    table: Vec<KvEntry>,
+    table_chunks: usize,
+}
+
+impl KvContainer {
+    /// Returns number of leading container chunks holding the key table,
+    /// which can be detected from the first container chunk.
+    pub fn table_chunk_count(
+        container: &Container,
+        first_chunk: &Chunk,
+    ) -> Result<usize, KvError> {
+        KvContainer::table_chunk_count_with_mime(
+            container,
+            first_chunk,
+            KV_CONTAINER_MIME,
+        )
+    }
+
+    fn table_chunk_count_with_mime(
+        container: &Container,
+        first_chunk: &Chunk,
+        mime: &str,
+    ) -> Result<usize, KvError> {
+        if container.header.mime.as_str() != mime
+            || !container.is_chunk_size_valid()
+            || first_chunk.len() < 4
+        {
+            return Err(KvError::Decoding);
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&first_chunk[..4]);
+        let len = u32::from_le_bytes(len) as u64 + 4;
+        let chunk_size = container.header.chunk_size as u64;
+        let count = ((len + chunk_size - 1) / chunk_size) as usize;
+        if count > container.chunks.len() {
+            return Err(KvError::Decoding);
+        }
+        Ok(count)
+    }
+
+    /// Decodes key table from the leading container chunks. Chunks following
+    /// the table chunks are ignored.
+    pub fn with(
+        container: Container,
+        chunks: &[Chunk],
+    ) -> Result<KvContainer, KvError> {
+        KvContainer::with_mime(container, chunks, KV_CONTAINER_MIME)
+    }
+
+    /// Decodes key table of a container with the key-value layout and the
+    /// given MIME type.
+    pub fn with_mime(
+        container: Container,
+        chunks: &[Chunk],
+        mime: &str,
+    ) -> Result<KvContainer, KvError> {
+        let first = chunks.first().ok_or(KvError::MissingChunk(0))?;
+        let table_chunks =
+            KvContainer::table_chunk_count_with_mime(&container, first, mime)?;
+        if chunks.len() < table_chunks {
+            return Err(KvError::MissingChunk(chunks.len()));
+        }
+        let data = chunks[..table_chunks]
+            .iter()
+            .flat_map(|chunk| chunk.iter().copied())
+            .collect::<Vec<_>>();
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&data[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+        let table = data
+            .get(4..4 + len)
+            .and_then(|data| Vec::<KvEntry>::strict_deserialize(data).ok())
+            .ok_or(KvError::Decoding)?;
+        let values_size = container.header.size
+            - (table_chunks as u64 * container.header.chunk_size as u64)
+                .min(container.header.size);
+        let sorted = table.windows(2).all(|pair| pair[0].key < pair[1].key);
+        let in_bounds = table.iter().all(|entry| {
+            entry
+                .offset
+                .checked_add(entry.len as u64)
+                .map_or(false, |end| end <= values_size)
+        });
+        if !sorted || !in_bounds {
+            return Err(KvError::Decoding);
+        }
+        Ok(KvContainer {
+            container,
+            table,
+            table_chunks,
+        })
+    }
+
+    pub fn container(&self) -> &Container { &self.container }
+
+    /// Number of leading container chunks holding the key table.
+    pub fn table_chunks(&self) -> usize { self.table_chunks }
+
+    pub fn len(&self) -> usize { self.table.len() }
+
+    pub fn is_empty(&self) -> bool { self.table.is_empty() }
+
+    fn entry(&self, key: &[u8]) -> Option<&KvEntry> {
+        self.table
+            .binary_search_by(|entry| entry.key.as_slice().cmp(key))
+            .ok()
+            .map(|pos| &self.table[pos])
+    }
+
+    fn slices(&self, entry: &KvEntry) -> Vec<ChunkSlice> {
+        let values_start =
+            self.table_chunks as u64 * self.container.header.chunk_size as u64;
+        if entry.len == 0 {
+            return vec![];
+        }
+        self.container
+            .chunks_for_range(values_start + entry.offset, entry.len as u64)
+    }
+
+    /// Returns chunk slices holding the value for the key, or `None` if the
+    /// key is absent.
+    pub fn lookup(&self, key: &[u8]) -> Option<Vec<ChunkSlice>> {
+        self.entry(key).map(|entry| self.slices(entry))
+    }
+
+    /// Reads value for the key from the container chunks, provided in the
+    /// container order. Only chunks holding the value have to be present;
+    /// the rest may be empty.
+    pub fn get(
+        &self,
+        key: &[u8],
+        chunks: &[Chunk],
+    ) -> Result<Option<Vec<u8>>, KvError> {
+        let entry = match self.entry(key) {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+        let mut value = Vec::with_capacity(entry.len as usize);
+        for slice in self.slices(entry) {
+            let start = slice.offset as usize;
+            let end = start + slice.len as usize;
+            let data = chunks
+                .get(slice.index)
+                .and_then(|chunk| chunk.get(start..end))
+                .ok_or(KvError::MissingChunk(slice.index))?;
+            value.extend(data);
+        }
+        Ok(Some(value))
+    }
+
+    /// Iterates over the keys in the lexicographic order.
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.table.iter().map(|entry| entry.key.as_slice())
+    }
+
+    /// Iterates over the keys in the lexicographic order together with the
+    /// chunk slices holding their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Vec<ChunkSlice>)> + '_ {
+        self.table
+            .iter()
+            .map(|entry| (entry.key.as_slice(), self.slices(entry)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kv_container() {
+        let mut builder = KvBuilder::new();
+        builder.insert(b"beta".to_vec(), vec![2u8; 100]);
+        builder.insert(b"alpha".to_vec(), vec![1u8; 10]);
+        builder.insert(b"empty".to_vec(), vec![]);
+        let (kv, chunks) = builder.build("dictionary", 32).unwrap();
+
+        let first = &chunks[0];
+        assert_eq!(
+            KvContainer::table_chunk_count(kv.container(), first),
+            Ok(kv.table_chunks())
+        );
+        let restored =
+            KvContainer::with(kv.container().clone(), &chunks).unwrap();
+        assert_eq!(restored, kv);
+        assert_eq!(kv.keys().collect::<Vec<_>>(), vec![
+            &b"alpha"[..],
+            b"beta",
+            b"empty"
+        ]);
+
+        assert_eq!(kv.get(b"alpha", &chunks), Ok(Some(vec![1u8; 10])));
+        assert_eq!(kv.get(b"beta", &chunks), Ok(Some(vec![2u8; 100])));
+        assert_eq!(kv.get(b"empty", &chunks), Ok(Some(vec![])));
+        assert_eq!(kv.get(b"gamma", &chunks), Ok(None));
+        assert_eq!(kv.lookup(b"empty"), Some(vec![]));
+
+        let table_only = &chunks[..kv.table_chunks()];
+        assert_eq!(
+            kv.get(b"beta", table_only),
+            Err(KvError::MissingChunk(kv.table_chunks()))
+        );
+    }
+
+    #[test]
+    fn test_malformed_table() {
+        let table = vec![KvEntry {
+            key: b"key".to_vec(),
+            offset: u64::MAX,
+            len: 16,
+        }];
+        let table_data = table.strict_serialize().unwrap();
+        let mut data = (table_data.len() as u32).to_le_bytes().to_vec();
+        data.extend(table_data);
+        data.resize(32, 0);
+        data.extend([1u8; 32]);
+        let mime = AsciiString::try_from(KV_CONTAINER_MIME).unwrap();
+        let (container, chunks) =
+            Container::chunkify(mime, "", &data, 32).unwrap();
+        assert_eq!(
+            KvContainer::with(container, &chunks),
+            Err(KvError::Decoding)
+        );
+    }
+}
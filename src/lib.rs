@@ -18,22 +18,165 @@ extern crate serde_crate as serde;
 #[macro_use]
 extern crate internet2;
 
+#[cfg(feature = "test-utils")]
+pub mod arbitrary;
+pub mod bloom;
+pub mod broadcast;
 pub mod chunk;
+pub mod credit;
+pub mod encryption;
+pub mod escrow;
+pub mod gcs;
+pub mod mailbox;
+pub mod membership;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod moderation;
 pub mod p2p;
+pub mod posting;
+pub mod reconcile;
+pub mod replication;
+pub mod rgb;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "shamir")]
+pub mod shamir;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub mod sketch;
+pub mod sla;
+pub mod storage;
+pub mod swap;
+pub mod varint;
 mod container;
 mod mesg;
 mod app;
+mod hashlock;
+mod catalog;
+mod stream;
+mod store;
+mod limits;
+mod event;
+mod addr;
+mod peers;
+mod policy;
+mod tracker;
+mod download;
+mod upload;
+mod prefetch;
+mod audit;
+mod journal;
+mod kv;
+mod identity;
+mod contacts;
+mod mesgstore;
+mod paging;
+mod announce;
+mod tag;
+mod search;
+mod authmap;
+mod duplicate;
+mod session;
+mod dictionary;
+mod delta;
+mod reuse;
+mod assembly;
+mod sink;
+mod signed;
+#[cfg(test)]
+mod fixtures;
 
+pub use addr::{AddrParseError, StormAddr, STORM_ADDR_SCHEME};
+pub use announce::{
+    AnnouncementCache, AnnouncementRecord, DEFAULT_ANNOUNCEMENT_TTL,
+};
 pub use app::{
-    StormApp, STORM_APP_CHAT, STORM_APP_RGB_CONTRACTS, STORM_APP_RGB_TRANSFERS,
-    STORM_APP_SEARCH, STORM_APP_STORAGE, STORM_APP_SYSTEM,
-    STORM_APP_VENDOR_MASK,
+    StormApp, UnknownAppName, STORM_APP_CHAT, STORM_APP_RGB_CONTRACTS,
+    STORM_APP_RGB_TRANSFERS, STORM_APP_SEARCH, STORM_APP_STORAGE,
+    STORM_APP_SYSTEM, STORM_APP_VENDOR_MASK,
+};
+pub use assembly::{
+    AssemblyError, ContainerAssembler, DEFAULT_ASSEMBLY_MEMORY,
+};
+pub use audit::{
+    AuditLog, AuditLogError, LogEntry, LogEntryId, AUDIT_LOG_MIME,
 };
+pub use authmap::{
+    AuthMap, AuthMapBuilder, AuthMapError, MapEntry, MapProof, AUTH_MAP_MIME,
+};
+pub use catalog::{Catalog, CatalogError, ContainerRef, POPULARITY_HALF_LIFE};
 pub use chunk::{
-    Chunk, ChunkFullId, ChunkId, ChunkIdExt, TryFromChunk, TryToChunk,
+    Chunk, ChunkFullId, ChunkFullIdSet, ChunkId, ChunkIdExt, CompactChunkIds,
+    TryFromChunk, TryToChunk,
 };
+pub use contacts::{Contact, ContactList, ContactsError, CONTACTS_MIME};
 pub use container::{
-    Container, ContainerFullId, ContainerHeader, ContainerId, ContainerInfo,
+    chunk_merkle_root, ChunkMerkleNode, ChunkPriority, ChunkProof,
+    ChunkSelection, ChunkSlice, Container, ContainerFullId, ContainerHeader,
+    ContainerHeaderId, ContainerId, ContainerInfo, ContainerPart,
+    ContainerVerifyError, CONTAINER_VERSION, MAX_PART_CHUNKS,
     STORM_CONTAINER_ID_HRP,
 };
-pub use mesg::{Mesg, MesgId, Topic};
+pub use delta::{
+    Delta, DeltaError, DeltaOp, RollingChecksum, DEFAULT_DELTA_BLOCK_SIZE,
+    DELTA_MIME,
+};
+pub use dictionary::{
+    CompressedBody, CompressionError, DictionaryBook, DictionaryCodec,
+    DICTIONARY_MIME, MAX_OFFERED_DICTIONARIES,
+};
+pub use download::{
+    ByteRange, CheckpointError, Download, DownloadError, ReceiveError,
+    RetrievalReceipt, TransferCheckpoint, DEFAULT_MAX_IN_FLIGHT,
+};
+pub use duplicate::{
+    DuplicateCache, DEFAULT_DUPLICATE_CAPACITY, DEFAULT_DUPLICATE_TTL,
+};
+pub use event::StormEvent;
+pub use hashlock::{HashLockError, PaymentHash, PaymentPreimage};
+pub use identity::{
+    EnvelopeTag, IdentityError, IdentityTag, ReplayGuard, ReplayWindow,
+    SignedEnvelope, StormIdentity, REPLAY_WINDOW,
+};
+pub use journal::{
+    Journal, JournalError, JournalHead, JournalSegment, SegmentRef,
+    JOURNAL_SEGMENT_MIME, MAX_JOURNAL_SEGMENTS,
+};
+pub use kv::{KvBuilder, KvContainer, KvEntry, KvError, KV_CONTAINER_MIME};
+pub use limits::{
+    AppsAdvert, BandwidthLimit, LimitError, PeerLimits, RateLimit, RateLimiter,
+    TokenBucket,
+};
+pub use mesg::{Mesg, MesgId, Topic, TypedMesg, TypedTopic};
+pub use mesgstore::{MesgRecord, MesgStore};
+pub use paging::{
+    ContainerPage, PageAssembler, PagingError, MAX_PAGE_CHUNKS,
+    PAGING_THRESHOLD,
+};
+pub use peers::{PeerBook, PeerInfo, CHUNK_MISMATCH_PENALTY};
+pub use policy::{AccessList, Policy, PolicyError};
+pub use prefetch::{PrefetchItem, PrefetchQueue, DEFAULT_MAX_PREFETCH};
+pub use reuse::{
+    BlockReuse, BlockSum, BlockSums, ReusePlan, DEFAULT_REUSE_BLOCK_SIZE,
+    MAX_BLOCK_SUMS, MAX_REUSE_BLOCK_SIZE, MAX_WEAK_CANDIDATES,
+    MIN_REUSE_BLOCK_SIZE,
+};
+pub use search::{
+    tokenize, KeywordFilter, SearchError, SearchIndex, SearchIndexBuilder,
+    SearchQuery, KEYWORD_FILTER_FP_RATE, MAX_TERM_LEN, MIN_TERM_LEN,
+    SEARCH_INDEX_MIME,
+};
+pub use session::{
+    SessionError, TransferKey, TransferTag, TRANSFER_COUNTER_LEN,
+};
+pub use signed::{Signable, SignatureError, Signed, SignedTag};
+pub use sink::{ChunkSink, SinkPoll};
+pub use store::{
+    ChunkCache, ChunkIter, ChunkIterError, ChunkStore, DedupError,
+};
+pub use stream::{StreamChunkInfo, StreamInfo};
+pub use tag::{check_tags, Tag, TagError, MAX_TAGS, MAX_TAG_LEN};
+pub use tracker::{
+    RequestKey, RequestTracker, ResponseMatch, DEFAULT_REQUEST_TIMEOUT_MS,
+};
+pub use upload::UploadQueue;
@@ -0,0 +1,421 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::chunk::{self, MAX_CHUNK_SIZE};
+use crate::p2p::fmt_summary;
+use crate::{Container, StormApp};
+
+/// Violations of limits declared by a peer.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum LimitError {
+    /// chunk of {0} bytes exceeds peer chunk size limit of {1} bytes.
+    ChunkTooLarge(u32, u32),
+
+    /// container of {0} bytes exceeds peer container size limit of {1} bytes.
+    ContainerTooLarge(u64, u64),
+
+    /// peer does not accept more than {0} concurrent transfers.
+    TooManyTransfers(u16),
+
+    /// rate limit exceeded.
+    RateLimited,
+}
+
+/// Limits on the data a peer is able to serve and accept.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display(
+    "max_chunk_size={max_chunk_size}, \
+     max_container_size={max_container_size}, max_transfers={max_transfers}"
+)]
+pub struct PeerLimits {
+    /// Maximum size of a chunk, in bytes.
+    pub max_chunk_size: u32,
+    /// Maximum size of a container, in bytes.
+    pub max_container_size: u64,
+    /// Maximum number of concurrent chunk transfers.
+    pub max_transfers: u16,
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        PeerLimits {
+            max_chunk_size: MAX_CHUNK_SIZE,
+            max_container_size: u64::MAX,
+            max_transfers: u16::MAX,
+        }
+    }
+}
+
+impl PeerLimits {
+    /// Chunk size which should be used for containers produced for the
+    /// peer, given our own maximum chunk size.
+    pub fn chunk_size(&self, local_max: u32) -> u32 {
+        chunk::negotiate_chunk_size(local_max, self.max_chunk_size)
+    }
+
+    /// Checks that a chunk of `len` bytes can be sent to the peer.
+    pub fn check_chunk(&self, len: usize) -> Result<(), LimitError> {
+        if len > self.max_chunk_size as usize {
+            return Err(LimitError::ChunkTooLarge(
+                len as u32,
+                self.max_chunk_size,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the container can be transferred to the peer.
+    pub fn check_container(
+        &self,
+        container: &Container,
+    ) -> Result<(), LimitError> {
+        if container.header.size > self.max_container_size {
+            return Err(LimitError::ContainerTooLarge(
+                container.header.size,
+                self.max_container_size,
+            ));
+        }
+        if container.chunks.len() > 1 {
+            self.check_chunk(container.header.chunk_size as usize)?;
+        } else {
+            self.check_chunk(container.header.size as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that one more transfer can be started with the peer, given the
+    /// number of currently `active` transfers.
+    pub fn check_transfers(&self, active: usize) -> Result<(), LimitError> {
+        if active >= self.max_transfers as usize {
+            return Err(LimitError::TooManyTransfers(self.max_transfers));
+        }
+        Ok(())
+    }
+}
+
+/// Advertisement of storm apps supported by the node together with the node
+/// limits.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct AppsAdvert {
+    pub apps: BTreeSet<StormApp>,
+    pub limits: PeerLimits,
+    /// Rate limits applied by the node to the inbound messages per app.
+    pub rate_limits: BTreeMap<StormApp, RateLimit>,
+}
+
+impl Display for AppsAdvert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt_summary(f, "app", self.apps.iter())?;
+        write!(f, "; {}", self.limits)?;
+        if !self.rate_limits.is_empty() {
+            write!(f, "; {} rate limits", self.rate_limits.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Rate limit applied by a node to the inbound messages of an app. Zero
+/// values mean that the rate is not limited.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{msgs_per_minute} msg/min, {bytes_per_minute} bytes/min")]
+pub struct RateLimit {
+    pub msgs_per_minute: u32,
+    pub bytes_per_minute: u32,
+}
+
+/// Token bucket refilled with `rate` tokens per `period_ms` milliseconds and
+/// holding no more than `burst` tokens.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    period_ms: u64,
+    burst: u64,
+    /// Available tokens multiplied by `period_ms`, which allows to account
+    /// for fractional refills without floating point arithmetics.
+    scaled_tokens: u128,
+    last_ms: u64,
+}
+
+impl TokenBucket {
+    /// Constructs full token bucket at time `now_ms`.
+    pub fn new(rate: u64, period_ms: u64, burst: u64, now_ms: u64) -> Self {
+        let period_ms = period_ms.max(1);
+        TokenBucket {
+            rate,
+            period_ms,
+            burst,
+            scaled_tokens: burst as u128 * period_ms as u128,
+            last_ms: now_ms,
+        }
+    }
+
+    fn refill(&mut self, now_ms: u64) {
+        let elapsed = now_ms.saturating_sub(self.last_ms) as u128;
+        let max = self.burst as u128 * self.period_ms as u128;
+        self.scaled_tokens =
+            (self.scaled_tokens + elapsed * self.rate as u128).min(max);
+        self.last_ms = self.last_ms.max(now_ms);
+    }
+
+    /// Number of tokens available at time `now_ms`.
+    pub fn available(&mut self, now_ms: u64) -> u64 {
+        self.refill(now_ms);
+        (self.scaled_tokens / self.period_ms as u128) as u64
+    }
+
+    /// Checks whether `amount` of tokens can be consumed at time `now_ms`
+    /// without consuming them.
+    pub fn can_consume(&mut self, amount: u64, now_ms: u64) -> bool {
+        self.refill(now_ms);
+        self.scaled_tokens >= amount as u128 * self.period_ms as u128
+    }
+
+    /// Consumes `amount` of tokens, if available at time `now_ms`.
+    pub fn try_consume(&mut self, amount: u64, now_ms: u64) -> bool {
+        if !self.can_consume(amount, now_ms) {
+            return false;
+        }
+        self.scaled_tokens -= amount as u128 * self.period_ms as u128;
+        true
+    }
+
+    /// Maximum number of tokens the bucket can hold.
+    pub fn burst(&self) -> u64 { self.burst }
+
+    /// Returns number of milliseconds after `now_ms` when `amount` of tokens
+    /// will become available, or `None` if it exceeds bucket capacity.
+    pub fn wait_ms(&mut self, amount: u64, now_ms: u64) -> Option<u64> {
+        if amount > self.burst || self.rate == 0 {
+            return None;
+        }
+        self.refill(now_ms);
+        let required = amount as u128 * self.period_ms as u128;
+        let missing = required.saturating_sub(self.scaled_tokens);
+        let rate = self.rate as u128;
+        Some(((missing + rate - 1) / rate) as u64)
+    }
+}
+
+/// Bandwidth limit for data transfers.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{bytes_per_sec} bytes/sec, burst {burst} bytes")]
+pub struct BandwidthLimit {
+    /// Sustained transfer rate, in bytes per second.
+    pub bytes_per_sec: u32,
+    /// Maximum amount of data which can be transferred at once after a
+    /// period of inactivity, in bytes.
+    pub burst: u32,
+}
+
+impl BandwidthLimit {
+    /// Constructs token bucket enforcing the limit, starting at time
+    /// `now_ms`.
+    pub fn token_bucket(self, now_ms: u64) -> TokenBucket {
+        TokenBucket::new(
+            self.bytes_per_sec as u64,
+            1000,
+            self.burst.max(1) as u64,
+            now_ms,
+        )
+    }
+}
+
+/// Enforces [`RateLimit`] on a stream of messages.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RateLimiter {
+    msgs: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit, now_ms: u64) -> Self {
+        let bucket = |rate: u32| {
+            if rate == 0 {
+                None
+            } else {
+                Some(TokenBucket::new(rate as u64, 60_000, rate as u64, now_ms))
+            }
+        };
+        RateLimiter {
+            msgs: bucket(limit.msgs_per_minute),
+            bytes: bucket(limit.bytes_per_minute),
+        }
+    }
+
+    /// Checks whether a message of `len` bytes can be processed at time
+    /// `now_ms`, accounting it if it can.
+    pub fn check(&mut self, len: usize, now_ms: u64) -> Result<(), LimitError> {
+        let msgs_ok = self
+            .msgs
+            .as_mut()
+            .map(|bucket| bucket.can_consume(1, now_ms))
+            .unwrap_or(true);
+        let bytes_ok = self
+            .bytes
+            .as_mut()
+            .map(|bucket| bucket.can_consume(len as u64, now_ms))
+            .unwrap_or(true);
+        if !msgs_ok || !bytes_ok {
+            return Err(LimitError::RateLimited);
+        }
+        if let Some(bucket) = self.msgs.as_mut() {
+            bucket.try_consume(1, now_ms);
+        }
+        if let Some(bucket) = self.bytes.as_mut() {
+            bucket.try_consume(len as u64, now_ms);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stens::AsciiString;
+
+    use super::*;
+
+    #[test]
+    fn test_peer_limits() {
+        let limits = PeerLimits {
+            max_chunk_size: 50,
+            max_container_size: 150,
+            max_transfers: 2,
+        };
+        assert_eq!(limits.chunk_size(100), 50);
+        assert_eq!(limits.chunk_size(10), 10);
+        assert_eq!(PeerLimits::default().chunk_size(0), 1);
+
+        assert_eq!(limits.check_chunk(50), Ok(()));
+        assert_eq!(
+            limits.check_chunk(51),
+            Err(LimitError::ChunkTooLarge(51, 50))
+        );
+
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data[..150], 50)
+                .unwrap();
+        assert_eq!(limits.check_container(&container), Ok(()));
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data, 50).unwrap();
+        assert_eq!(
+            limits.check_container(&container),
+            Err(LimitError::ContainerTooLarge(200, 150))
+        );
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data[..100], 100)
+                .unwrap();
+        assert_eq!(
+            limits.check_container(&container),
+            Err(LimitError::ChunkTooLarge(100, 50))
+        );
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data[..40], 100)
+                .unwrap();
+        assert_eq!(limits.check_container(&container), Ok(()));
+
+        assert_eq!(limits.check_transfers(1), Ok(()));
+        assert_eq!(
+            limits.check_transfers(2),
+            Err(LimitError::TooManyTransfers(2))
+        );
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(10, 1000, 20, 1000);
+        assert_eq!(bucket.available(1000), 20);
+        assert!(bucket.try_consume(15, 1000));
+        assert!(!bucket.can_consume(10, 1000));
+        assert!(!bucket.try_consume(10, 1000));
+        assert_eq!(bucket.wait_ms(10, 1000), Some(500));
+        assert_eq!(bucket.wait_ms(21, 1000), None);
+
+        // Fractions of tokens are accumulated between refills
+        assert_eq!(bucket.available(1250), 7);
+        assert!(bucket.try_consume(7, 1250));
+        assert_eq!(bucket.available(1300), 1);
+
+        // Time going backwards neither refills the bucket nor makes it
+        // refill the same period twice
+        assert_eq!(bucket.available(500), 1);
+        assert_eq!(bucket.available(1300), 1);
+        assert_eq!(bucket.available(1400), 2);
+
+        // Refills never exceed the burst
+        assert_eq!(bucket.available(u64::MAX), 20);
+        assert_eq!(bucket.wait_ms(20, u64::MAX), Some(0));
+        assert_eq!(TokenBucket::new(0, 1000, 20, 0).wait_ms(1, 0), None);
+
+        let limit = BandwidthLimit {
+            bytes_per_sec: 100,
+            burst: 0,
+        };
+        assert_eq!(limit.token_bucket(0).burst(), 1);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limit = RateLimit {
+            msgs_per_minute: 2,
+            bytes_per_minute: 100,
+        };
+        let mut limiter = RateLimiter::new(limit, 0);
+        assert_eq!(limiter.check(40, 0), Ok(()));
+        // Rejected messages are not accounted against any of the limits
+        assert_eq!(limiter.check(70, 0), Err(LimitError::RateLimited));
+        assert_eq!(limiter.check(60, 0), Ok(()));
+        assert_eq!(limiter.check(0, 0), Err(LimitError::RateLimited));
+
+        // Half of the minute refills half of the limits
+        assert_eq!(limiter.check(51, 30_000), Err(LimitError::RateLimited));
+        assert_eq!(limiter.check(50, 30_000), Ok(()));
+        assert_eq!(limiter.check(0, 30_000), Err(LimitError::RateLimited));
+        assert_eq!(limiter.check(0, 10_000), Err(LimitError::RateLimited));
+
+        // Zero values do not limit the rate
+        let mut limiter = RateLimiter::new(RateLimit::default(), 0);
+        for _ in 0..1000 {
+            assert_eq!(limiter.check(1 << 20, 0), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Store-and-forward mailboxes for offline peers.
+//!
+//! A sender which can't reach the recipient deposits the sealed message
+//! with a third-party node holding a mailbox. Once the recipient reconnects,
+//! it collects the mail by presenting a fresh request signed with its node
+//! key; the mailbox hands over all mail deposited for the recipient and
+//! removes it. Mail is encrypted to the recipient, so the mailbox learns
+//! only the recipient identity and the size of the messages.
+//!
+//! Large containers are delivered offline through storage providers: the
+//! sender uploads the container to a provider together with a sealed
+//! notification, which carries the container key and references the
+//! container among the message attachments. The provider keeps the
+//! notification in its mailbox, and the recipient, once it collects the
+//! notification, downloads the container from the provider. This way the
+//! transfer completes even when the parties are never online at the same
+//! time.
+
+use std::collections::BTreeMap;
+
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::encryption::SealedPost;
+use crate::signed::{Signable, Signed};
+use crate::storage::StorageRequest;
+
+/// Maximal age of the collect request, in seconds.
+pub const COLLECT_MAX_AGE: u64 = 600;
+
+/// Default maximal number of messages held for a single recipient.
+pub const DEFAULT_MAILBOX_LIMIT: usize = 256;
+
+/// Errors depositing and collecting mail.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum MailboxError {
+    /// mail has expired at {0}.
+    Expired(u64),
+
+    /// sealed message is not addressed to {0}.
+    NotAddressed(PublicKey),
+
+    /// mailbox of {0} is full.
+    Full(PublicKey),
+
+    /// collect request signature is invalid.
+    InvalidSignature,
+
+    /// collect request is outdated.
+    Outdated,
+
+    /// container storage ends at {0}, before the notification expires.
+    StorageTooShort(u64),
+}
+
+/// Message deposited for an offline recipient.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("mail to {recipient} until {expiry}")]
+pub struct DepositMail {
+    pub recipient: PublicKey,
+    pub sealed_mesg: SealedPost,
+    /// Unix timestamp after which the mail may be discarded.
+    pub expiry: u64,
+}
+
+/// Request of the recipient to hand over mail deposited for it.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("collect mail of {recipient}")]
+pub struct CollectMail {
+    pub recipient: PublicKey,
+    pub timestamp: u64,
+}
+
+impl Signable for CollectMail {
+    const SIGNED_TYPE: u8 = 21;
+}
+
+/// Container uploaded to a storage provider for delivery to an offline
+/// recipient, together with the sealed notification for the recipient.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{storage}, {notification}")]
+pub struct OfflineDelivery {
+    pub storage: StorageRequest,
+    pub notification: DepositMail,
+}
+
+/// Mail handed over to the recipient in response to the collect request.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{recipient}, ...")]
+pub struct MailDelivery {
+    pub recipient: PublicKey,
+    pub mail: Vec<SealedPost>,
+}
+
+/// Mail held by a node for offline recipients.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Mailbox {
+    mail: BTreeMap<PublicKey, Vec<DepositMail>>,
+    limit: usize,
+}
+
+impl Default for Mailbox {
+    fn default() -> Self { Mailbox::with_limit(DEFAULT_MAILBOX_LIMIT) }
+}
+
+impl Mailbox {
+    pub fn new() -> Mailbox { Mailbox::default() }
+
+    /// Constructs mailbox holding at most `limit` messages per recipient.
+    pub fn with_limit(limit: usize) -> Mailbox {
+        Mailbox {
+            mail: empty!(),
+            limit,
+        }
+    }
+
+    /// Number of messages held for the recipient.
+    pub fn count(&self, recipient: PublicKey) -> usize {
+        self.mail.get(&recipient).map(Vec::len).unwrap_or_default()
+    }
+
+    /// Accepts mail deposited at time `now`.
+    pub fn deposit(
+        &mut self,
+        deposit: DepositMail,
+        now: u64,
+    ) -> Result<(), MailboxError> {
+        if deposit.expiry <= now {
+            return Err(MailboxError::Expired(deposit.expiry));
+        }
+        if !deposit.sealed_mesg.is_recipient(deposit.recipient) {
+            return Err(MailboxError::NotAddressed(deposit.recipient));
+        }
+        let mail = self.mail.entry(deposit.recipient).or_default();
+        mail.retain(|mail| mail.expiry > now);
+        if mail.len() >= self.limit {
+            return Err(MailboxError::Full(deposit.recipient));
+        }
+        mail.push(deposit);
+        Ok(())
+    }
+
+    /// Verifies collect request received at time `now` and hands over all
+    /// unexpired mail of the recipient, removing it from the mailbox.
+    pub fn collect<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        request: &Signed<CollectMail>,
+        now: u64,
+    ) -> Result<MailDelivery, MailboxError> {
+        request.verify(secp).map_err(|_| MailboxError::InvalidSignature)?;
+        let recipient = request.data.recipient;
+        if request.signer != recipient {
+            return Err(MailboxError::InvalidSignature);
+        }
+        if request.data.timestamp.saturating_add(COLLECT_MAX_AGE) < now {
+            return Err(MailboxError::Outdated);
+        }
+        let mail = self
+            .mail
+            .remove(&recipient)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|mail| mail.expiry > now)
+            .map(|mail| mail.sealed_mesg)
+            .collect();
+        Ok(MailDelivery { recipient, mail })
+    }
+
+    /// Accepts container uploaded at time `now` for delivery to an offline
+    /// recipient, depositing the notification for the recipient. Returns
+    /// storage request, which has to be processed with the storage flow
+    /// before the container data are accepted.
+    pub fn deliver_offline(
+        &mut self,
+        delivery: OfflineDelivery,
+        now: u64,
+    ) -> Result<StorageRequest, MailboxError> {
+        let storage_end = now.saturating_add(delivery.storage.duration);
+        if delivery.notification.expiry > storage_end {
+            return Err(MailboxError::StorageTooShort(storage_end));
+        }
+        self.deposit(delivery.notification, now)?;
+        Ok(delivery.storage)
+    }
+
+    /// Discards mail which has expired at `now`.
+    pub fn prune(&mut self, now: u64) {
+        self.mail.retain(|_, mail| {
+            mail.retain(|mail| mail.expiry > now);
+            !mail.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::SecretKey;
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::encryption::ContainerKey;
+    use crate::posting::Post;
+    use crate::{Chunk, Mesg, MesgId};
+
+    #[test]
+    fn test_mailbox() {
+        let secp = Secp256k1::new();
+        let recipient_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let other_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let recipient = PublicKey::from_secret_key(&secp, &recipient_key);
+        let other = PublicKey::from_secret_key(&secp, &other_key);
+
+        let post = Post::from(Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"offline".to_vec()).unwrap(),
+            container_ids: vec![],
+        });
+        let sealed_mesg = SealedPost::seal(
+            &secp,
+            post.clone(),
+            empty!(),
+            &ContainerKey::from([1u8; 32]),
+            [recipient],
+            &SecretKey::from_slice(&[0x33; 32]).unwrap(),
+        )
+        .unwrap();
+
+        let mut mailbox = Mailbox::with_limit(1);
+        let deposit = DepositMail {
+            recipient,
+            sealed_mesg,
+            expiry: 200,
+        };
+        assert_eq!(
+            mailbox.deposit(
+                DepositMail {
+                    recipient: other,
+                    ..deposit.clone()
+                },
+                100
+            ),
+            Err(MailboxError::NotAddressed(other))
+        );
+        mailbox.deposit(deposit.clone(), 100).unwrap();
+        assert_eq!(
+            mailbox.deposit(deposit, 100),
+            Err(MailboxError::Full(recipient))
+        );
+
+        let request = CollectMail {
+            recipient,
+            timestamp: 150,
+        };
+        let forged = Signed::sign(&secp, request, &other_key);
+        assert_eq!(
+            mailbox.collect(&secp, &forged, 150),
+            Err(MailboxError::InvalidSignature)
+        );
+        let signed = Signed::sign(&secp, request, &recipient_key);
+        let delivery = mailbox.collect(&secp, &signed, 150).unwrap();
+        assert_eq!(delivery.mail.len(), 1);
+        assert_eq!(
+            delivery.mail[0].open(&secp, &recipient_key).unwrap().0,
+            post
+        );
+        assert_eq!(mailbox.count(recipient), 0);
+    }
+
+    #[test]
+    fn test_offline_delivery() {
+        let secp = Secp256k1::new();
+        let recipient_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let recipient = PublicKey::from_secret_key(&secp, &recipient_key);
+
+        let container_key = ContainerKey::from([2u8; 32]);
+        let (container, _) = container_key
+            .encrypt_container(AsciiString::new(), "", b"large file", 64)
+            .unwrap();
+        let container_id = container.container_id();
+        let post = Post::from(Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"file for you".to_vec()).unwrap(),
+            container_ids: vec![container_id],
+        });
+        let sealed_mesg = SealedPost::seal(
+            &secp,
+            post,
+            bmap! { container_id => container_key },
+            &ContainerKey::from([1u8; 32]),
+            [recipient],
+            &SecretKey::from_slice(&[0x33; 32]).unwrap(),
+        )
+        .unwrap();
+        let storage = StorageRequest {
+            container_id,
+            size: container.header.size,
+            duration: 100,
+            replication: default!(),
+        };
+        let delivery = OfflineDelivery {
+            storage: storage.clone(),
+            notification: DepositMail {
+                recipient,
+                sealed_mesg,
+                expiry: 300,
+            },
+        };
+
+        let mut mailbox = Mailbox::new();
+        assert_eq!(
+            mailbox.deliver_offline(delivery.clone(), 100),
+            Err(MailboxError::StorageTooShort(200))
+        );
+        assert_eq!(mailbox.deliver_offline(delivery, 200), Ok(storage));
+
+        let request = CollectMail {
+            recipient,
+            timestamp: 250,
+        };
+        let signed = Signed::sign(&secp, request, &recipient_key);
+        let delivery = mailbox.collect(&secp, &signed, 250).unwrap();
+        let (post, keys) =
+            delivery.mail[0].open(&secp, &recipient_key).unwrap();
+        assert_eq!(post.mesg.container_ids, vec![container_id]);
+        assert_eq!(keys[&container_id], container_key);
+    }
+}
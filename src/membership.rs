@@ -0,0 +1,406 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Membership of multi-party chat topics.
+//!
+//! A topic is created by its owner, who may invite members and admins;
+//! admins may invite members only. An invited node joins the topic by
+//! presenting the signed invite, and may leave the topic at any time. The
+//! authoritative list of members is published by the owner or an admin as a
+//! signed [`MemberList`] with a growing version number; admins may change
+//! the list of plain members only.
+
+use std::collections::BTreeMap;
+
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::signed::{Signable, SignatureError, Signed};
+use crate::MesgId;
+
+/// Violations of the topic membership rules.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum MembershipError {
+    /// signature of {0} is invalid.
+    InvalidSignature(PublicKey),
+
+    /// membership data relate to topic {0}.
+    TopicMismatch(MesgId),
+
+    /// {0} is not allowed to perform the operation.
+    NotAuthorized(PublicKey),
+
+    /// invite is issued to {0}.
+    WrongInvitee(PublicKey),
+
+    /// invite has expired at {0}.
+    InviteExpired(u64),
+
+    /// {0} is already a member of the topic.
+    AlreadyMember(PublicKey),
+
+    /// {0} is not a member of the topic.
+    NotMember(PublicKey),
+
+    /// topic owner can't leave the topic.
+    OwnerLeaving,
+
+    /// member list version {0} is not greater than the current one.
+    Outdated(u64),
+}
+
+impl From<SignatureError> for MembershipError {
+    fn from(err: SignatureError) -> Self {
+        match err {
+            SignatureError::Invalid(signer) => {
+                MembershipError::InvalidSignature(signer)
+            }
+        }
+    }
+}
+
+/// Role of a topic member.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[strict_encoding(by_value, repr = u8)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[repr(u8)]
+pub enum MemberRole {
+    #[display("member")]
+    Member = 0,
+
+    #[display("admin")]
+    Admin = 1,
+
+    #[display("owner")]
+    Owner = 2,
+}
+
+/// Invitation to join the topic, signed by the topic owner or an admin.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("invite {invitee} to {topic_id} as {role}")]
+pub struct Invite {
+    pub topic_id: MesgId,
+    pub invitee: PublicKey,
+    pub role: MemberRole,
+    /// Unix timestamp after which the invite is no longer valid.
+    pub expiry: u64,
+}
+
+impl Signable for Invite {
+    const SIGNED_TYPE: u8 = 1;
+}
+
+/// Request to join the topic, signed by the joining node.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("join {topic_id}")]
+pub struct Join {
+    pub topic_id: MesgId,
+    pub invite: Signed<Invite>,
+}
+
+impl Signable for Join {
+    const SIGNED_TYPE: u8 = 2;
+}
+
+/// Notification about leaving the topic, signed by the leaving member.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("leave {topic_id}")]
+pub struct Leave {
+    pub topic_id: MesgId,
+    /// Unix timestamp of leaving the topic.
+    pub timestamp: u64,
+}
+
+impl Signable for Leave {
+    const SIGNED_TYPE: u8 = 3;
+}
+
+/// Authoritative list of the topic members.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("members of {topic_id} v{version}")]
+pub struct MemberList {
+    pub topic_id: MesgId,
+    /// Version of the list, growing with each change of the members.
+    pub version: u64,
+    pub members: BTreeMap<PublicKey, MemberRole>,
+}
+
+impl Signable for MemberList {
+    const SIGNED_TYPE: u8 = 4;
+}
+
+impl MemberList {
+    /// Topic owner, if present in the list.
+    pub fn owner(&self) -> Option<PublicKey> {
+        self.members
+            .iter()
+            .find(|(_, role)| **role == MemberRole::Owner)
+            .map(|(node_id, _)| *node_id)
+    }
+
+    pub fn role(&self, node_id: PublicKey) -> Option<MemberRole> {
+        self.members.get(&node_id).copied()
+    }
+}
+
+/// Local view of the topic membership, validating membership changes.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Membership {
+    owner: PublicKey,
+    list: MemberList,
+}
+
+impl Membership {
+    /// Constructs membership of a new topic with the owner as the only
+    /// member.
+    pub fn new(topic_id: MesgId, owner: PublicKey) -> Membership {
+        Membership {
+            owner,
+            list: MemberList {
+                topic_id,
+                version: 0,
+                members: bmap! { owner => MemberRole::Owner },
+            },
+        }
+    }
+
+    pub fn topic_id(&self) -> MesgId { self.list.topic_id }
+
+    pub fn owner(&self) -> PublicKey { self.owner }
+
+    pub fn list(&self) -> &MemberList { &self.list }
+
+    pub fn role(&self, node_id: PublicKey) -> Option<MemberRole> {
+        self.list.role(node_id)
+    }
+
+    fn check_topic(&self, topic_id: MesgId) -> Result<(), MembershipError> {
+        if topic_id != self.list.topic_id {
+            return Err(MembershipError::TopicMismatch(topic_id));
+        }
+        Ok(())
+    }
+
+    /// Checks that the invite is valid at time `now`.
+    pub fn check_invite<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        invite: &Signed<Invite>,
+        now: u64,
+    ) -> Result<(), MembershipError> {
+        invite.verify(secp)?;
+        self.check_topic(invite.data.topic_id)?;
+        if invite.data.expiry < now {
+            return Err(MembershipError::InviteExpired(invite.data.expiry));
+        }
+        let allowed = match (self.role(invite.signer), invite.data.role) {
+            (_, MemberRole::Owner) => false,
+            (Some(MemberRole::Owner), _) => true,
+            (Some(MemberRole::Admin), MemberRole::Member) => true,
+            _ => false,
+        };
+        if !allowed {
+            return Err(MembershipError::NotAuthorized(invite.signer));
+        }
+        Ok(())
+    }
+
+    /// Adds the joining node to the members with the role from the invite.
+    pub fn join<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        join: &Signed<Join>,
+        now: u64,
+    ) -> Result<MemberRole, MembershipError> {
+        join.verify(secp)?;
+        self.check_topic(join.data.topic_id)?;
+        self.check_invite(secp, &join.data.invite, now)?;
+        let invitee = join.data.invite.data.invitee;
+        if invitee != join.signer {
+            return Err(MembershipError::WrongInvitee(invitee));
+        }
+        if self.list.members.contains_key(&invitee) {
+            return Err(MembershipError::AlreadyMember(invitee));
+        }
+        let role = join.data.invite.data.role;
+        self.list.members.insert(invitee, role);
+        self.list.version += 1;
+        Ok(role)
+    }
+
+    /// Removes leaving member.
+    pub fn leave<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        leave: &Signed<Leave>,
+    ) -> Result<(), MembershipError> {
+        leave.verify(secp)?;
+        self.check_topic(leave.data.topic_id)?;
+        if leave.signer == self.owner {
+            return Err(MembershipError::OwnerLeaving);
+        }
+        if self.list.members.remove(&leave.signer).is_none() {
+            return Err(MembershipError::NotMember(leave.signer));
+        }
+        self.list.version += 1;
+        Ok(())
+    }
+
+    /// Replaces local view of the members with the member list published by
+    /// the owner or an admin.
+    pub fn apply_list<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        list: &Signed<MemberList>,
+    ) -> Result<(), MembershipError> {
+        list.verify(secp)?;
+        self.check_topic(list.data.topic_id)?;
+        if list.data.version <= self.list.version {
+            return Err(MembershipError::Outdated(list.data.version));
+        }
+        let privileged = |list: &MemberList| {
+            list.members
+                .iter()
+                .filter(|(_, role)| **role != MemberRole::Member)
+                .map(|(node_id, role)| (*node_id, *role))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let allowed = match self.role(list.signer) {
+            Some(MemberRole::Owner) => list.data.owner() == Some(self.owner),
+            Some(MemberRole::Admin) => {
+                privileged(&list.data) == privileged(&self.list)
+            }
+            _ => false,
+        };
+        let owners = list
+            .data
+            .members
+            .values()
+            .filter(|role| **role == MemberRole::Owner)
+            .count();
+        if !allowed || owners != 1 {
+            return Err(MembershipError::NotAuthorized(list.signer));
+        }
+        self.list = list.data.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn test_membership() {
+        let secp = Secp256k1::new();
+        let owner_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let admin_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let member_key = SecretKey::from_slice(&[0x33; 32]).unwrap();
+        let owner = PublicKey::from_secret_key(&secp, &owner_key);
+        let admin = PublicKey::from_secret_key(&secp, &admin_key);
+        let member = PublicKey::from_secret_key(&secp, &member_key);
+        let topic_id = MesgId::default();
+        let mut membership = Membership::new(topic_id, owner);
+
+        let invite = |key: &SecretKey, invitee, role| {
+            let invite = Invite {
+                topic_id,
+                invitee,
+                role,
+                expiry: 100,
+            };
+            Signed::sign(&secp, invite, key)
+        };
+        let join = |key: &SecretKey, invite| {
+            Signed::sign(&secp, Join { topic_id, invite }, key)
+        };
+
+        let admin_invite = invite(&owner_key, admin, MemberRole::Admin);
+        let admin_join = join(&admin_key, admin_invite.clone());
+        assert_eq!(
+            membership.join(&secp, &admin_join, 200),
+            Err(MembershipError::InviteExpired(100))
+        );
+        assert_eq!(
+            membership.join(&secp, &admin_join, 50),
+            Ok(MemberRole::Admin)
+        );
+
+        let bad_invite = invite(&admin_key, member, MemberRole::Admin);
+        assert_eq!(
+            membership.join(&secp, &join(&member_key, bad_invite), 50),
+            Err(MembershipError::NotAuthorized(admin))
+        );
+        let stolen = join(&member_key, admin_invite);
+        assert_eq!(
+            membership.join(&secp, &stolen, 50),
+            Err(MembershipError::WrongInvitee(admin))
+        );
+        let member_invite = invite(&admin_key, member, MemberRole::Member);
+        membership.join(&secp, &join(&member_key, member_invite), 50).unwrap();
+        assert_eq!(membership.list().members.len(), 3);
+
+        let mut list = membership.list().clone();
+        list.version += 1;
+        list.members.insert(member, MemberRole::Admin);
+        let signed = Signed::sign(&secp, list.clone(), &admin_key);
+        assert_eq!(
+            membership.apply_list(&secp, &signed),
+            Err(MembershipError::NotAuthorized(admin))
+        );
+        let signed = Signed::sign(&secp, list, &owner_key);
+        membership.apply_list(&secp, &signed).unwrap();
+        assert_eq!(membership.role(member), Some(MemberRole::Admin));
+
+        let leave = Signed::sign(
+            &secp,
+            Leave {
+                topic_id,
+                timestamp: 60,
+            },
+            &owner_key,
+        );
+        assert_eq!(
+            membership.leave(&secp, &leave),
+            Err(MembershipError::OwnerLeaving)
+        );
+    }
+}
@@ -8,6 +8,8 @@
 // You should have received a copy of the MIT License along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::BTreeSet;
+
 use bitcoin_hashes::{sha256, sha256t};
 use commit_verify::{
     commit_encode, CommitVerify, ConsensusCommit, PrehashedProtocol, TaggedHash,
@@ -15,7 +17,8 @@ use commit_verify::{
 #[cfg(feature = "serde")]
 use serde_with::{hex::Hex, As};
 
-use crate::ContainerId;
+use crate::chunk::TooLargeData;
+use crate::{Chunk, ContainerId, Tag, TryFromChunk, TryToChunk};
 
 // "storm:message"
 static MIDSTATE_MESG_ID: [u8; 32] = [
@@ -64,12 +67,16 @@ where Msg: AsRef<[u8]>
 )]
 pub struct Topic {
     /// Topic message body. The encoding of the body data and their semantics
-    /// is storm application-specific.
+    /// is storm application-specific; typed data are converted into the body
+    /// with [`crate::TryToChunk`].
     #[cfg_attr(feature = "serde", serde(with = "As::<Hex>"))]
-    pub body: Vec<u8>,
+    pub body: Chunk,
 
     /// Ids of the container attachments.
     pub container_ids: Vec<ContainerId>,
+
+    /// Tags used for the topic discovery, up to [`crate::MAX_TAGS`].
+    pub tags: BTreeSet<Tag>,
 }
 
 impl commit_encode::Strategy for Topic {
@@ -82,6 +89,11 @@ impl ConsensusCommit for Topic {
 
 impl Topic {
     pub fn mesg_id(&self) -> MesgId { self.consensus_commit() }
+
+    /// Checks whether the topic is tagged with all the given tags.
+    pub fn has_tags(&self, tags: &BTreeSet<Tag>) -> bool {
+        tags.is_subset(&self.tags)
+    }
 }
 
 /// Storm message data type
@@ -98,9 +110,10 @@ pub struct Mesg {
     pub parent_id: MesgId,
 
     /// Message body. The encoding of the body data and their semantics is
-    /// storm application-specific.
+    /// storm application-specific; typed data are converted into the body
+    /// with [`crate::TryToChunk`].
     #[cfg_attr(feature = "serde", serde(with = "As::<Hex>"))]
-    pub body: Vec<u8>,
+    pub body: Chunk,
 
     /// Ids of the container attachments.
     pub container_ids: Vec<ContainerId>,
@@ -118,16 +131,127 @@ impl Mesg {
     pub fn mesg_id(&self) -> MesgId { self.consensus_commit() }
 }
 
+/// Topic with the body holding typed payload, which is converted into the
+/// raw body using [`TryToChunk`] and [`TryFromChunk`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TypedTopic<T> {
+    pub payload: T,
+    pub container_ids: Vec<ContainerId>,
+    pub tags: BTreeSet<Tag>,
+}
+
+impl<T> TypedTopic<T>
+where T: TryToChunk
+{
+    pub fn to_topic(&self) -> Result<Topic, TooLargeData> {
+        Ok(Topic {
+            body: self.payload.try_to_chunk()?,
+            container_ids: self.container_ids.clone(),
+            tags: self.tags.clone(),
+        })
+    }
+
+    pub fn mesg_id(&self) -> Result<MesgId, TooLargeData> {
+        self.to_topic().as_ref().map(Topic::mesg_id)
+    }
+}
+
+impl<T> TypedTopic<T>
+where T: TryFromChunk
+{
+    pub fn from_topic(topic: Topic) -> Result<Self, T::Error> {
+        Ok(TypedTopic {
+            payload: T::try_from_chunk(topic.body)?,
+            container_ids: topic.container_ids,
+            tags: topic.tags,
+        })
+    }
+}
+
+/// Message with the body holding typed payload, which is converted into the
+/// raw body using [`TryToChunk`] and [`TryFromChunk`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TypedMesg<T> {
+    pub parent_id: MesgId,
+    pub payload: T,
+    pub container_ids: Vec<ContainerId>,
+}
+
+impl<T> TypedMesg<T>
+where T: TryToChunk
+{
+    pub fn to_mesg(&self) -> Result<Mesg, TooLargeData> {
+        Ok(Mesg {
+            parent_id: self.parent_id,
+            body: self.payload.try_to_chunk()?,
+            container_ids: self.container_ids.clone(),
+        })
+    }
+
+    pub fn mesg_id(&self) -> Result<MesgId, TooLargeData> {
+        self.to_mesg().as_ref().map(Mesg::mesg_id)
+    }
+}
+
+impl<T> TypedMesg<T>
+where T: TryFromChunk
+{
+    pub fn from_mesg(mesg: Mesg) -> Result<Self, T::Error> {
+        Ok(TypedMesg {
+            parent_id: mesg.parent_id,
+            payload: T::try_from_chunk(mesg.body)?,
+            container_ids: mesg.container_ids,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use amplify::Wrapper;
     use commit_verify::tagged_hash;
 
     use super::*;
+    use crate::chunk;
 
     #[test]
     fn test_container_id_midstate() {
         let midstate = tagged_hash::Midstate::with(b"storm:message");
         assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_MESG_ID);
     }
+
+    #[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+    struct Greeting {
+        text: String,
+        reactions: u16,
+    }
+
+    impl chunk::encoding::ApplyStrictEncoding for Greeting {}
+
+    #[test]
+    fn test_typed_mesg() {
+        let typed = TypedMesg {
+            parent_id: MesgId::default(),
+            payload: Greeting {
+                text: s!("hello"),
+                reactions: 2,
+            },
+            container_ids: vec![ContainerId::default()],
+        };
+        let mesg = typed.to_mesg().unwrap();
+        assert_eq!(typed.mesg_id(), Ok(mesg.mesg_id()));
+        assert_eq!(TypedMesg::<Greeting>::from_mesg(mesg).unwrap(), typed);
+
+        let typed = TypedTopic {
+            payload: Greeting {
+                text: s!("topic"),
+                reactions: 0,
+            },
+            container_ids: vec![],
+            tags: bset! { "chat".parse().unwrap() },
+        };
+        let topic = typed.to_topic().unwrap();
+        assert!(topic.has_tags(&bset! { "chat".parse().unwrap() }));
+        assert!(!topic.has_tags(&bset! { "news".parse().unwrap() }));
+        assert_eq!(TypedTopic::<Greeting>::from_topic(topic).unwrap(), typed);
+    }
 }
@@ -0,0 +1,344 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Message store with secondary indexes by topic, parent, author and time.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use secp256k1::PublicKey;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::{Mesg, MesgId};
+
+/// Index key ordering messages chronologically.
+type TimeKey = (u64, MesgId);
+
+/// Message kept in [`MesgStore`] together with its metadata.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mesg} in {topic_id} at {timestamp}")]
+pub struct MesgRecord {
+    pub mesg: Mesg,
+    /// Topic to which the message thread belongs.
+    pub topic_id: MesgId,
+    /// Message author, if known.
+    pub author: Option<PublicKey>,
+    /// Unix timestamp when the message was posted or received.
+    pub timestamp: u64,
+}
+
+impl MesgRecord {
+    fn time_key(&self) -> TimeKey { (self.timestamp, self.mesg.mesg_id()) }
+}
+
+/// Store of messages with secondary indexes answering topic, thread, author
+/// and time queries. Index queries return messages in chronological order.
+///
+/// Only the messages are persisted with strict encoding; the indexes are
+/// rebuilt on decoding.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct MesgStore {
+    records: BTreeMap<MesgId, MesgRecord>,
+    by_topic: BTreeMap<MesgId, BTreeSet<TimeKey>>,
+    by_parent: BTreeMap<MesgId, BTreeSet<TimeKey>>,
+    by_author: BTreeMap<PublicKey, BTreeSet<TimeKey>>,
+    by_time: BTreeSet<TimeKey>,
+}
+
+impl StrictEncode for MesgStore {
+    fn strict_encode<E: io::Write>(
+        &self,
+        e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        self.records.strict_encode(e)
+    }
+}
+
+impl StrictDecode for MesgStore {
+    fn strict_decode<D: io::Read>(
+        d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let records = BTreeMap::<MesgId, MesgRecord>::strict_decode(d)?;
+        let mut store = MesgStore::new();
+        for (mesg_id, record) in records {
+            if record.mesg.mesg_id() != mesg_id {
+                return Err(strict_encoding::Error::DataIntegrityError(
+                    format!("message is stored under wrong id {}", mesg_id),
+                ));
+            }
+            store.index(&record);
+            store.records.insert(mesg_id, record);
+        }
+        Ok(store)
+    }
+}
+
+impl MesgStore {
+    pub fn new() -> MesgStore { MesgStore::default() }
+
+    pub fn len(&self) -> usize { self.records.len() }
+
+    pub fn is_empty(&self) -> bool { self.records.is_empty() }
+
+    pub fn contains(&self, mesg_id: MesgId) -> bool {
+        self.records.contains_key(&mesg_id)
+    }
+
+    pub fn get(&self, mesg_id: MesgId) -> Option<&MesgRecord> {
+        self.records.get(&mesg_id)
+    }
+
+    fn index(&mut self, record: &MesgRecord) {
+        let key = record.time_key();
+        self.by_topic.entry(record.topic_id).or_default().insert(key);
+        self.by_parent.entry(record.mesg.parent_id).or_default().insert(key);
+        if let Some(author) = record.author {
+            self.by_author.entry(author).or_default().insert(key);
+        }
+        self.by_time.insert(key);
+    }
+
+    fn unindex(&mut self, record: &MesgRecord) {
+        let key = record.time_key();
+        fn remove<K: Ord>(
+            index: &mut BTreeMap<K, BTreeSet<TimeKey>>,
+            k: K,
+            key: &TimeKey,
+        ) {
+            if let Some(set) = index.get_mut(&k) {
+                set.remove(key);
+                if set.is_empty() {
+                    index.remove(&k);
+                }
+            }
+        }
+        remove(&mut self.by_topic, record.topic_id, &key);
+        remove(&mut self.by_parent, record.mesg.parent_id, &key);
+        if let Some(author) = record.author {
+            remove(&mut self.by_author, author, &key);
+        }
+        self.by_time.remove(&key);
+    }
+
+    /// Resolves topic of a message with the given parent: if the parent is a
+    /// stored message, the message belongs to the parent topic; otherwise
+    /// the parent is assumed to be the topic itself.
+    fn resolve_topic(&self, parent_id: MesgId) -> MesgId {
+        self.records
+            .get(&parent_id)
+            .map(|parent| parent.topic_id)
+            .unwrap_or(parent_id)
+    }
+
+    /// Adds message to the store, returning its id. If the message is
+    /// already present, its metadata are not changed.
+    ///
+    /// Replies which were stored before this message are moved into the
+    /// message topic.
+    pub fn insert(
+        &mut self,
+        mesg: Mesg,
+        author: Option<PublicKey>,
+        timestamp: u64,
+    ) -> MesgId {
+        let mesg_id = mesg.mesg_id();
+        if self.records.contains_key(&mesg_id) {
+            return mesg_id;
+        }
+        let record = MesgRecord {
+            topic_id: self.resolve_topic(mesg.parent_id),
+            mesg,
+            author,
+            timestamp,
+        };
+        let topic_id = record.topic_id;
+        self.index(&record);
+        self.records.insert(mesg_id, record);
+
+        let mut stack = self.reply_ids(mesg_id);
+        while let Some(reply_id) = stack.pop() {
+            let mut record = match self.records.remove(&reply_id) {
+                Some(record) => record,
+                None => continue,
+            };
+            if record.topic_id != topic_id {
+                self.unindex(&record);
+                record.topic_id = topic_id;
+                self.index(&record);
+                stack.extend(self.reply_ids(reply_id));
+            }
+            self.records.insert(reply_id, record);
+        }
+        mesg_id
+    }
+
+    /// Removes message from the store. Replies to the message are kept.
+    pub fn remove(&mut self, mesg_id: MesgId) -> Option<MesgRecord> {
+        let record = self.records.remove(&mesg_id)?;
+        self.unindex(&record);
+        Some(record)
+    }
+
+    fn reply_ids(&self, parent_id: MesgId) -> Vec<MesgId> {
+        self.by_parent
+            .get(&parent_id)
+            .map(|set| set.iter().map(|(_, id)| *id).collect())
+            .unwrap_or_default()
+    }
+
+    fn resolve<'a>(
+        &'a self,
+        keys: impl Iterator<Item = &'a TimeKey> + 'a,
+    ) -> impl Iterator<Item = &'a MesgRecord> + 'a {
+        keys.filter_map(move |(_, mesg_id)| self.records.get(mesg_id))
+    }
+
+    /// Messages belonging to the topic.
+    pub fn topic(
+        &self,
+        topic_id: MesgId,
+    ) -> impl Iterator<Item = &MesgRecord> + '_ {
+        self.resolve(self.by_topic.get(&topic_id).into_iter().flatten())
+    }
+
+    /// Messages of the topic posted at or after the `since` timestamp, which
+    /// is used for topic synchronization.
+    pub fn topic_since(
+        &self,
+        topic_id: MesgId,
+        since: u64,
+    ) -> impl Iterator<Item = &MesgRecord> + '_ {
+        self.resolve(
+            self.by_topic
+                .get(&topic_id)
+                .into_iter()
+                .flat_map(move |set| set.range((since, MesgId::default())..)),
+        )
+    }
+
+    /// Direct replies to the message or topic.
+    pub fn replies(
+        &self,
+        parent_id: MesgId,
+    ) -> impl Iterator<Item = &MesgRecord> + '_ {
+        self.resolve(self.by_parent.get(&parent_id).into_iter().flatten())
+    }
+
+    /// All messages in the thread started by the message, in depth-first
+    /// order, with replies to each message ordered chronologically.
+    pub fn thread(&self, root_id: MesgId) -> Vec<&MesgRecord> {
+        let mut thread = vec![];
+        let mut stack = self.reply_ids(root_id);
+        stack.reverse();
+        while let Some(mesg_id) = stack.pop() {
+            if let Some(record) = self.records.get(&mesg_id) {
+                thread.push(record);
+                stack.extend(self.reply_ids(mesg_id).into_iter().rev());
+            }
+        }
+        thread
+    }
+
+    /// Messages authored by the node.
+    pub fn author(
+        &self,
+        author: PublicKey,
+    ) -> impl Iterator<Item = &MesgRecord> + '_ {
+        self.resolve(self.by_author.get(&author).into_iter().flatten())
+    }
+
+    /// Messages with timestamps starting at `from` and before `to`.
+    pub fn between(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> impl Iterator<Item = &MesgRecord> + '_ {
+        let range = if from < to {
+            Some((from, MesgId::default())..(to, MesgId::default()))
+        } else {
+            None
+        };
+        self.resolve(
+            range.into_iter().flat_map(move |range| self.by_time.range(range)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+    use crate::Chunk;
+
+    fn mesg(parent_id: MesgId, body: &[u8]) -> Mesg {
+        Mesg {
+            parent_id,
+            body: Chunk::try_from(body).unwrap(),
+            container_ids: vec![],
+        }
+    }
+
+    #[test]
+    fn test_indexes() {
+        let secp = Secp256k1::new();
+        let author = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x11; 32]).unwrap(),
+        );
+        let topic_id = mesg(MesgId::default(), b"topic").mesg_id();
+        let mut store = MesgStore::new();
+
+        let post = mesg(topic_id, b"post");
+        let reply = mesg(post.mesg_id(), b"reply");
+        let nested = mesg(reply.mesg_id(), b"nested");
+        // Replies arriving before their parents are moved into the topic
+        let nested_id = store.insert(nested, None, 30);
+        let reply_id = store.insert(reply, Some(author), 20);
+        assert_eq!(store.get(nested_id).unwrap().topic_id, post.mesg_id());
+        let post_id = store.insert(post, Some(author), 10);
+        assert_eq!(store.get(nested_id).unwrap().topic_id, topic_id);
+
+        let ids = |iter: &mut dyn Iterator<Item = &MesgRecord>| {
+            iter.map(|record| record.mesg.mesg_id()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&mut store.topic(topic_id)), vec![
+            post_id, reply_id, nested_id
+        ]);
+        assert_eq!(ids(&mut store.topic_since(topic_id, 20)), vec![
+            reply_id, nested_id
+        ]);
+        assert_eq!(ids(&mut store.replies(post_id)), vec![reply_id]);
+        assert_eq!(ids(&mut store.author(author)), vec![post_id, reply_id]);
+        assert_eq!(ids(&mut store.between(15, 30)), vec![reply_id]);
+        assert_eq!(
+            store
+                .thread(post_id)
+                .into_iter()
+                .map(|record| record.mesg.mesg_id())
+                .collect::<Vec<_>>(),
+            vec![reply_id, nested_id]
+        );
+
+        let data = store.strict_serialize().unwrap();
+        let decoded = MesgStore::strict_deserialize(data).unwrap();
+        assert_eq!(decoded, store);
+
+        store.remove(reply_id).unwrap();
+        assert_eq!(ids(&mut store.author(author)), vec![post_id]);
+        assert_eq!(store.replies(post_id).count(), 0);
+    }
+}
@@ -0,0 +1,158 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Registry of storm node metrics exportable in Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::StormApp;
+
+/// Monotonically increasing metric.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) { self.add(1) }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 { self.0.load(Ordering::Relaxed) }
+}
+
+/// Metric which may go up and down.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) { self.0.fetch_add(1, Ordering::Relaxed); }
+
+    pub fn dec(&self) { self.0.fetch_sub(1, Ordering::Relaxed); }
+
+    pub fn set(&self, value: i64) { self.0.store(value, Ordering::Relaxed); }
+
+    pub fn get(&self) -> i64 { self.0.load(Ordering::Relaxed) }
+}
+
+/// Counters partitioned by storm app.
+#[derive(Debug, Default)]
+pub struct AppCounter(Mutex<BTreeMap<StormApp, u64>>);
+
+impl AppCounter {
+    pub fn add(&self, app: StormApp, value: u64) {
+        let mut map = self.0.lock().expect("metrics lock poisoned");
+        *map.entry(app).or_default() += value;
+    }
+
+    pub fn get(&self, app: StormApp) -> u64 {
+        let map = self.0.lock().expect("metrics lock poisoned");
+        map.get(&app).copied().unwrap_or_default()
+    }
+
+    fn snapshot(&self) -> BTreeMap<StormApp, u64> {
+        self.0.lock().expect("metrics lock poisoned").clone()
+    }
+}
+
+/// Metrics collected by a storm node.
+#[derive(Debug, Default)]
+pub struct StormMetrics {
+    /// Number of chunks served to remote peers.
+    pub chunks_served: Counter,
+    /// Number of bytes sent to remote peers per app.
+    pub bytes_sent: AppCounter,
+    /// Number of bytes received from remote peers per app.
+    pub bytes_received: AppCounter,
+    /// Number of currently active chunk transfers.
+    pub active_transfers: Gauge,
+    /// Number of chunks or messages which failed verification.
+    pub failed_verifications: Counter,
+    /// Number of received messages rejected due to invalid signatures per
+    /// app.
+    pub rejected_messages: AppCounter,
+}
+
+impl StormMetrics {
+    pub fn new() -> StormMetrics { StormMetrics::default() }
+
+    /// Exports metrics in Prometheus text exposition format.
+    pub fn export_prometheus(&self) -> String {
+        let mut s = String::new();
+        let mut scalar = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(s, "# HELP {} {}", name, help);
+            let _ = writeln!(s, "# TYPE {} {}", name, kind);
+            let _ = writeln!(s, "{} {}", name, value);
+        };
+        scalar(
+            "storm_chunks_served_total",
+            "counter",
+            "Number of chunks served to remote peers.",
+            self.chunks_served.get().to_string(),
+        );
+        scalar(
+            "storm_active_transfers",
+            "gauge",
+            "Number of currently active chunk transfers.",
+            self.active_transfers.get().to_string(),
+        );
+        scalar(
+            "storm_failed_verifications_total",
+            "counter",
+            "Number of chunks or messages which failed verification.",
+            self.failed_verifications.get().to_string(),
+        );
+        for (name, help, counter) in [
+            (
+                "storm_bytes_sent_total",
+                "Number of bytes sent to remote peers.",
+                &self.bytes_sent,
+            ),
+            (
+                "storm_bytes_received_total",
+                "Number of bytes received from remote peers.",
+                &self.bytes_received,
+            ),
+            (
+                "storm_rejected_messages_total",
+                "Number of received messages rejected due to invalid \
+                 signatures.",
+                &self.rejected_messages,
+            ),
+        ] {
+            let _ = writeln!(s, "# HELP {} {}", name, help);
+            let _ = writeln!(s, "# TYPE {} counter", name);
+            for (app, value) in counter.snapshot() {
+                let _ = writeln!(s, "{}{{app=\"{}\"}} {}", name, app, value);
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let metrics = StormMetrics::new();
+        metrics.chunks_served.add(3);
+        metrics.active_transfers.inc();
+        metrics.bytes_sent.add(StormApp::Chat, 1024);
+        let text = metrics.export_prometheus();
+        assert!(text.contains("storm_chunks_served_total 3\n"));
+        assert!(text.contains("storm_active_transfers 1\n"));
+        assert!(text.contains("storm_bytes_sent_total{app=\"chat\"} 1024\n"));
+    }
+}
@@ -0,0 +1,265 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Moderation of chat topics by the topic owner and admins.
+//!
+//! Moderation actions are signed by the topic owner or an admin and are
+//! distributed along with the topic messages. Nodes relaying or storing
+//! topic posts apply them to decide whether a post should be accepted.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::membership::{MemberRole, Membership, MembershipError};
+use crate::signed::{Signable, Signed};
+use crate::{Mesg, MesgId};
+
+/// Ban of a node from posting to the topic.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("ban {node_id} in {topic_id}")]
+pub struct Ban {
+    pub topic_id: MesgId,
+    pub node_id: PublicKey,
+    /// Unix timestamp when the ban ends; `None` for a permanent ban.
+    pub until: Option<u64>,
+    pub reason: String,
+}
+
+impl Signable for Ban {
+    const SIGNED_TYPE: u8 = 5;
+}
+
+/// Lifting of a previously issued ban.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("unban {node_id} in {topic_id}")]
+pub struct Unban {
+    pub topic_id: MesgId,
+    pub node_id: PublicKey,
+}
+
+impl Signable for Unban {
+    const SIGNED_TYPE: u8 = 6;
+}
+
+/// Removal of a message from the topic.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("remove {mesg_id} from {topic_id}")]
+pub struct RemoveMesg {
+    pub topic_id: MesgId,
+    pub mesg_id: MesgId,
+    pub reason: String,
+}
+
+impl Signable for RemoveMesg {
+    const SIGNED_TYPE: u8 = 7;
+}
+
+/// Decision on a topic post taken according to the moderation actions.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Verdict {
+    /// Post may be relayed and stored.
+    #[display("accept")]
+    Accept,
+
+    /// Post author is banned from the topic.
+    #[display("author_banned")]
+    AuthorBanned,
+
+    /// Post was removed by a moderator.
+    #[display("removed")]
+    Removed,
+}
+
+impl Verdict {
+    pub fn is_accepted(self) -> bool { self == Verdict::Accept }
+}
+
+/// Moderation state of a topic.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Moderation {
+    topic_id: MesgId,
+    bans: BTreeMap<PublicKey, Option<u64>>,
+    removed: BTreeSet<MesgId>,
+}
+
+impl Moderation {
+    pub fn new(topic_id: MesgId) -> Moderation {
+        Moderation {
+            topic_id,
+            bans: empty!(),
+            removed: empty!(),
+        }
+    }
+
+    pub fn topic_id(&self) -> MesgId { self.topic_id }
+
+    fn check_moderator<C: Verification, T: Signable>(
+        &self,
+        secp: &Secp256k1<C>,
+        action: &Signed<T>,
+        topic_id: MesgId,
+        membership: &Membership,
+    ) -> Result<MemberRole, MembershipError> {
+        action.verify(secp)?;
+        if topic_id != self.topic_id || topic_id != membership.topic_id() {
+            return Err(MembershipError::TopicMismatch(topic_id));
+        }
+        match membership.role(action.signer) {
+            Some(role) if role != MemberRole::Member => Ok(role),
+            _ => Err(MembershipError::NotAuthorized(action.signer)),
+        }
+    }
+
+    /// Applies ban issued by a moderator. Admins can't ban other admins and
+    /// nobody can ban the topic owner.
+    pub fn ban<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        ban: &Signed<Ban>,
+        membership: &Membership,
+    ) -> Result<(), MembershipError> {
+        let role =
+            self.check_moderator(secp, ban, ban.data.topic_id, membership)?;
+        if membership.role(ban.data.node_id).unwrap_or(MemberRole::Member)
+            >= role
+        {
+            return Err(MembershipError::NotAuthorized(ban.signer));
+        }
+        self.bans.insert(ban.data.node_id, ban.data.until);
+        Ok(())
+    }
+
+    /// Lifts the ban of a node.
+    pub fn unban<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        unban: &Signed<Unban>,
+        membership: &Membership,
+    ) -> Result<(), MembershipError> {
+        self.check_moderator(secp, unban, unban.data.topic_id, membership)?;
+        self.bans.remove(&unban.data.node_id);
+        Ok(())
+    }
+
+    /// Marks message as removed from the topic.
+    pub fn remove_mesg<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        remove: &Signed<RemoveMesg>,
+        membership: &Membership,
+    ) -> Result<(), MembershipError> {
+        self.check_moderator(secp, remove, remove.data.topic_id, membership)?;
+        self.removed.insert(remove.data.mesg_id);
+        Ok(())
+    }
+
+    /// Checks whether the node is banned at time `now`.
+    pub fn is_banned(&self, node_id: PublicKey, now: u64) -> bool {
+        match self.bans.get(&node_id) {
+            None => false,
+            Some(None) => true,
+            Some(Some(until)) => *until > now,
+        }
+    }
+
+    /// Removes bans which have ended before `now`.
+    pub fn prune(&mut self, now: u64) {
+        self.bans
+            .retain(|_, until| until.map(|until| until > now).unwrap_or(true));
+    }
+
+    /// Decides whether a post authored by `author` should be relayed and
+    /// stored at time `now`.
+    pub fn evaluate(
+        &self,
+        author: PublicKey,
+        mesg: &Mesg,
+        now: u64,
+    ) -> Verdict {
+        if self.is_banned(author, now) {
+            Verdict::AuthorBanned
+        } else if self.removed.contains(&mesg.mesg_id()) {
+            Verdict::Removed
+        } else {
+            Verdict::Accept
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::SecretKey;
+
+    use super::*;
+    use crate::Chunk;
+
+    #[test]
+    fn test_moderation() {
+        let secp = Secp256k1::new();
+        let owner_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let user_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let owner = PublicKey::from_secret_key(&secp, &owner_key);
+        let user = PublicKey::from_secret_key(&secp, &user_key);
+        let topic_id = MesgId::default();
+        let membership = Membership::new(topic_id, owner);
+        let mut moderation = Moderation::new(topic_id);
+
+        let mesg = Mesg {
+            parent_id: topic_id,
+            body: Chunk::try_from(b"spam".to_vec()).unwrap(),
+            container_ids: vec![],
+        };
+        assert_eq!(moderation.evaluate(user, &mesg, 0), Verdict::Accept);
+
+        let ban = Ban {
+            topic_id,
+            node_id: user,
+            until: Some(100),
+            reason: s!("spam"),
+        };
+        let forged = Signed::sign(&secp, ban.clone(), &user_key);
+        assert_eq!(
+            moderation.ban(&secp, &forged, &membership),
+            Err(MembershipError::NotAuthorized(user))
+        );
+        let signed = Signed::sign(&secp, ban, &owner_key);
+        moderation.ban(&secp, &signed, &membership).unwrap();
+        assert_eq!(moderation.evaluate(user, &mesg, 50), Verdict::AuthorBanned);
+        assert_eq!(moderation.evaluate(user, &mesg, 100), Verdict::Accept);
+
+        let remove = RemoveMesg {
+            topic_id,
+            mesg_id: mesg.mesg_id(),
+            reason: s!("spam"),
+        };
+        let signed = Signed::sign(&secp, remove, &owner_key);
+        moderation.remove_mesg(&secp, &signed, &membership).unwrap();
+        assert_eq!(moderation.evaluate(owner, &mesg, 100), Verdict::Removed);
+    }
+}
@@ -12,21 +12,182 @@
 
 use std::collections::BTreeSet;
 use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::sync::Arc;
 
+use amplify::Wrapper;
 use internet2::{CreateUnmarshaller, Unmarshaller};
 use once_cell::sync::Lazy;
+use secp256k1::{Secp256k1, Verification, VerifyOnly};
 use strict_encoding::{StrictDecode, StrictEncode};
 
+use crate::authmap::MapProof;
+use crate::bloom::BloomFilter;
+use crate::broadcast::Broadcast;
+use crate::chunk::CompactChunkIds;
 use crate::container::ContainerFullId;
-use crate::mesg::Topic;
+use crate::credit::{Credit, SignedIou};
+use crate::encryption::SealedPost;
+use crate::gcs::ContainerFilter;
+use crate::limits::AppsAdvert;
+use crate::mailbox::{CollectMail, DepositMail, MailDelivery, OfflineDelivery};
+use crate::membership::{Invite, Join, Leave, MemberList};
+#[cfg(feature = "metrics")]
+use crate::metrics::StormMetrics;
+use crate::moderation::{Ban, RemoveMesg, Unban};
+use crate::posting::{
+    DeliveryFailure, Post, PostInvoice, PostInvoiceRequest, PostRequirements,
+    PostingToken, TopicProposal,
+};
+use crate::reconcile::Reconciliation;
+use crate::replication::{AgreementId, ReplicationTerms};
+use crate::reuse::{BlockSums, ReusePlan};
+use crate::rgb::ContractAnnouncement;
+use crate::signed::Signed;
+use crate::sketch::PinSketch;
+use crate::sla::{
+    ChallengeResponse, Dispute, DisputeClaim, DisputeEvidence,
+    DisputeResolution, Evidence, StorageChallenge,
+};
+use crate::storage::{
+    RemovalAck, RemovalRequest, ReplicaPlacement, StorageQuota, StorageReceipt,
+    StorageRequest,
+};
+use crate::swap::{SwapQuote, SwapRequest};
 use crate::{
-    Chunk, ChunkId, Container, ContainerId, ContainerInfo, Mesg, MesgId,
-    StormApp,
+    Chunk, ChunkId, ChunkPriority, ChunkSelection, Container, ContainerId,
+    ContainerInfo, ContainerPage, ContainerPart, HashLockError, Mesg, MesgId,
+    PaymentHash, PaymentPreimage, RetrievalReceipt, SignedEnvelope, StormApp,
+    Tag, Topic,
 };
 
+/// Maximal number of containers announced with a single
+/// `AnnounceContainers` message.
+pub const MAX_ANNOUNCE_BATCH: usize = 1024;
+
 pub static STORM_P2P_UNMARSHALLER: Lazy<Unmarshaller<Messages>> =
     Lazy::new(Messages::create_unmarshaller);
 
+/// Unmarshalls storm P2P message from the raw data.
+pub fn unmarshall(
+    data: &[u8],
+) -> Result<Arc<Messages>, internet2::presentation::Error> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::trace_span!("unmarshall", len = data.len()).entered();
+    let res = STORM_P2P_UNMARSHALLER.unmarshall(data);
+    #[cfg(feature = "tracing")]
+    match &res {
+        Ok(mesg) => tracing::trace!(
+            app = %mesg.storm_app(),
+            mesg = %mesg,
+            "storm message unmarshalled"
+        ),
+        Err(err) => {
+            tracing::debug!(error = %err, "unable to unmarshall storm message")
+        }
+    }
+    res
+}
+
+/// Errors produced by [`VerifyingUnmarshaller`].
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum UnmarshallError {
+    /// unable to decode storm message: {0}
+    #[from]
+    Decode(internet2::presentation::Error),
+
+    /// message for {0} app carries invalid signature.
+    Forged(StormApp),
+}
+
+/// Unmarshaller which verifies signatures of the signed payloads and the
+/// identity bindings of message authors, rejecting forged messages before
+/// they reach the application logic.
+pub struct VerifyingUnmarshaller {
+    secp: Secp256k1<VerifyOnly>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<StormMetrics>>,
+}
+
+impl Default for VerifyingUnmarshaller {
+    fn default() -> Self {
+        VerifyingUnmarshaller {
+            secp: Secp256k1::verification_only(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+}
+
+impl VerifyingUnmarshaller {
+    pub fn new() -> VerifyingUnmarshaller { VerifyingUnmarshaller::default() }
+
+    /// Constructs unmarshaller accounting rejected messages in the metrics.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(metrics: Arc<StormMetrics>) -> VerifyingUnmarshaller {
+        VerifyingUnmarshaller {
+            metrics: Some(metrics),
+            ..default!()
+        }
+    }
+
+    /// Unmarshalls storm P2P message and verifies its signatures.
+    pub fn unmarshall(
+        &self,
+        data: &[u8],
+    ) -> Result<Arc<Messages>, UnmarshallError> {
+        let mesg = unmarshall(data)?;
+        if !mesg.has_valid_signatures(&self.secp) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                mesg = %mesg,
+                "rejecting message with invalid signature"
+            );
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                metrics.rejected_messages.add(mesg.storm_app(), 1);
+            }
+            return Err(UnmarshallError::Forged(mesg.storm_app()));
+        }
+        Ok(mesg)
+    }
+}
+
+/// Number of leading characters of ids shown in summarizing displays.
+const SHORT_ID_LEN: usize = 8;
+
+/// Number of items of large sets shown in summarizing displays.
+const SUMMARY_ITEMS: usize = 3;
+
+/// Shortens id to its first characters for displaying in logs.
+pub(crate) fn short_id(id: impl Display) -> String {
+    let mut id = id.to_string();
+    if let Some((pos, _)) = id.char_indices().nth(SHORT_ID_LEN) {
+        id.truncate(pos);
+        id.push_str("...");
+    }
+    id
+}
+
+/// Writes summary of a large set: number of the items followed by the first
+/// few of them. The item `noun` is given in singular.
+pub(crate) fn fmt_summary<T: Display>(
+    f: &mut Formatter<'_>,
+    noun: &str,
+    items: impl ExactSizeIterator<Item = T>,
+) -> fmt::Result {
+    let count = items.len();
+    write!(f, "{} {}{}", count, noun, if count == 1 { "" } else { "s" })?;
+    for (index, item) in items.take(SUMMARY_ITEMS).enumerate() {
+        write!(f, "{}{}", if index == 0 { ": " } else { ", " }, item)?;
+    }
+    if count > SUMMARY_ITEMS {
+        f.write_str(", ...")?;
+    }
+    Ok(())
+}
+
 pub trait StormMesg {
     fn storm_app(&self) -> StormApp;
 }
@@ -40,10 +201,11 @@ pub enum Messages {
     #[api(type = 0x0002)]
     ListApps,
 
-    /// List of Storm apps registered with the node for public announcement.
-    #[display("active_apps(...)")]
+    /// List of Storm apps registered with the node for public announcement,
+    /// together with the node limits on served and accepted data.
+    #[display("active_apps({0})")]
     #[api(type = 0x0003)]
-    ActiveApps(BTreeSet<StormApp>),
+    ActiveApps(AppsAdvert),
 
     /// List topics under the specified app.
     #[display("list_topics(...)")]
@@ -58,27 +220,29 @@ pub enum Messages {
     /// Propose to create a new Storm application topic.
     #[display("propose_topic(...)")]
     #[api(type = 0x0006)]
-    ProposeTopic(AppMsg<Topic>),
+    ProposeTopic(AppMsg<TopicProposal>),
 
     /// Post a message under specific app and topic from one peer to another.
     /// Can be a reply to `Read` message or a spontaneous message, which will
     /// require reply in form of `Accept` or `Decline` messages.
     #[api(type = 0x0008)]
     #[display("post({0})")]
-    Post(AppMsg<Mesg>),
+    Post(AppMsg<Post>),
 
     /// Read a message or a topic from an app.
     #[api(type = 0x000a)]
     #[display("read({0})")]
     Read(AppMsg<MesgId>),
 
+    /// Refuse to accept the message, stating the reason.
     #[api(type = 0x000c)]
     #[display("decline({0})")]
-    Decline(AppMsg<MesgId>),
+    Decline(AppMsg<Decline>),
 
+    /// Accept the message, optionally under the attached terms.
     #[api(type = 0x000e)]
     #[display("accept({0})")]
-    Accept(AppMsg<MesgId>),
+    Accept(AppMsg<Accept>),
 
     // TODO: Consider using Storm Post message for this.
     /// Announce container.
@@ -86,6 +250,13 @@ pub enum Messages {
     #[display("announce_container({0})")]
     AnnounceContainer(AppMsg<ContainerInfo>),
 
+    /// Announce multiple containers at once, up to [`MAX_ANNOUNCE_BATCH`]
+    /// of them. Used when announcing a large catalog, for instance after
+    /// reconnection.
+    #[api(type = 0x0060)]
+    #[display("announce_containers(...)")]
+    AnnounceContainers(AppMsg<Vec<ContainerInfo>>),
+
     /// Request to obtain container information.
     #[api(type = 0x0010)]
     #[display("pull_container({0})")]
@@ -97,20 +268,591 @@ pub enum Messages {
     #[display("push_container(...)")]
     PushContainer(AppMsg<Container>),
 
-    /// Reject to provide the container
+    /// Reject to provide the container, stating the reason.
     #[api(type = 0x0012)]
     #[display("reject({0})")]
-    Reject(AppMsg<ContainerFullId>),
+    Reject(AppMsg<Reject>),
 
     /// Pull a chunk data from a peer, if they are known to it.
     #[api(type = 0x0014)]
     #[display("pull_chunk({0})")]
     PullChunk(ChunkPull),
 
-    /// Response to a chunk pull request, providing source data.
+    /// Response to a chunk pull request, providing source data. The data may
+    /// be encrypted with the [`crate::TransferKey`] derived by both peers.
     #[api(type = 0x0015)]
     #[display("push_chunk({0})")]
     PushChunk(ChunkPush),
+
+    /// Response to a chunk pull request, providing source data encrypted
+    /// with the preimage of a lightning payment hash. The receiver can
+    /// decrypt the data only once it pays the invoice with that hash.
+    #[api(type = 0x0017)]
+    #[display("push_locked_chunk({0})")]
+    PushLockedChunk(LockedChunkPush),
+
+    /// Reveal payment preimage unlocking chunks previously delivered with
+    /// `PushLockedChunk` message.
+    #[api(type = 0x0019)]
+    #[display("reveal_key({0})")]
+    RevealKey(KeyReveal),
+
+    /// Request a quote for paid retrieval of container chunks.
+    #[api(type = 0x0020)]
+    #[display("request_quote({0})")]
+    RequestQuote(AppMsg<SwapRequest>),
+
+    /// Quote for paid chunk retrieval, containing lightning invoice whose
+    /// payment hash locks the delivered chunks.
+    #[api(type = 0x0021)]
+    #[display("quote({0})")]
+    Quote(AppMsg<SwapQuote>),
+
+    /// Pull chunks of a streaming media container covering a playback time
+    /// range. Peer responds with `PushChunk` messages in playback order.
+    #[api(type = 0x0022)]
+    #[display("pull_stream_chunks({0})")]
+    PullStreamChunks(StreamChunkPull),
+
+    /// Advertise maximum chunk size the peer accepts and serves for the app.
+    /// Containers produced for this peer should use chunks not exceeding
+    /// this size.
+    #[api(type = 0x0024)]
+    #[display("chunk_size_limit({0})")]
+    ChunkSizeLimit(AppMsg<u32>),
+
+    /// Request messages posted under a topic after some cursor position,
+    /// allowing reconnecting peers to fetch only the messages they missed.
+    #[api(type = 0x0026)]
+    #[display("sync_mesgs({0})")]
+    SyncMesgs(AppMsg<MesgSync>),
+
+    /// Response to `SyncMesgs` request with a batch of topic messages.
+    #[api(type = 0x0027)]
+    #[display("synced_mesgs({0})")]
+    SyncedMesgs(AppMsg<MesgBatch>),
+
+    /// Round of topic history reconciliation, sent by both peers until the
+    /// message contains no range items.
+    #[api(type = 0x0028)]
+    #[display("reconcile({0})")]
+    Reconcile(AppMsg<Reconciliation>),
+
+    /// Compact PinSketch of the container chunks available to the peer,
+    /// allowing peers with mostly overlapping chunk sets to find which chunks
+    /// each of them is missing.
+    #[api(type = 0x002a)]
+    #[display("chunk_sketch({0})")]
+    ChunkSketch(ChunkSetSketch),
+
+    /// Bloom filter of the container chunks available to the peer, for cases
+    /// where exact chunk lists are too large or not desired to be shared.
+    #[api(type = 0x002c)]
+    #[display("chunk_filter({0})")]
+    ChunkFilter(ChunkBloomFilter),
+
+    /// Request filter summarizing all containers the node serves under the
+    /// app.
+    #[api(type = 0x002e)]
+    #[display("get_container_filter(...)")]
+    GetContainerFilter(AppMsg<()>),
+
+    /// Golomb-coded set filter of containers served by the node under the
+    /// app, allowing clients to skip nodes which definitely lack the content.
+    #[api(type = 0x002f)]
+    #[display("container_filter(...)")]
+    ContainerFilter(AppMsg<ContainerFilter>),
+
+    /// Request an IOU acknowledging the service provided to the peer. The
+    /// request contains cumulative amounts of the service as known to the
+    /// requesting node.
+    #[api(type = 0x0030)]
+    #[display("request_iou({0})")]
+    RequestIou(Credit),
+
+    /// IOU acknowledging cumulative amounts of data relayed or stored by the
+    /// receiving peer on behalf of the sender.
+    #[api(type = 0x0031)]
+    #[display("iou({0})")]
+    Iou(SignedIou),
+
+    /// Propose to the remote storage provider mutual replication of
+    /// containers.
+    #[api(type = 0x0032)]
+    #[display("propose_replication({0})")]
+    ProposeReplication(AppMsg<ReplicationTerms>),
+
+    /// Accept replication agreement proposed by the remote provider.
+    #[api(type = 0x0033)]
+    #[display("accept_replication({0})")]
+    AcceptReplication(AppMsg<AgreementId>),
+
+    /// Decline replication agreement proposed by the remote provider.
+    #[api(type = 0x0034)]
+    #[display("decline_replication({0})")]
+    DeclineReplication(AppMsg<AgreementId>),
+
+    /// Request storage provider to store container under the specified
+    /// replication constraints.
+    #[api(type = 0x0036)]
+    #[display("request_storage({0})")]
+    RequestStorage(AppMsg<StorageRequest>),
+
+    /// Response to `RequestStorage` committing to the placement of the
+    /// container replicas.
+    #[api(type = 0x0037)]
+    #[display("storage_placement({0})")]
+    StoragePlacement(AppMsg<ReplicaPlacement>),
+
+    /// Invite node to join chat topic.
+    #[api(type = 0x0038)]
+    #[display("invite({0})")]
+    Invite(AppMsg<Signed<Invite>>),
+
+    /// Join chat topic using the invite.
+    #[api(type = 0x0039)]
+    #[display("join({0})")]
+    Join(AppMsg<Signed<Join>>),
+
+    /// Leave chat topic.
+    #[api(type = 0x003a)]
+    #[display("leave({0})")]
+    Leave(AppMsg<Signed<Leave>>),
+
+    /// Authoritative list of the chat topic members.
+    #[api(type = 0x003b)]
+    #[display("member_list({0})")]
+    MemberList(AppMsg<Signed<MemberList>>),
+
+    /// Ban node from posting to the topic.
+    #[api(type = 0x003c)]
+    #[display("ban({0})")]
+    Ban(AppMsg<Signed<Ban>>),
+
+    /// Lift previously issued ban.
+    #[api(type = 0x003d)]
+    #[display("unban({0})")]
+    Unban(AppMsg<Signed<Unban>>),
+
+    /// Remove message from the topic.
+    #[api(type = 0x003e)]
+    #[display("remove_mesg({0})")]
+    RemoveMesg(AppMsg<Signed<RemoveMesg>>),
+
+    /// Post a message signed by its author identity.
+    #[api(type = 0x003f)]
+    #[display("signed_post({0})")]
+    SignedPost(AppMsg<SignedEnvelope>),
+
+    /// Requirements for posting to the topic advertised by the topic owner.
+    #[api(type = 0x0040)]
+    #[display("post_requirements({0})")]
+    PostRequirements(AppMsg<PostRequirements>),
+
+    /// Request the topic owner for an invoice paying for a post.
+    #[api(type = 0x0041)]
+    #[display("request_post_invoice({0})")]
+    RequestPostInvoice(AppMsg<PostInvoiceRequest>),
+
+    /// Invoice for a post signed by the topic owner.
+    #[api(type = 0x0042)]
+    #[display("post_invoice({0})")]
+    PostInvoice(AppMsg<Signed<PostInvoice>>),
+
+    /// Posting token issued by the topic owner to a member.
+    #[api(type = 0x0043)]
+    #[display("posting_token({0})")]
+    PostingToken(AppMsg<Signed<PostingToken>>),
+
+    /// Hint the seeder about chunks which will be pulled soon, allowing it
+    /// to load them from slow storage before the `PullChunk` arrives. The
+    /// message does not require a response.
+    #[api(type = 0x0044)]
+    #[display("prefetch_hint({0})")]
+    PrefetchHint(PrefetchHint),
+
+    /// List containers offered by the node under the app, optionally
+    /// filtered by MIME type. Large lists are returned in pages.
+    #[api(type = 0x0046)]
+    #[display("list_containers({0})")]
+    ListContainers(AppMsg<ContainerQuery>),
+
+    /// Response to `ListContainers` request with a page of containers.
+    #[api(type = 0x0047)]
+    #[display("container_list({0})")]
+    ContainerList(AppMsg<ContainerList>),
+
+    /// Request storage provider to remove container previously uploaded by
+    /// the sender. The request is signed by the container owner.
+    #[api(type = 0x0048)]
+    #[display("request_removal({0})")]
+    RequestRemoval(AppMsg<Signed<RemovalRequest>>),
+
+    /// Confirmation of the container removal signed by the storage
+    /// provider.
+    #[api(type = 0x0049)]
+    #[display("removal_ack({0})")]
+    RemovalAck(AppMsg<Signed<RemovalAck>>),
+
+    /// Receipt of a successful container upload signed by the storage
+    /// provider.
+    #[api(type = 0x004a)]
+    #[display("storage_receipt({0})")]
+    StorageReceipt(AppMsg<Signed<StorageReceipt>>),
+
+    /// Receipt of a successful container retrieval signed by the downloader
+    /// and sent to the provider which served the container chunks.
+    #[api(type = 0x004b)]
+    #[display("retrieval_receipt({0})")]
+    RetrievalReceipt(AppMsg<Signed<RetrievalReceipt>>),
+
+    /// Challenge the storage provider to prove it holds a container chunk.
+    #[api(type = 0x004c)]
+    #[display("storage_challenge({0})")]
+    StorageChallenge(AppMsg<StorageChallenge>),
+
+    /// Response to the storage challenge signed by the provider.
+    #[api(type = 0x004d)]
+    #[display("challenge_response({0})")]
+    ChallengeResponse(AppMsg<Signed<ChallengeResponse>>),
+
+    /// Open dispute over a storage agreement, sent by the client to the
+    /// provider and the arbiter.
+    #[api(type = 0x004e)]
+    #[display("open_dispute(...)")]
+    OpenDispute(AppMsg<Signed<Dispute>>),
+
+    /// Evidence submitted by a dispute party.
+    #[api(type = 0x004f)]
+    #[display("dispute_evidence(...)")]
+    DisputeEvidence(AppMsg<Signed<DisputeEvidence>>),
+
+    /// Resolution of a dispute signed by the arbiter.
+    #[api(type = 0x0050)]
+    #[display("resolve_dispute({0})")]
+    ResolveDispute(AppMsg<Signed<DisputeResolution>>),
+
+    /// Announce RGB contract available from the node.
+    #[api(type = 0x0051)]
+    #[display("announce_contract({0})")]
+    AnnounceContract(ContractAnnouncement),
+
+    /// Post encrypted once for multiple recipients, fanned out to each of
+    /// them.
+    #[api(type = 0x0052)]
+    #[display("sealed_post(...)")]
+    SealedPost(AppMsg<SealedPost>),
+
+    /// Broadcast of the topic owner, which must be forwarded to every peer
+    /// subscribed to the topic.
+    #[api(type = 0x0053)]
+    #[display("broadcast({0})")]
+    Broadcast(AppMsg<Signed<Broadcast>>),
+
+    /// Deposit sealed message with a mailbox node for an offline recipient.
+    #[api(type = 0x0054)]
+    #[display("deposit_mail({0})")]
+    DepositMail(AppMsg<DepositMail>),
+
+    /// Request the mailbox node to hand over mail deposited for the signer.
+    #[api(type = 0x0055)]
+    #[display("collect_mail({0})")]
+    CollectMail(AppMsg<Signed<CollectMail>>),
+
+    /// Response to `CollectMail` request handing over the deposited mail.
+    #[api(type = 0x0056)]
+    #[display("mail_delivery({0})")]
+    MailDelivery(AppMsg<MailDelivery>),
+
+    /// Upload container to a storage provider for delivery to an offline
+    /// recipient, depositing sealed notification for the recipient.
+    #[api(type = 0x0057)]
+    #[display("deliver_offline({0})")]
+    DeliverOffline(AppMsg<OfflineDelivery>),
+
+    /// Notify the sender that delivery of its post was abandoned.
+    #[api(type = 0x0058)]
+    #[display("delivery_failure({0})")]
+    DeliveryFailure(AppMsg<DeliveryFailure>),
+
+    /// Pull chunks listed in the compact encoding, which is preferred over
+    /// `PullChunk` for requests of many chunks.
+    #[api(type = 0x0059)]
+    #[display("pull_chunk_compact({0})")]
+    PullChunkCompact(CompactChunkPull),
+
+    /// Request part of the container index covering the selected chunks.
+    #[api(type = 0x005a)]
+    #[display("pull_container_part({0})")]
+    PullContainerPart(AppMsg<ContainerPartPull>),
+
+    /// Response to `PullContainerPart` providing the selected chunk ids
+    /// together with the proofs of their inclusion into the container.
+    #[api(type = 0x005b)]
+    #[display("push_container_part(...)")]
+    PushContainerPart(AppMsg<ContainerPart>),
+
+    /// Response on container pull request providing a page of the container
+    /// index, used instead of `PushContainer` for containers with large
+    /// indexes.
+    #[api(type = 0x005c)]
+    #[display("push_container_page({0})")]
+    PushContainerPage(AppMsg<ContainerPage>),
+
+    /// Check that the app is served by the peer. Unlike connection-level
+    /// pings, the peer replies with `AppPong` only if it serves the app.
+    #[api(type = 0x005d)]
+    #[display("app_ping({0})")]
+    AppPing(AppMsg<u64>),
+
+    /// Response to `AppPing`, echoing its app and nonce.
+    #[api(type = 0x005e)]
+    #[display("app_pong({0})")]
+    AppPong(AppMsg<u64>),
+
+    /// Advertise remaining storage capacity and the range of container
+    /// sizes accepted by the storage provider.
+    #[api(type = 0x005f)]
+    #[display("storage_quota({0})")]
+    StorageQuota(AppMsg<StorageQuota>),
+
+    /// Find public topics of the app tagged with all the given tags.
+    #[api(type = 0x0061)]
+    #[display("find_topics({0})")]
+    FindTopics(AppMsg<FindTopics>),
+
+    /// Response to `FindTopics` request.
+    #[api(type = 0x0062)]
+    #[display("found_topics(...)")]
+    FoundTopics(AppMsg<Vec<Topic>>),
+
+    /// Request value of a key from an authenticated map container.
+    #[api(type = 0x0063)]
+    #[display("lookup_key({0})")]
+    LookupKey(AppMsg<KeyLookup>),
+
+    /// Response to `LookupKey` proving the value of the key or its absence.
+    #[api(type = 0x0064)]
+    #[display("key_proof({0})")]
+    KeyProof(AppMsg<MapProof>),
+
+    /// Report to the peer that the data it pushed do not match the
+    /// requested chunk id.
+    #[api(type = 0x0065)]
+    #[display("chunk_mismatch({0})")]
+    ChunkMismatch(ChunkMismatch),
+
+    /// Offer compression dictionaries held for the app, in the order of
+    /// preference.
+    #[api(type = 0x0066)]
+    #[display("offer_dictionaries(...)")]
+    OfferDictionaries(AppMsg<Vec<ContainerId>>),
+
+    /// Response to `OfferDictionaries` selecting the dictionary to compress
+    /// message bodies of the app with, if any.
+    #[api(type = 0x0067)]
+    #[display("select_dictionary(...)")]
+    SelectDictionary(AppMsg<Option<ContainerId>>),
+
+    /// Provide checksums of the local data similar to the requested
+    /// container, asking which of them can be reused.
+    #[api(type = 0x0068)]
+    #[display("block_sums({0})")]
+    BlockSums(AppMsg<BlockSums>),
+
+    /// Response to `BlockSums` listing local blocks to reuse and chunks to
+    /// pull.
+    #[api(type = 0x0069)]
+    #[display("reuse_blocks({0})")]
+    ReuseBlocks(AppMsg<ReusePlan>),
+
+    /// Hint on the order in which chunks of the pushed container should be
+    /// retrieved.
+    #[api(type = 0x006a)]
+    #[display("chunk_priority({0})")]
+    ChunkPriority(AppMsg<ChunkPriority>),
+}
+
+impl Messages {
+    /// Constructs `AnnounceContainers` messages announcing all the
+    /// containers, splitting them into batches of at most
+    /// [`MAX_ANNOUNCE_BATCH`] containers.
+    pub fn announce_containers(
+        app: StormApp,
+        containers: impl IntoIterator<Item = ContainerInfo>,
+    ) -> Vec<Messages> {
+        let mut messages = vec![];
+        let mut batch = Vec::with_capacity(MAX_ANNOUNCE_BATCH);
+        for info in containers {
+            batch.push(info);
+            if batch.len() == MAX_ANNOUNCE_BATCH {
+                let data = std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(MAX_ANNOUNCE_BATCH),
+                );
+                messages
+                    .push(Messages::AnnounceContainers(AppMsg::new(app, data)));
+            }
+        }
+        if !batch.is_empty() {
+            messages
+                .push(Messages::AnnounceContainers(AppMsg::new(app, batch)));
+        }
+        messages
+    }
+
+    /// Produces `AppPong` reply to `AppPing` message if the app is among
+    /// the apps served by the node.
+    pub fn app_pong(&self, served: &BTreeSet<StormApp>) -> Option<Messages> {
+        match self {
+            Messages::AppPing(ping) if served.contains(&ping.app) => {
+                Some(Messages::AppPong(*ping))
+            }
+            _ => None,
+        }
+    }
+
+    /// Verifies signatures of the signed message payloads, including the
+    /// signed data nested into them. Messages without signed payloads are
+    /// always valid.
+    pub fn has_valid_signatures<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> bool {
+        match self {
+            Messages::ListApps
+            | Messages::ActiveApps(_)
+            | Messages::ListTopics(_)
+            | Messages::AppTopics(_)
+            | Messages::ProposeTopic(_)
+            | Messages::Read(_)
+            | Messages::Decline(_)
+            | Messages::Accept(_)
+            | Messages::AnnounceContainer(_)
+            | Messages::AnnounceContainers(_)
+            | Messages::PullContainer(_)
+            | Messages::PushContainer(_)
+            | Messages::Reject(_)
+            | Messages::PullChunk(_)
+            | Messages::PushChunk(_)
+            | Messages::PushLockedChunk(_)
+            | Messages::RevealKey(_)
+            | Messages::RequestQuote(_)
+            | Messages::Quote(_)
+            | Messages::PullStreamChunks(_)
+            | Messages::ChunkSizeLimit(_)
+            | Messages::SyncMesgs(_)
+            | Messages::SyncedMesgs(_)
+            | Messages::Reconcile(_)
+            | Messages::ChunkSketch(_)
+            | Messages::ChunkFilter(_)
+            | Messages::GetContainerFilter(_)
+            | Messages::ContainerFilter(_)
+            | Messages::RequestIou(_)
+            | Messages::ProposeReplication(_)
+            | Messages::AcceptReplication(_)
+            | Messages::DeclineReplication(_)
+            | Messages::RequestStorage(_)
+            | Messages::StoragePlacement(_)
+            | Messages::PostRequirements(_)
+            | Messages::RequestPostInvoice(_)
+            | Messages::PrefetchHint(_)
+            | Messages::ListContainers(_)
+            | Messages::ContainerList(_)
+            | Messages::StorageChallenge(_)
+            | Messages::AnnounceContract(_)
+            | Messages::SealedPost(_)
+            | Messages::DepositMail(_)
+            | Messages::MailDelivery(_)
+            | Messages::DeliverOffline(_)
+            | Messages::DeliveryFailure(_)
+            | Messages::PullChunkCompact(_)
+            | Messages::PullContainerPart(_)
+            | Messages::PushContainerPart(_)
+            | Messages::PushContainerPage(_)
+            | Messages::AppPing(_)
+            | Messages::AppPong(_)
+            | Messages::StorageQuota(_)
+            | Messages::FindTopics(_)
+            | Messages::FoundTopics(_)
+            | Messages::LookupKey(_)
+            | Messages::KeyProof(_)
+            | Messages::ChunkMismatch(_)
+            | Messages::OfferDictionaries(_)
+            | Messages::SelectDictionary(_)
+            | Messages::BlockSums(_)
+            | Messages::ReuseBlocks(_)
+            | Messages::ChunkPriority(_) => true,
+
+            Messages::Post(msg) => has_valid_post_signatures(&msg.data, secp),
+            Messages::Iou(iou) => iou.verify(secp).is_ok(),
+            Messages::Invite(msg) => msg.data.verify(secp).is_ok(),
+            Messages::Join(msg) => {
+                msg.data.verify(secp).is_ok()
+                    && msg.data.data.invite.verify(secp).is_ok()
+            }
+            Messages::Leave(msg) => msg.data.verify(secp).is_ok(),
+            Messages::MemberList(msg) => msg.data.verify(secp).is_ok(),
+            Messages::Ban(msg) => msg.data.verify(secp).is_ok(),
+            Messages::Unban(msg) => msg.data.verify(secp).is_ok(),
+            Messages::RemoveMesg(msg) => msg.data.verify(secp).is_ok(),
+            Messages::SignedPost(msg) => msg.data.verify(secp).is_ok(),
+            Messages::PostInvoice(msg) => msg.data.verify(secp).is_ok(),
+            Messages::PostingToken(msg) => msg.data.verify(secp).is_ok(),
+            Messages::RequestRemoval(msg) => msg.data.verify(secp).is_ok(),
+            Messages::RemovalAck(msg) => msg.data.verify(secp).is_ok(),
+            Messages::StorageReceipt(msg) => msg.data.verify(secp).is_ok(),
+            Messages::RetrievalReceipt(msg) => msg.data.verify(secp).is_ok(),
+            Messages::ChallengeResponse(msg) => msg.data.verify(secp).is_ok(),
+            Messages::OpenDispute(msg) => {
+                let dispute = &msg.data.data;
+                msg.data.verify(secp).is_ok()
+                    && dispute.receipt.verify(secp).is_ok()
+                    && match &dispute.claim {
+                        DisputeClaim::Violation(proof) => {
+                            proof.receipt.verify(secp).is_ok()
+                                && proof.response.verify(secp).is_ok()
+                        }
+                        DisputeClaim::Unanswered(_) => true,
+                    }
+            }
+            Messages::DisputeEvidence(msg) => {
+                msg.data.verify(secp).is_ok()
+                    && match &msg.data.data.evidence {
+                        Evidence::ChallengeResponse(response) => {
+                            response.verify(secp).is_ok()
+                        }
+                        Evidence::RetrievalReceipt(receipt) => {
+                            receipt.verify(secp).is_ok()
+                        }
+                        Evidence::Other(_) => true,
+                    }
+            }
+            Messages::ResolveDispute(msg) => msg.data.verify(secp).is_ok(),
+            Messages::Broadcast(msg) => {
+                msg.data.verify(secp).is_ok()
+                    && has_valid_post_signatures(&msg.data.data.post, secp)
+            }
+            Messages::CollectMail(msg) => msg.data.verify(secp).is_ok(),
+        }
+    }
+}
+
+/// Verifies signatures of the posting token and payment invoice attached to
+/// the post.
+fn has_valid_post_signatures<C: Verification>(
+    post: &Post,
+    secp: &Secp256k1<C>,
+) -> bool {
+    let token_valid = post.token.as_ref().map_or(true, |token_use| {
+        token_use.verify(secp).is_ok()
+            && token_use.data.token.verify(secp).is_ok()
+    });
+    let payment_valid = post
+        .payment
+        .as_ref()
+        .map_or(true, |payment| payment.invoice.verify(secp).is_ok());
+    token_valid && payment_valid
 }
 
 impl StormMesg for Messages {
@@ -123,6 +865,7 @@ impl StormMesg for Messages {
             Messages::ProposeTopic(msg) => msg.storm_app(),
             Messages::Accept(msg) => msg.storm_app(),
             Messages::AnnounceContainer(msg) => msg.storm_app(),
+            Messages::AnnounceContainers(msg) => msg.storm_app(),
             Messages::PullContainer(msg) => msg.storm_app(),
             Messages::PushContainer(msg) => msg.storm_app(),
             Messages::Post(msg) => msg.storm_app(),
@@ -131,36 +874,347 @@ impl StormMesg for Messages {
             Messages::PullChunk(msg) => msg.storm_app(),
             Messages::Decline(msg) => msg.storm_app(),
             Messages::Reject(msg) => msg.storm_app(),
+            Messages::PushLockedChunk(msg) => msg.storm_app(),
+            Messages::RevealKey(msg) => msg.storm_app(),
+            Messages::RequestQuote(msg) => msg.storm_app(),
+            Messages::Quote(msg) => msg.storm_app(),
+            Messages::PullStreamChunks(msg) => msg.storm_app(),
+            Messages::ChunkSizeLimit(msg) => msg.storm_app(),
+            Messages::SyncMesgs(msg) => msg.storm_app(),
+            Messages::SyncedMesgs(msg) => msg.storm_app(),
+            Messages::Reconcile(msg) => msg.storm_app(),
+            Messages::ChunkSketch(msg) => msg.storm_app(),
+            Messages::ChunkFilter(msg) => msg.storm_app(),
+            Messages::GetContainerFilter(msg) => msg.storm_app(),
+            Messages::ContainerFilter(msg) => msg.storm_app(),
+            Messages::RequestIou(_) => StormApp::System,
+            Messages::Iou(_) => StormApp::System,
+            Messages::ProposeReplication(msg) => msg.storm_app(),
+            Messages::AcceptReplication(msg) => msg.storm_app(),
+            Messages::DeclineReplication(msg) => msg.storm_app(),
+            Messages::RequestStorage(msg) => msg.storm_app(),
+            Messages::StoragePlacement(msg) => msg.storm_app(),
+            Messages::Invite(msg) => msg.storm_app(),
+            Messages::Join(msg) => msg.storm_app(),
+            Messages::Leave(msg) => msg.storm_app(),
+            Messages::MemberList(msg) => msg.storm_app(),
+            Messages::Ban(msg) => msg.storm_app(),
+            Messages::Unban(msg) => msg.storm_app(),
+            Messages::RemoveMesg(msg) => msg.storm_app(),
+            Messages::SignedPost(msg) => msg.storm_app(),
+            Messages::PostRequirements(msg) => msg.storm_app(),
+            Messages::RequestPostInvoice(msg) => msg.storm_app(),
+            Messages::PostInvoice(msg) => msg.storm_app(),
+            Messages::PostingToken(msg) => msg.storm_app(),
+            Messages::PrefetchHint(msg) => msg.storm_app(),
+            Messages::ListContainers(msg) => msg.storm_app(),
+            Messages::ContainerList(msg) => msg.storm_app(),
+            Messages::RequestRemoval(msg) => msg.storm_app(),
+            Messages::RemovalAck(msg) => msg.storm_app(),
+            Messages::StorageReceipt(msg) => msg.storm_app(),
+            Messages::RetrievalReceipt(msg) => msg.storm_app(),
+            Messages::StorageChallenge(msg) => msg.storm_app(),
+            Messages::ChallengeResponse(msg) => msg.storm_app(),
+            Messages::OpenDispute(msg) => msg.storm_app(),
+            Messages::DisputeEvidence(msg) => msg.storm_app(),
+            Messages::ResolveDispute(msg) => msg.storm_app(),
+            Messages::AnnounceContract(msg) => msg.storm_app(),
+            Messages::SealedPost(msg) => msg.storm_app(),
+            Messages::Broadcast(msg) => msg.storm_app(),
+            Messages::DepositMail(msg) => msg.storm_app(),
+            Messages::CollectMail(msg) => msg.storm_app(),
+            Messages::MailDelivery(msg) => msg.storm_app(),
+            Messages::DeliverOffline(msg) => msg.storm_app(),
+            Messages::DeliveryFailure(msg) => msg.storm_app(),
+            Messages::PullChunkCompact(msg) => msg.storm_app(),
+            Messages::PullContainerPart(msg) => msg.storm_app(),
+            Messages::PushContainerPart(msg) => msg.storm_app(),
+            Messages::PushContainerPage(msg) => msg.storm_app(),
+            Messages::AppPing(msg) => msg.storm_app(),
+            Messages::AppPong(msg) => msg.storm_app(),
+            Messages::StorageQuota(msg) => msg.storm_app(),
+            Messages::FindTopics(msg) => msg.storm_app(),
+            Messages::FoundTopics(msg) => msg.storm_app(),
+            Messages::LookupKey(msg) => msg.storm_app(),
+            Messages::KeyProof(msg) => msg.storm_app(),
+            Messages::ChunkMismatch(msg) => msg.storm_app(),
+            Messages::OfferDictionaries(msg) => msg.storm_app(),
+            Messages::SelectDictionary(msg) => msg.storm_app(),
+            Messages::BlockSums(msg) => msg.storm_app(),
+            Messages::ReuseBlocks(msg) => msg.storm_app(),
+            Messages::ChunkPriority(msg) => msg.storm_app(),
         }
     }
 }
 
+/// Payload of a message addressed to a specific storm app.
+///
+/// The struct itself puts no bounds on the payload type, so it can be
+/// constructed and transformed by generic code; encoding and display require
+/// the payload to support them.
 #[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
-#[derive(NetworkEncode, NetworkDecode)]
-pub struct AppMsg<T>
-where T: StrictEncode + StrictDecode
-{
+pub struct AppMsg<T> {
     pub app: StormApp,
     pub data: T,
 }
 
-impl<T> StormMesg for AppMsg<T>
-where T: StrictEncode + StrictDecode
-{
+impl<T> AppMsg<T> {
+    pub fn new(app: StormApp, data: T) -> AppMsg<T> { AppMsg { app, data } }
+
+    /// Converts the payload keeping the app.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> AppMsg<U> {
+        AppMsg {
+            app: self.app,
+            data: f(self.data),
+        }
+    }
+
+    pub fn as_ref(&self) -> AppMsg<&T> {
+        AppMsg {
+            app: self.app,
+            data: &self.data,
+        }
+    }
+
+    /// Returns formatter writing the app and the name of the payload type,
+    /// which does not require the payload to implement either `Display` or
+    /// `Debug`.
+    pub fn summary(&self) -> AppMsgSummary<'_, T> { AppMsgSummary(self) }
+}
+
+impl<T> StormMesg for AppMsg<T> {
     fn storm_app(&self) -> StormApp { self.app }
 }
 
 impl<T> Display for AppMsg<T>
-where T: Display + StrictEncode + StrictDecode
+where T: Display
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}, {}", self.app, self.data)
     }
 }
 
-#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+impl<T> StrictEncode for AppMsg<T>
+where T: StrictEncode
+{
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let len = self.app.strict_encode(&mut e)?;
+        Ok(len + self.data.strict_encode(e)?)
+    }
+}
+
+impl<T> StrictDecode for AppMsg<T>
+where T: StrictDecode
+{
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        Ok(AppMsg {
+            app: StormApp::strict_decode(&mut d)?,
+            data: T::strict_decode(d)?,
+        })
+    }
+}
+
+/// Structured formatter of [`AppMsg`] returned by [`AppMsg::summary`].
+pub struct AppMsgSummary<'msg, T>(&'msg AppMsg<T>);
+
+impl<'msg, T> AppMsgSummary<'msg, T> {
+    fn type_name() -> &'static str {
+        let name = std::any::type_name::<T>();
+        // Strip module path, keeping generic arguments
+        let end = name.find('<').unwrap_or(name.len());
+        let start = name[..end].rfind("::").map(|pos| pos + 2).unwrap_or(0);
+        &name[start..]
+    }
+}
+
+impl<'msg, T> Display for AppMsgSummary<'msg, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}", self.0.app, Self::type_name())
+    }
+}
+
+impl<'msg, T> fmt::Debug for AppMsgSummary<'msg, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppMsg")
+            .field("app", &self.0.app)
+            .field("data", &format_args!("{}", Self::type_name()))
+            .finish()
+    }
+}
+
+/// Reason for declining a message or rejecting a container request.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[strict_encoding(by_value, repr = u8)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[repr(u8)]
+pub enum RefusalReason {
+    /// The peer is not interested in the data.
+    #[display("not_interested")]
+    NotInterested = 1,
+
+    /// The requester has exhausted its quota with the peer.
+    #[display("over_quota")]
+    OverQuota = 2,
+
+    /// The peer provides the data only after a payment.
+    #[display("payment_required")]
+    PaymentRequired = 3,
+
+    /// The data or the request violate the peer policy.
+    #[display("policy_violation")]
+    PolicyViolation = 4,
+
+    /// The peer can't process the request now; it may be retried later.
+    #[display("temporarily_unavailable")]
+    TemporarilyUnavailable = 5,
+}
+
+impl RefusalReason {
+    /// Detects whether the same request may succeed if repeated later.
+    pub fn is_retriable(self) -> bool {
+        matches!(
+            self,
+            RefusalReason::OverQuota | RefusalReason::TemporarilyUnavailable
+        )
+    }
+}
+
+/// Refusal to accept a message.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{mesg_id}, {reason}")]
+pub struct Decline {
+    pub mesg_id: MesgId,
+    pub reason: RefusalReason,
+}
+
+/// Terms under which a message or a container request is accepted.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display(AcceptTerms::to_terms_string)]
+pub struct AcceptTerms {
+    /// Amount which has to be paid for the data, in millisatoshis.
+    pub price_msat: Option<u64>,
+    /// Maximal bandwidth the peer dedicates to the transfer, in bytes per
+    /// second.
+    pub bandwidth_cap: Option<u64>,
+    /// Unix timestamp after which the acceptance is no longer valid.
+    pub expiry: Option<u64>,
+}
+
+impl AcceptTerms {
+    /// Detects whether the terms do not impose any conditions.
+    pub fn is_empty(&self) -> bool { *self == AcceptTerms::default() }
+
+    /// Checks that the terms have not expired at `now`.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.expiry.map(|expiry| now < expiry).unwrap_or(true)
+    }
+
+    fn to_terms_string(&self) -> String {
+        let mut terms = vec![];
+        if let Some(price) = self.price_msat {
+            terms.push(format!("price={}msat", price));
+        }
+        if let Some(cap) = self.bandwidth_cap {
+            terms.push(format!("bandwidth_cap={}B/s", cap));
+        }
+        if let Some(expiry) = self.expiry {
+            terms.push(format!("expiry={}", expiry));
+        }
+        terms.join(", ")
+    }
+}
+
+/// Acceptance of a message and the containers it references, which may be
+/// conditional on the terms.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(NetworkEncode, NetworkDecode)]
+pub struct Accept {
+    pub mesg_id: MesgId,
+    /// Containers referenced by the message which are accepted with it.
+    pub container_ids: BTreeSet<ContainerId>,
+    pub terms: Option<AcceptTerms>,
+}
+
+impl From<MesgId> for Accept {
+    fn from(mesg_id: MesgId) -> Self {
+        Accept {
+            mesg_id,
+            container_ids: empty!(),
+            terms: None,
+        }
+    }
+}
+
+impl From<&Mesg> for Accept {
+    fn from(mesg: &Mesg) -> Self {
+        Accept {
+            mesg_id: mesg.mesg_id(),
+            container_ids: mesg.container_ids.iter().copied().collect(),
+            terms: None,
+        }
+    }
+}
+
+impl Display for Accept {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.mesg_id, f)?;
+        if !self.container_ids.is_empty() {
+            f.write_str(", ")?;
+            fmt_summary(
+                f,
+                "container",
+                self.container_ids.iter().map(short_id),
+            )?;
+        }
+        match &self.terms {
+            Some(terms) if !terms.is_empty() => write!(f, ", {}", terms),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Accept {
+    /// Detects whether the acceptance holds without any conditions.
+    pub fn is_unconditional(&self) -> bool {
+        self.terms.as_ref().map(AcceptTerms::is_empty).unwrap_or(true)
+    }
+
+    /// Checks whether the container is accepted along with the message.
+    pub fn covers(&self, container_id: ContainerId) -> bool {
+        self.container_ids.contains(&container_id)
+    }
+
+    /// Checks that the acceptance is still valid at `now`.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.terms.as_ref().map(|terms| terms.is_valid_at(now)).unwrap_or(true)
+    }
+}
+
+/// Refusal to provide a container.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{container}, {reason}")]
+pub struct Reject {
+    pub container: ContainerFullId,
+    pub reason: RefusalReason,
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
 #[derive(NetworkEncode, NetworkDecode)]
-#[display("{app}, {message_id}, {container_id}, ...")]
 pub struct ChunkPull {
     pub app: StormApp,
     pub message_id: MesgId,
@@ -168,10 +1222,82 @@ pub struct ChunkPull {
     pub chunk_ids: BTreeSet<ChunkId>,
 }
 
+impl Display for ChunkPull {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {}, {}, ",
+            self.app, self.message_id, self.container_id
+        )?;
+        fmt_summary(f, "chunk", self.chunk_ids.iter().map(short_id))
+    }
+}
+
 impl StormMesg for ChunkPull {
     fn storm_app(&self) -> StormApp { self.app }
 }
 
+/// Chunk pull request with the chunk ids in compact encoding.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(NetworkEncode, NetworkDecode)]
+pub struct CompactChunkPull {
+    pub app: StormApp,
+    pub message_id: MesgId,
+    pub container_id: ContainerId,
+    pub chunk_ids: CompactChunkIds,
+}
+
+impl Display for CompactChunkPull {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {}, {}, ",
+            self.app, self.message_id, self.container_id
+        )?;
+        let chunk_ids = self.chunk_ids.as_inner();
+        fmt_summary(f, "chunk", chunk_ids.iter().map(short_id))
+    }
+}
+
+impl StormMesg for CompactChunkPull {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+impl From<ChunkPull> for CompactChunkPull {
+    fn from(pull: ChunkPull) -> Self {
+        CompactChunkPull {
+            app: pull.app,
+            message_id: pull.message_id,
+            container_id: pull.container_id,
+            chunk_ids: pull.chunk_ids.into(),
+        }
+    }
+}
+
+impl From<CompactChunkPull> for ChunkPull {
+    fn from(pull: CompactChunkPull) -> Self {
+        ChunkPull {
+            app: pull.app,
+            message_id: pull.message_id,
+            container_id: pull.container_id,
+            chunk_ids: pull.chunk_ids.into_inner(),
+        }
+    }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {container_id}, ...")]
+pub struct PrefetchHint {
+    pub app: StormApp,
+    pub container_id: ContainerId,
+    pub chunk_ids: BTreeSet<ChunkId>,
+}
+
+impl StormMesg for PrefetchHint {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
 #[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
 #[derive(NetworkEncode, NetworkDecode)]
 #[display("{app}, {container_id}, {chunk_id}, ...")]
@@ -185,3 +1311,622 @@ pub struct ChunkPush {
 impl StormMesg for ChunkPush {
     fn storm_app(&self) -> StormApp { self.app }
 }
+
+/// Report of a pushed chunk which data hash to a different chunk id than
+/// the requested one.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {container_id}, {expected} -> {actual}")]
+pub struct ChunkMismatch {
+    pub app: StormApp,
+    pub container_id: ContainerId,
+    /// Requested chunk id.
+    pub expected: ChunkId,
+    /// Id of the pushed chunk data.
+    pub actual: ChunkId,
+}
+
+impl StormMesg for ChunkMismatch {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+/// Request of the container index part covering the selected chunks.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{container}, {selection}")]
+pub struct ContainerPartPull {
+    pub container: ContainerFullId,
+    pub selection: ChunkSelection,
+}
+
+/// Request of the value of a key from an authenticated map container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{container_id}, ...")]
+pub struct KeyLookup {
+    pub container_id: ContainerId,
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {container_id}, {chunk_id}, {payment_hash}, ...")]
+pub struct LockedChunkPush {
+    pub app: StormApp,
+    pub container_id: ContainerId,
+    /// Id of the chunk before encryption, which can be verified once the
+    /// chunk is unlocked.
+    pub chunk_id: ChunkId,
+    /// Payment hash of the lightning invoice which preimage unlocks the
+    /// chunk.
+    pub payment_hash: PaymentHash,
+    pub locked_chunk: Chunk,
+}
+
+impl StormMesg for LockedChunkPush {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {container_id}, {payment_hash}")]
+pub struct KeyReveal {
+    pub app: StormApp,
+    pub container_id: ContainerId,
+    pub payment_hash: PaymentHash,
+    pub preimage: PaymentPreimage,
+}
+
+impl StormMesg for KeyReveal {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+impl KeyReveal {
+    /// Checks that the revealed preimage matches the payment hash.
+    pub fn verify(&self) -> Result<(), HashLockError> {
+        self.preimage.verify(self.payment_hash)
+    }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {message_id}, {container_id}, {start_ms}..{end_ms}")]
+pub struct StreamChunkPull {
+    pub app: StormApp,
+    pub message_id: MesgId,
+    pub container_id: ContainerId,
+    /// Start of the requested playback time range, in milliseconds.
+    pub start_ms: u64,
+    /// End of the requested playback time range, in milliseconds.
+    pub end_ms: u64,
+}
+
+impl StormMesg for StreamChunkPull {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+/// Position in the topic message history from which synchronization should
+/// start.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum SyncCursor {
+    /// Synchronize from the very first message in the topic.
+    #[display("start")]
+    Start,
+
+    /// Synchronize messages which were received by the peer after the given
+    /// message.
+    #[display("after({0})")]
+    After(MesgId),
+
+    /// Synchronize messages which were received by the peer at or after the
+    /// given unix timestamp.
+    #[display("since({0})")]
+    Since(u64),
+}
+
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{topic_id}, {since}, {limit}")]
+pub struct MesgSync {
+    pub topic_id: MesgId,
+    pub since: SyncCursor,
+    /// Maximum number of messages to return in a single batch.
+    pub limit: u16,
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{topic_id}, ...")]
+pub struct MesgBatch {
+    pub topic_id: MesgId,
+    /// Messages in the order they were received by the peer.
+    pub mesgs: Vec<Mesg>,
+    /// Indicates that there are more messages after the last one in this
+    /// batch, which can be requested with a subsequent `SyncMesgs` using
+    /// [`MesgBatch::next_cursor`].
+    pub more: bool,
+}
+
+impl MesgBatch {
+    /// Returns cursor for requesting next batch of messages, if there are
+    /// more messages to synchronize.
+    pub fn next_cursor(&self) -> Option<SyncCursor> {
+        if !self.more {
+            return None;
+        }
+        self.mesgs.last().map(|mesg| SyncCursor::After(mesg.mesg_id()))
+    }
+}
+
+/// Query of the `FindTopics` request.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{limit} topics, ...")]
+pub struct FindTopics {
+    /// Tags which all must be present in the returned topics. Empty set
+    /// matches all topics.
+    pub tags: BTreeSet<Tag>,
+    /// Maximum number of topics to return.
+    pub limit: u16,
+}
+
+impl FindTopics {
+    /// Checks whether the topic matches the query.
+    pub fn matches(&self, topic: &Topic) -> bool { topic.has_tags(&self.tags) }
+
+    /// Selects topics matching the query for the `FoundTopics` response.
+    pub fn select<'topic>(
+        &self,
+        topics: impl IntoIterator<Item = &'topic Topic>,
+    ) -> Vec<Topic> {
+        topics
+            .into_iter()
+            .filter(|topic| self.matches(topic))
+            .take(self.limit as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Filter and page position of the `ListContainers` request.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mime_prefix}, {limit}")]
+pub struct ContainerQuery {
+    /// Prefix of the MIME type of the listed containers (like `image/`); an
+    /// empty prefix matches all containers.
+    pub mime_prefix: String,
+    /// List containers with ids following this one, which is the last
+    /// container of the previous page.
+    pub after: Option<ContainerId>,
+    /// Maximum number of containers to return in a single page.
+    pub limit: u16,
+}
+
+impl ContainerQuery {
+    /// Checks whether the container matches the query filter.
+    pub fn matches(
+        &self,
+        container_id: ContainerId,
+        container: &Container,
+    ) -> bool {
+        container.header.mime.as_str().starts_with(&self.mime_prefix)
+            && self.after.map(|after| container_id > after).unwrap_or(true)
+    }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{mime_prefix}, ...")]
+pub struct ContainerList {
+    /// MIME type prefix of the query this list responds to.
+    pub mime_prefix: String,
+    /// Containers ordered by their ids.
+    pub containers: Vec<ContainerInfo>,
+    /// Indicates that there are more containers after the last one in this
+    /// page, which can be requested with a subsequent `ListContainers` using
+    /// [`ContainerList::next_query`].
+    pub more: bool,
+}
+
+impl ContainerList {
+    /// Returns query for the next page of containers, if there are more
+    /// containers to list.
+    pub fn next_query(&self, limit: u16) -> Option<ContainerQuery> {
+        if !self.more {
+            return None;
+        }
+        self.containers.last().map(|info| ContainerQuery {
+            mime_prefix: self.mime_prefix.clone(),
+            after: Some(info.id.container_id),
+            limit,
+        })
+    }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {container_id}, ...")]
+pub struct ChunkSetSketch {
+    pub app: StormApp,
+    pub container_id: ContainerId,
+    pub sketch: PinSketch,
+}
+
+impl StormMesg for ChunkSetSketch {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[display("{app}, {container_id}, ...")]
+pub struct ChunkBloomFilter {
+    pub app: StormApp,
+    pub container_id: ContainerId,
+    pub filter: BloomFilter,
+}
+
+impl StormMesg for ChunkBloomFilter {
+    fn storm_app(&self) -> StormApp { self.app }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use internet2::TypedEnum;
+    use secp256k1::{PublicKey, SecretKey};
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::posting::TokenUse;
+
+    #[test]
+    fn test_summary_display() {
+        let chunks = (0u8..5)
+            .map(|byte| Chunk::try_from(vec![byte; 16]).unwrap())
+            .collect::<Vec<_>>();
+        let chunk_id = chunks[0].chunk_id();
+        assert_eq!(
+            chunks[0].to_string(),
+            format!("16 bytes, {}", short_id(chunk_id))
+        );
+        assert_eq!(short_id(chunk_id).len(), SHORT_ID_LEN + 3);
+        // Ids are truncated on a character boundary
+        assert_eq!(short_id("ßßßßßßßßß"), "ßßßßßßßß...");
+        assert_eq!(short_id("ßßßßßßßß"), "ßßßßßßßß");
+
+        let pull = ChunkPull {
+            app: StormApp::Storage,
+            message_id: MesgId::default(),
+            container_id: ContainerId::default(),
+            chunk_ids: chunks.iter().map(Chunk::chunk_id).collect(),
+        };
+        let display = pull.to_string();
+        assert!(display.contains(", 5 chunks: "));
+        assert!(display.ends_with(", ..."));
+        let pull = ChunkPull {
+            chunk_ids: bset! { chunk_id },
+            ..pull
+        };
+        assert!(pull
+            .to_string()
+            .ends_with(&format!(", 1 chunk: {}", short_id(chunk_id))));
+    }
+
+    #[test]
+    fn test_app_msg_summary() {
+        struct Opaque;
+
+        let msg = AppMsg::new(StormApp::Chat, Opaque);
+        assert_eq!(msg.storm_app(), StormApp::Chat);
+        assert_eq!(msg.summary().to_string(), "chat, Opaque");
+        assert_eq!(
+            format!("{:?}", msg.summary()),
+            "AppMsg { app: Chat, data: Opaque }"
+        );
+
+        let msg = msg.map(|_| MesgId::default());
+        let data = msg.strict_serialize().unwrap();
+        assert_eq!(AppMsg::<MesgId>::strict_deserialize(data).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_refusal() {
+        let decline = Decline {
+            mesg_id: MesgId::default(),
+            reason: RefusalReason::OverQuota,
+        };
+        assert!(decline.reason.is_retriable());
+        assert!(!RefusalReason::PolicyViolation.is_retriable());
+        let data = decline.strict_serialize().unwrap();
+        assert_eq!(data.len(), 33);
+        assert_eq!(Decline::strict_deserialize(data).unwrap(), decline);
+        assert!(decline.to_string().ends_with(", over_quota"));
+    }
+
+    #[test]
+    fn test_conditional_accept() {
+        let accept = Accept::from(MesgId::default());
+        assert!(accept.is_unconditional());
+        assert!(accept.is_valid_at(u64::MAX));
+
+        let accept = Accept {
+            terms: Some(AcceptTerms::default()),
+            ..accept
+        };
+        assert!(accept.is_unconditional());
+        assert_eq!(accept.to_string(), MesgId::default().to_string());
+
+        let mesg = Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"mesg".to_vec()).unwrap(),
+            container_ids: vec![ContainerId::default()],
+        };
+        let accept = Accept {
+            terms: Some(AcceptTerms {
+                price_msat: Some(1000),
+                bandwidth_cap: None,
+                expiry: Some(100),
+            }),
+            ..Accept::from(&mesg)
+        };
+        assert!(!accept.is_unconditional());
+        assert!(accept.covers(ContainerId::default()));
+        assert!(accept.is_valid_at(99));
+        assert!(!accept.is_valid_at(100));
+        assert!(accept.to_string().ends_with(
+            ", 1 container: 00000000..., price=1000msat, expiry=100"
+        ));
+        let data = accept.strict_serialize().unwrap();
+        assert_eq!(Accept::strict_deserialize(data).unwrap(), accept);
+    }
+
+    #[test]
+    fn test_app_ping() {
+        let served = bset! { StormApp::Chat };
+        let ping = Messages::AppPing(AppMsg::new(StormApp::Chat, 42));
+        assert_eq!(
+            ping.app_pong(&served).map(|pong| pong.to_string()),
+            Some(s!("app_pong(chat, 42)"))
+        );
+        let ping = Messages::AppPing(AppMsg::new(StormApp::Storage, 42));
+        assert!(ping.app_pong(&served).is_none());
+    }
+
+    fn forged_messages() -> (Messages, Vec<Messages>) {
+        let secp = Secp256k1::new();
+        let owner = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let holder = SecretKey::from_slice(&[0x22; 32]).unwrap();
+
+        let leave = Signed::sign(
+            &secp,
+            Leave {
+                topic_id: MesgId::default(),
+                timestamp: 1,
+            },
+            &holder,
+        );
+        let mut forged_leave = leave.clone();
+        forged_leave.data.timestamp = 2;
+
+        // Token is tampered before the holder signs its use, so only the
+        // nested signature is invalid
+        let mut token = Signed::sign(
+            &secp,
+            PostingToken {
+                topic_id: MesgId::default(),
+                holder: PublicKey::from_secret_key(&secp, &holder),
+                posts: 1,
+                period: 3600,
+                expiry: 100,
+            },
+            &owner,
+        );
+        token.data.posts = 1000;
+        let mut post = Post::from(Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"post".to_vec()).unwrap(),
+            container_ids: vec![],
+        });
+        post.token = Some(Signed::sign(
+            &secp,
+            TokenUse {
+                mesg_id: post.mesg.mesg_id(),
+                token,
+            },
+            &holder,
+        ));
+
+        (Messages::Leave(AppMsg::new(StormApp::Chat, leave)), vec![
+            Messages::Leave(AppMsg::new(StormApp::Chat, forged_leave)),
+            Messages::Post(AppMsg::new(StormApp::Chat, post)),
+        ])
+    }
+
+    #[test]
+    fn test_forged_signatures() {
+        let unmarshaller = VerifyingUnmarshaller::new();
+        let (valid, forged) = forged_messages();
+        assert!(unmarshaller.unmarshall(&valid.serialize()).is_ok());
+        for mesg in forged {
+            assert!(matches!(
+                unmarshaller.unmarshall(&mesg.serialize()),
+                Err(UnmarshallError::Forged(StormApp::Chat))
+            ));
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_rejected_metrics() {
+        let metrics = Arc::new(StormMetrics::new());
+        let unmarshaller = VerifyingUnmarshaller::with_metrics(metrics.clone());
+        let (valid, forged) = forged_messages();
+        unmarshaller.unmarshall(&valid.serialize()).unwrap();
+        assert_eq!(metrics.rejected_messages.get(StormApp::Chat), 0);
+        for mesg in &forged {
+            assert!(unmarshaller.unmarshall(&mesg.serialize()).is_err());
+        }
+        assert_eq!(
+            metrics.rejected_messages.get(StormApp::Chat),
+            forged.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_mesg_sync() {
+        let topic_id = MesgId::default();
+        let mesgs = (0u8..5)
+            .map(|no| Mesg {
+                parent_id: topic_id,
+                body: Chunk::try_from(vec![no]).unwrap(),
+                container_ids: vec![],
+            })
+            .collect::<Vec<_>>();
+        // Peer serving the topic messages in batches
+        let serve = |sync: MesgSync| {
+            let start = match sync.since {
+                SyncCursor::Start => 0,
+                SyncCursor::After(mesg_id) => {
+                    mesgs
+                        .iter()
+                        .position(|mesg| mesg.mesg_id() == mesg_id)
+                        .unwrap()
+                        + 1
+                }
+                SyncCursor::Since(_) => unreachable!(),
+            };
+            let end = mesgs.len().min(start + sync.limit as usize);
+            MesgBatch {
+                topic_id: sync.topic_id,
+                mesgs: mesgs[start..end].to_vec(),
+                more: end < mesgs.len(),
+            }
+        };
+
+        let mut sync = MesgSync {
+            topic_id,
+            since: SyncCursor::Start,
+            limit: 2,
+        };
+        let mut synced = vec![];
+        let mut batches = 0;
+        loop {
+            let request =
+                Messages::SyncMesgs(AppMsg::new(StormApp::Chat, sync));
+            let request = unmarshall(&request.serialize()).unwrap();
+            let batch = match &*request {
+                Messages::SyncMesgs(msg) => serve(msg.data),
+                _ => panic!("unexpected message"),
+            };
+            batches += 1;
+            synced.extend(batch.mesgs.iter().cloned());
+            match batch.next_cursor() {
+                Some(since) => sync.since = since,
+                None => break,
+            }
+        }
+        assert_eq!(batches, 3);
+        assert_eq!(synced, mesgs);
+        assert_eq!(sync.since, SyncCursor::After(mesgs[3].mesg_id()));
+        assert_eq!(
+            sync.to_string(),
+            format!("{}, after({}), 2", topic_id, mesgs[3].mesg_id())
+        );
+
+        // Cursor is not provided for empty batches
+        let batch = MesgBatch {
+            topic_id,
+            mesgs: vec![],
+            more: true,
+        };
+        assert_eq!(batch.next_cursor(), None);
+    }
+
+    #[test]
+    fn test_find_topics() {
+        let topic = |tags: BTreeSet<Tag>| Topic {
+            body: Chunk::try_from(b"topic".to_vec()).unwrap(),
+            container_ids: vec![],
+            tags,
+        };
+        let rust = Tag::from_str("rust").unwrap();
+        let news = Tag::from_str("news").unwrap();
+        let topics = vec![
+            topic(bset! { rust.clone() }),
+            topic(bset! { rust.clone(), news.clone() }),
+            topic(bset! { news.clone() }),
+        ];
+        let query = FindTopics {
+            tags: bset! { rust },
+            limit: 10,
+        };
+        assert_eq!(query.select(&topics), topics[..2].to_vec());
+        let query = FindTopics {
+            tags: empty!(),
+            limit: 1,
+        };
+        assert_eq!(query.select(&topics), topics[..1].to_vec());
+    }
+
+    #[test]
+    fn test_announce_batches() {
+        let info = ContainerInfo {
+            header: Container::chunkify(AsciiString::new(), "", b"data", 64)
+                .unwrap()
+                .0
+                .header,
+            id: ContainerFullId {
+                message_id: default!(),
+                container_id: default!(),
+            },
+            tags: empty!(),
+        };
+        let messages = Messages::announce_containers(StormApp::Storage, vec![
+                info;
+                MAX_ANNOUNCE_BATCH
+                    + 1
+            ]);
+        assert_eq!(messages.len(), 2);
+        match (&messages[0], &messages[1]) {
+            (
+                Messages::AnnounceContainers(first),
+                Messages::AnnounceContainers(last),
+            ) => {
+                assert_eq!(first.data.len(), MAX_ANNOUNCE_BATCH);
+                assert_eq!(last.data.len(), 1);
+            }
+            _ => panic!("unexpected messages"),
+        }
+        assert!(
+            Messages::announce_containers(StormApp::Storage, None).is_empty()
+        );
+    }
+}
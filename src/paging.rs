@@ -0,0 +1,231 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Paged transfer of large container indexes.
+//!
+//! Index of a container with hundreds of thousands of chunks approaches the
+//! frame size limit, so instead of a single `PushContainer` message such
+//! containers are sent as a sequence of `PushContainerPage` messages. Each
+//! page carries the container header and a segment of the chunk index;
+//! all pages except the last one are marked as having a continuation. The
+//! receiver reassembles the index with [`PageAssembler`] and checks it
+//! against the requested container id.
+
+use bitcoin_hashes::Hash;
+use strict_encoding::MediumVec;
+
+use crate::chunk::MAX_CHUNK_SIZE;
+use crate::{ChunkId, Container, ContainerHeader, ContainerId};
+
+/// Maximal number of chunk ids in a single index page.
+pub const MAX_PAGE_CHUNKS: usize = u16::MAX as usize;
+
+/// Maximal number of chunk ids in a container index.
+const MAX_INDEX_CHUNKS: usize = (1 << 24) - 1;
+
+/// Size of the container index, in bytes, above which the index should be
+/// transferred in pages.
+pub const PAGING_THRESHOLD: usize = MAX_CHUNK_SIZE as usize / 2;
+
+/// Errors reassembling paged container index.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PagingError {
+    /// page belongs to container {0} which is not being assembled.
+    ContainerMismatch(ContainerId),
+
+    /// page starts at chunk #{0} while chunk #{1} is expected.
+    UnexpectedPage(u32, u32),
+
+    /// page header differs from the header of the previous pages.
+    HeaderMismatch,
+
+    /// assembled index does not match container {0}.
+    InvalidIndex(ContainerId),
+
+    /// assembled index exceeds the number of chunks implied by the container
+    /// header or the maximal index size.
+    TooLarge,
+}
+
+/// Segment of a container index.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, page at #{first_index}")]
+pub struct ContainerPage {
+    pub container_id: ContainerId,
+    pub header: ContainerHeader,
+    /// Index of the first chunk of the page within the container.
+    pub first_index: u32,
+    pub chunk_ids: Vec<ChunkId>,
+    /// Whether more pages follow this one.
+    pub has_more: bool,
+}
+
+impl Container {
+    /// Detects whether the container index is large enough to be
+    /// transferred in pages.
+    pub fn needs_paging(&self) -> bool {
+        self.chunks.len() * ChunkId::LEN > PAGING_THRESHOLD
+    }
+
+    /// Splits container index into pages of at most `page_chunks` chunk ids
+    /// (clamped to `1..=MAX_PAGE_CHUNKS`). A container without chunks
+    /// produces a single empty page.
+    pub fn to_pages(&self, page_chunks: usize) -> Vec<ContainerPage> {
+        let page_chunks = page_chunks.clamp(1, MAX_PAGE_CHUNKS);
+        let container_id = self.container_id();
+        let count = self.chunks.len();
+        let mut pages = vec![];
+        let mut first_index = 0;
+        loop {
+            let end = (first_index + page_chunks).min(count);
+            pages.push(ContainerPage {
+                container_id,
+                header: self.header.clone(),
+                first_index: first_index as u32,
+                chunk_ids: self.chunks[first_index..end].to_vec(),
+                has_more: end < count,
+            });
+            if end >= count {
+                break;
+            }
+            first_index = end;
+        }
+        pages
+    }
+}
+
+/// Receiver-side reassembly of paged container index.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PageAssembler {
+    container_id: ContainerId,
+    header: Option<ContainerHeader>,
+    chunk_ids: Vec<ChunkId>,
+}
+
+impl PageAssembler {
+    /// Starts reassembly of the index of the requested container.
+    pub fn new(container_id: ContainerId) -> PageAssembler {
+        PageAssembler {
+            container_id,
+            header: None,
+            chunk_ids: vec![],
+        }
+    }
+
+    pub fn container_id(&self) -> ContainerId { self.container_id }
+
+    /// Number of chunk ids received so far.
+    pub fn received(&self) -> usize { self.chunk_ids.len() }
+
+    /// Accepts the next page, returning the container once its last page is
+    /// received and the index matches the container id. Pages must arrive
+    /// in order. Pages extending the index beyond the number of chunks
+    /// implied by the container header are rejected as soon as they arrive.
+    pub fn push(
+        &mut self,
+        page: ContainerPage,
+    ) -> Result<Option<Container>, PagingError> {
+        if page.container_id != self.container_id {
+            return Err(PagingError::ContainerMismatch(page.container_id));
+        }
+        let expected = self.chunk_ids.len() as u32;
+        if page.first_index != expected {
+            return Err(PagingError::UnexpectedPage(
+                page.first_index,
+                expected,
+            ));
+        }
+        match &self.header {
+            Some(header) if *header != page.header => {
+                return Err(PagingError::HeaderMismatch)
+            }
+            Some(_) => {}
+            None => self.header = Some(page.header),
+        }
+        let limit = self
+            .header
+            .as_ref()
+            .and_then(ContainerHeader::chunk_count)
+            .ok_or(PagingError::InvalidIndex(self.container_id))?
+            .min(MAX_INDEX_CHUNKS as u64) as usize;
+        if self.chunk_ids.len() + page.chunk_ids.len() > limit {
+            return Err(PagingError::TooLarge);
+        }
+        self.chunk_ids.extend(page.chunk_ids);
+        if page.has_more {
+            return Ok(None);
+        }
+
+        let container = Container {
+            header: self.header.clone().expect("header is set by first page"),
+            chunks: MediumVec::try_from(self.chunk_ids.clone())
+                .map_err(|_| PagingError::TooLarge)?,
+        };
+        if container.container_id() != self.container_id {
+            return Err(PagingError::InvalidIndex(self.container_id));
+        }
+        Ok(Some(container))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stens::AsciiString;
+
+    use super::*;
+
+    #[test]
+    fn test_paging() {
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &[1u8; 1000], 10)
+                .unwrap();
+        assert!(!container.needs_paging());
+        let pages = container.to_pages(30);
+        assert_eq!(pages.len(), 4);
+        assert!(pages[..3].iter().all(|page| page.has_more));
+        assert!(!pages[3].has_more);
+
+        let mut assembler = PageAssembler::new(container.container_id());
+        assert_eq!(
+            assembler.push(pages[1].clone()),
+            Err(PagingError::UnexpectedPage(30, 0))
+        );
+        for page in &pages[..3] {
+            assert_eq!(assembler.push(page.clone()), Ok(None));
+        }
+        assert_eq!(assembler.push(pages[3].clone()), Ok(Some(container)));
+    }
+
+    #[test]
+    fn test_page_limit() {
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &[1u8; 1000], 10)
+                .unwrap();
+        let mut pages = container.to_pages(60);
+        assert_eq!(pages.len(), 2);
+        pages[1].chunk_ids.push(ChunkId::default());
+        pages[1].has_more = true;
+
+        let mut assembler = PageAssembler::new(container.container_id());
+        assert_eq!(assembler.push(pages[0].clone()), Ok(None));
+        assert_eq!(
+            assembler.push(pages[1].clone()),
+            Err(PagingError::TooLarge)
+        );
+        assert_eq!(assembler.received(), 60);
+    }
+}
@@ -0,0 +1,189 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+
+use secp256k1::PublicKey;
+
+use crate::limits::AppsAdvert;
+use crate::StormApp;
+
+/// Number of failures recorded for a peer which delivered data not matching
+/// the requested chunk id.
+pub const CHUNK_MISMATCH_PENALTY: u32 = 10;
+
+/// Information about a known remote peer.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PeerInfo {
+    /// Apps and limits advertised by the peer.
+    pub advert: AppsAdvert,
+    /// Unix timestamp when the peer was last seen online.
+    pub last_seen: u64,
+    /// Number of requests successfully served by the peer.
+    pub successes: u32,
+    /// Number of requests failed or timed out by the peer.
+    pub failures: u32,
+}
+
+impl PeerInfo {
+    /// Reliability score of the peer in per mille. Peers without history
+    /// have score of 500.
+    pub fn reliability(&self) -> u16 {
+        let total = self.successes as u64 + self.failures as u64 + 2;
+        ((self.successes as u64 + 1) * 1000 / total) as u16
+    }
+
+    pub fn supports(&self, app: StormApp) -> bool {
+        self.advert.apps.contains(&app)
+    }
+}
+
+/// Address book of known remote peers.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PeerBook {
+    peers: BTreeMap<PublicKey, PeerInfo>,
+}
+
+impl PeerBook {
+    pub fn new() -> PeerBook { PeerBook::default() }
+
+    pub fn len(&self) -> usize { self.peers.len() }
+
+    pub fn is_empty(&self) -> bool { self.peers.is_empty() }
+
+    pub fn peer(&self, node_id: PublicKey) -> Option<&PeerInfo> {
+        self.peers.get(&node_id)
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = PublicKey> + '_ {
+        self.peers.keys().copied()
+    }
+
+    /// Registers apps advertised by the peer at time `now`, adding the peer
+    /// to the book if it was not known before.
+    pub fn update_advert(
+        &mut self,
+        node_id: PublicKey,
+        advert: AppsAdvert,
+        now: u64,
+    ) {
+        let info = self.peers.entry(node_id).or_default();
+        info.advert = advert;
+        info.last_seen = info.last_seen.max(now);
+    }
+
+    /// Marks peer as seen online at time `now`, adding the peer to the book
+    /// if it was not known before.
+    pub fn seen(&mut self, node_id: PublicKey, now: u64) {
+        let info = self.peers.entry(node_id).or_default();
+        info.last_seen = info.last_seen.max(now);
+    }
+
+    /// Records request successfully served by a known peer.
+    pub fn record_success(&mut self, node_id: PublicKey) {
+        if let Some(info) = self.peers.get_mut(&node_id) {
+            info.successes = info.successes.saturating_add(1);
+        }
+    }
+
+    /// Records request failed or timed out by a known peer.
+    pub fn record_failure(&mut self, node_id: PublicKey) {
+        if let Some(info) = self.peers.get_mut(&node_id) {
+            info.failures = info.failures.saturating_add(1);
+        }
+    }
+
+    /// Records delivery of data not matching the requested chunk id by a
+    /// known peer. Unlike timeouts, such failures can't be caused by network
+    /// conditions, so they are penalized as [`CHUNK_MISMATCH_PENALTY`]
+    /// failures.
+    pub fn record_mismatch(&mut self, node_id: PublicKey) {
+        if let Some(info) = self.peers.get_mut(&node_id) {
+            info.failures =
+                info.failures.saturating_add(CHUNK_MISMATCH_PENALTY);
+        }
+    }
+
+    pub fn remove(&mut self, node_id: PublicKey) -> Option<PeerInfo> {
+        self.peers.remove(&node_id)
+    }
+
+    /// Removes peers which were not seen since `since` timestamp, returning
+    /// their ids.
+    pub fn prune(&mut self, since: u64) -> Vec<PublicKey> {
+        let stale = self
+            .peers
+            .iter()
+            .filter(|(_, info)| info.last_seen < since)
+            .map(|(node_id, _)| *node_id)
+            .collect::<Vec<_>>();
+        for node_id in &stale {
+            self.peers.remove(node_id);
+        }
+        stale
+    }
+
+    /// Lists peers supporting the app, ordered from the most to the least
+    /// reliable.
+    pub fn peers_for_app(&self, app: StormApp) -> Vec<PublicKey> {
+        let mut peers = self
+            .peers
+            .iter()
+            .filter(|(_, info)| info.supports(app))
+            .map(|(node_id, info)| {
+                (info.reliability(), info.last_seen, *node_id)
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| b.cmp(a));
+        peers.into_iter().map(|(_, _, node_id)| node_id).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+    use crate::fixtures::{alice, bob};
+
+    #[test]
+    fn test_peer_book() {
+        let alice = alice();
+        let bob = bob();
+        let mut advert = AppsAdvert::default();
+        advert.apps.insert(StormApp::Chat);
+
+        let mut book = PeerBook::new();
+        book.update_advert(alice, advert.clone(), 10);
+        book.update_advert(bob, advert, 20);
+        book.record_failure(alice);
+        book.record_success(bob);
+        assert_eq!(book.peers_for_app(StormApp::Chat), vec![bob, alice]);
+        assert!(book.peers_for_app(StormApp::Storage).is_empty());
+
+        let data = book.strict_serialize().unwrap();
+        assert_eq!(PeerBook::strict_deserialize(data).unwrap(), book);
+
+        assert_eq!(book.prune(15), vec![alice]);
+        assert_eq!(book.len(), 1);
+    }
+}
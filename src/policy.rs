@@ -0,0 +1,152 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeSet;
+
+use internet2::TypedEnum;
+use secp256k1::PublicKey;
+
+use crate::p2p::{Messages, StormMesg};
+use crate::StormApp;
+
+/// Reasons for refusing to process an inbound message.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PolicyError {
+    /// peer {0} is not allowed by the node policy.
+    Peer(PublicKey),
+
+    /// app {0} is not allowed by the node policy.
+    App(StormApp),
+
+    /// message type {0:#06x} is not allowed by the node policy.
+    MesgType(u16),
+}
+
+/// Allow and deny lists for a single kind of items. Denied items are always
+/// refused; if the allowlist is non-empty, only listed items are accepted.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct AccessList<T>
+where T: Ord
+{
+    pub allow: BTreeSet<T>,
+    pub deny: BTreeSet<T>,
+}
+
+impl<T> Default for AccessList<T>
+where T: Ord
+{
+    fn default() -> Self {
+        AccessList {
+            allow: empty!(),
+            deny: empty!(),
+        }
+    }
+}
+
+impl<T> AccessList<T>
+where T: Ord
+{
+    pub fn is_allowed(&self, item: &T) -> bool {
+        !self.deny.contains(item)
+            && (self.allow.is_empty() || self.allow.contains(item))
+    }
+}
+
+/// Node policy consulted before processing inbound messages.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Policy {
+    pub peers: AccessList<PublicKey>,
+    pub apps: AccessList<StormApp>,
+    pub mesg_types: AccessList<u16>,
+}
+
+impl Policy {
+    /// Constructs policy allowing everything.
+    pub fn new() -> Policy { Policy::default() }
+
+    pub fn check_peer(&self, node_id: PublicKey) -> Result<(), PolicyError> {
+        if !self.peers.is_allowed(&node_id) {
+            return Err(PolicyError::Peer(node_id));
+        }
+        Ok(())
+    }
+
+    /// Checks whether inbound message of the given type and app, sent by the
+    /// peer, can be processed.
+    pub fn check(
+        &self,
+        node_id: PublicKey,
+        app: StormApp,
+        mesg_type: u16,
+    ) -> Result<(), PolicyError> {
+        self.check_peer(node_id)?;
+        if !self.apps.is_allowed(&app) {
+            return Err(PolicyError::App(app));
+        }
+        if !self.mesg_types.is_allowed(&mesg_type) {
+            return Err(PolicyError::MesgType(mesg_type));
+        }
+        Ok(())
+    }
+
+    /// Checks whether inbound message, sent by the peer, can be processed.
+    pub fn check_mesg(
+        &self,
+        node_id: PublicKey,
+        mesg: &Messages,
+    ) -> Result<(), PolicyError> {
+        self.check(node_id, mesg.storm_app(), mesg.get_type())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{alice, bob};
+
+    #[test]
+    fn test_policy() {
+        let alice = alice();
+        let bob = bob();
+
+        let mut policy = Policy::new();
+        assert_eq!(policy.check(alice, StormApp::Chat, 0x0002), Ok(()));
+
+        policy.peers.deny.insert(bob);
+        policy.apps.allow.insert(StormApp::Chat);
+        policy.mesg_types.deny.insert(0x0010);
+        assert_eq!(policy.check(alice, StormApp::Chat, 0x0002), Ok(()));
+        assert_eq!(
+            policy.check(bob, StormApp::Chat, 0x0002),
+            Err(PolicyError::Peer(bob))
+        );
+        assert_eq!(
+            policy.check(alice, StormApp::Storage, 0x0002),
+            Err(PolicyError::App(StormApp::Storage))
+        );
+        assert_eq!(
+            policy.check(alice, StormApp::Chat, 0x0010),
+            Err(PolicyError::MesgType(0x0010))
+        );
+    }
+}
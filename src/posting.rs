@@ -0,0 +1,1076 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Posting requirements of public topics and the proofs attached to posts to
+//! satisfy them.
+//!
+//! Topic owners advertise [`PostRequirements`] for their topics; nodes
+//! relaying or storing topic posts reject posts which do not satisfy them.
+//!
+//! Topics may require a lightning payment for each post. In this case the
+//! author requests from the topic owner an invoice for the specific message
+//! id; the owner replies with [`PostInvoice`] signed by the owner key. After
+//! paying the invoice, the author attaches the signed invoice together with
+//! the payment preimage to the post, which allows any node to verify that the
+//! post was paid for.
+//!
+//! Alternatively, the topic owner may issue to members [`PostingToken`]s
+//! granting a number of posts per period. Members attach the token to their
+//! posts, signing the message id, and relays account the posts made with each
+//! token in a [`TokenLedger`].
+//!
+//! Posts may carry [`DeliveryPolicy`] telling relays and mailboxes how long
+//! and how often to attempt delivery. Once delivery is abandoned, the node
+//! which has been attempting it notifies the sender with [`DeliveryFailure`]
+//! if the policy requests so.
+//!
+//! Post bodies exceeding [`MAX_INLINE_BODY`] do not fit into a single frame
+//! together with the rest of the message. Such bodies are moved into a body
+//! container of [`BODY_CONTAINER_MIME`] type, which is referenced among the
+//! message containers and is transferred chunk by chunk like any other
+//! container; the message body carries the id of the body container instead,
+//! so the body is committed to by the message id and all of the signatures
+//! and stamps made over it. [`Post::new`] moves oversized bodies into a body
+//! container automatically. Readers use [`Post::body`], which returns the
+//! body regardless of how it was transferred.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use amplify::Wrapper;
+use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine};
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use stens::AsciiString;
+
+use crate::signed::{Signable, Signed};
+use crate::{
+    Chunk, Container, ContainerId, Mesg, MesgId, PaymentHash, PaymentPreimage,
+    Topic,
+};
+
+// "storm:stamp"
+static MIDSTATE_STAMP: [u8; 32] = [
+    7, 156, 15, 219, 130, 101, 13, 82, 201, 48, 216, 105, 14, 191, 252, 174,
+    85, 89, 73, 25, 252, 28, 178, 204, 27, 132, 186, 164, 33, 62, 35, 104,
+];
+
+/// Tag used for proof-of-work stamp hashes.
+pub struct StampTag;
+
+impl sha256t::Tag for StampTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_STAMP);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Maximum proof-of-work difficulty, in leading zero bits.
+pub const MAX_STAMP_DIFFICULTY: u8 = 64;
+
+/// Maximum size of the post body sent within the message, in bytes. Larger
+/// bodies are moved into a body container.
+pub const MAX_INLINE_BODY: usize = 1 << 16;
+
+/// MIME type of containers holding bodies of oversized posts.
+pub const BODY_CONTAINER_MIME: &str = "application/x-storm-body";
+
+/// Errors checking post against topic posting requirements.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PostingError {
+    /// post requirements relate to topic {0}.
+    TopicMismatch(MesgId),
+
+    /// topic requires proof-of-work stamp, which is absent.
+    NoStamp,
+
+    /// stamp has difficulty of {actual} bits while the topic requires
+    /// {required} bits.
+    InsufficientWork { required: u8, actual: u8 },
+
+    /// topic requires payment for the post, which is absent.
+    NoPayment,
+
+    /// post invoice is not signed by the topic owner.
+    InvalidInvoice,
+
+    /// post invoice is issued for a different message.
+    InvoiceMismatch,
+
+    /// post invoice amount of {paid} msat is below the required {required}
+    /// msat.
+    InsufficientPayment { required: u64, paid: u64 },
+
+    /// payment preimage does not match the invoice payment hash.
+    PreimageMismatch,
+
+    /// topic requires posting token, which is absent.
+    NoToken,
+
+    /// posting token is not issued by the topic owner or is not signed by
+    /// its holder.
+    InvalidToken,
+
+    /// posting token is issued for a different topic or attached to a
+    /// different message.
+    TokenMismatch,
+
+    /// posting token has expired.
+    TokenExpired,
+
+    /// posting token allowance of {0} posts per period is exhausted.
+    TokenExhausted(u32),
+}
+
+/// Errors splitting and reassembling post bodies.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PostBodyError {
+    /// post body is too large to fit into a container.
+    TooLarge,
+
+    /// body container {0} is not referenced by the message.
+    NotReferenced(ContainerId),
+
+    /// message body does not commit to the body container {0}.
+    NotCommitted(ContainerId),
+
+    /// container is not the body container {0} of the post.
+    ContainerMismatch(ContainerId),
+
+    /// chunk #{0} of the body container is missing or invalid.
+    InvalidChunk(usize),
+}
+
+/// Hashcash-style proof-of-work stamp committing to a message id.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("stamp:{nonce}")]
+pub struct PowStamp {
+    pub nonce: u64,
+}
+
+impl PowStamp {
+    fn hash(mesg_id: MesgId, nonce: u64) -> sha256t::Hash<StampTag> {
+        let mut engine = sha256t::Hash::<StampTag>::engine();
+        engine.input(&mesg_id.as_inner()[..]);
+        engine.input(&nonce.to_le_bytes());
+        sha256t::Hash::from_engine(engine)
+    }
+
+    /// Difficulty of the stamp for the message, measured in the number of
+    /// leading zero bits of the stamp hash.
+    pub fn difficulty(&self, mesg_id: MesgId) -> u8 {
+        let hash = Self::hash(mesg_id, self.nonce);
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&hash[..8]);
+        u64::from_be_bytes(head).leading_zeros() as u8
+    }
+
+    /// Finds stamp of at least the given difficulty for the message. The
+    /// difficulty is capped at [`MAX_STAMP_DIFFICULTY`]; each additional bit
+    /// doubles the expected amount of work.
+    pub fn mint(mesg_id: MesgId, difficulty: u8) -> PowStamp {
+        let difficulty = difficulty.min(MAX_STAMP_DIFFICULTY);
+        (0u64..)
+            .map(|nonce| PowStamp { nonce })
+            .find(|stamp| stamp.difficulty(mesg_id) >= difficulty)
+            .expect("stamp search space is exhausted")
+    }
+
+    /// Verifies that the stamp satisfies the required difficulty.
+    pub fn verify(
+        &self,
+        mesg_id: MesgId,
+        required: u8,
+    ) -> Result<(), PostingError> {
+        let actual = self.difficulty(mesg_id);
+        if actual < required.min(MAX_STAMP_DIFFICULTY) {
+            return Err(PostingError::InsufficientWork { required, actual });
+        }
+        Ok(())
+    }
+}
+
+/// Requirements for posting to a topic, advertised by the topic owner.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display(
+    "{topic_id}, pow {pow_difficulty} bits, fee {fee_msat} msat, \
+     token_required={token_required}"
+)]
+pub struct PostRequirements {
+    pub topic_id: MesgId,
+    /// Required proof-of-work difficulty of the post stamps, in leading zero
+    /// bits. Zero means that stamps are not required.
+    pub pow_difficulty: u8,
+    /// Required payment per post, in millisatoshis. Zero means that posting
+    /// is free.
+    pub fee_msat: u64,
+    /// Whether posts must carry a posting token issued by the topic owner.
+    pub token_required: bool,
+}
+
+impl PostRequirements {
+    fn check_stamp(
+        &self,
+        mesg_id: MesgId,
+        stamp: Option<PowStamp>,
+    ) -> Result<(), PostingError> {
+        if self.pow_difficulty == 0 {
+            return Ok(());
+        }
+        stamp.ok_or(PostingError::NoStamp)?.verify(mesg_id, self.pow_difficulty)
+    }
+
+    fn check_payment<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        mesg_id: MesgId,
+        payment: Option<&PaymentProof>,
+        owner: PublicKey,
+    ) -> Result<(), PostingError> {
+        if self.fee_msat == 0 {
+            return Ok(());
+        }
+        let proof = payment.ok_or(PostingError::NoPayment)?;
+        let invoice = &proof.invoice;
+        if invoice.signer != owner || invoice.verify(secp).is_err() {
+            return Err(PostingError::InvalidInvoice);
+        }
+        if invoice.data.topic_id != self.topic_id
+            || invoice.data.mesg_id != mesg_id
+        {
+            return Err(PostingError::InvoiceMismatch);
+        }
+        if invoice.data.amount_msat < self.fee_msat {
+            return Err(PostingError::InsufficientPayment {
+                required: self.fee_msat,
+                paid: invoice.data.amount_msat,
+            });
+        }
+        proof
+            .preimage
+            .verify(invoice.data.payment_hash)
+            .map_err(|_| PostingError::PreimageMismatch)
+    }
+
+    fn check_token<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        mesg_id: MesgId,
+        token_use: Option<&Signed<TokenUse>>,
+        owner: PublicKey,
+        now: u64,
+    ) -> Result<(), PostingError> {
+        if !self.token_required {
+            return Ok(());
+        }
+        let token_use = token_use.ok_or(PostingError::NoToken)?;
+        let token = &token_use.data.token;
+        if token.signer != owner
+            || token_use.signer != token.data.holder
+            || token.verify(secp).is_err()
+            || token_use.verify(secp).is_err()
+        {
+            return Err(PostingError::InvalidToken);
+        }
+        if token.data.topic_id != self.topic_id
+            || token_use.data.mesg_id != mesg_id
+        {
+            return Err(PostingError::TokenMismatch);
+        }
+        if token.data.expiry <= now {
+            return Err(PostingError::TokenExpired);
+        }
+        Ok(())
+    }
+
+    /// Checks that the post satisfies the topic requirements at time `now`.
+    /// Post invoices and posting tokens must be signed by the topic `owner`.
+    ///
+    /// The check does not account posts made with posting tokens; relays
+    /// should use [`TokenLedger`] for that.
+    pub fn check_post<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        post: &Post,
+        owner: PublicKey,
+        now: u64,
+    ) -> Result<(), PostingError> {
+        if post.mesg.parent_id != self.topic_id {
+            return Err(PostingError::TopicMismatch(self.topic_id));
+        }
+        let mesg_id = post.mesg.mesg_id();
+        self.check_stamp(mesg_id, post.stamp)?;
+        self.check_payment(secp, mesg_id, post.payment.as_ref(), owner)?;
+        self.check_token(secp, mesg_id, post.token.as_ref(), owner, now)
+    }
+}
+
+/// Request for an invoice paying for a post.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{topic_id}, {mesg_id}")]
+pub struct PostInvoiceRequest {
+    pub topic_id: MesgId,
+    pub mesg_id: MesgId,
+}
+
+/// Invoice for a post issued by the topic owner.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mesg_id}, {amount_msat} msat, {payment_hash}")]
+pub struct PostInvoice {
+    pub topic_id: MesgId,
+    pub mesg_id: MesgId,
+    pub amount_msat: u64,
+    pub payment_hash: PaymentHash,
+    /// BOLT-11 invoice to be paid by the post author.
+    pub invoice: String,
+}
+
+impl Signable for PostInvoice {
+    const SIGNED_TYPE: u8 = 8;
+}
+
+/// Proof of payment for a post.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PaymentProof {
+    pub invoice: Signed<PostInvoice>,
+    pub preimage: PaymentPreimage,
+}
+
+/// Token issued by the topic owner granting its holder a number of posts per
+/// period.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{holder} in {topic_id}, {posts} posts per {period} sec")]
+pub struct PostingToken {
+    pub topic_id: MesgId,
+    pub holder: PublicKey,
+    /// Number of posts allowed per period.
+    pub posts: u32,
+    /// Period length, in seconds. Zero means that the number of posts is
+    /// limited over the whole token lifetime.
+    pub period: u64,
+    /// Unix timestamp when the token expires.
+    pub expiry: u64,
+}
+
+impl Signable for PostingToken {
+    const SIGNED_TYPE: u8 = 9;
+}
+
+impl PostingToken {
+    fn window(&self, now: u64) -> u64 {
+        if self.period == 0 {
+            0
+        } else {
+            now / self.period
+        }
+    }
+}
+
+/// Posting token attached to a specific message by the token holder.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mesg_id}, {token}")]
+pub struct TokenUse {
+    pub mesg_id: MesgId,
+    pub token: Signed<PostingToken>,
+}
+
+impl Signable for TokenUse {
+    const SIGNED_TYPE: u8 = 10;
+}
+
+/// Accounts posts made with posting tokens of a topic.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TokenLedger {
+    /// Current period window of each token holder together with the messages
+    /// posted by the holder within the window.
+    usage: BTreeMap<PublicKey, (u64, BTreeSet<MesgId>)>,
+}
+
+impl TokenLedger {
+    pub fn new() -> TokenLedger { TokenLedger::default() }
+
+    /// Accounts post with the token at time `now`, failing if the token
+    /// allowance for the current period is exhausted. Repeated accounting of
+    /// the same message is not counted twice.
+    pub fn consume(
+        &mut self,
+        token_use: &TokenUse,
+        now: u64,
+    ) -> Result<(), PostingError> {
+        let token = &token_use.token.data;
+        let window = token.window(now);
+        let (current, posted) =
+            self.usage.entry(token.holder).or_insert((window, empty!()));
+        if *current != window {
+            *current = window;
+            posted.clear();
+        }
+        if posted.contains(&token_use.mesg_id) {
+            return Ok(());
+        }
+        if posted.len() >= token.posts as usize {
+            return Err(PostingError::TokenExhausted(token.posts));
+        }
+        posted.insert(token_use.mesg_id);
+        Ok(())
+    }
+
+    /// Number of posts made by the holder within the period window
+    /// containing `now`.
+    pub fn used(&self, token: &PostingToken, now: u64) -> usize {
+        match self.usage.get(&token.holder) {
+            Some((window, posted)) if *window == token.window(now) => {
+                posted.len()
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Reason for abandoning delivery of a post.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[strict_encoding(by_value, repr = u8)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[repr(u8)]
+pub enum DeliveryFailureReason {
+    /// Delivery deadline has passed.
+    #[display("deadline_expired")]
+    DeadlineExpired = 1,
+
+    /// Maximal number of delivery attempts was made.
+    #[display("attempts_exhausted")]
+    AttemptsExhausted = 2,
+}
+
+/// Deadline and retry policy for delivering a post to its recipients.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("until {deadline}, {max_attempts} attempts")]
+pub struct DeliveryPolicy {
+    /// Unix timestamp after which delivery must not be attempted.
+    pub deadline: u64,
+    pub max_attempts: u16,
+    /// Delay before the first retry, in seconds; doubled with each next
+    /// retry.
+    pub retry_interval: u32,
+    /// Whether the sender must be notified once delivery is abandoned.
+    pub notify_failure: bool,
+}
+
+impl DeliveryPolicy {
+    /// Time of the next delivery attempt after `attempts` unsuccessful
+    /// attempts, the last of which was made at `last_attempt`; or the reason
+    /// to abandon delivery.
+    pub fn next_attempt(
+        &self,
+        attempts: u16,
+        last_attempt: u64,
+    ) -> Result<u64, DeliveryFailureReason> {
+        if attempts >= self.max_attempts {
+            return Err(DeliveryFailureReason::AttemptsExhausted);
+        }
+        let backoff = (self.retry_interval as u64)
+            .saturating_mul(1 << attempts.saturating_sub(1).min(32));
+        let next = if attempts == 0 {
+            last_attempt
+        } else {
+            last_attempt.saturating_add(backoff)
+        };
+        if next >= self.deadline {
+            return Err(DeliveryFailureReason::DeadlineExpired);
+        }
+        Ok(next)
+    }
+}
+
+/// Notification of the sender that delivery of a post was abandoned.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mesg_id} to {recipient}: {reason}")]
+pub struct DeliveryFailure {
+    pub mesg_id: MesgId,
+    pub recipient: PublicKey,
+    pub attempts: u16,
+    pub reason: DeliveryFailureReason,
+}
+
+/// Message posted to a topic together with the proofs required by the topic.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{mesg}")]
+pub struct Post {
+    pub mesg: Mesg,
+    pub stamp: Option<PowStamp>,
+    pub payment: Option<PaymentProof>,
+    pub token: Option<Signed<TokenUse>>,
+    pub delivery: Option<DeliveryPolicy>,
+    /// Container holding the post body, if the body was too large to be
+    /// sent within the message. The container is always listed among the
+    /// message containers, and its id is used as the message body.
+    pub body_container: Option<ContainerId>,
+}
+
+impl From<Mesg> for Post {
+    fn from(mesg: Mesg) -> Self {
+        Post {
+            mesg,
+            stamp: None,
+            payment: None,
+            token: None,
+            delivery: None,
+            body_container: None,
+        }
+    }
+}
+
+impl Post {
+    /// Constructs post with the given body. Bodies which do not fit into a
+    /// message, i.e. are larger than [`MAX_INLINE_BODY`] bytes, are split into
+    /// chunks of [`MAX_INLINE_BODY`] bytes and moved into a body container,
+    /// which is returned together with its chunks and must be made available
+    /// to the post recipients.
+    #[allow(clippy::type_complexity)]
+    pub fn new(
+        parent_id: MesgId,
+        body: &[u8],
+        container_ids: Vec<ContainerId>,
+    ) -> Result<(Post, Option<(Container, Vec<Chunk>)>), PostBodyError> {
+        Post::with_body(
+            parent_id,
+            body,
+            container_ids,
+            MAX_INLINE_BODY,
+            MAX_INLINE_BODY as u32,
+        )
+    }
+
+    /// Constructs post with the given body. Bodies larger than `max_inline`
+    /// bytes (which can't exceed [`MAX_INLINE_BODY`]) are split into chunks
+    /// of `chunk_size` bytes and moved into a body container, which is
+    /// returned together with its chunks and must be made available to the
+    /// post recipients.
+    #[allow(clippy::type_complexity)]
+    pub fn with_body(
+        parent_id: MesgId,
+        body: &[u8],
+        mut container_ids: Vec<ContainerId>,
+        max_inline: usize,
+        chunk_size: u32,
+    ) -> Result<(Post, Option<(Container, Vec<Chunk>)>), PostBodyError> {
+        if body.len() <= max_inline.min(MAX_INLINE_BODY) {
+            let mesg = Mesg {
+                parent_id,
+                body: Chunk::try_from(body)
+                    .expect("inline body does not exceed maximum chunk size"),
+                container_ids,
+            };
+            return Ok((Post::from(mesg), None));
+        }
+
+        let mime = AsciiString::try_from(BODY_CONTAINER_MIME)
+            .expect("static MIME type is ASCII");
+        let (container, chunks) =
+            Container::chunkify(mime, "", body, chunk_size)
+                .map_err(|_| PostBodyError::TooLarge)?;
+        let container_id = container.container_id();
+        container_ids.push(container_id);
+        let mut post = Post::from(Mesg {
+            parent_id,
+            body: Chunk::try_from(&container_id[..])
+                .expect("container id fits into a chunk"),
+            container_ids,
+        });
+        post.body_container = Some(container_id);
+        Ok((post, Some((container, chunks))))
+    }
+
+    /// Detects whether the post body is stored in a body container.
+    pub fn is_body_external(&self) -> bool { self.body_container.is_some() }
+
+    /// Returns post body. If the body is stored in a body container, it is
+    /// reassembled from the container chunks, which must be provided in the
+    /// container order; otherwise the container and chunks are ignored.
+    pub fn body(
+        &self,
+        container: Option<&Container>,
+        chunks: &[Chunk],
+    ) -> Result<Vec<u8>, PostBodyError> {
+        let container_id = match self.body_container {
+            None => return Ok(self.mesg.body.as_ref().to_vec()),
+            Some(container_id) => container_id,
+        };
+        if !self.mesg.container_ids.contains(&container_id) {
+            return Err(PostBodyError::NotReferenced(container_id));
+        }
+        if self.mesg.body.as_ref() != &container_id[..] {
+            return Err(PostBodyError::NotCommitted(container_id));
+        }
+        let container = container
+            .filter(|container| {
+                container.container_id() == container_id
+                    && container.header.mime.as_str() == BODY_CONTAINER_MIME
+                    && container.is_chunk_size_valid()
+            })
+            .ok_or(PostBodyError::ContainerMismatch(container_id))?;
+        // Container size is not verified until all chunks are checked, so
+        // we do not reserve more than the size of the provided data
+        let provided = chunks.iter().map(Chunk::len).sum::<usize>();
+        let capacity = provided.min(container.header.size as usize);
+        let mut body = Vec::with_capacity(capacity);
+        for (index, chunk_id) in container.chunks.iter().enumerate() {
+            match chunks.get(index) {
+                Some(chunk) if chunk.chunk_id() == *chunk_id => {
+                    body.extend_from_slice(chunk.as_ref())
+                }
+                _ => return Err(PostBodyError::InvalidChunk(index)),
+            }
+        }
+        if body.len() as u64 != container.header.size {
+            return Err(PostBodyError::InvalidChunk(container.chunks.len()));
+        }
+        Ok(body)
+    }
+
+    /// Attaches proof-of-work stamp of the given difficulty to the post.
+    pub fn mint_stamp(&mut self, difficulty: u8) {
+        self.stamp = Some(PowStamp::mint(self.mesg.mesg_id(), difficulty));
+    }
+
+    /// Attaches posting token to the post, signing it with the token holder
+    /// key.
+    pub fn attach_token<C: Signing>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        token: Signed<PostingToken>,
+        holder_key: &SecretKey,
+    ) {
+        let token_use = TokenUse {
+            mesg_id: self.mesg.mesg_id(),
+            token,
+        };
+        self.token = Some(Signed::sign(secp, token_use, holder_key));
+    }
+
+    /// Notification of the sender on abandoning delivery of the post to the
+    /// recipient, if requested by the post delivery policy.
+    pub fn delivery_failure(
+        &self,
+        recipient: PublicKey,
+        attempts: u16,
+        reason: DeliveryFailureReason,
+    ) -> Option<DeliveryFailure> {
+        self.delivery.filter(|policy| policy.notify_failure).map(|_| {
+            DeliveryFailure {
+                mesg_id: self.mesg.mesg_id(),
+                recipient,
+                attempts,
+                reason,
+            }
+        })
+    }
+}
+
+/// Proposal of a new topic together with an optional proof-of-work stamp.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct TopicProposal {
+    pub topic: Topic,
+    pub stamp: Option<PowStamp>,
+}
+
+impl From<Topic> for TopicProposal {
+    fn from(topic: Topic) -> Self { TopicProposal { topic, stamp: None } }
+}
+
+impl TopicProposal {
+    /// Attaches proof-of-work stamp of the given difficulty to the proposal.
+    pub fn mint_stamp(&mut self, difficulty: u8) {
+        self.stamp = Some(PowStamp::mint(self.topic.mesg_id(), difficulty));
+    }
+
+    /// Checks that the proposal carries a stamp of the difficulty required
+    /// by the node for new topics.
+    pub fn check(&self, pow_difficulty: u8) -> Result<(), PostingError> {
+        if pow_difficulty == 0 {
+            return Ok(());
+        }
+        self.stamp
+            .ok_or(PostingError::NoStamp)?
+            .verify(self.topic.mesg_id(), pow_difficulty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::tagged_hash;
+
+    use super::*;
+    use crate::chunk::MAX_CHUNK_SIZE;
+    use crate::Chunk;
+
+    #[test]
+    fn test_stamp_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:stamp");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_STAMP);
+    }
+
+    #[test]
+    fn test_pow_stamp() {
+        let topic_id = MesgId::default();
+        let secp = Secp256k1::new();
+        let owner = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x11; 32]).unwrap(),
+        );
+        let requirements = PostRequirements {
+            topic_id,
+            pow_difficulty: 8,
+            fee_msat: 0,
+            token_required: false,
+        };
+        let mut post = Post::from(Mesg {
+            parent_id: topic_id,
+            body: Chunk::try_from(b"hello".to_vec()).unwrap(),
+            container_ids: vec![],
+        });
+        assert_eq!(
+            requirements.check_post(&secp, &post, owner, 0),
+            Err(PostingError::NoStamp)
+        );
+        post.mint_stamp(8);
+        assert_eq!(requirements.check_post(&secp, &post, owner, 0), Ok(()));
+        assert!(post.stamp.unwrap().difficulty(post.mesg.mesg_id()) >= 8);
+
+        post.mesg.body = Chunk::try_from(b"changed".to_vec()).unwrap();
+        assert!(PostRequirements {
+            topic_id,
+            pow_difficulty: 16,
+            fee_msat: 0,
+            token_required: false,
+        }
+        .check_post(&secp, &post, owner, 0)
+        .is_err());
+    }
+
+    #[test]
+    fn test_paid_post() {
+        let secp = Secp256k1::new();
+        let owner_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let owner = PublicKey::from_secret_key(&secp, &owner_key);
+        let topic_id = MesgId::default();
+        let requirements = PostRequirements {
+            topic_id,
+            pow_difficulty: 0,
+            fee_msat: 1000,
+            token_required: false,
+        };
+        let mut post = Post::from(Mesg {
+            parent_id: topic_id,
+            body: Chunk::try_from(b"paid".to_vec()).unwrap(),
+            container_ids: vec![],
+        });
+        assert_eq!(
+            requirements.check_post(&secp, &post, owner, 0),
+            Err(PostingError::NoPayment)
+        );
+
+        let preimage = PaymentPreimage::from([7u8; 32]);
+        let invoice = PostInvoice {
+            topic_id,
+            mesg_id: post.mesg.mesg_id(),
+            amount_msat: 1000,
+            payment_hash: preimage.payment_hash(),
+            invoice: s!("lnbc..."),
+        };
+        post.payment = Some(PaymentProof {
+            invoice: Signed::sign(&secp, invoice, &owner_key),
+            preimage,
+        });
+        assert_eq!(requirements.check_post(&secp, &post, owner, 0), Ok(()));
+
+        post.payment.as_mut().unwrap().preimage =
+            PaymentPreimage::from([8; 32]);
+        assert_eq!(
+            requirements.check_post(&secp, &post, owner, 0),
+            Err(PostingError::PreimageMismatch)
+        );
+    }
+
+    #[test]
+    fn test_posting_token() {
+        let secp = Secp256k1::new();
+        let owner_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let user_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let owner = PublicKey::from_secret_key(&secp, &owner_key);
+        let user = PublicKey::from_secret_key(&secp, &user_key);
+        let topic_id = MesgId::default();
+        let requirements = PostRequirements {
+            topic_id,
+            pow_difficulty: 0,
+            fee_msat: 0,
+            token_required: true,
+        };
+        let token = PostingToken {
+            topic_id,
+            holder: user,
+            posts: 2,
+            period: 60,
+            expiry: 1000,
+        };
+        let posts = (0u8..3)
+            .map(|no| {
+                let mut post = Post::from(Mesg {
+                    parent_id: topic_id,
+                    body: Chunk::try_from(vec![no]).unwrap(),
+                    container_ids: vec![],
+                });
+                let token = Signed::sign(&secp, token, &owner_key);
+                post.attach_token(&secp, token, &user_key);
+                post
+            })
+            .collect::<Vec<_>>();
+
+        let mut forged = posts[0].clone();
+        let forged_token = Signed::sign(&secp, token, &user_key);
+        forged.attach_token(&secp, forged_token, &user_key);
+        assert_eq!(
+            requirements.check_post(&secp, &forged, owner, 0),
+            Err(PostingError::InvalidToken)
+        );
+        assert_eq!(
+            requirements.check_post(&secp, &posts[0], owner, 1000),
+            Err(PostingError::TokenExpired)
+        );
+
+        let mut ledger = TokenLedger::new();
+        for post in &posts {
+            requirements.check_post(&secp, post, owner, 0).unwrap();
+        }
+        let uses = posts
+            .iter()
+            .map(|post| post.token.clone().unwrap().data)
+            .collect::<Vec<_>>();
+        ledger.consume(&uses[0], 0).unwrap();
+        ledger.consume(&uses[0], 10).unwrap();
+        ledger.consume(&uses[1], 20).unwrap();
+        assert_eq!(
+            ledger.consume(&uses[2], 30),
+            Err(PostingError::TokenExhausted(2))
+        );
+        assert_eq!(ledger.used(&token, 30), 2);
+        ledger.consume(&uses[2], 60).unwrap();
+        assert_eq!(ledger.used(&token, 60), 1);
+    }
+
+    #[test]
+    fn test_delivery_policy() {
+        let policy = DeliveryPolicy {
+            deadline: 1000,
+            max_attempts: 4,
+            retry_interval: 100,
+            notify_failure: true,
+        };
+        assert_eq!(policy.next_attempt(0, 10), Ok(10));
+        assert_eq!(policy.next_attempt(1, 10), Ok(110));
+        assert_eq!(policy.next_attempt(2, 110), Ok(310));
+        assert_eq!(
+            policy.next_attempt(3, 810),
+            Err(DeliveryFailureReason::DeadlineExpired)
+        );
+        assert_eq!(
+            policy.next_attempt(4, 310),
+            Err(DeliveryFailureReason::AttemptsExhausted)
+        );
+
+        let secp = Secp256k1::new();
+        let recipient = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[0x11; 32]).unwrap(),
+        );
+        let mut post = Post::from(Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(b"hello".to_vec()).unwrap(),
+            container_ids: vec![],
+        });
+        let reason = DeliveryFailureReason::AttemptsExhausted;
+        assert_eq!(post.delivery_failure(recipient, 4, reason), None);
+        post.delivery = Some(policy);
+        assert_eq!(
+            post.delivery_failure(recipient, 4, reason),
+            Some(DeliveryFailure {
+                mesg_id: post.mesg.mesg_id(),
+                recipient,
+                attempts: 4,
+                reason
+            })
+        );
+    }
+
+    #[test]
+    fn test_body_container() {
+        let (post, container) = Post::with_body(
+            MesgId::default(),
+            b"short",
+            vec![],
+            MAX_INLINE_BODY,
+            64,
+        )
+        .unwrap();
+        assert!(container.is_none());
+        assert!(!post.is_body_external());
+        assert_eq!(post.body(None, &[]).unwrap(), b"short");
+
+        let body = vec![0xA5u8; 1000];
+        let (post, container) =
+            Post::with_body(MesgId::default(), &body, vec![], 100, 64).unwrap();
+        let (container, chunks) = container.unwrap();
+        let container_id = container.container_id();
+        assert_eq!(post.mesg.body.as_ref(), &container_id[..]);
+        assert_eq!(post.mesg.container_ids, vec![container_id]);
+        assert_eq!(post.body_container, Some(container_id));
+        assert_eq!(chunks.len(), 16);
+        assert_eq!(post.body(Some(&container), &chunks).unwrap(), body);
+        assert_eq!(
+            post.body(None, &chunks),
+            Err(PostBodyError::ContainerMismatch(container_id))
+        );
+        assert_eq!(
+            post.body(Some(&container), &chunks[1..]),
+            Err(PostBodyError::InvalidChunk(0))
+        );
+
+        // Forged container size does not match the size of the chunks
+        let mut forged = container;
+        forged.header.chunk_size = MAX_CHUNK_SIZE;
+        forged.header.size = 16 * MAX_CHUNK_SIZE as u64 - 1;
+        assert!(forged.is_chunk_size_valid());
+        let forged_id = forged.container_id();
+        let mut post = Post::from(Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::try_from(&forged_id[..]).unwrap(),
+            container_ids: vec![forged_id],
+        });
+        post.body_container = Some(forged_id);
+        assert_eq!(
+            post.body(Some(&forged), &chunks),
+            Err(PostBodyError::InvalidChunk(16))
+        );
+    }
+
+    #[test]
+    fn test_body_commitment() {
+        let (post, container) =
+            Post::new(MesgId::default(), &[1u8; MAX_INLINE_BODY], vec![])
+                .unwrap();
+        assert!(container.is_none());
+        assert!(!post.is_body_external());
+
+        let body = vec![2u8; MAX_INLINE_BODY + 1];
+        let (attachment, _) = Container::chunkify(
+            AsciiString::try_from("image/png").unwrap(),
+            "",
+            &[3u8; 100],
+            64,
+        )
+        .unwrap();
+        let attachment_id = attachment.container_id();
+        let (post, container) =
+            Post::new(MesgId::default(), &body, vec![attachment_id]).unwrap();
+        let (container, chunks) = container.unwrap();
+        let container_id = container.container_id();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(post.mesg.container_ids, vec![attachment_id, container_id]);
+        assert_eq!(post.body(Some(&container), &chunks).unwrap(), body);
+
+        // Body container can't be swapped for another attached container
+        // without changing the message id
+        let mut forged = post.clone();
+        forged.body_container = Some(attachment_id);
+        assert_eq!(forged.mesg.mesg_id(), post.mesg.mesg_id());
+        assert_eq!(
+            forged.body(Some(&attachment), &[]),
+            Err(PostBodyError::NotCommitted(attachment_id))
+        );
+        forged.mesg.body = Chunk::try_from(&attachment_id[..]).unwrap();
+        assert_ne!(forged.mesg.mesg_id(), post.mesg.mesg_id());
+    }
+}
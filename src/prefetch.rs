@@ -0,0 +1,218 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::{
+    ChunkId, ChunkStore, Container, ContainerFullId, ContainerId, Download,
+    Mesg, StormApp,
+};
+
+/// Default maximum number of prefetch downloads running simultaneously.
+pub const DEFAULT_MAX_PREFETCH: usize = 1;
+
+/// Item scheduled for prefetching into the local store.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{app}, {container}, ...")]
+pub struct PrefetchItem {
+    pub app: StormApp,
+    pub container: ContainerFullId,
+    /// Chunks which should be prefetched; if empty, all of the container
+    /// chunks are prefetched.
+    pub chunk_ids: BTreeSet<ChunkId>,
+}
+
+/// Queue of containers and chunks to be prefetched into the local store in
+/// the background, at a lower priority than the downloads requested by the
+/// user.
+///
+/// The transfer manager takes next item with [`PrefetchQueue::start_next`]
+/// only when there are no foreground downloads, pulls the container if it is
+/// not known yet, and starts a download with
+/// [`PrefetchQueue::start_download`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrefetchQueue {
+    queue: VecDeque<PrefetchItem>,
+    active: BTreeSet<ContainerId>,
+    max_active: usize,
+}
+
+impl Default for PrefetchQueue {
+    fn default() -> Self {
+        PrefetchQueue {
+            queue: empty!(),
+            active: empty!(),
+            max_active: DEFAULT_MAX_PREFETCH,
+        }
+    }
+}
+
+impl PrefetchQueue {
+    pub fn new() -> PrefetchQueue { PrefetchQueue::default() }
+
+    pub fn set_max_active(&mut self, max_active: usize) {
+        self.max_active = max_active;
+    }
+
+    pub fn len(&self) -> usize { self.queue.len() }
+
+    pub fn is_empty(&self) -> bool { self.queue.is_empty() }
+
+    fn is_queued(&self, container_id: ContainerId) -> bool {
+        self.active.contains(&container_id)
+            || self
+                .queue
+                .iter()
+                .any(|item| item.container.container_id == container_id)
+    }
+
+    /// Schedules prefetching of the whole container. Returns `false` if the
+    /// container is already scheduled.
+    pub fn enqueue_container(
+        &mut self,
+        app: StormApp,
+        container: ContainerFullId,
+    ) -> bool {
+        self.enqueue_chunks(app, container, empty!())
+    }
+
+    /// Schedules prefetching of specific container chunks. Returns `false`
+    /// if the container is already scheduled.
+    pub fn enqueue_chunks(
+        &mut self,
+        app: StormApp,
+        container: ContainerFullId,
+        chunk_ids: BTreeSet<ChunkId>,
+    ) -> bool {
+        if self.is_queued(container.container_id) {
+            return false;
+        }
+        self.queue.push_back(PrefetchItem {
+            app,
+            container,
+            chunk_ids,
+        });
+        true
+    }
+
+    /// Schedules prefetching of all containers attached to the message.
+    /// Returns number of newly scheduled containers.
+    pub fn enqueue_attachments(&mut self, app: StormApp, mesg: &Mesg) -> usize {
+        let message_id = mesg.mesg_id();
+        mesg.container_ids
+            .iter()
+            .filter(|container_id| {
+                self.enqueue_container(app, ContainerFullId {
+                    message_id,
+                    container_id: **container_id,
+                })
+            })
+            .count()
+    }
+
+    /// Removes item from the queue, if it was not started yet.
+    pub fn cancel(&mut self, container_id: ContainerId) -> bool {
+        let len = self.queue.len();
+        self.queue.retain(|item| item.container.container_id != container_id);
+        self.queue.len() != len
+    }
+
+    /// Takes next item to be prefetched, provided that there are no
+    /// `foreground` downloads and the number of active prefetch downloads is
+    /// below the limit.
+    pub fn start_next(&mut self, foreground: usize) -> Option<PrefetchItem> {
+        if foreground > 0 || self.active.len() >= self.max_active {
+            return None;
+        }
+        let item = self.queue.pop_front()?;
+        self.active.insert(item.container.container_id);
+        Some(item)
+    }
+
+    /// Constructs download for the prefetch item once the container is
+    /// known, skipping chunks which are not requested or already present in
+    /// the store. Returns `None` and finishes the item if there is nothing to
+    /// download.
+    pub fn start_download<S: ChunkStore>(
+        &mut self,
+        item: &PrefetchItem,
+        container: Container,
+        store: &S,
+        timeout_ms: u64,
+    ) -> Result<Option<Download>, S::Error> {
+        let mut present = BTreeSet::new();
+        for chunk_id in container.chunks.iter() {
+            if (!item.chunk_ids.is_empty()
+                && !item.chunk_ids.contains(chunk_id))
+                || store.has_chunk(*chunk_id)?
+            {
+                present.insert(*chunk_id);
+            }
+        }
+        let mut download = Download::new(
+            item.app,
+            item.container.message_id,
+            container,
+            timeout_ms,
+        );
+        download.mark_present(present);
+        if download.is_complete() {
+            self.finish(item.container.container_id);
+            return Ok(None);
+        }
+        Ok(Some(download))
+    }
+
+    /// Marks prefetch download as completed or abandoned.
+    pub fn finish(&mut self, container_id: ContainerId) {
+        self.active.remove(&container_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::{Chunk, MesgId};
+
+    #[test]
+    fn test_prefetch() {
+        let (container, chunks) = Container::chunkify(
+            AsciiString::new(),
+            "",
+            &(0u8..=255).collect::<Vec<_>>(),
+            64,
+        )
+        .unwrap();
+        let container_id = container.container_id();
+        let mesg = Mesg {
+            parent_id: MesgId::default(),
+            body: Chunk::default(),
+            container_ids: vec![container_id, container_id],
+        };
+
+        let mut queue = PrefetchQueue::new();
+        assert_eq!(queue.enqueue_attachments(StormApp::Chat, &mesg), 1);
+        assert_eq!(queue.start_next(1), None);
+        let item = queue.start_next(0).unwrap();
+        assert_eq!(queue.start_next(0), None);
+
+        let mut store = BTreeMap::new();
+        store.store_chunk(chunks[0].clone()).unwrap();
+        let download = queue
+            .start_download(&item, container, &store, 1000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(download.missing().len(), 3);
+    }
+}
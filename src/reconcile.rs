@@ -0,0 +1,255 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Range-based reconciliation of topic message histories.
+//!
+//! Each peer represents topic history as a sorted set of message ids. Peers
+//! exchange fingerprints of id ranges; ranges with matching fingerprints are
+//! considered synchronized, ranges which differ are split into sub-ranges
+//! until they become small enough to be exchanged as plain id lists. This
+//! takes logarithmic number of rounds and communication proportional to the
+//! size of the set difference.
+
+use std::collections::BTreeSet;
+
+use amplify::Wrapper;
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+
+use crate::MesgId;
+
+/// Number of sub-ranges into which a range with mismatched fingerprint is
+/// split.
+pub const RECONCILE_BRANCHING: usize = 16;
+
+/// Ranges with no more than this number of items are sent as plain id lists
+/// instead of fingerprints.
+pub const RECONCILE_ID_LIST_THRESHOLD: usize = 32;
+
+/// Range of message ids, including `lower` bound and excluding `upper` one.
+/// Absent upper bound means that the range spans to the end of the id space.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct MesgIdRange {
+    pub lower: MesgId,
+    pub upper: Option<MesgId>,
+}
+
+impl MesgIdRange {
+    /// Range covering all possible message ids.
+    pub fn full() -> MesgIdRange { MesgIdRange::default() }
+
+    pub fn contains(&self, id: MesgId) -> bool {
+        id >= self.lower && self.upper.map(|upper| id < upper).unwrap_or(true)
+    }
+}
+
+/// Single item of a reconciliation round.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum RangeItem {
+    /// Fingerprint of the ids within the range.
+    Fingerprint {
+        range: MesgIdRange,
+        count: u32,
+        fingerprint: sha256::Hash,
+    },
+
+    /// Full list of ids within the range.
+    Ids {
+        range: MesgIdRange,
+        ids: BTreeSet<MesgId>,
+    },
+}
+
+/// Reconciliation round message for a topic history.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{topic_id}, ...")]
+pub struct Reconciliation {
+    pub topic_id: MesgId,
+    pub items: Vec<RangeItem>,
+}
+
+impl Reconciliation {
+    /// Detects whether reconciliation is completed, i.e. there is nothing
+    /// more to send to the remote peer.
+    pub fn is_complete(&self) -> bool { self.items.is_empty() }
+}
+
+/// Result of processing a reconciliation round from the remote peer.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ReconcileOutcome {
+    /// Items which has to be sent back to the remote peer. If empty, the
+    /// reconciliation is completed.
+    pub response: Vec<RangeItem>,
+    /// Message ids known to the remote peer and missed locally.
+    pub need: BTreeSet<MesgId>,
+    /// Message ids known locally and missed by the remote peer.
+    pub have: BTreeSet<MesgId>,
+}
+
+/// Set of message ids of a topic, used for reconciliation.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct TopicHistory {
+    ids: BTreeSet<MesgId>,
+}
+
+impl TopicHistory {
+    pub fn new() -> TopicHistory { TopicHistory::default() }
+
+    pub fn insert(&mut self, id: MesgId) -> bool { self.ids.insert(id) }
+
+    pub fn contains(&self, id: MesgId) -> bool { self.ids.contains(&id) }
+
+    pub fn len(&self) -> usize { self.ids.len() }
+
+    pub fn is_empty(&self) -> bool { self.ids.is_empty() }
+
+    fn ids_in(&self, range: MesgIdRange) -> impl Iterator<Item = MesgId> + '_ {
+        self.ids
+            .range(range.lower..)
+            .copied()
+            .take_while(move |id| range.contains(*id))
+    }
+
+    /// Computes number of items and fingerprint of the given range.
+    pub fn fingerprint(&self, range: MesgIdRange) -> (u32, sha256::Hash) {
+        let mut engine = sha256::Hash::engine();
+        let mut count = 0u32;
+        for id in self.ids_in(range) {
+            engine.input(&id.as_inner()[..]);
+            count += 1;
+        }
+        (count, sha256::Hash::from_engine(engine))
+    }
+
+    fn describe(&self, range: MesgIdRange) -> RangeItem {
+        let ids = self.ids_in(range).collect::<BTreeSet<_>>();
+        if ids.len() <= RECONCILE_ID_LIST_THRESHOLD {
+            return RangeItem::Ids { range, ids };
+        }
+        let (count, fingerprint) = self.fingerprint(range);
+        RangeItem::Fingerprint {
+            range,
+            count,
+            fingerprint,
+        }
+    }
+
+    fn split(&self, range: MesgIdRange) -> Vec<RangeItem> {
+        let ids = self.ids_in(range).collect::<Vec<_>>();
+        let group = (ids.len() + RECONCILE_BRANCHING - 1) / RECONCILE_BRANCHING;
+        let mut bounds = ids
+            .chunks(group.max(1))
+            .skip(1)
+            .map(|chunk| chunk[0])
+            .collect::<Vec<_>>();
+        bounds.insert(0, range.lower);
+        let mut uppers =
+            bounds.iter().skip(1).copied().map(Some).collect::<Vec<_>>();
+        uppers.push(range.upper);
+        bounds
+            .into_iter()
+            .zip(uppers)
+            .map(|(lower, upper)| self.describe(MesgIdRange { lower, upper }))
+            .collect()
+    }
+
+    /// Starts reconciliation of the topic history with a remote peer.
+    pub fn initiate(&self, topic_id: MesgId) -> Reconciliation {
+        Reconciliation {
+            topic_id,
+            items: vec![self.describe(MesgIdRange::full())],
+        }
+    }
+
+    /// Processes reconciliation round received from the remote peer.
+    pub fn reconcile(&self, items: &[RangeItem]) -> ReconcileOutcome {
+        let mut outcome = ReconcileOutcome::default();
+        for item in items {
+            match item {
+                RangeItem::Fingerprint {
+                    range,
+                    count,
+                    fingerprint,
+                } => {
+                    if self.fingerprint(*range) == (*count, *fingerprint) {
+                        continue;
+                    }
+                    outcome.response.extend(self.split(*range));
+                }
+                RangeItem::Ids { range, ids } => {
+                    let ours = self.ids_in(*range).collect::<BTreeSet<_>>();
+                    outcome.need.extend(ids.difference(&ours));
+                    outcome.have.extend(ours.difference(ids));
+                }
+            }
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::CommitVerify;
+
+    use super::*;
+
+    fn history(range: std::ops::Range<u32>) -> TopicHistory {
+        let mut history = TopicHistory::new();
+        for no in range {
+            history.insert(MesgId::commit(&no.to_be_bytes()));
+        }
+        history
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let alice = history(0..1000);
+        let bob = history(10..1005);
+        let topic_id = MesgId::default();
+
+        let mut need_alice = BTreeSet::new();
+        let mut need_bob = BTreeSet::new();
+        let mut items = alice.initiate(topic_id).items;
+        let mut turn_bob = true;
+        while !items.is_empty() {
+            let (local, need, have) = if turn_bob {
+                (&bob, &mut need_bob, &mut need_alice)
+            } else {
+                (&alice, &mut need_alice, &mut need_bob)
+            };
+            let outcome = local.reconcile(&items);
+            need.extend(outcome.need);
+            have.extend(outcome.have);
+            items = outcome.response;
+            turn_bob = !turn_bob;
+        }
+
+        assert_eq!(need_bob, history(0..10).ids);
+        assert_eq!(need_alice, history(1000..1005).ids);
+    }
+}
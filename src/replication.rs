@@ -0,0 +1,385 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Agreements between storage providers to mirror containers of each other.
+//!
+//! A provider proposes [`ReplicationTerms`] with `ProposeReplication`
+//! message; the remote provider replies with `AcceptReplication` or
+//! `DeclineReplication` referencing the [`AgreementId`] of the terms. Once
+//! accepted, both providers keep copies of the agreed containers until the
+//! agreement expires and periodically verify that the other party still
+//! holds the copies, challenging it to prove possession of a chunk with a
+//! [`StorageChallenge`].
+
+use std::collections::BTreeSet;
+
+use bitcoin_hashes::{sha256, sha256t};
+use commit_verify::{
+    commit_encode, CommitVerify, ConsensusCommit, PrehashedProtocol, TaggedHash,
+};
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::signed::Signed;
+use crate::sla::{ChallengeResponse, StorageChallenge};
+use crate::{Chunk, Container, ContainerId};
+
+// "storm:replication"
+static MIDSTATE_AGREEMENT_ID: [u8; 32] = [
+    238, 36, 67, 248, 206, 129, 131, 228, 92, 232, 169, 42, 21, 190, 229, 22,
+    129, 1, 155, 189, 170, 164, 112, 239, 209, 145, 127, 211, 191, 211, 213,
+    222,
+];
+
+/// Tag used for [`AgreementId`] hash type
+pub struct AgreementIdTag;
+
+impl sha256t::Tag for AgreementIdTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_AGREEMENT_ID);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Unique replication agreement identifier
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(
+    Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, From
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[wrapper(Debug, Display)]
+pub struct AgreementId(sha256t::Hash<AgreementIdTag>);
+
+impl<Msg> CommitVerify<Msg, PrehashedProtocol> for AgreementId
+where Msg: AsRef<[u8]>
+{
+    #[inline]
+    fn commit(msg: &Msg) -> AgreementId { AgreementId::hash(msg) }
+}
+
+/// Errors verifying copies held by the remote party of a replication
+/// agreement.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ReplicationError {
+    /// agreement has expired.
+    Expired,
+
+    /// container {0} is not mirrored by the remote party.
+    NotMirrored(ContainerId),
+
+    /// container has no chunk with index {0}.
+    UnknownChunk(u32),
+
+    /// challenge response is signed by {0} and not the remote party.
+    PeerMismatch(PublicKey),
+
+    /// signature of {0} is invalid.
+    InvalidSignature(PublicKey),
+
+    /// response does not answer the issued challenge.
+    ChallengeMismatch,
+
+    /// chunk data do not match the challenged container chunk.
+    ChunkMismatch,
+
+    /// remote party failed to prove possession of the challenged chunk.
+    InvalidProof,
+}
+
+/// Terms of mutual container replication between two storage providers.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{proposer}, expires at {expiry}, ...")]
+pub struct ReplicationTerms {
+    /// Node proposing the agreement.
+    pub proposer: PublicKey,
+    /// Containers of the proposer to be mirrored by the remote provider.
+    pub mirrored_by_remote: BTreeSet<ContainerId>,
+    /// Containers of the remote provider to be mirrored by the proposer.
+    pub mirrored_by_proposer: BTreeSet<ContainerId>,
+    /// Interval between verifications of the mirrored copies, in seconds.
+    pub verify_interval: u64,
+    /// Unix timestamp when the agreement ends.
+    pub expiry: u64,
+}
+
+impl commit_encode::Strategy for ReplicationTerms {
+    type Strategy = commit_encode::strategies::UsingStrict;
+}
+
+impl ConsensusCommit for ReplicationTerms {
+    type Commitment = AgreementId;
+}
+
+impl ReplicationTerms {
+    pub fn agreement_id(&self) -> AgreementId { self.consensus_commit() }
+}
+
+/// Replication agreement accepted by both providers.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ReplicationAgreement {
+    /// Remote party of the agreement.
+    pub peer: PublicKey,
+    pub terms: ReplicationTerms,
+    /// Unix timestamp of the agreement acceptance.
+    pub accepted_at: u64,
+    /// Unix timestamp of the last successful verification of the copies
+    /// held by the remote party.
+    pub last_verified: u64,
+}
+
+impl ReplicationAgreement {
+    pub fn new(peer: PublicKey, terms: ReplicationTerms, now: u64) -> Self {
+        ReplicationAgreement {
+            peer,
+            terms,
+            accepted_at: now,
+            last_verified: now,
+        }
+    }
+
+    pub fn agreement_id(&self) -> AgreementId { self.terms.agreement_id() }
+
+    pub fn is_active(&self, now: u64) -> bool { now < self.terms.expiry }
+
+    /// Containers which must be held by the local node under the agreement.
+    pub fn local_obligations(&self) -> &BTreeSet<ContainerId> {
+        if self.terms.proposer == self.peer {
+            &self.terms.mirrored_by_remote
+        } else {
+            &self.terms.mirrored_by_proposer
+        }
+    }
+
+    /// Containers which must be held by the remote peer under the agreement.
+    pub fn remote_obligations(&self) -> &BTreeSet<ContainerId> {
+        if self.terms.proposer == self.peer {
+            &self.terms.mirrored_by_proposer
+        } else {
+            &self.terms.mirrored_by_remote
+        }
+    }
+
+    /// Detects whether copies held by the remote peer should be verified.
+    pub fn is_verification_due(&self, now: u64) -> bool {
+        self.is_active(now)
+            && now.saturating_sub(self.last_verified)
+                >= self.terms.verify_interval
+    }
+
+    /// Issues challenge to prove possession of the container chunk mirrored
+    /// by the remote peer.
+    pub fn challenge(
+        &self,
+        container: &Container,
+        chunk_index: u32,
+        nonce: [u8; 32],
+        now: u64,
+    ) -> Result<StorageChallenge, ReplicationError> {
+        let container_id = container.container_id();
+        if !self.is_active(now) {
+            return Err(ReplicationError::Expired);
+        }
+        if !self.remote_obligations().contains(&container_id) {
+            return Err(ReplicationError::NotMirrored(container_id));
+        }
+        if chunk_index as usize >= container.chunks.len() {
+            return Err(ReplicationError::UnknownChunk(chunk_index));
+        }
+        Ok(StorageChallenge {
+            container_id,
+            chunk_index,
+            nonce,
+            timestamp: now,
+        })
+    }
+
+    /// Verifies response of the remote peer to the challenge against the
+    /// proof of the chunk inclusion into the mirrored container, registering
+    /// successful verification of the remote copies.
+    pub fn verify<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        challenge: StorageChallenge,
+        container: &Container,
+        chunk: &Chunk,
+        response: &Signed<ChallengeResponse>,
+    ) -> Result<(), ReplicationError> {
+        if response.signer != self.peer {
+            return Err(ReplicationError::PeerMismatch(response.signer));
+        }
+        response
+            .verify(secp)
+            .map_err(|_| ReplicationError::InvalidSignature(self.peer))?;
+        if response.data.challenge != challenge {
+            return Err(ReplicationError::ChallengeMismatch);
+        }
+
+        let container_id = container.container_id();
+        if challenge.container_id != container_id {
+            return Err(ReplicationError::NotMirrored(challenge.container_id));
+        }
+        let chunk_proof = container
+            .chunk_proof(challenge.chunk_index as usize)
+            .ok_or(ReplicationError::UnknownChunk(challenge.chunk_index))?;
+        if !chunk_proof.verify(
+            chunk.chunk_id(),
+            container_id,
+            &container.header,
+        ) {
+            return Err(ReplicationError::ChunkMismatch);
+        }
+        if !response.data.is_valid(chunk) {
+            return Err(ReplicationError::InvalidProof);
+        }
+
+        self.last_verified = self.last_verified.max(challenge.timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use commit_verify::tagged_hash;
+    use secp256k1::SecretKey;
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::fixtures::{alice, bob, sample};
+
+    #[test]
+    fn test_agreement_id_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:replication");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_AGREEMENT_ID);
+    }
+
+    #[test]
+    fn test_obligations() {
+        let alice = alice();
+        let bob = bob();
+        let ours = ContainerId::commit(b"alice");
+        let theirs = ContainerId::commit(b"bob");
+        let terms = ReplicationTerms {
+            proposer: alice,
+            mirrored_by_remote: bset![ours],
+            mirrored_by_proposer: bset![theirs],
+            verify_interval: 100,
+            expiry: 1000,
+        };
+
+        let at_alice = ReplicationAgreement::new(bob, terms.clone(), 0);
+        let at_bob = ReplicationAgreement::new(alice, terms, 0);
+        assert_eq!(at_alice.agreement_id(), at_bob.agreement_id());
+        assert_eq!(at_alice.local_obligations(), &bset![theirs]);
+        assert_eq!(at_bob.local_obligations(), &bset![ours]);
+        assert!(!at_alice.is_verification_due(50));
+        assert!(at_alice.is_verification_due(100));
+        assert!(!at_alice.is_verification_due(1000));
+    }
+
+    #[test]
+    fn test_verification() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let peer = PublicKey::from_secret_key(&secp, &key);
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &sample(100, 1), 16)
+                .unwrap();
+        let (other, _) =
+            Container::chunkify(AsciiString::new(), "", &sample(100, 2), 16)
+                .unwrap();
+        let terms = ReplicationTerms {
+            proposer: alice(),
+            mirrored_by_remote: bset![container.container_id()],
+            mirrored_by_proposer: empty!(),
+            verify_interval: 100,
+            expiry: 1000,
+        };
+        let mut agreement = ReplicationAgreement::new(peer, terms, 0);
+        assert_eq!(
+            agreement.challenge(&other, 0, [1; 32], 100),
+            Err(ReplicationError::NotMirrored(other.container_id()))
+        );
+        assert_eq!(
+            agreement.challenge(&container, 7, [1; 32], 100),
+            Err(ReplicationError::UnknownChunk(7))
+        );
+        assert_eq!(
+            agreement.challenge(&container, 0, [1; 32], 1000),
+            Err(ReplicationError::Expired)
+        );
+
+        let challenge =
+            agreement.challenge(&container, 2, [1; 32], 100).unwrap();
+        let answer = |chunk: &Chunk| {
+            Signed::sign(
+                &secp,
+                ChallengeResponse::answer(challenge, chunk),
+                &key,
+            )
+        };
+        let response = answer(&chunks[2]);
+        // Chunk not belonging to the container can't be used for verification
+        assert_eq!(
+            agreement
+                .verify(&secp, challenge, &container, &chunks[1], &response),
+            Err(ReplicationError::ChunkMismatch)
+        );
+        // Peer must prove possession of the challenged chunk
+        let wrong = answer(&chunks[1]);
+        assert_eq!(
+            agreement.verify(&secp, challenge, &container, &chunks[2], &wrong),
+            Err(ReplicationError::InvalidProof)
+        );
+        let forged = Signed::sign(
+            &secp,
+            response.data,
+            &SecretKey::from_slice(&[0x33; 32]).unwrap(),
+        );
+        assert_eq!(
+            agreement.verify(&secp, challenge, &container, &chunks[2], &forged),
+            Err(ReplicationError::PeerMismatch(forged.signer))
+        );
+        let stale = StorageChallenge {
+            nonce: [2; 32],
+            ..challenge
+        };
+        assert_eq!(
+            agreement.verify(&secp, stale, &container, &chunks[2], &response),
+            Err(ReplicationError::ChallengeMismatch)
+        );
+        assert!(agreement.is_verification_due(100));
+
+        assert_eq!(
+            agreement
+                .verify(&secp, challenge, &container, &chunks[2], &response),
+            Ok(())
+        );
+        assert_eq!(agreement.last_verified, 100);
+        assert!(!agreement.is_verification_due(150));
+    }
+}
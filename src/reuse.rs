@@ -0,0 +1,439 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Reuse of local data when retrieving similar containers.
+//!
+//! A node retrieving a container which is similar to data it already holds
+//! (like a new version of a file) sends `BlockSums` message with the
+//! [`BlockSums`] of its local data: a weak [`RollingChecksum`] and a strong
+//! hash of each fixed-size block. The provider scans the container data
+//! with the rolling checksum and replies with `ReuseBlocks` message holding
+//! [`ReusePlan`]: which local blocks of the requester reproduce which parts
+//! of the container and which chunks are not covered by the local blocks.
+//! The requester assembles covered chunks from its local data, checking
+//! them against the chunk ids from the container index, and pulls only the
+//! rest of the chunks (including the ones which failed the check).
+//!
+//! Since both the checksums and the block size are chosen by the requester,
+//! the provider bounds the work spent on the scan: block sizes outside of
+//! the [`MIN_REUSE_BLOCK_SIZE`]..=[`MAX_REUSE_BLOCK_SIZE`] range disable
+//! reuse, and at most [`MAX_WEAK_CANDIDATES`] blocks are considered for each
+//! weak checksum.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin_hashes::{sha256, Hash};
+use strict_encoding::MediumVec;
+
+use crate::delta::RollingChecksum;
+use crate::{Chunk, ChunkId, Container, ContainerId};
+
+/// Default size of the blocks which checksums are sent by the requester.
+pub const DEFAULT_REUSE_BLOCK_SIZE: u32 = 1024;
+
+/// Maximal number of block checksums in a single `BlockSums` message.
+pub const MAX_BLOCK_SUMS: usize = u16::MAX as usize;
+
+/// Minimal block size accepted by the provider.
+pub const MIN_REUSE_BLOCK_SIZE: u32 = 64;
+
+/// Maximal block size accepted by the provider. Together with the limit on
+/// the candidates per weak checksum it bounds the hashing work the requester
+/// can cause at each position of the container data.
+pub const MAX_REUSE_BLOCK_SIZE: u32 = 64 * 1024;
+
+/// Maximal number of blocks with the same weak checksum considered by the
+/// provider; other blocks with the same weak checksum are ignored.
+pub const MAX_WEAK_CANDIDATES: usize = 4;
+
+// Lists in the plan have 24-bit length prefix
+const MAX_PLAN_ITEMS: usize = (1 << 24) - 1;
+
+/// Checksums of a single block of the requester data.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{weak}:{strong}")]
+pub struct BlockSum {
+    /// Value of the [`RollingChecksum`] of the block.
+    pub weak: u32,
+    /// First eight bytes of the SHA256 hash of the block, as a
+    /// little-endian integer.
+    pub strong: u64,
+}
+
+impl BlockSum {
+    fn strong(block: &[u8]) -> u64 {
+        let hash = sha256::Hash::hash(block);
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn with(block: &[u8]) -> BlockSum {
+        BlockSum {
+            weak: RollingChecksum::new(block).value(),
+            strong: Self::strong(block),
+        }
+    }
+}
+
+/// Checksums of the data held by the requester of a container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {block_size}, ...")]
+pub struct BlockSums {
+    /// Container requested by the node.
+    pub container_id: ContainerId,
+    pub block_size: u32,
+    pub sums: Vec<BlockSum>,
+}
+
+impl BlockSums {
+    /// Computes checksums of the blocks of the local data. The block size
+    /// is clamped to the [`MIN_REUSE_BLOCK_SIZE`]..=[`MAX_REUSE_BLOCK_SIZE`]
+    /// range; if the data consist of more than [`MAX_BLOCK_SUMS`] blocks of
+    /// the given size, the block size is increased up to the maximum and
+    /// only the blocks from the start of the data are used.
+    pub fn compute(
+        container_id: ContainerId,
+        local: &[u8],
+        block_size: u32,
+    ) -> BlockSums {
+        let min_size = (local.len() + MAX_BLOCK_SUMS - 1) / MAX_BLOCK_SUMS;
+        let block_size = (block_size as usize).max(min_size).clamp(
+            MIN_REUSE_BLOCK_SIZE as usize,
+            MAX_REUSE_BLOCK_SIZE as usize,
+        );
+        let sums = local
+            .chunks_exact(block_size)
+            .take(MAX_BLOCK_SUMS)
+            .map(BlockSum::with)
+            .collect();
+        BlockSums {
+            container_id,
+            block_size: block_size as u32,
+            sums,
+        }
+    }
+
+    /// Plans retrieval of the container by the requester, given the
+    /// container and its data held by the provider. If the requested block
+    /// size is out of the accepted range, no blocks are reused.
+    pub fn plan(&self, container: &Container, data: &[u8]) -> ReusePlan {
+        let block_size = self.block_size as usize;
+        let mut blocks = BTreeMap::<u32, Vec<(u64, u32)>>::new();
+        if (MIN_REUSE_BLOCK_SIZE..=MAX_REUSE_BLOCK_SIZE)
+            .contains(&self.block_size)
+        {
+            for (no, sum) in self.sums.iter().enumerate() {
+                let candidates = blocks.entry(sum.weak).or_default();
+                if candidates.len() < MAX_WEAK_CANDIDATES {
+                    candidates.push((sum.strong, no as u32));
+                }
+            }
+        }
+
+        let mut segments = vec![];
+        let mut pos = 0usize;
+        let mut checksum = None;
+        while !blocks.is_empty()
+            && segments.len() < MAX_PLAN_ITEMS
+            && pos + block_size <= data.len()
+        {
+            let window = &data[pos..pos + block_size];
+            let sum =
+                *checksum.get_or_insert_with(|| RollingChecksum::new(window));
+            let found = blocks.get(&sum.value()).and_then(|candidates| {
+                let strong = BlockSum::strong(window);
+                candidates.iter().find(|(s, _)| *s == strong)
+            });
+            if let Some((_, block)) = found {
+                segments.push(BlockReuse {
+                    offset: pos as u64,
+                    block: *block,
+                });
+                pos += block_size;
+                checksum = None;
+                continue;
+            }
+            if let (Some(sum), Some(next)) =
+                (checksum.as_mut(), data.get(pos + block_size))
+            {
+                sum.roll(data[pos], *next);
+            }
+            pos += 1;
+        }
+
+        // Runs of adjacent segments covering continuous ranges of the data
+        let mut runs: Vec<(u64, u64)> = vec![];
+        for segment in &segments {
+            let end = segment.offset + block_size as u64;
+            match runs.last_mut() {
+                Some(run) if run.1 == segment.offset => run.1 = end,
+                _ => runs.push((segment.offset, end)),
+            }
+        }
+
+        // Both runs and chunks are ordered by their offsets, so each of them
+        // is visited only once
+        let chunk_size = container.header.chunk_size as u64;
+        let mut covered = vec![];
+        let mut pull = BTreeSet::new();
+        let mut run = 0usize;
+        for (index, chunk_id) in container.chunks.iter().enumerate() {
+            let start = index as u64 * chunk_size;
+            let end = (start + chunk_size).min(container.header.size);
+            while run < runs.len() && runs[run].1 <= start {
+                run += 1;
+            }
+            match runs.get(run) {
+                Some((from, to)) if *from <= start && end <= *to => {
+                    covered.push((start, end))
+                }
+                _ => {
+                    pull.insert(*chunk_id);
+                }
+            }
+        }
+        let mut range = 0usize;
+        let reuse = segments
+            .into_iter()
+            .filter(|segment| {
+                let seg_end = segment.offset + block_size as u64;
+                while range < covered.len()
+                    && covered[range].1 <= segment.offset
+                {
+                    range += 1;
+                }
+                covered
+                    .get(range)
+                    .map(|(start, _)| *start < seg_end)
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+
+        ReusePlan {
+            container_id: self.container_id,
+            block_size: self.block_size,
+            reuse: MediumVec::try_from(reuse)
+                .expect("number of segments is limited"),
+            pull: MediumVec::try_from(pull.into_iter().collect::<Vec<_>>())
+                .expect("pulled chunks are part of the container index"),
+        }
+    }
+}
+
+/// Block of the requester data reproducing part of the container data.
+#[derive(
+    Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, Display
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("#{block}@{offset}")]
+pub struct BlockReuse {
+    /// Offset within the container data.
+    pub offset: u64,
+    /// Index of the block within the requester data.
+    pub block: u32,
+}
+
+/// Response to [`BlockSums`] defining how the requester retrieves the
+/// container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {block_size}, ...")]
+pub struct ReusePlan {
+    pub container_id: ContainerId,
+    pub block_size: u32,
+    /// Requester blocks covering container chunks.
+    pub reuse: MediumVec<BlockReuse>,
+    /// Ids of the chunks which are not covered by the requester blocks and
+    /// must be pulled, in ascending order.
+    pub pull: MediumVec<ChunkId>,
+}
+
+impl ReusePlan {
+    /// Assembles covered chunks from the local data, returning them
+    /// together with the ids of the chunks to pull. Chunks which do not
+    /// match their ids in the container index are pulled as well, as are
+    /// chunks covered by malformed segments of the plan.
+    pub fn assemble(
+        &self,
+        container: &Container,
+        local: &[u8],
+    ) -> (Vec<Chunk>, BTreeSet<ChunkId>) {
+        let block_size = self.block_size.max(1) as u64;
+        let chunk_size = container.header.chunk_size as u64;
+        let mut chunks = vec![];
+        let planned = self.pull.iter().copied().collect::<BTreeSet<_>>();
+        let mut pull = planned.clone();
+        for (index, chunk_id) in container.chunks.iter().enumerate() {
+            if planned.contains(chunk_id) {
+                continue;
+            }
+            let start = index as u64 * chunk_size;
+            let end = (start + chunk_size).min(container.header.size);
+            let mut data = vec![0u8; (end - start) as usize];
+            let mut filled = 0u64;
+            for segment in self.reuse.iter() {
+                let seg_end = match segment.offset.checked_add(block_size) {
+                    Some(seg_end) => seg_end,
+                    None => continue,
+                };
+                if segment.offset >= end || seg_end <= start {
+                    continue;
+                }
+                let from = segment.offset.max(start);
+                let to = seg_end.min(end);
+                let local_from =
+                    segment.block as u64 * block_size + (from - segment.offset);
+                let local_to = local_from + (to - from);
+                if local_to > local.len() as u64 {
+                    break;
+                }
+                data[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(
+                        &local[local_from as usize..local_to as usize],
+                    );
+                filled += to - from;
+            }
+            match Chunk::try_from(data) {
+                Ok(chunk)
+                    if filled == end - start
+                        && chunk.chunk_id() == *chunk_id =>
+                {
+                    chunks.push(chunk)
+                }
+                _ => {
+                    pull.insert(*chunk_id);
+                }
+            }
+        }
+        (chunks, pull)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stens::AsciiString;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+    use crate::fixtures::sample;
+
+    #[test]
+    fn test_reuse() {
+        let local = sample(16_384, 1);
+        let mut target = local.clone();
+        target.splice(5000..5000, b"patch".iter().copied());
+
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &target, 2048).unwrap();
+        let sums = BlockSums::compute(container.container_id(), &local, 256);
+        assert_eq!(sums.sums.len(), 64);
+        let plan = sums.plan(&container, &target);
+        // Only the chunk with the inserted data is pulled
+        assert_eq!(plan.pull.to_vec(), vec![chunks[2].chunk_id()]);
+
+        let (reused, pull) = plan.assemble(&container, &local);
+        assert_eq!(reused.len(), chunks.len() - 1);
+        assert!(reused.iter().all(|chunk| chunks.contains(chunk)));
+        assert_eq!(pull, bset! { chunks[2].chunk_id() });
+
+        // Corrupted local data lead to pulling the affected chunk
+        let mut corrupted = local.clone();
+        corrupted[100] ^= 0xFF;
+        let (reused, pull) = plan.assemble(&container, &corrupted);
+        assert_eq!(reused.len(), chunks.len() - 2);
+        assert!(pull.contains(&chunks[0].chunk_id()));
+    }
+
+    #[test]
+    fn test_malformed_plan() {
+        let local = sample(4096, 1);
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &local, 1024).unwrap();
+        let sums = BlockSums::compute(container.container_id(), &local, 256);
+        let mut plan = sums.plan(&container, &local);
+        assert!(plan.pull.is_empty());
+
+        // Segment overflowing the data offset is skipped, so the chunk it
+        // covers is pulled
+        let mut reuse = plan.reuse.to_vec();
+        reuse[0].offset = u64::MAX - 10;
+        plan.reuse = MediumVec::try_from(reuse).unwrap();
+        let (reused, pull) = plan.assemble(&container, &local);
+        assert_eq!(reused.len(), chunks.len() - 1);
+        assert_eq!(pull, bset! { chunks[0].chunk_id() });
+    }
+
+    #[test]
+    fn test_plan_limits() {
+        let data = sample(4096, 1);
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 1024).unwrap();
+
+        // Block size out of the accepted range disables reuse
+        let mut sums = BlockSums::compute(container.container_id(), &data, 64);
+        sums.block_size = 1;
+        let plan = sums.plan(&container, &data);
+        assert!(plan.reuse.is_empty());
+        assert_eq!(plan.pull.len(), chunks.len());
+
+        // Only a limited number of blocks with the same weak checksum is
+        // considered, so the matching block listed after forged ones is
+        // not found
+        let mut sums = BlockSums::compute(container.container_id(), &data, 64);
+        let target = sums.sums[0];
+        let forged = BlockSum {
+            weak: target.weak,
+            strong: !target.strong,
+        };
+        sums.sums = vec![forged; MAX_WEAK_CANDIDATES];
+        sums.sums.push(target);
+        let plan = sums.plan(&container, &data);
+        assert!(plan.reuse.is_empty());
+    }
+
+    #[test]
+    fn test_large_plan_encoding() {
+        // Container with more chunks than fit into a 16-bit length prefix
+        let data =
+            (0u32..70_000).flat_map(|no| no.to_le_bytes()).collect::<Vec<_>>();
+        let (container, _) =
+            Container::chunkify(AsciiString::new(), "", &data, 4).unwrap();
+        let sums = BlockSums::compute(container.container_id(), &[], 64);
+        let plan = sums.plan(&container, &data);
+        assert_eq!(plan.pull.len(), 70_000);
+        let encoded = plan.strict_serialize().unwrap();
+        assert_eq!(ReusePlan::strict_deserialize(&encoded).unwrap(), plan);
+    }
+}
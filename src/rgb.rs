@@ -0,0 +1,76 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Payloads of the RGB smart contract distribution app.
+//!
+//! Storm does not depend on RGB libraries: contract and schema ids are
+//! transferred as opaque 32-byte hashes, while the contract genesis and
+//! other consignment data are distributed as Storm containers.
+
+use bitcoin_hashes::sha256;
+
+use crate::p2p::StormMesg;
+use crate::{ContainerFullId, StormApp};
+
+/// RGB contract id.
+pub type ContractId = sha256::Hash;
+
+/// RGB schema id.
+pub type SchemaId = sha256::Hash;
+
+/// Announcement of an RGB contract available from the node, gossiped under
+/// [`StormApp::RgbContracts`].
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(NetworkEncode, NetworkDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{contract_id}, {schema_id}, {genesis}")]
+pub struct ContractAnnouncement {
+    pub contract_id: ContractId,
+    pub schema_id: SchemaId,
+    /// Container holding the contract genesis.
+    pub genesis: ContainerFullId,
+}
+
+impl StormMesg for ContractAnnouncement {
+    fn storm_app(&self) -> StormApp { StormApp::RgbContracts }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin_hashes::Hash;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+    use crate::p2p::Messages;
+    use crate::{ContainerId, MesgId};
+
+    #[test]
+    fn test_announcement() {
+        let announcement = ContractAnnouncement {
+            contract_id: ContractId::hash(b"contract"),
+            schema_id: SchemaId::hash(b"schema"),
+            genesis: ContainerFullId {
+                message_id: MesgId::default(),
+                container_id: ContainerId::default(),
+            },
+        };
+        let data = announcement.strict_serialize().unwrap();
+        assert_eq!(
+            ContractAnnouncement::strict_deserialize(data).unwrap(),
+            announcement
+        );
+        let msg = Messages::AnnounceContract(announcement);
+        assert_eq!(msg.storm_app(), StormApp::RgbContracts);
+    }
+}
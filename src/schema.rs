@@ -0,0 +1,2228 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Formal description of the wire layout of Storm data types and messages.
+//!
+//! [`ProtocolSchema::core`] describes the strict encoding of all the storm
+//! protocol messages and of all the types they are composed of. Other
+//! implementations and auditors can compare the layout with their own
+//! mechanically; the schema is printed in a strict-types-like notation, one
+//! type per line:
+//!
+//! ```text
+//! ContainerFullId :: messageId MesgId, containerId ContainerId
+//! MesgId :: [U8 ^ 32]
+//! ```
+//!
+//! Lists are written as `[T ^ ..MAX]` with the maximal number of items
+//! defining the size of the length prefix, fixed arrays as `[T ^ LEN]`,
+//! optional values as `T?`, sets as `{T ^ ..MAX}` and maps as
+//! `{K -> V ^ ..MAX}`. Messages are listed with their type ids and payload
+//! types.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+
+use bitcoin_hashes::sha256;
+use secp256k1::{ecdsa, PublicKey};
+use stens::AsciiString;
+use strict_encoding::{MediumVec, StrictDecode, StrictEncode};
+
+use crate::app::{
+    STORM_APP_CHAT, STORM_APP_FILE_TRANSFER, STORM_APP_RGB_CONTRACTS,
+    STORM_APP_RGB_TRANSFERS, STORM_APP_SEARCH, STORM_APP_STORAGE,
+    STORM_APP_SYSTEM,
+};
+use crate::bloom::BloomFilter;
+use crate::broadcast::Broadcast;
+use crate::credit::{Credit, Iou, SignedIou};
+use crate::encryption::{SealedPost, WrappedKey};
+use crate::gcs::ContainerFilter;
+use crate::mailbox::{CollectMail, DepositMail, MailDelivery, OfflineDelivery};
+use crate::membership::{Invite, Join, Leave, MemberList, MemberRole};
+use crate::moderation::{Ban, RemoveMesg, Unban};
+use crate::p2p::{
+    Accept, AcceptTerms, AppMsg, ChunkBloomFilter, ChunkMismatch, ChunkPull,
+    ChunkPush, ChunkSetSketch, CompactChunkPull, ContainerList,
+    ContainerPartPull, ContainerQuery, Decline, FindTopics, KeyLookup,
+    KeyReveal, LockedChunkPush, MesgBatch, MesgSync, PrefetchHint,
+    RefusalReason, Reject, StreamChunkPull, SyncCursor,
+};
+use crate::posting::{
+    DeliveryFailure, DeliveryFailureReason, DeliveryPolicy, PaymentProof, Post,
+    PostInvoice, PostInvoiceRequest, PostRequirements, PostingToken, PowStamp,
+    TokenUse, TopicProposal,
+};
+use crate::reconcile::{MesgIdRange, RangeItem, Reconciliation};
+use crate::replication::{AgreementId, ReplicationTerms};
+use crate::rgb::ContractAnnouncement;
+use crate::sketch::PinSketch;
+use crate::sla::{
+    ChallengeResponse, Dispute, DisputeClaim, DisputeEvidence, DisputeId,
+    DisputeResolution, DisputeVerdict, Evidence, StorageChallenge,
+    ViolationProof,
+};
+use crate::storage::{
+    LeaseTerms, RemovalAck, RemovalRequest, Replica, ReplicaPlacement,
+    ReplicationConstraints, StorageQuota, StorageReceipt, StorageRequest,
+};
+use crate::swap::{SwapQuote, SwapRequest};
+use crate::varint::VarInt;
+use crate::{
+    AppsAdvert, BlockReuse, BlockSum, BlockSums, Chunk, ChunkId, ChunkPriority,
+    ChunkProof, ChunkSelection, CompactChunkIds, Container, ContainerFullId,
+    ContainerHeader, ContainerId, ContainerInfo, ContainerPage, ContainerPart,
+    MapProof, Mesg, MesgId, PaymentPreimage, PeerLimits, RateLimit,
+    RetrievalReceipt, ReusePlan, Signed, SignedEnvelope, StormApp,
+    StormIdentity, Tag, Topic, MAX_TAG_LEN,
+};
+
+/// Maximal number of items in the lists with 16-bit length prefix.
+const MAX_U16: u32 = u16::MAX as u32;
+
+/// Maximal number of items in the lists with 24-bit length prefix.
+const MAX_U24: u32 = (1 << 24) - 1;
+
+/// Primitive types of strict encoding.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Primitive {
+    #[display("U8")]
+    U8,
+    #[display("U16")]
+    U16,
+    #[display("U32")]
+    U32,
+    #[display("U64")]
+    U64,
+    #[display("Bool")]
+    Bool,
+    #[display("Unit")]
+    Unit,
+    #[display("Ascii")]
+    Ascii,
+    #[display("Unicode")]
+    Unicode,
+    /// Unsigned LEB128 integer of up to 64 bits.
+    #[display("VarInt")]
+    VarInt,
+}
+
+/// Reference to a type used within other types.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+pub enum TypeRef {
+    Primitive(Primitive),
+    /// Type defined in the schema under the given name.
+    Named(&'static str),
+    /// Fixed-size array.
+    Array(Box<TypeRef>, u32),
+    /// Variable-length list with the maximal number of items.
+    List(Box<TypeRef>, u32),
+    /// Sorted set of unique items with the maximal number of items.
+    Set(Box<TypeRef>, u32),
+    /// Sorted map with unique keys and the maximal number of items.
+    Map(Box<TypeRef>, Box<TypeRef>, u32),
+    Option(Box<TypeRef>),
+    /// Anonymous structure, used for generic types.
+    Inline(Vec<(&'static str, TypeRef)>),
+}
+
+impl TypeRef {
+    /// Size of the encoded value, if it does not depend on the value.
+    pub fn fixed_size(&self, schema: &ProtocolSchema) -> Option<usize> {
+        match self {
+            TypeRef::Primitive(Primitive::U8 | Primitive::Bool) => Some(1),
+            TypeRef::Primitive(Primitive::U16) => Some(2),
+            TypeRef::Primitive(Primitive::U32) => Some(4),
+            TypeRef::Primitive(Primitive::U64) => Some(8),
+            TypeRef::Primitive(Primitive::Unit) => Some(0),
+            TypeRef::Primitive(_) => None,
+            TypeRef::Named(name) => schema.types.get(name)?.fixed_size(schema),
+            TypeRef::Array(item, len) => {
+                item.fixed_size(schema).map(|size| size * *len as usize)
+            }
+            TypeRef::List(..)
+            | TypeRef::Set(..)
+            | TypeRef::Map(..)
+            | TypeRef::Option(_) => None,
+            TypeRef::Inline(fields) => {
+                fields.iter().map(|(_, ty)| ty.fixed_size(schema)).sum()
+            }
+        }
+    }
+}
+
+impl Display for TypeRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeRef::Primitive(primitive) => Display::fmt(primitive, f),
+            TypeRef::Named(name) => f.write_str(name),
+            TypeRef::Array(item, len) => write!(f, "[{} ^ {}]", item, len),
+            TypeRef::List(item, max) => write!(f, "[{} ^ ..{:#x}]", item, max),
+            TypeRef::Set(item, max) => write!(f, "{{{} ^ ..{:#x}}}", item, max),
+            TypeRef::Map(key, value, max) => {
+                write!(f, "{{{} -> {} ^ ..{:#x}}}", key, value, max)
+            }
+            TypeRef::Option(item) => write!(f, "{}?", item),
+            TypeRef::Inline(fields) => {
+                f.write_str("(")?;
+                fmt_fields(f, fields)?;
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+fn fmt_fields(
+    f: &mut Formatter<'_>,
+    fields: &[(&'static str, TypeRef)],
+) -> fmt::Result {
+    for (index, (name, ty)) in fields.iter().enumerate() {
+        if index > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{} {}", name, ty)?;
+    }
+    Ok(())
+}
+
+/// Definition of a named type.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+pub enum TypeDef {
+    /// Newtype over the other type, having the same encoding.
+    Alias(TypeRef),
+    /// Structure encoded as its fields in the order of definition.
+    Struct(Vec<(&'static str, TypeRef)>),
+    /// Enumeration of the values encoded with the given primitive. Values
+    /// not listed are reserved for future use.
+    Enum(Primitive, Vec<(&'static str, u64)>),
+    /// Tagged union: tag encoded with the given primitive followed by the
+    /// data of the variant.
+    Union(Primitive, Vec<(&'static str, u64, TypeRef)>),
+    /// Encoding which can't be expressed with the other definitions,
+    /// described in words.
+    Custom(&'static str),
+}
+
+impl TypeDef {
+    pub fn fixed_size(&self, schema: &ProtocolSchema) -> Option<usize> {
+        match self {
+            TypeDef::Alias(ty) => ty.fixed_size(schema),
+            TypeDef::Struct(fields) => {
+                fields.iter().map(|(_, ty)| ty.fixed_size(schema)).sum()
+            }
+            TypeDef::Enum(primitive, _) => {
+                TypeRef::Primitive(*primitive).fixed_size(schema)
+            }
+            TypeDef::Union(..) | TypeDef::Custom(_) => None,
+        }
+    }
+}
+
+impl Display for TypeDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeDef::Alias(ty) => Display::fmt(ty, f),
+            TypeDef::Struct(fields) if fields.is_empty() => f.write_str("()"),
+            TypeDef::Struct(fields) => fmt_fields(f, fields),
+            TypeDef::Enum(primitive, variants) => {
+                write!(f, "{} enum", primitive)?;
+                for (index, (name, value)) in variants.iter().enumerate() {
+                    let sep = if index == 0 { " " } else { " | " };
+                    write!(f, "{}{} = {:#x}", sep, name, value)?;
+                }
+                Ok(())
+            }
+            TypeDef::Union(primitive, variants) => {
+                write!(f, "{} union", primitive)?;
+                for (index, (name, tag, ty)) in variants.iter().enumerate() {
+                    let sep = if index == 0 { " " } else { " | " };
+                    write!(f, "{}{} = {:#x} {}", sep, name, tag, ty)?;
+                }
+                Ok(())
+            }
+            TypeDef::Custom(description) => {
+                write!(f, "custom {:?}", description)
+            }
+        }
+    }
+}
+
+/// Wire message of the protocol.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{type_id:#06x} {name} :: {payload}")]
+pub struct MessageDef {
+    pub type_id: u16,
+    pub name: &'static str,
+    pub payload: TypeRef,
+}
+
+/// Types which can describe their wire layout.
+pub trait WireType {
+    /// Registers type definition and definitions of all types it depends on
+    /// in the schema, returning reference to the type.
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef;
+}
+
+/// Schema of the protocol types and messages.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ProtocolSchema {
+    pub types: BTreeMap<&'static str, TypeDef>,
+    pub messages: Vec<MessageDef>,
+}
+
+impl ProtocolSchema {
+    pub fn new() -> ProtocolSchema { ProtocolSchema::default() }
+
+    /// Schema of all the protocol messages.
+    pub fn core() -> ProtocolSchema {
+        let mut schema = ProtocolSchema::new();
+        schema.message::<()>(0x0002, "ListApps");
+        schema.message::<AppsAdvert>(0x0003, "ActiveApps");
+        schema.message::<AppMsg<()>>(0x0004, "ListTopics");
+        schema.message::<AppMsg<BTreeSet<MesgId>>>(0x0005, "AppTopics");
+        schema.message::<AppMsg<TopicProposal>>(0x0006, "ProposeTopic");
+        schema.message::<AppMsg<Post>>(0x0008, "Post");
+        schema.message::<AppMsg<MesgId>>(0x000a, "Read");
+        schema.message::<AppMsg<Decline>>(0x000c, "Decline");
+        schema.message::<AppMsg<Accept>>(0x000e, "Accept");
+        schema.message::<AppMsg<ContainerFullId>>(0x0010, "PullContainer");
+        schema.message::<AppMsg<ContainerInfo>>(0x0011, "AnnounceContainer");
+        schema.message::<AppMsg<Reject>>(0x0012, "Reject");
+        schema.message::<AppMsg<Container>>(0x0013, "PushContainer");
+        schema.message::<ChunkPull>(0x0014, "PullChunk");
+        schema.message::<ChunkPush>(0x0015, "PushChunk");
+        schema.message::<LockedChunkPush>(0x0017, "PushLockedChunk");
+        schema.message::<KeyReveal>(0x0019, "RevealKey");
+        schema.message::<AppMsg<SwapRequest>>(0x0020, "RequestQuote");
+        schema.message::<AppMsg<SwapQuote>>(0x0021, "Quote");
+        schema.message::<StreamChunkPull>(0x0022, "PullStreamChunks");
+        schema.message::<AppMsg<u32>>(0x0024, "ChunkSizeLimit");
+        schema.message::<AppMsg<MesgSync>>(0x0026, "SyncMesgs");
+        schema.message::<AppMsg<MesgBatch>>(0x0027, "SyncedMesgs");
+        schema.message::<AppMsg<Reconciliation>>(0x0028, "Reconcile");
+        schema.message::<ChunkSetSketch>(0x002a, "ChunkSketch");
+        schema.message::<ChunkBloomFilter>(0x002c, "ChunkFilter");
+        schema.message::<AppMsg<()>>(0x002e, "GetContainerFilter");
+        schema.message::<AppMsg<ContainerFilter>>(0x002f, "ContainerFilter");
+        schema.message::<Credit>(0x0030, "RequestIou");
+        schema.message::<SignedIou>(0x0031, "Iou");
+        schema
+            .message::<AppMsg<ReplicationTerms>>(0x0032, "ProposeReplication");
+        schema.message::<AppMsg<AgreementId>>(0x0033, "AcceptReplication");
+        schema.message::<AppMsg<AgreementId>>(0x0034, "DeclineReplication");
+        schema.message::<AppMsg<StorageRequest>>(0x0036, "RequestStorage");
+        schema.message::<AppMsg<ReplicaPlacement>>(0x0037, "StoragePlacement");
+        schema.message::<AppMsg<Signed<Invite>>>(0x0038, "Invite");
+        schema.message::<AppMsg<Signed<Join>>>(0x0039, "Join");
+        schema.message::<AppMsg<Signed<Leave>>>(0x003a, "Leave");
+        schema.message::<AppMsg<Signed<MemberList>>>(0x003b, "MemberList");
+        schema.message::<AppMsg<Signed<Ban>>>(0x003c, "Ban");
+        schema.message::<AppMsg<Signed<Unban>>>(0x003d, "Unban");
+        schema.message::<AppMsg<Signed<RemoveMesg>>>(0x003e, "RemoveMesg");
+        schema.message::<AppMsg<SignedEnvelope>>(0x003f, "SignedPost");
+        schema.message::<AppMsg<PostRequirements>>(0x0040, "PostRequirements");
+        schema.message::<AppMsg<PostInvoiceRequest>>(
+            0x0041,
+            "RequestPostInvoice",
+        );
+        schema.message::<AppMsg<Signed<PostInvoice>>>(0x0042, "PostInvoice");
+        schema.message::<AppMsg<Signed<PostingToken>>>(0x0043, "PostingToken");
+        schema.message::<PrefetchHint>(0x0044, "PrefetchHint");
+        schema.message::<AppMsg<ContainerQuery>>(0x0046, "ListContainers");
+        schema.message::<AppMsg<ContainerList>>(0x0047, "ContainerList");
+        schema.message::<AppMsg<Signed<RemovalRequest>>>(
+            0x0048,
+            "RequestRemoval",
+        );
+        schema.message::<AppMsg<Signed<RemovalAck>>>(0x0049, "RemovalAck");
+        schema.message::<AppMsg<Signed<StorageReceipt>>>(
+            0x004a,
+            "StorageReceipt",
+        );
+        schema.message::<AppMsg<Signed<RetrievalReceipt>>>(
+            0x004b,
+            "RetrievalReceipt",
+        );
+        schema.message::<AppMsg<StorageChallenge>>(0x004c, "StorageChallenge");
+        schema.message::<AppMsg<Signed<ChallengeResponse>>>(
+            0x004d,
+            "ChallengeResponse",
+        );
+        schema.message::<AppMsg<Signed<Dispute>>>(0x004e, "OpenDispute");
+        schema.message::<AppMsg<Signed<DisputeEvidence>>>(
+            0x004f,
+            "DisputeEvidence",
+        );
+        schema.message::<AppMsg<Signed<DisputeResolution>>>(
+            0x0050,
+            "ResolveDispute",
+        );
+        schema.message::<ContractAnnouncement>(0x0051, "AnnounceContract");
+        schema.message::<AppMsg<SealedPost>>(0x0052, "SealedPost");
+        schema.message::<AppMsg<Signed<Broadcast>>>(0x0053, "Broadcast");
+        schema.message::<AppMsg<DepositMail>>(0x0054, "DepositMail");
+        schema.message::<AppMsg<Signed<CollectMail>>>(0x0055, "CollectMail");
+        schema.message::<AppMsg<MailDelivery>>(0x0056, "MailDelivery");
+        schema.message::<AppMsg<OfflineDelivery>>(0x0057, "DeliverOffline");
+        schema.message::<AppMsg<DeliveryFailure>>(0x0058, "DeliveryFailure");
+        schema.message::<CompactChunkPull>(0x0059, "PullChunkCompact");
+        schema
+            .message::<AppMsg<ContainerPartPull>>(0x005a, "PullContainerPart");
+        schema.message::<AppMsg<ContainerPart>>(0x005b, "PushContainerPart");
+        schema.message::<AppMsg<ContainerPage>>(0x005c, "PushContainerPage");
+        schema.message::<AppMsg<u64>>(0x005d, "AppPing");
+        schema.message::<AppMsg<u64>>(0x005e, "AppPong");
+        schema.message::<AppMsg<StorageQuota>>(0x005f, "StorageQuota");
+        schema.message::<AppMsg<Vec<ContainerInfo>>>(
+            0x0060,
+            "AnnounceContainers",
+        );
+        schema.message::<AppMsg<FindTopics>>(0x0061, "FindTopics");
+        schema.message::<AppMsg<Vec<Topic>>>(0x0062, "FoundTopics");
+        schema.message::<AppMsg<KeyLookup>>(0x0063, "LookupKey");
+        schema.message::<AppMsg<MapProof>>(0x0064, "KeyProof");
+        schema.message::<ChunkMismatch>(0x0065, "ChunkMismatch");
+        schema.message::<AppMsg<Vec<ContainerId>>>(0x0066, "OfferDictionaries");
+        schema
+            .message::<AppMsg<Option<ContainerId>>>(0x0067, "SelectDictionary");
+        schema.message::<AppMsg<BlockSums>>(0x0068, "BlockSums");
+        schema.message::<AppMsg<ReusePlan>>(0x0069, "ReuseBlocks");
+        schema.message::<AppMsg<ChunkPriority>>(0x006a, "ChunkPriority");
+        schema
+    }
+
+    /// Adds message with the payload of type `T`.
+    pub fn message<T: WireType>(&mut self, type_id: u16, name: &'static str) {
+        let payload = T::wire_type(self);
+        self.messages.push(MessageDef {
+            type_id,
+            name,
+            payload,
+        });
+        self.messages.sort_by_key(|message| message.type_id);
+    }
+
+    /// Registers named type, computing its definition only once.
+    pub fn define(
+        &mut self,
+        name: &'static str,
+        definition: impl FnOnce(&mut ProtocolSchema) -> TypeDef,
+    ) -> TypeRef {
+        if !self.types.contains_key(name) {
+            let definition = definition(self);
+            self.types.insert(name, definition);
+        }
+        TypeRef::Named(name)
+    }
+}
+
+impl Display for ProtocolSchema {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (name, definition) in &self.types {
+            writeln!(f, "{} :: {}", name, definition)?;
+        }
+        for message in &self.messages {
+            writeln!(f, "{}", message)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! wire_primitive {
+    ($ty:ty, $primitive:ident) => {
+        impl WireType for $ty {
+            fn wire_type(_: &mut ProtocolSchema) -> TypeRef {
+                TypeRef::Primitive(Primitive::$primitive)
+            }
+        }
+    };
+}
+
+wire_primitive!(u8, U8);
+wire_primitive!(u16, U16);
+wire_primitive!(u32, U32);
+wire_primitive!(u64, U64);
+wire_primitive!(bool, Bool);
+wire_primitive!((), Unit);
+
+impl WireType for AsciiString {
+    fn wire_type(_: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::List(Box::new(TypeRef::Primitive(Primitive::Ascii)), MAX_U16)
+    }
+}
+
+impl WireType for String {
+    fn wire_type(_: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::List(Box::new(TypeRef::Primitive(Primitive::Unicode)), MAX_U16)
+    }
+}
+
+impl<T: WireType> WireType for Vec<T> {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::List(Box::new(T::wire_type(schema)), MAX_U16)
+    }
+}
+
+impl<T: WireType> WireType for BTreeSet<T> {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Set(Box::new(T::wire_type(schema)), MAX_U16)
+    }
+}
+
+impl<T: WireType> WireType for Option<T> {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Option(Box::new(T::wire_type(schema)))
+    }
+}
+
+impl<T: WireType> WireType for MediumVec<T> {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::List(Box::new(T::wire_type(schema)), MAX_U24)
+    }
+}
+
+impl<K: WireType, V: WireType> WireType for BTreeMap<K, V> {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Map(
+            Box::new(K::wire_type(schema)),
+            Box::new(V::wire_type(schema)),
+            MAX_U16,
+        )
+    }
+}
+
+impl<A: WireType, B: WireType> WireType for (A, B) {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Inline(vec![
+            ("0", A::wire_type(schema)),
+            ("1", B::wire_type(schema)),
+        ])
+    }
+}
+
+impl<const LEN: usize> WireType for [u8; LEN] {
+    fn wire_type(_: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Array(Box::new(TypeRef::Primitive(Primitive::U8)), LEN as u32)
+    }
+}
+
+impl WireType for VarInt {
+    fn wire_type(_: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Primitive(Primitive::VarInt)
+    }
+}
+
+fn hash_type(name: &'static str, schema: &mut ProtocolSchema) -> TypeRef {
+    schema.define(name, |_| {
+        TypeDef::Alias(TypeRef::Array(
+            Box::new(TypeRef::Primitive(Primitive::U8)),
+            32,
+        ))
+    })
+}
+
+impl WireType for MesgId {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("MesgId", schema)
+    }
+}
+
+impl WireType for ContainerId {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("ContainerId", schema)
+    }
+}
+
+impl WireType for ChunkId {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("ChunkId", schema)
+    }
+}
+
+impl WireType for sha256::Hash {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("Sha256", schema)
+    }
+}
+
+impl WireType for AgreementId {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("AgreementId", schema)
+    }
+}
+
+impl WireType for DisputeId {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("DisputeId", schema)
+    }
+}
+
+impl WireType for PaymentPreimage {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        hash_type("PaymentPreimage", schema)
+    }
+}
+
+impl WireType for PublicKey {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PublicKey", |schema| {
+            TypeDef::Alias(<[u8; 33]>::wire_type(schema))
+        })
+    }
+}
+
+impl WireType for ecdsa::Signature {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Signature", |schema| {
+            TypeDef::Alias(<[u8; 64]>::wire_type(schema))
+        })
+    }
+}
+
+impl WireType for StormApp {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StormApp", |_| {
+            TypeDef::Enum(Primitive::U16, vec![
+                ("system", STORM_APP_SYSTEM as u64),
+                ("chat", STORM_APP_CHAT as u64),
+                ("fileTransfer", STORM_APP_FILE_TRANSFER as u64),
+                ("storage", STORM_APP_STORAGE as u64),
+                ("search", STORM_APP_SEARCH as u64),
+                ("rgbContracts", STORM_APP_RGB_CONTRACTS as u64),
+                ("rgbTransfers", STORM_APP_RGB_TRANSFERS as u64),
+            ])
+        })
+    }
+}
+
+impl WireType for Chunk {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Chunk", |_| {
+            TypeDef::Alias(TypeRef::List(
+                Box::new(TypeRef::Primitive(Primitive::U8)),
+                MAX_U24,
+            ))
+        })
+    }
+}
+
+impl WireType for Mesg {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Mesg", |schema| {
+            TypeDef::Struct(vec![
+                ("parentId", MesgId::wire_type(schema)),
+                ("body", Chunk::wire_type(schema)),
+                ("containerIds", Vec::<ContainerId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Tag {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Tag", |_| {
+            TypeDef::Alias(TypeRef::List(
+                Box::new(TypeRef::Primitive(Primitive::Ascii)),
+                MAX_TAG_LEN as u32,
+            ))
+        })
+    }
+}
+
+impl WireType for FindTopics {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("FindTopics", |schema| {
+            TypeDef::Struct(vec![
+                ("tags", BTreeSet::<Tag>::wire_type(schema)),
+                ("limit", u16::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Topic {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Topic", |schema| {
+            TypeDef::Struct(vec![
+                ("body", Chunk::wire_type(schema)),
+                ("containerIds", Vec::<ContainerId>::wire_type(schema)),
+                ("tags", BTreeSet::<Tag>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerFullId {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerFullId", |schema| {
+            TypeDef::Struct(vec![
+                ("messageId", MesgId::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerHeader {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerHeader", |schema| {
+            let mime = AsciiString::wire_type(schema);
+            let info = String::wire_type(schema);
+            let size = u64::wire_type(schema);
+            TypeDef::Union(Primitive::U16, vec![
+                (
+                    "v0",
+                    0,
+                    TypeRef::Inline(vec![
+                        ("mime", mime.clone()),
+                        ("info", info.clone()),
+                        ("size", size.clone()),
+                    ]),
+                ),
+                (
+                    "v1",
+                    1,
+                    TypeRef::Inline(vec![
+                        ("mime", mime),
+                        ("info", info),
+                        ("size", size),
+                        ("chunkSize", u32::wire_type(schema)),
+                    ]),
+                ),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerInfo {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerInfo", |schema| {
+            TypeDef::Struct(vec![
+                ("header", ContainerHeader::wire_type(schema)),
+                ("id", ContainerFullId::wire_type(schema)),
+                ("tags", BTreeSet::<Tag>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Container {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Container", |schema| {
+            let chunk_id = ChunkId::wire_type(schema);
+            TypeDef::Struct(vec![
+                ("header", ContainerHeader::wire_type(schema)),
+                ("chunks", TypeRef::List(Box::new(chunk_id), MAX_U24)),
+            ])
+        })
+    }
+}
+
+impl<T: WireType> WireType for AppMsg<T> {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Inline(vec![
+            ("app", StormApp::wire_type(schema)),
+            ("data", T::wire_type(schema)),
+        ])
+    }
+}
+
+impl WireType for RefusalReason {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RefusalReason", |_| {
+            TypeDef::Enum(Primitive::U8, vec![
+                ("notInterested", RefusalReason::NotInterested as u64),
+                ("overQuota", RefusalReason::OverQuota as u64),
+                ("paymentRequired", RefusalReason::PaymentRequired as u64),
+                ("policyViolation", RefusalReason::PolicyViolation as u64),
+                (
+                    "temporarilyUnavailable",
+                    RefusalReason::TemporarilyUnavailable as u64,
+                ),
+            ])
+        })
+    }
+}
+
+impl WireType for Decline {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Decline", |schema| {
+            TypeDef::Struct(vec![
+                ("mesgId", MesgId::wire_type(schema)),
+                ("reason", RefusalReason::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for AcceptTerms {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("AcceptTerms", |schema| {
+            TypeDef::Struct(vec![
+                ("priceMsat", Option::<u64>::wire_type(schema)),
+                ("bandwidthCap", Option::<u64>::wire_type(schema)),
+                ("expiry", Option::<u64>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Accept {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Accept", |schema| {
+            TypeDef::Struct(vec![
+                ("mesgId", MesgId::wire_type(schema)),
+                ("containerIds", BTreeSet::<ContainerId>::wire_type(schema)),
+                ("terms", Option::<AcceptTerms>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Reject {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Reject", |schema| {
+            TypeDef::Struct(vec![
+                ("container", ContainerFullId::wire_type(schema)),
+                ("reason", RefusalReason::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkPull {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkPull", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("messageId", MesgId::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkIds", BTreeSet::<ChunkId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkPush {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkPush", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkId", ChunkId::wire_type(schema)),
+                ("chunk", Chunk::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl<T> WireType for Signed<T>
+where T: WireType + StrictEncode + StrictDecode
+{
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        TypeRef::Inline(vec![
+            ("data", T::wire_type(schema)),
+            ("signer", PublicKey::wire_type(schema)),
+            ("signature", ecdsa::Signature::wire_type(schema)),
+        ])
+    }
+}
+
+impl WireType for CompactChunkIds {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        // Description refers to the chunk id type, which must be defined
+        ChunkId::wire_type(schema);
+        schema.define("CompactChunkIds", |_| {
+            TypeDef::Custom(
+                "U32 count, first ChunkId, then for each next id U8 length of \
+                 the prefix shared with the previous id and the remaining \
+                 bytes",
+            )
+        })
+    }
+}
+
+impl WireType for AppsAdvert {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("AppsAdvert", |schema| {
+            TypeDef::Struct(vec![
+                ("apps", BTreeSet::<StormApp>::wire_type(schema)),
+                ("limits", PeerLimits::wire_type(schema)),
+                (
+                    "rateLimits",
+                    BTreeMap::<StormApp, RateLimit>::wire_type(schema),
+                ),
+            ])
+        })
+    }
+}
+
+impl WireType for PeerLimits {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PeerLimits", |schema| {
+            TypeDef::Struct(vec![
+                ("maxChunkSize", u32::wire_type(schema)),
+                ("maxContainerSize", u64::wire_type(schema)),
+                ("maxTransfers", u16::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for RateLimit {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RateLimit", |schema| {
+            TypeDef::Struct(vec![
+                ("msgsPerMinute", u32::wire_type(schema)),
+                ("bytesPerMinute", u32::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PowStamp {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PowStamp", |schema| {
+            TypeDef::Struct(vec![("nonce", u64::wire_type(schema))])
+        })
+    }
+}
+
+impl WireType for TopicProposal {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("TopicProposal", |schema| {
+            TypeDef::Struct(vec![
+                ("topic", Topic::wire_type(schema)),
+                ("stamp", Option::<PowStamp>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Post {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Post", |schema| {
+            TypeDef::Struct(vec![
+                ("mesg", Mesg::wire_type(schema)),
+                ("stamp", Option::<PowStamp>::wire_type(schema)),
+                ("payment", Option::<PaymentProof>::wire_type(schema)),
+                ("token", Option::<Signed<TokenUse>>::wire_type(schema)),
+                ("delivery", Option::<DeliveryPolicy>::wire_type(schema)),
+                ("bodyContainer", Option::<ContainerId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PaymentProof {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PaymentProof", |schema| {
+            TypeDef::Struct(vec![
+                ("invoice", Signed::<PostInvoice>::wire_type(schema)),
+                ("preimage", PaymentPreimage::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for TokenUse {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("TokenUse", |schema| {
+            TypeDef::Struct(vec![
+                ("mesgId", MesgId::wire_type(schema)),
+                ("token", Signed::<PostingToken>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DeliveryPolicy {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DeliveryPolicy", |schema| {
+            TypeDef::Struct(vec![
+                ("deadline", u64::wire_type(schema)),
+                ("maxAttempts", u16::wire_type(schema)),
+                ("retryInterval", u32::wire_type(schema)),
+                ("notifyFailure", bool::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PostRequirements {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PostRequirements", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("powDifficulty", u8::wire_type(schema)),
+                ("feeMsat", u64::wire_type(schema)),
+                ("tokenRequired", bool::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PostInvoiceRequest {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PostInvoiceRequest", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("mesgId", MesgId::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PostInvoice {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PostInvoice", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("mesgId", MesgId::wire_type(schema)),
+                ("amountMsat", u64::wire_type(schema)),
+                ("paymentHash", hash_type("PaymentHash", schema)),
+                ("invoice", String::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PostingToken {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PostingToken", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("holder", PublicKey::wire_type(schema)),
+                ("posts", u32::wire_type(schema)),
+                ("period", u64::wire_type(schema)),
+                ("expiry", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DeliveryFailure {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DeliveryFailure", |schema| {
+            TypeDef::Struct(vec![
+                ("mesgId", MesgId::wire_type(schema)),
+                ("recipient", PublicKey::wire_type(schema)),
+                ("attempts", u16::wire_type(schema)),
+                ("reason", DeliveryFailureReason::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DeliveryFailureReason {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DeliveryFailureReason", |_| {
+            TypeDef::Enum(Primitive::U8, vec![
+                (
+                    "deadlineExpired",
+                    DeliveryFailureReason::DeadlineExpired as u64,
+                ),
+                (
+                    "attemptsExhausted",
+                    DeliveryFailureReason::AttemptsExhausted as u64,
+                ),
+            ])
+        })
+    }
+}
+
+impl WireType for LockedChunkPush {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("LockedChunkPush", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkId", ChunkId::wire_type(schema)),
+                ("paymentHash", hash_type("PaymentHash", schema)),
+                ("lockedChunk", Chunk::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for KeyReveal {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("KeyReveal", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("paymentHash", hash_type("PaymentHash", schema)),
+                ("preimage", PaymentPreimage::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for SwapRequest {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("SwapRequest", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkIds", BTreeSet::<ChunkId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for SwapQuote {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("SwapQuote", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkIds", BTreeSet::<ChunkId>::wire_type(schema)),
+                ("amountMsat", u64::wire_type(schema)),
+                ("paymentHash", hash_type("PaymentHash", schema)),
+                ("invoice", String::wire_type(schema)),
+                ("expiry", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for StreamChunkPull {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StreamChunkPull", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("messageId", MesgId::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("startMs", u64::wire_type(schema)),
+                ("endMs", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for MesgSync {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MesgSync", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("since", SyncCursor::wire_type(schema)),
+                ("limit", u16::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for SyncCursor {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("SyncCursor", |schema| {
+            TypeDef::Union(Primitive::U8, vec![
+                ("start", 0, <()>::wire_type(schema)),
+                ("after", 1, MesgId::wire_type(schema)),
+                ("since", 2, u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for MesgBatch {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MesgBatch", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("mesgs", Vec::<Mesg>::wire_type(schema)),
+                ("more", bool::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Reconciliation {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Reconciliation", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("items", Vec::<RangeItem>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for RangeItem {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RangeItem", |schema| {
+            TypeDef::Union(Primitive::U8, vec![
+                (
+                    "fingerprint",
+                    0,
+                    TypeRef::Inline(vec![
+                        ("range", MesgIdRange::wire_type(schema)),
+                        ("count", u32::wire_type(schema)),
+                        ("fingerprint", sha256::Hash::wire_type(schema)),
+                    ]),
+                ),
+                (
+                    "ids",
+                    1,
+                    TypeRef::Inline(vec![
+                        ("range", MesgIdRange::wire_type(schema)),
+                        ("ids", BTreeSet::<MesgId>::wire_type(schema)),
+                    ]),
+                ),
+            ])
+        })
+    }
+}
+
+impl WireType for MesgIdRange {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MesgIdRange", |schema| {
+            TypeDef::Struct(vec![
+                ("lower", MesgId::wire_type(schema)),
+                ("upper", Option::<MesgId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkSetSketch {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkSetSketch", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("sketch", PinSketch::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PinSketch {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PinSketch", |schema| {
+            TypeDef::Struct(vec![("syndromes", Vec::<u32>::wire_type(schema))])
+        })
+    }
+}
+
+impl WireType for ChunkBloomFilter {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkBloomFilter", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("filter", BloomFilter::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for BloomFilter {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("BloomFilter", |schema| {
+            TypeDef::Struct(vec![
+                ("hashFuncs", u8::wire_type(schema)),
+                ("tweak", u32::wire_type(schema)),
+                ("bits", MediumVec::<u8>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerFilter {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerFilter", |schema| {
+            TypeDef::Struct(vec![
+                ("key", <[u8; 16]>::wire_type(schema)),
+                ("count", u32::wire_type(schema)),
+                ("data", MediumVec::<u8>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Credit {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Credit", |schema| {
+            TypeDef::Struct(vec![
+                ("bytesRelayed", u64::wire_type(schema)),
+                ("bytesStored", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Iou {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Iou", |schema| {
+            TypeDef::Struct(vec![
+                ("issuer", PublicKey::wire_type(schema)),
+                ("beneficiary", PublicKey::wire_type(schema)),
+                ("seq", u64::wire_type(schema)),
+                ("credit", Credit::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for SignedIou {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("SignedIou", |schema| {
+            TypeDef::Struct(vec![
+                ("iou", Iou::wire_type(schema)),
+                ("signature", ecdsa::Signature::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ReplicationTerms {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ReplicationTerms", |schema| {
+            TypeDef::Struct(vec![
+                ("proposer", PublicKey::wire_type(schema)),
+                (
+                    "mirroredByRemote",
+                    BTreeSet::<ContainerId>::wire_type(schema),
+                ),
+                (
+                    "mirroredByProposer",
+                    BTreeSet::<ContainerId>::wire_type(schema),
+                ),
+                ("verifyInterval", u64::wire_type(schema)),
+                ("expiry", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for StorageRequest {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StorageRequest", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("size", u64::wire_type(schema)),
+                ("duration", u64::wire_type(schema)),
+                ("replication", ReplicationConstraints::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ReplicationConstraints {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ReplicationConstraints", |schema| {
+            TypeDef::Struct(vec![
+                ("factor", u8::wire_type(schema)),
+                ("minRegions", u8::wire_type(schema)),
+                ("allowedRegions", BTreeSet::<String>::wire_type(schema)),
+                ("excludedNodes", BTreeSet::<PublicKey>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ReplicaPlacement {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ReplicaPlacement", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("replicas", Vec::<Replica>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Replica {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Replica", |schema| {
+            TypeDef::Struct(vec![
+                ("nodeId", PublicKey::wire_type(schema)),
+                ("region", Option::<String>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for StorageQuota {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StorageQuota", |schema| {
+            TypeDef::Struct(vec![
+                ("available", u64::wire_type(schema)),
+                ("minContainerSize", u64::wire_type(schema)),
+                ("maxContainerSize", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for RemovalRequest {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RemovalRequest", |schema| {
+            TypeDef::Struct(vec![
+                ("id", ContainerFullId::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for RemovalAck {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RemovalAck", |schema| {
+            TypeDef::Struct(vec![
+                ("request", RemovalRequest::wire_type(schema)),
+                ("removedAt", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for LeaseTerms {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("LeaseTerms", |schema| {
+            TypeDef::Struct(vec![
+                ("start", u64::wire_type(schema)),
+                ("expiry", u64::wire_type(schema)),
+                ("feeMsat", u64::wire_type(schema)),
+                ("replicas", u8::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for StorageReceipt {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StorageReceipt", |schema| {
+            TypeDef::Struct(vec![
+                ("client", PublicKey::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("size", VarInt::wire_type(schema)),
+                ("lease", LeaseTerms::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for RetrievalReceipt {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RetrievalReceipt", |schema| {
+            TypeDef::Struct(vec![
+                ("provider", PublicKey::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("size", VarInt::wire_type(schema)),
+                ("chunks", VarInt::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for StorageChallenge {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StorageChallenge", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkIndex", u32::wire_type(schema)),
+                ("nonce", <[u8; 32]>::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChallengeResponse {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChallengeResponse", |schema| {
+            TypeDef::Struct(vec![
+                ("challenge", StorageChallenge::wire_type(schema)),
+                ("digest", hash_type("ChallengeDigest", schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Dispute {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Dispute", |schema| {
+            TypeDef::Struct(vec![
+                ("receipt", Signed::<StorageReceipt>::wire_type(schema)),
+                ("claim", DisputeClaim::wire_type(schema)),
+                ("openedAt", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DisputeClaim {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DisputeClaim", |schema| {
+            TypeDef::Union(Primitive::U8, vec![
+                ("violation", 0, ViolationProof::wire_type(schema)),
+                ("unanswered", 1, StorageChallenge::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ViolationProof {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ViolationProof", |schema| {
+            TypeDef::Struct(vec![
+                ("receipt", Signed::<StorageReceipt>::wire_type(schema)),
+                ("header", ContainerHeader::wire_type(schema)),
+                ("chunkProof", ChunkProof::wire_type(schema)),
+                ("chunk", Chunk::wire_type(schema)),
+                ("response", Signed::<ChallengeResponse>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DisputeEvidence {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DisputeEvidence", |schema| {
+            TypeDef::Struct(vec![
+                ("disputeId", DisputeId::wire_type(schema)),
+                ("evidence", Evidence::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Evidence {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Evidence", |schema| {
+            TypeDef::Union(Primitive::U8, vec![
+                (
+                    "challengeResponse",
+                    0,
+                    Signed::<ChallengeResponse>::wire_type(schema),
+                ),
+                (
+                    "retrievalReceipt",
+                    1,
+                    Signed::<RetrievalReceipt>::wire_type(schema),
+                ),
+                ("other", 2, Vec::<u8>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DisputeResolution {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DisputeResolution", |schema| {
+            TypeDef::Struct(vec![
+                ("disputeId", DisputeId::wire_type(schema)),
+                ("verdict", DisputeVerdict::wire_type(schema)),
+                ("reason", String::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DisputeVerdict {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DisputeVerdict", |_| {
+            TypeDef::Enum(Primitive::U8, vec![
+                ("providerAtFault", DisputeVerdict::ProviderAtFault as u64),
+                ("dismissed", DisputeVerdict::Dismissed as u64),
+            ])
+        })
+    }
+}
+
+impl WireType for Invite {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Invite", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("invitee", PublicKey::wire_type(schema)),
+                ("role", MemberRole::wire_type(schema)),
+                ("expiry", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for MemberRole {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MemberRole", |_| {
+            TypeDef::Enum(Primitive::U8, vec![
+                ("member", MemberRole::Member as u64),
+                ("admin", MemberRole::Admin as u64),
+                ("owner", MemberRole::Owner as u64),
+            ])
+        })
+    }
+}
+
+impl WireType for Join {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Join", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("invite", Signed::<Invite>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Leave {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Leave", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for MemberList {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MemberList", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("version", u64::wire_type(schema)),
+                (
+                    "members",
+                    BTreeMap::<PublicKey, MemberRole>::wire_type(schema),
+                ),
+            ])
+        })
+    }
+}
+
+impl WireType for Ban {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Ban", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("nodeId", PublicKey::wire_type(schema)),
+                ("until", Option::<u64>::wire_type(schema)),
+                ("reason", String::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Unban {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Unban", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("nodeId", PublicKey::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for RemoveMesg {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("RemoveMesg", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("mesgId", MesgId::wire_type(schema)),
+                ("reason", String::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for StormIdentity {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("StormIdentity", |schema| {
+            TypeDef::Struct(vec![
+                ("nodeId", PublicKey::wire_type(schema)),
+                ("alias", Option::<String>::wire_type(schema)),
+                ("proof", ecdsa::Signature::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for SignedEnvelope {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("SignedEnvelope", |schema| {
+            TypeDef::Struct(vec![
+                ("author", StormIdentity::wire_type(schema)),
+                ("mesg", Mesg::wire_type(schema)),
+                ("seq", u64::wire_type(schema)),
+                ("signature", ecdsa::Signature::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for PrefetchHint {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("PrefetchHint", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkIds", BTreeSet::<ChunkId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerQuery {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerQuery", |schema| {
+            TypeDef::Struct(vec![
+                ("mimePrefix", String::wire_type(schema)),
+                ("after", Option::<ContainerId>::wire_type(schema)),
+                ("limit", u16::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerList {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerList", |schema| {
+            TypeDef::Struct(vec![
+                ("mimePrefix", String::wire_type(schema)),
+                ("containers", Vec::<ContainerInfo>::wire_type(schema)),
+                ("more", bool::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContractAnnouncement {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContractAnnouncement", |schema| {
+            TypeDef::Struct(vec![
+                ("contractId", hash_type("ContractId", schema)),
+                ("schemaId", hash_type("SchemaId", schema)),
+                ("genesis", ContainerFullId::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for WrappedKey {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("WrappedKey", |schema| {
+            TypeDef::Struct(vec![
+                ("ephemeral", PublicKey::wire_type(schema)),
+                ("recipient", PublicKey::wire_type(schema)),
+                ("ciphertext", Vec::<u8>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for SealedPost {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("SealedPost", |schema| {
+            TypeDef::Struct(vec![
+                (
+                    "recipients",
+                    BTreeMap::<PublicKey, WrappedKey>::wire_type(schema),
+                ),
+                ("ciphertext", MediumVec::<u8>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for Broadcast {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("Broadcast", |schema| {
+            TypeDef::Struct(vec![
+                ("topicId", MesgId::wire_type(schema)),
+                ("post", Post::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for DepositMail {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("DepositMail", |schema| {
+            TypeDef::Struct(vec![
+                ("recipient", PublicKey::wire_type(schema)),
+                ("sealedMesg", SealedPost::wire_type(schema)),
+                ("expiry", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for CollectMail {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("CollectMail", |schema| {
+            TypeDef::Struct(vec![
+                ("recipient", PublicKey::wire_type(schema)),
+                ("timestamp", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for MailDelivery {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MailDelivery", |schema| {
+            TypeDef::Struct(vec![
+                ("recipient", PublicKey::wire_type(schema)),
+                ("mail", Vec::<SealedPost>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for OfflineDelivery {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("OfflineDelivery", |schema| {
+            TypeDef::Struct(vec![
+                ("storage", StorageRequest::wire_type(schema)),
+                ("notification", DepositMail::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for CompactChunkPull {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("CompactChunkPull", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("messageId", MesgId::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("chunkIds", CompactChunkIds::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkSelection {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkSelection", |schema| {
+            TypeDef::Struct(vec![
+                ("start", u32::wire_type(schema)),
+                ("end", u32::wire_type(schema)),
+                ("bitmap", Option::<Vec<u8>>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerPartPull {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerPartPull", |schema| {
+            TypeDef::Struct(vec![
+                ("container", ContainerFullId::wire_type(schema)),
+                ("selection", ChunkSelection::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkProof {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkProof", |schema| {
+            let node = hash_type("ChunkMerkleNode", schema);
+            TypeDef::Struct(vec![
+                ("index", u32::wire_type(schema)),
+                ("path", TypeRef::List(Box::new(node), MAX_U16)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerPart {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerPart", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("header", ContainerHeader::wire_type(schema)),
+                ("chunks", Vec::<(ChunkId, ChunkProof)>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ContainerPage {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ContainerPage", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("header", ContainerHeader::wire_type(schema)),
+                ("firstIndex", u32::wire_type(schema)),
+                ("chunkIds", Vec::<ChunkId>::wire_type(schema)),
+                ("hasMore", bool::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for KeyLookup {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("KeyLookup", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("key", Vec::<u8>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for MapProof {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("MapProof", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("key", Vec::<u8>::wire_type(schema)),
+                ("header", ContainerHeader::wire_type(schema)),
+                ("chunks", Vec::<(Chunk, ChunkProof)>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkMismatch {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkMismatch", |schema| {
+            TypeDef::Struct(vec![
+                ("app", StormApp::wire_type(schema)),
+                ("containerId", ContainerId::wire_type(schema)),
+                ("expected", ChunkId::wire_type(schema)),
+                ("actual", ChunkId::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for BlockSum {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("BlockSum", |schema| {
+            TypeDef::Struct(vec![
+                ("weak", u32::wire_type(schema)),
+                ("strong", u64::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for BlockSums {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("BlockSums", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("blockSize", u32::wire_type(schema)),
+                ("sums", Vec::<BlockSum>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for BlockReuse {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("BlockReuse", |schema| {
+            TypeDef::Struct(vec![
+                ("offset", u64::wire_type(schema)),
+                ("block", u32::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ReusePlan {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ReusePlan", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("blockSize", u32::wire_type(schema)),
+                ("reuse", MediumVec::<BlockReuse>::wire_type(schema)),
+                ("pull", MediumVec::<ChunkId>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+impl WireType for ChunkPriority {
+    fn wire_type(schema: &mut ProtocolSchema) -> TypeRef {
+        schema.define("ChunkPriority", |schema| {
+            TypeDef::Struct(vec![
+                ("containerId", ContainerId::wire_type(schema)),
+                ("order", MediumVec::<u32>::wire_type(schema)),
+            ])
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_core_schema() {
+        let schema = ProtocolSchema::core();
+        assert_eq!(schema.messages.len(), 85);
+        let text = schema.to_string();
+        assert!(text.contains(
+            "ContainerFullId :: messageId MesgId, containerId ContainerId\n"
+        ));
+        assert!(text.contains("MesgId :: [U8 ^ 32]\n"));
+        assert!(text.contains("0x000a Read :: (app StormApp, data MesgId)\n"));
+        assert!(text.contains(
+            "SyncCursor :: U8 union start = 0x0 Unit | after = 0x1 MesgId | \
+             since = 0x2 U64\n"
+        ));
+        assert!(text.contains(
+            "MemberList :: topicId MesgId, version U64, members {PublicKey -> \
+             MemberRole ^ ..0xffff}\n"
+        ));
+        assert!(text.contains(
+            "RetrievalReceipt :: provider PublicKey, containerId ContainerId, \
+             size VarInt, chunks VarInt, timestamp U64\n"
+        ));
+
+        let full_id = ContainerFullId {
+            message_id: MesgId::default(),
+            container_id: ContainerId::default(),
+        };
+        assert_eq!(
+            TypeRef::Named("ContainerFullId").fixed_size(&schema),
+            Some(full_id.strict_serialize().unwrap().len())
+        );
+        assert_eq!(TypeRef::Named("Mesg").fixed_size(&schema), None);
+        assert_eq!(TypeRef::Named("PublicKey").fixed_size(&schema), Some(33));
+        assert_eq!(TypeRef::Named("SyncCursor").fixed_size(&schema), None);
+    }
+
+    /// Parses value of the type from the front of the data according to
+    /// the schema, collecting names of the parsed types.
+    fn parse(
+        schema: &ProtocolSchema,
+        ty: &TypeRef,
+        data: &mut &[u8],
+        visited: &mut BTreeSet<&'static str>,
+    ) -> Result<(), String> {
+        fn take<'a>(
+            data: &mut &'a [u8],
+            len: usize,
+        ) -> Result<&'a [u8], String> {
+            if data.len() < len {
+                return Err(format!(
+                    "{} bytes expected, {} left",
+                    len,
+                    data.len()
+                ));
+            }
+            let (head, tail) = data.split_at(len);
+            *data = tail;
+            Ok(head)
+        }
+
+        fn uint(data: &mut &[u8], len: usize) -> Result<u64, String> {
+            Ok(take(data, len)?
+                .iter()
+                .rev()
+                .fold(0u64, |acc, byte| acc << 8 | *byte as u64))
+        }
+
+        fn primitive(
+            data: &mut &[u8],
+            primitive: Primitive,
+        ) -> Result<u64, String> {
+            match primitive {
+                Primitive::U8 => uint(data, 1),
+                Primitive::U16 => uint(data, 2),
+                Primitive::U32 => uint(data, 4),
+                Primitive::U64 => uint(data, 8),
+                Primitive::Unit => Ok(0),
+                Primitive::Bool => match uint(data, 1)? {
+                    value @ (0 | 1) => Ok(value),
+                    value => Err(format!("invalid bool value {}", value)),
+                },
+                Primitive::VarInt => {
+                    let mut value = 0u64;
+                    for shift in (0..64).step_by(7) {
+                        let byte = uint(data, 1)?;
+                        value |= (byte & 0x7f) << shift;
+                        if byte & 0x80 == 0 {
+                            return Ok(value);
+                        }
+                    }
+                    Err(s!("too long varint"))
+                }
+                Primitive::Ascii | Primitive::Unicode => {
+                    Err(format!("{} characters outside of a list", primitive))
+                }
+            }
+        }
+
+        fn count(data: &mut &[u8], max: u32) -> Result<usize, String> {
+            let len = if max <= MAX_U16 {
+                2
+            } else if max <= MAX_U24 {
+                3
+            } else {
+                4
+            };
+            let count = uint(data, len)?;
+            if count > max as u64 {
+                return Err(format!(
+                    "{} items exceed maximum of {}",
+                    count, max
+                ));
+            }
+            Ok(count as usize)
+        }
+
+        match ty {
+            TypeRef::Primitive(p) => primitive(data, *p).map(|_| ()),
+            TypeRef::Named(name) => {
+                visited.insert(*name);
+                let definition = schema
+                    .types
+                    .get(name)
+                    .ok_or_else(|| format!("{} is not defined", name))?;
+                let len = data.len();
+                match definition {
+                    TypeDef::Alias(ty) => parse(schema, ty, data, visited)?,
+                    TypeDef::Struct(fields) => {
+                        for (_, ty) in fields {
+                            parse(schema, ty, data, visited)?;
+                        }
+                    }
+                    TypeDef::Enum(p, _) => {
+                        primitive(data, *p)?;
+                    }
+                    TypeDef::Union(p, variants) => {
+                        let tag = primitive(data, *p)?;
+                        let (_, _, ty) = variants
+                            .iter()
+                            .find(|(_, value, _)| *value == tag)
+                            .ok_or_else(|| {
+                                format!("unknown {} variant {}", name, tag)
+                            })?;
+                        parse(schema, ty, data, visited)?;
+                    }
+                    TypeDef::Custom(_) if *name == "CompactChunkIds" => {
+                        CompactChunkIds::strict_decode(&mut *data)
+                            .map_err(|err| err.to_string())?;
+                    }
+                    TypeDef::Custom(_) => {
+                        return Err(format!("unknown layout of {}", name))
+                    }
+                }
+                match definition.fixed_size(schema) {
+                    Some(size) if size != len - data.len() => Err(format!(
+                        "{} takes {} bytes instead of {}",
+                        name,
+                        len - data.len(),
+                        size
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            TypeRef::Array(item, len) => {
+                for _ in 0..*len {
+                    parse(schema, item, data, visited)?;
+                }
+                Ok(())
+            }
+            TypeRef::List(item, max) | TypeRef::Set(item, max) => {
+                let count = count(data, *max)?;
+                match **item {
+                    TypeRef::Primitive(Primitive::Ascii) => {
+                        if !take(data, count)?.is_ascii() {
+                            return Err(s!("non-ASCII string"));
+                        }
+                    }
+                    TypeRef::Primitive(Primitive::Unicode) => {
+                        String::from_utf8(take(data, count)?.to_vec())
+                            .map_err(|err| err.to_string())?;
+                    }
+                    _ => {
+                        for _ in 0..count {
+                            parse(schema, item, data, visited)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            TypeRef::Map(key, value, max) => {
+                for _ in 0..count(data, *max)? {
+                    parse(schema, key, data, visited)?;
+                    parse(schema, value, data, visited)?;
+                }
+                Ok(())
+            }
+            TypeRef::Option(item) => match uint(data, 1)? {
+                0 => Ok(()),
+                1 => parse(schema, item, data, visited),
+                tag => Err(format!("invalid option tag {}", tag)),
+            },
+            TypeRef::Inline(fields) => {
+                for (_, ty) in fields {
+                    parse(schema, ty, data, visited)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Parses encoded messages of all types with the schema, checking that
+    /// the layout of every described type matches its encoding.
+    #[test]
+    #[cfg(feature = "test-utils")]
+    fn test_schema_layout() {
+        use internet2::TypedEnum;
+        use proptest::prelude::*;
+        use proptest::strategy::ValueTree;
+        use proptest::test_runner::TestRunner;
+
+        use crate::p2p::Messages;
+
+        let schema = ProtocolSchema::core();
+        let strategy = any::<Messages>();
+        let mut runner = TestRunner::deterministic();
+        let mut sampled = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        for _ in 0..schema.messages.len() * 32 {
+            let mesg = strategy.new_tree(&mut runner).unwrap().current();
+            let type_id = mesg.get_type();
+            let message = schema
+                .messages
+                .iter()
+                .find(|message| message.type_id == type_id)
+                .unwrap_or_else(|| {
+                    panic!("message {:#06x} is not described", type_id)
+                });
+            let data = mesg.serialize();
+            let mut payload = &data[2..];
+            parse(&schema, &message.payload, &mut payload, &mut visited)
+                .unwrap_or_else(|err| panic!("{}: {}", message.name, err));
+            assert!(
+                payload.is_empty(),
+                "{} has {} bytes not described by the schema",
+                message.name,
+                payload.len()
+            );
+            sampled.insert(type_id);
+        }
+
+        let described = schema
+            .messages
+            .iter()
+            .map(|message| message.type_id)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(sampled, described);
+        let defined = schema.types.keys().copied().collect::<BTreeSet<_>>();
+        assert_eq!(visited, defined);
+    }
+}
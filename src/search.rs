@@ -0,0 +1,429 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Full-text index containers of the search app.
+//!
+//! Search index is a key-value container (see [`crate::KvContainer`]) with
+//! the MIME type [`SEARCH_INDEX_MIME`]. The empty key holds the strict-encoded
+//! list of the indexed container ids; each other key is a term, holding the
+//! strict-encoded sorted list of positions of the containers containing the
+//! term in the container list. Readers fetch the key table, the container
+//! list and the posting lists of the query terms only.
+//!
+//! Containers are indexed by their description, MIME type and tags, and
+//! optionally by their content, if it is a valid UTF-8 text. Text is split
+//! into terms at non-alphanumeric characters and lowercased; terms shorter
+//! than [`MIN_TERM_LEN`] or longer than [`MAX_TERM_LEN`] characters are
+//! skipped.
+//!
+//! Lightweight peers, which can't afford downloading posting lists, may use
+//! per-container [`KeywordFilter`]s distributed as single chunks: a Bloom
+//! filter over the container terms tells which containers may match a query
+//! before the full postings are pulled.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use amplify::Wrapper;
+use bitcoin_hashes::Hash;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::bloom::{BloomError, BloomFilter};
+use crate::chunk::encoding::ApplyStrictEncoding;
+use crate::{
+    Chunk, ChunkSlice, Container, ContainerId, ContainerInfo, KvBuilder,
+    KvContainer, KvError,
+};
+
+/// MIME type of search index containers.
+pub const SEARCH_INDEX_MIME: &str = "application/x-storm-search-index";
+
+/// Minimal length of an indexed term, in characters.
+pub const MIN_TERM_LEN: usize = 2;
+
+/// Maximal length of an indexed term, in characters.
+pub const MAX_TERM_LEN: usize = 64;
+
+/// Default false-positive rate of the keyword filters.
+pub const KEYWORD_FILTER_FP_RATE: f64 = 0.01;
+
+/// Key of the container list in the index container.
+const DOCUMENTS_KEY: &[u8] = b"";
+
+/// Errors building and reading search indexes.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SearchError {
+    /// index has too many containers or terms.
+    TooLarge,
+
+    /// container is not a search index or has malformed data.
+    Decoding,
+
+    /// index chunk #{0} required to answer the query is not provided.
+    MissingChunk(usize),
+
+    /// search query has no terms to search for.
+    EmptyQuery,
+}
+
+impl From<KvError> for SearchError {
+    fn from(err: KvError) -> Self {
+        match err {
+            KvError::TooLarge => SearchError::TooLarge,
+            KvError::Decoding => SearchError::Decoding,
+            KvError::MissingChunk(index) => SearchError::MissingChunk(index),
+        }
+    }
+}
+
+/// Splits text into the set of lowercase search terms.
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| {
+            let len = term.chars().count();
+            (MIN_TERM_LEN..=MAX_TERM_LEN).contains(&len)
+        })
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Builder for search index containers.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SearchIndexBuilder {
+    documents: BTreeMap<ContainerId, BTreeSet<String>>,
+}
+
+impl SearchIndexBuilder {
+    pub fn new() -> SearchIndexBuilder { SearchIndexBuilder::default() }
+
+    /// Number of indexed containers.
+    pub fn len(&self) -> usize { self.documents.len() }
+
+    pub fn is_empty(&self) -> bool { self.documents.is_empty() }
+
+    /// Indexes terms of the text under the container.
+    pub fn add_text(&mut self, container_id: ContainerId, text: &str) {
+        self.documents.entry(container_id).or_default().extend(tokenize(text));
+    }
+
+    /// Indexes container description, MIME type and tags.
+    pub fn add_container(&mut self, info: &ContainerInfo) {
+        let container_id = info.id.container_id;
+        self.add_text(container_id, &info.header.info);
+        self.add_text(container_id, info.header.mime.as_str());
+        for tag in &info.tags {
+            self.add_text(container_id, tag.as_str());
+        }
+    }
+
+    /// Indexes container content, provided as the container chunks in the
+    /// container order. Content which is not a valid UTF-8 text is not
+    /// indexed; returns whether the content was indexed.
+    pub fn add_content(
+        &mut self,
+        container: &Container,
+        chunks: &[Chunk],
+    ) -> bool {
+        let data = chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter().copied())
+            .collect::<Vec<_>>();
+        match String::from_utf8(data) {
+            Ok(text) => {
+                self.add_text(container.container_id(), &text);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Produces keyword filters for each of the indexed containers.
+    pub fn keyword_filters(
+        &self,
+        fp_rate: f64,
+    ) -> Result<Vec<KeywordFilter>, BloomError> {
+        self.documents
+            .iter()
+            .map(|(container_id, terms)| {
+                KeywordFilter::with_terms(*container_id, terms, fp_rate)
+            })
+            .collect()
+    }
+
+    /// Produces search index container and its chunks.
+    pub fn build(
+        &self,
+        info: impl ToString,
+        chunk_size: u32,
+    ) -> Result<(SearchIndex, Vec<Chunk>), SearchError> {
+        let documents = self.documents.keys().copied().collect::<Vec<_>>();
+        let mut postings = BTreeMap::<&str, Vec<u32>>::new();
+        for (pos, terms) in self.documents.values().enumerate() {
+            for term in terms {
+                postings.entry(term).or_default().push(pos as u32);
+            }
+        }
+
+        let mut kv = KvBuilder::new();
+        kv.insert(
+            DOCUMENTS_KEY,
+            documents.strict_serialize().map_err(|_| SearchError::TooLarge)?,
+        );
+        for (term, positions) in postings {
+            kv.insert(
+                term.as_bytes(),
+                positions
+                    .strict_serialize()
+                    .map_err(|_| SearchError::TooLarge)?,
+            );
+        }
+        let (kv, chunks) =
+            kv.build_with_mime(SEARCH_INDEX_MIME, info, chunk_size)?;
+        let index = SearchIndex::with_kv(kv, &chunks)?;
+        Ok((index, chunks))
+    }
+}
+
+/// Search index container with the decoded key table and container list.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SearchIndex {
+    kv: KvContainer,
+    documents: Vec<ContainerId>,
+}
+
+impl SearchIndex {
+    fn with_kv(
+        kv: KvContainer,
+        chunks: &[Chunk],
+    ) -> Result<SearchIndex, SearchError> {
+        let documents =
+            kv.get(DOCUMENTS_KEY, chunks)?.ok_or(SearchError::Decoding)?;
+        let documents = Vec::<ContainerId>::strict_deserialize(documents)
+            .map_err(|_| SearchError::Decoding)?;
+        Ok(SearchIndex { kv, documents })
+    }
+
+    /// Decodes index from the container chunks, provided in the container
+    /// order. Only the chunks holding the key table and the container list
+    /// have to be present; the rest may be empty.
+    pub fn with(
+        container: Container,
+        chunks: &[Chunk],
+    ) -> Result<SearchIndex, SearchError> {
+        let kv = KvContainer::with_mime(container, chunks, SEARCH_INDEX_MIME)?;
+        SearchIndex::with_kv(kv, chunks)
+    }
+
+    pub fn container(&self) -> &Container { self.kv.container() }
+
+    /// Ids of the indexed containers.
+    pub fn containers(&self) -> &[ContainerId] { &self.documents }
+
+    /// Chunk slices holding the posting list of the term, or `None` if the
+    /// term is not indexed. Allows to pull only the chunks required to
+    /// answer a query.
+    pub fn lookup(&self, term: &str) -> Option<Vec<ChunkSlice>> {
+        self.kv.lookup(term.to_lowercase().as_bytes())
+    }
+
+    /// Containers containing the term.
+    pub fn postings(
+        &self,
+        term: &str,
+        chunks: &[Chunk],
+    ) -> Result<BTreeSet<ContainerId>, SearchError> {
+        let data = match self.kv.get(term.to_lowercase().as_bytes(), chunks)? {
+            None => return Ok(empty!()),
+            Some(data) => data,
+        };
+        Vec::<u32>::strict_deserialize(data)
+            .map_err(|_| SearchError::Decoding)?
+            .into_iter()
+            .map(|pos| {
+                self.documents
+                    .get(pos as usize)
+                    .copied()
+                    .ok_or(SearchError::Decoding)
+            })
+            .collect()
+    }
+
+    /// Evaluates the query against the index.
+    pub fn search(
+        &self,
+        query: &SearchQuery,
+        chunks: &[Chunk],
+    ) -> Result<BTreeSet<ContainerId>, SearchError> {
+        let mut terms = query.required.iter();
+        let first = terms.next().ok_or(SearchError::EmptyQuery)?;
+        let mut found = self.postings(first, chunks)?;
+        for term in terms {
+            let postings = self.postings(term, chunks)?;
+            found.retain(|id| postings.contains(id));
+        }
+        for term in &query.excluded {
+            let postings = self.postings(term, chunks)?;
+            found.retain(|id| !postings.contains(id));
+        }
+        Ok(found)
+    }
+}
+
+/// Bloom filter over the terms of a container, which is transferred as a
+/// single chunk.
+///
+/// The filter is tweaked with the container id, so filters of different
+/// containers produce independent false positives.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct KeywordFilter {
+    pub container_id: ContainerId,
+    filter: BloomFilter,
+}
+
+impl ApplyStrictEncoding for KeywordFilter {}
+
+impl KeywordFilter {
+    /// Constructs filter holding the terms of the container.
+    pub fn with_terms<'term>(
+        container_id: ContainerId,
+        terms: impl IntoIterator<Item = &'term String>,
+        fp_rate: f64,
+    ) -> Result<KeywordFilter, BloomError> {
+        let terms = terms.into_iter().collect::<Vec<_>>();
+        let id = container_id.into_inner().into_inner();
+        let tweak = u32::from_le_bytes([id[0], id[1], id[2], id[3]]);
+        let mut filter = BloomFilter::with_rate(terms.len(), fp_rate, tweak)?;
+        for term in terms {
+            filter.insert(term.as_bytes());
+        }
+        Ok(KeywordFilter {
+            container_id,
+            filter,
+        })
+    }
+
+    /// Checks whether the container may contain the term. Returns `false`
+    /// only if the term is definitely absent.
+    pub fn may_contain(&self, term: &str) -> bool {
+        self.filter.contains(term.to_lowercase().as_bytes())
+    }
+
+    /// Checks whether the container may match the query. Excluded terms are
+    /// ignored, since the filter can't prove their presence.
+    pub fn may_match(&self, query: &SearchQuery) -> bool {
+        query.required.iter().all(|term| self.may_contain(term))
+    }
+}
+
+/// Search query matching containers containing all the required terms and
+/// none of the excluded terms.
+///
+/// Text representation lists the terms separated by whitespace, with the
+/// excluded terms prefixed by `-`, like `lightning -bitcoin`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SearchQuery {
+    pub required: BTreeSet<String>,
+    pub excluded: BTreeSet<String>,
+}
+
+impl FromStr for SearchQuery {
+    type Err = SearchError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = SearchQuery::default();
+        for word in s.split_whitespace() {
+            match word.strip_prefix('-') {
+                Some(word) => query.excluded.extend(tokenize(word)),
+                None => query.required.extend(tokenize(word)),
+            }
+        }
+        if query.required.is_empty() {
+            return Err(SearchError::EmptyQuery);
+        }
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::{ContainerFullId, MesgId, TryFromChunk, TryToChunk};
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Lightning-network: a 2nd layer"),
+            bset! { s!("lightning"), s!("network"), s!("2nd"), s!("layer") }
+        );
+    }
+
+    #[test]
+    fn test_keyword_filter() {
+        let mut builder = SearchIndexBuilder::new();
+        builder.add_text(ContainerId::default(), "lightning channels");
+        let filter =
+            builder.keyword_filters(KEYWORD_FILTER_FP_RATE).unwrap().remove(0);
+        let chunk = filter.try_to_chunk().unwrap();
+        assert_eq!(KeywordFilter::try_from_chunk(chunk).unwrap(), filter);
+
+        assert!(filter.may_contain("Lightning"));
+        let query = SearchQuery::from_str("lightning -channels").unwrap();
+        assert!(filter.may_match(&query));
+        let query = SearchQuery::from_str("lightning bitcoin").unwrap();
+        assert!(!filter.may_match(&query));
+    }
+
+    #[test]
+    fn test_search() {
+        let text = b"Lightning network channels and routing";
+        let (doc, doc_chunks) =
+            Container::chunkify(AsciiString::new(), "Notes", text, 16).unwrap();
+        let info = ContainerInfo {
+            header: doc.header.clone(),
+            id: ContainerFullId {
+                message_id: MesgId::default(),
+                container_id: doc.container_id(),
+            },
+            tags: bset! { "bitcoin".parse().unwrap() },
+        };
+        let other = ContainerId::default();
+
+        let mut builder = SearchIndexBuilder::new();
+        builder.add_container(&info);
+        assert!(builder.add_content(&doc, &doc_chunks));
+        builder.add_text(other, "bitcoin routing");
+        let (index, chunks) = builder.build("index", 64).unwrap();
+
+        let restored =
+            SearchIndex::with(index.container().clone(), &chunks).unwrap();
+        assert_eq!(restored, index);
+
+        let doc_id = doc.container_id();
+        let query = SearchQuery::from_str("routing").unwrap();
+        assert_eq!(index.search(&query, &chunks), Ok(bset! { doc_id, other }));
+        let query = SearchQuery::from_str("Routing -lightning").unwrap();
+        assert_eq!(index.search(&query, &chunks), Ok(bset! { other }));
+        let query = SearchQuery::from_str("notes bitcoin").unwrap();
+        assert_eq!(index.search(&query, &chunks), Ok(bset! { doc_id }));
+        assert_eq!(
+            SearchQuery::from_str("-lightning"),
+            Err(SearchError::EmptyQuery)
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Confidentiality of chunk transfers between connected peers.
+//!
+//! Peers connected over the encrypted transport share a secret exported from
+//! the transport handshake. For each transfer both peers derive a
+//! [`TransferKey`] from that secret, the transfer id, which is the id of
+//! the transferred container, and the node id of the sending peer:
+//! `SHA256t("storm:transfer", handshake_secret || container_id || sender)`.
+//! The sender encrypts the data of each `PushChunk` message with the
+//! transfer key using ChaCha20-Poly1305, while the chunk id in the message
+//! keeps referring to the plaintext data. Thus the chunk content stays
+//! protected even when the frames are logged or relayed by intermediate
+//! nodes, and the receiver still verifies the decrypted data against the
+//! requested chunk id.
+//!
+//! The nonce is a per-message counter maintained by the sender, encoded as
+//! a 64-bit little-endian number in the last 8 bytes of the 12-byte nonce
+//! and prepended to the encrypted chunk data. Since each direction of the
+//! transfer uses its own key, a nonce is never reused as long as the sender
+//! never repeats a counter value; in particular, identical chunks produce
+//! unrelated ciphertexts. [`crate::UploadQueue`] maintains the counters for
+//! its transfers.
+
+use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use secp256k1::PublicKey;
+use strict_encoding::MediumVec;
+
+use crate::chunk::TooLargeData;
+use crate::p2p::ChunkPush;
+use crate::{Chunk, ChunkId, ContainerId};
+
+/// Length of the message counter prepended to the encrypted chunk data.
+pub const TRANSFER_COUNTER_LEN: usize = 8;
+
+// "storm:transfer"
+static MIDSTATE_TRANSFER: [u8; 32] = [
+    123, 79, 220, 149, 76, 203, 62, 190, 93, 27, 66, 101, 153, 209, 69, 158,
+    24, 123, 240, 246, 57, 107, 62, 125, 122, 46, 103, 214, 64, 249, 91, 212,
+];
+
+/// Tag used for deriving transfer keys.
+pub struct TransferTag;
+
+impl sha256t::Tag for TransferTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_TRANSFER);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Errors decrypting transferred chunks.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SessionError {
+    /// unable to decrypt pushed chunk {0} with the transfer key.
+    Decryption(ChunkId),
+
+    /// decrypted chunk id {actual} does not match expected {expected}.
+    ChunkIdMismatch { expected: ChunkId, actual: ChunkId },
+}
+
+/// Symmetric key encrypting chunk data of a single transfer.
+#[derive(Wrapper, Copy, Clone, PartialEq, Eq, Hash, From)]
+pub struct TransferKey([u8; 32]);
+
+// We do not want the key material to end up in logs
+impl std::fmt::Debug for TransferKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TransferKey(..)")
+    }
+}
+
+impl TransferKey {
+    /// Derives key for the chunks of the container sent by the `sender`
+    /// from the secret exported by the transport handshake, as defined in
+    /// the [module documentation](self).
+    pub fn derive(
+        handshake_secret: &[u8; 32],
+        container_id: ContainerId,
+        sender: PublicKey,
+    ) -> TransferKey {
+        let mut engine = sha256t::Hash::<TransferTag>::engine();
+        engine.input(handshake_secret);
+        engine.input(&container_id[..]);
+        engine.input(&sender.serialize());
+        TransferKey(
+            sha256t::Hash::<TransferTag>::from_engine(engine).into_inner(),
+        )
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+
+    fn nonce(counter: [u8; TRANSFER_COUNTER_LEN]) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[12 - TRANSFER_COUNTER_LEN..].copy_from_slice(&counter);
+        nonce
+    }
+
+    /// Encrypts chunk data of the push message using the message `counter`
+    /// as the nonce. The caller must never reuse a counter value with the
+    /// same key.
+    ///
+    /// Encrypted data are 24 bytes longer than the source chunk due to the
+    /// counter and the authentication tag; thus encryption of a chunk close
+    /// to the maximum chunk size may fail with [`TooLargeData`].
+    pub fn seal_push(
+        &self,
+        push: ChunkPush,
+        counter: u64,
+    ) -> Result<ChunkPush, TooLargeData> {
+        let counter = counter.to_le_bytes();
+        let mut data = counter.to_vec();
+        data.extend(
+            self.cipher()
+                .encrypt(
+                    Nonce::from_slice(&Self::nonce(counter)),
+                    push.chunk.as_ref(),
+                )
+                .expect("chacha20poly1305 encryption of in-memory data"),
+        );
+        let chunk = MediumVec::try_from(data)
+            .map(Chunk::from)
+            .map_err(|_| TooLargeData)?;
+        Ok(ChunkPush { chunk, ..push })
+    }
+
+    /// Decrypts chunk data of the push message and verifies that they match
+    /// the chunk id of the message.
+    pub fn open_push(
+        &self,
+        push: ChunkPush,
+    ) -> Result<ChunkPush, SessionError> {
+        if push.chunk.len() < TRANSFER_COUNTER_LEN {
+            return Err(SessionError::Decryption(push.chunk_id));
+        }
+        let (counter, ciphertext) =
+            push.chunk.as_ref().split_at(TRANSFER_COUNTER_LEN);
+        let mut nonce = [0u8; TRANSFER_COUNTER_LEN];
+        nonce.copy_from_slice(counter);
+        let data = self
+            .cipher()
+            .decrypt(Nonce::from_slice(&Self::nonce(nonce)), ciphertext)
+            .map_err(|_| SessionError::Decryption(push.chunk_id))?;
+        // Decrypted data are always shorter than the encrypted chunk
+        let chunk = Chunk::try_from(data)
+            .expect("decrypted data are smaller than the encrypted chunk");
+        let actual = chunk.chunk_id();
+        if actual != push.chunk_id {
+            return Err(SessionError::ChunkIdMismatch {
+                expected: push.chunk_id,
+                actual,
+            });
+        }
+        Ok(ChunkPush { chunk, ..push })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::tagged_hash;
+
+    use super::*;
+    use crate::fixtures::{alice, bob};
+    use crate::StormApp;
+
+    #[test]
+    fn test_transfer_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:transfer");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_TRANSFER);
+    }
+
+    #[test]
+    fn test_transfer_key() {
+        let alice = alice();
+        let bob = bob();
+        let secret = [0x42u8; 32];
+        let container_id = ContainerId::default();
+        let key = TransferKey::derive(&secret, container_id, alice);
+        assert_eq!(key, TransferKey::derive(&secret, container_id, alice));
+        assert_ne!(key, TransferKey::derive(&secret, container_id, bob));
+        assert_ne!(
+            key,
+            TransferKey::derive(&[0x43u8; 32], container_id, alice)
+        );
+
+        let chunk = Chunk::try_from(b"confidential data".to_vec()).unwrap();
+        let push = ChunkPush {
+            app: StormApp::Storage,
+            container_id,
+            chunk_id: chunk.chunk_id(),
+            chunk,
+        };
+        let sealed = key.seal_push(push.clone(), 0).unwrap();
+        assert_ne!(sealed.chunk, push.chunk);
+        assert_eq!(sealed.chunk.len(), push.chunk.len() + 24);
+        assert_eq!(sealed.chunk_id, push.chunk_id);
+        assert_eq!(key.open_push(sealed.clone()).unwrap(), push);
+
+        // Identical chunks sent in different messages are not linkable
+        let resealed = key.seal_push(push.clone(), 1).unwrap();
+        assert_ne!(resealed.chunk.as_ref()[8..], sealed.chunk.as_ref()[8..]);
+        assert_eq!(key.open_push(resealed).unwrap(), push);
+
+        let other = TransferKey::derive(&[0x43u8; 32], container_id, alice);
+        assert_eq!(
+            other.open_push(sealed),
+            Err(SessionError::Decryption(push.chunk_id))
+        );
+    }
+}
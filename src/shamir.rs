@@ -0,0 +1,231 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Shamir secret sharing of container keys over `GF(2^8)`.
+//!
+//! A container key is split into `N` shares such that any `K` of them
+//! (the threshold) restore the key, while fewer shares reveal nothing about
+//! it. Each share is small enough to be delivered to its custodian as a
+//! single message or chunk.
+
+use std::collections::BTreeSet;
+
+use amplify::Wrapper;
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+
+use crate::chunk::encoding::ApplyStrictEncoding;
+use crate::encryption::ContainerKey;
+use crate::ContainerId;
+
+/// Errors splitting and combining key shares.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ShamirError {
+    /// threshold {0} must be non-zero and not exceed number of shares {1}.
+    InvalidThreshold(u8, u8),
+
+    /// only {0} shares provided while the threshold is {1}.
+    NotEnoughShares(usize, u8),
+
+    /// shares belong to different containers or use different thresholds.
+    Inconsistent,
+
+    /// share #{0} is provided more than once.
+    DuplicateShare(u8),
+
+    /// share index must be non-zero.
+    ZeroIndex,
+}
+
+// Multiplication runs in constant time: key bytes are never used in branches
+// or loop bounds, only in masks.
+fn gf_mul(mut a: u8, b: u8) -> u8 {
+    let mut r = 0u8;
+    for bit in 0..8 {
+        r ^= a & ((b >> bit) & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        // x^8 + x^4 + x^3 + x + 1
+        a = (a << 1) ^ (0x1B & carry);
+    }
+    r
+}
+
+fn gf_inv(a: u8) -> u8 {
+    // a^254 = a^-1 for a non-zero element
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exp = 254u8;
+    while exp != 0 {
+        if exp & 1 == 1 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Share of a container key.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct KeyShare {
+    pub container_id: ContainerId,
+    /// Number of shares required to restore the key.
+    pub threshold: u8,
+    /// Index of the share, which is the point at which the sharing
+    /// polynomial is evaluated. Never zero.
+    pub index: u8,
+    pub data: [u8; 32],
+}
+
+impl ApplyStrictEncoding for KeyShare {}
+
+// We do not want the key material to end up in logs
+impl std::fmt::Debug for KeyShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyShare")
+            .field("container_id", &self.container_id)
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ContainerKey {
+    /// Splits the key into `shares` shares, `threshold` of which are required
+    /// to restore the key. Polynomial coefficients are derived from the
+    /// `seed`, which must be random and kept secret.
+    pub fn split(
+        &self,
+        container_id: ContainerId,
+        threshold: u8,
+        shares: u8,
+        seed: [u8; 32],
+    ) -> Result<Vec<KeyShare>, ShamirError> {
+        if threshold == 0 || threshold > shares {
+            return Err(ShamirError::InvalidThreshold(threshold, shares));
+        }
+        let mut coefficients = vec![*self.as_inner()];
+        for no in 1..threshold {
+            let mut engine = sha256::Hash::engine();
+            engine.input(&seed);
+            engine.input(self.as_inner());
+            engine.input(&[no]);
+            coefficients.push(sha256::Hash::from_engine(engine).into_inner());
+        }
+        Ok((1..=shares)
+            .map(|index| {
+                let mut data = [0u8; 32];
+                for (pos, byte) in data.iter_mut().enumerate() {
+                    // Horner's scheme, starting from the highest coefficient
+                    *byte = coefficients
+                        .iter()
+                        .rev()
+                        .fold(0u8, |acc, coef| gf_mul(acc, index) ^ coef[pos]);
+                }
+                KeyShare {
+                    container_id,
+                    threshold,
+                    index,
+                    data,
+                }
+            })
+            .collect())
+    }
+
+    /// Restores the key from the shares using Lagrange interpolation. Shares
+    /// exceeding the threshold are ignored.
+    pub fn combine(shares: &[KeyShare]) -> Result<ContainerKey, ShamirError> {
+        let first = shares.first().ok_or(ShamirError::NotEnoughShares(0, 1))?;
+        let threshold = first.threshold;
+        if shares.iter().any(|share| {
+            share.container_id != first.container_id
+                || share.threshold != threshold
+        }) {
+            return Err(ShamirError::Inconsistent);
+        }
+        let mut seen = BTreeSet::new();
+        for share in shares {
+            if share.index == 0 {
+                return Err(ShamirError::ZeroIndex);
+            }
+            if !seen.insert(share.index) {
+                return Err(ShamirError::DuplicateShare(share.index));
+            }
+        }
+        if shares.len() < threshold as usize {
+            return Err(ShamirError::NotEnoughShares(shares.len(), threshold));
+        }
+        let shares = &shares[..threshold as usize];
+
+        let mut key = [0u8; 32];
+        for (i, share) in shares.iter().enumerate() {
+            // Lagrange basis polynomial evaluated at zero
+            let basis = shares
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .fold(1u8, |acc, (_, other)| {
+                    let denom = other.index ^ share.index;
+                    gf_mul(acc, gf_mul(other.index, gf_inv(denom)))
+                });
+            for (byte, share_byte) in key.iter_mut().zip(share.data) {
+                *byte ^= gf_mul(basis, share_byte);
+            }
+        }
+        Ok(ContainerKey::from(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{TryFromChunk, TryToChunk};
+
+    #[test]
+    fn test_gf_mul() {
+        // Reference values from FIPS-197
+        assert_eq!(gf_mul(0x57, 0x83), 0xC1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xFE);
+        for a in 0..=255u8 {
+            assert_eq!(gf_mul(a, 0), 0);
+            assert_eq!(gf_mul(a, 1), a);
+        }
+    }
+
+    #[test]
+    fn test_gf_inv() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() {
+        let key = ContainerKey::from([0xA5; 32]);
+        let container_id = ContainerId::default();
+        let shares = key.split(container_id, 3, 5, [1u8; 32]).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        let subset = [shares[4].clone(), shares[1].clone(), shares[2].clone()];
+        assert_eq!(ContainerKey::combine(&subset), Ok(key));
+        assert_eq!(
+            ContainerKey::combine(&shares[..2]),
+            Err(ShamirError::NotEnoughShares(2, 3))
+        );
+
+        let debug = format!("{:?}", shares[0]);
+        assert!(debug.contains("index: 1"));
+        assert!(!debug.contains(&format!("{:?}", shares[0].data)));
+
+        let chunk = shares[0].try_to_chunk().unwrap();
+        assert_eq!(KeyShare::try_from_chunk(chunk).unwrap(), shares[0]);
+    }
+}
@@ -0,0 +1,194 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Data signed by nodes.
+//!
+//! Many of the storm messages (membership changes, moderation actions,
+//! receipts, disputes etc) carry data signed by a node key. All of them use
+//! [`Signed`], which signs a tagged hash of the data type id and the strict
+//! encoding of the data, so data of one type can't be replayed as another.
+//!
+//! Data type ids are assigned by [`Signable`] implementations and must be
+//! unique within the crate:
+//!
+//! | Id | Type                  | Id | Type                |
+//! |----|-----------------------|----|---------------------|
+//! | 1  | `Invite`              | 12 | `RemovalAck`        |
+//! | 2  | `Join`                | 13 | `StorageReceipt`    |
+//! | 3  | `Leave`               | 14 | `RetrievalReceipt`  |
+//! | 4  | `MemberList`          | 15 | `ChallengeResponse` |
+//! | 5  | `Ban`                 | 16 | `Dispute`           |
+//! | 6  | `Unban`               | 17 | `DisputeEvidence`   |
+//! | 7  | `RemoveMesg`          | 18 | `DisputeResolution` |
+//! | 8  | `PostInvoice`         | 19 | `EscrowRef`         |
+//! | 9  | `PostingToken`        | 20 | `Broadcast`         |
+//! | 10 | `TokenUse`            | 21 | `CollectMail`       |
+//! | 11 | `RemovalRequest`      |    |                     |
+//!
+//! Signatures made before the introduction of the data type ids, or under
+//! the former `storm:membership` tag, do not verify and must be re-issued.
+
+use std::fmt::{self, Display, Formatter};
+
+use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine};
+use secp256k1::{
+    ecdsa, Message, PublicKey, Secp256k1, SecretKey, Signing, Verification,
+};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+// "storm:signed"
+static MIDSTATE_SIGNED: [u8; 32] = [
+    129, 109, 161, 205, 146, 208, 107, 191, 120, 44, 121, 47, 198, 47, 95, 133,
+    187, 97, 194, 214, 57, 234, 203, 112, 110, 111, 250, 47, 186, 19, 82, 64,
+];
+
+/// Tag used for hashes of the signed data.
+pub struct SignedTag;
+
+impl sha256t::Tag for SignedTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_SIGNED);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Errors verifying signed data.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SignatureError {
+    /// signature of {0} is invalid.
+    Invalid(PublicKey),
+}
+
+/// Data which can be signed by nodes. Signature commits to the data type,
+/// such that data of one type can't be replayed as another.
+pub trait Signable: StrictEncode + StrictDecode {
+    /// Unique identifier of the data type.
+    const SIGNED_TYPE: u8;
+}
+
+/// Data signed by a node.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Signed<T>
+where T: StrictEncode + StrictDecode
+{
+    pub data: T,
+    pub signer: PublicKey,
+    pub signature: ecdsa::Signature,
+}
+
+impl<T> Display for Signed<T>
+where T: Display + StrictEncode + StrictDecode
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} signed by {}", self.data, self.signer)
+    }
+}
+
+impl<T> Signed<T>
+where T: Signable
+{
+    fn message(data: &T) -> Message {
+        let mut engine = sha256t::Hash::<SignedTag>::engine();
+        engine.input(&[T::SIGNED_TYPE]);
+        data.strict_encode(&mut engine).expect("memory encoders do not fail");
+        let hash = sha256t::Hash::<SignedTag>::from_engine(engine);
+        Message::from_slice(&hash[..])
+            .expect("hash has the size of a signed message")
+    }
+
+    pub fn sign<C: Signing>(
+        secp: &Secp256k1<C>,
+        data: T,
+        key: &SecretKey,
+    ) -> Signed<T> {
+        Signed {
+            signature: secp.sign_ecdsa(&Self::message(&data), key),
+            signer: PublicKey::from_secret_key(secp, key),
+            data,
+        }
+    }
+
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), SignatureError> {
+        secp.verify_ecdsa(
+            &Self::message(&self.data),
+            &self.signature,
+            &self.signer,
+        )
+        .map_err(|_| SignatureError::Invalid(self.signer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::tagged_hash;
+
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+    struct First {
+        value: u64,
+    }
+
+    impl Signable for First {
+        const SIGNED_TYPE: u8 = 0xFE;
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+    struct Second {
+        value: u64,
+    }
+
+    impl Signable for Second {
+        const SIGNED_TYPE: u8 = 0xFF;
+    }
+
+    #[test]
+    fn test_signed_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:signed");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_SIGNED);
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let signer = PublicKey::from_secret_key(&secp, &key);
+
+        let mut signed = Signed::sign(&secp, First { value: 42 }, &key);
+        assert_eq!(signed.signer, signer);
+        assert_eq!(signed.verify(&secp), Ok(()));
+        signed.data.value += 1;
+        assert_eq!(signed.verify(&secp), Err(SignatureError::Invalid(signer)));
+
+        // Signature can't be replayed for the data of another type with the
+        // same encoding
+        let signed = Signed::sign(&secp, First { value: 42 }, &key);
+        let replayed = Signed {
+            data: Second { value: 42 },
+            signer,
+            signature: signed.signature,
+        };
+        assert_eq!(
+            replayed.verify(&secp),
+            Err(SignatureError::Invalid(signer))
+        );
+    }
+}
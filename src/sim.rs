@@ -0,0 +1,496 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Simulation of storm peers exchanging messages in memory.
+//!
+//! [`SimNetwork`] connects in-memory endpoints identified by node keys.
+//! Messages sent between the endpoints are serialized and pass through the
+//! real [`VerifyingUnmarshaller`] on delivery, so transfer state machines,
+//! gossip and sync protocols can be tested without a network. Delivery is
+//! deterministic: each endpoint receives messages in the order they were
+//! sent, and endpoints are served in the order of their node keys.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
+
+use internet2::TypedEnum;
+use secp256k1::PublicKey;
+
+use crate::p2p::{Messages, UnmarshallError, VerifyingUnmarshaller};
+
+/// Errors of the simulated network.
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SimError {
+    /// node {0} is not part of the simulated network.
+    UnknownNode(PublicKey),
+
+    /// nodes {0} and {1} are not connected.
+    NotConnected(PublicKey, PublicKey),
+
+    /// message sent to {0} can't be delivered: {1}
+    Delivery(PublicKey, UnmarshallError),
+
+    /// simulation has not settled within {0} steps.
+    StepLimit(usize),
+}
+
+/// Message received by a simulated endpoint.
+#[derive(Clone, Debug)]
+pub struct SimMesg {
+    pub from: PublicKey,
+    pub mesg: Arc<Messages>,
+}
+
+/// In-memory network of simulated storm endpoints.
+#[derive(Default)]
+pub struct SimNetwork {
+    unmarshaller: VerifyingUnmarshaller,
+    inboxes: BTreeMap<PublicKey, VecDeque<(PublicKey, Vec<u8>)>>,
+    links: BTreeSet<(PublicKey, PublicKey)>,
+    delivered: usize,
+}
+
+impl SimNetwork {
+    pub fn new() -> SimNetwork { SimNetwork::default() }
+
+    /// Constructs network with the given nodes connected to each other.
+    pub fn with_nodes(nodes: impl IntoIterator<Item = PublicKey>) -> Self {
+        let mut network = SimNetwork::new();
+        let nodes = nodes.into_iter().collect::<Vec<_>>();
+        for node in &nodes {
+            network.add_node(*node);
+        }
+        for (index, a) in nodes.iter().enumerate() {
+            for b in &nodes[index + 1..] {
+                network.connect(*a, *b);
+            }
+        }
+        network
+    }
+
+    pub fn add_node(&mut self, node_id: PublicKey) {
+        self.inboxes.entry(node_id).or_default();
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = PublicKey> + '_ {
+        self.inboxes.keys().copied()
+    }
+
+    fn link(a: PublicKey, b: PublicKey) -> (PublicKey, PublicKey) {
+        if a < b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    pub fn connect(&mut self, a: PublicKey, b: PublicKey) {
+        self.links.insert(Self::link(a, b));
+    }
+
+    /// Disconnects the nodes, simulating one of them going offline. Messages
+    /// already sent remain queued.
+    pub fn disconnect(&mut self, a: PublicKey, b: PublicKey) {
+        self.links.remove(&Self::link(a, b));
+    }
+
+    pub fn is_connected(&self, a: PublicKey, b: PublicKey) -> bool {
+        self.links.contains(&Self::link(a, b))
+    }
+
+    /// Number of messages delivered since the network was created.
+    pub fn delivered(&self) -> usize { self.delivered }
+
+    /// Number of messages queued for delivery to all nodes.
+    pub fn pending(&self) -> usize {
+        self.inboxes.values().map(VecDeque::len).sum()
+    }
+
+    /// Serializes and queues message for delivery.
+    pub fn send(
+        &mut self,
+        from: PublicKey,
+        to: PublicKey,
+        mesg: &Messages,
+    ) -> Result<(), SimError> {
+        if !self.inboxes.contains_key(&from) {
+            return Err(SimError::UnknownNode(from));
+        }
+        if !self.is_connected(from, to) {
+            return Err(SimError::NotConnected(from, to));
+        }
+        self.inboxes
+            .get_mut(&to)
+            .ok_or(SimError::UnknownNode(to))?
+            .push_back((from, mesg.serialize()));
+        Ok(())
+    }
+
+    /// Delivers next message queued for the node, unmarshalling it with the
+    /// verifying unmarshaller.
+    pub fn recv(
+        &mut self,
+        node_id: PublicKey,
+    ) -> Result<Option<SimMesg>, SimError> {
+        let (from, data) = match self
+            .inboxes
+            .get_mut(&node_id)
+            .ok_or(SimError::UnknownNode(node_id))?
+            .pop_front()
+        {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let mesg = self
+            .unmarshaller
+            .unmarshall(&data)
+            .map_err(|err| SimError::Delivery(node_id, err))?;
+        self.delivered += 1;
+        Ok(Some(SimMesg { from, mesg }))
+    }
+
+    /// Delivers queued messages to the nodes, passing each of them to the
+    /// handler together with the receiving node id, until no messages are
+    /// left. Messages returned by the handler are sent from the receiving
+    /// node to the specified peers. Returns the number of delivered
+    /// messages.
+    pub fn run<F>(
+        &mut self,
+        max_steps: usize,
+        mut handler: F,
+    ) -> Result<usize, SimError>
+    where
+        F: FnMut(PublicKey, SimMesg) -> Vec<(PublicKey, Messages)>,
+    {
+        let start = self.delivered;
+        for _ in 0..max_steps {
+            if self.pending() == 0 {
+                return Ok(self.delivered - start);
+            }
+            for node_id in self.nodes().collect::<Vec<_>>() {
+                if let Some(mesg) = self.recv(node_id)? {
+                    for (to, reply) in handler(node_id, mesg) {
+                        self.send(node_id, to, &reply)?;
+                    }
+                }
+            }
+        }
+        if self.pending() == 0 {
+            Ok(self.delivered - start)
+        } else {
+            Err(SimError::StepLimit(max_steps))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::CommitVerify;
+    use secp256k1::{Secp256k1, SecretKey};
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::limits::AppsAdvert;
+    use crate::p2p::{Accept, AppMsg, ChunkPull, ChunkPush};
+    use crate::reconcile::{Reconciliation, TopicHistory};
+    use crate::{
+        Chunk, ChunkId, Container, ContainerId, Download, MesgId, PeerBook,
+        StormApp, TransferKey, UploadQueue,
+    };
+
+    fn node(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::signing_only(),
+            &SecretKey::from_slice(&[byte; 32]).unwrap(),
+        )
+    }
+
+    fn container() -> (Container, Vec<Chunk>) {
+        let data = (0u8..200).collect::<Vec<_>>();
+        Container::chunkify(AsciiString::new(), "", &data, 20).unwrap()
+    }
+
+    fn pulls(
+        requests: Vec<(PublicKey, ChunkPull)>,
+    ) -> Vec<(PublicKey, Messages)> {
+        requests
+            .into_iter()
+            .map(|(peer, pull)| (peer, Messages::PullChunk(pull)))
+            .collect()
+    }
+
+    /// Handshake secret shared by all the simulated nodes.
+    const SECRET: [u8; 32] = [0x42; 32];
+
+    /// Node serving chunks of a container to a peer over encrypted
+    /// transfers.
+    struct Seeder {
+        chunks: BTreeMap<ChunkId, Chunk>,
+        uploads: UploadQueue,
+    }
+
+    impl Seeder {
+        fn new(
+            node_id: PublicKey,
+            peer: PublicKey,
+            container_id: ContainerId,
+            chunks: &[Chunk],
+        ) -> Self {
+            let mut uploads = UploadQueue::new();
+            let key = TransferKey::derive(&SECRET, container_id, node_id);
+            uploads.set_transfer_key(peer, container_id, key);
+            Seeder {
+                chunks: chunks
+                    .iter()
+                    .map(|chunk| (chunk.chunk_id(), chunk.clone()))
+                    .collect(),
+                uploads,
+            }
+        }
+
+        fn serve(
+            &mut self,
+            from: PublicKey,
+            pull: &ChunkPull,
+        ) -> Vec<(PublicKey, Messages)> {
+            for chunk_id in &pull.chunk_ids {
+                let push = ChunkPush {
+                    app: pull.app,
+                    container_id: pull.container_id,
+                    chunk_id: *chunk_id,
+                    chunk: self.chunks[chunk_id].clone(),
+                };
+                self.uploads.enqueue(from, push, 0).unwrap();
+            }
+            self.uploads
+                .drain_ready(0)
+                .into_iter()
+                .map(|(peer, push)| (peer, Messages::PushChunk(push)))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_request_response() {
+        let (alice, bob, carol) = (node(0x11), node(0x22), node(0x33));
+        let mut network = SimNetwork::with_nodes([alice, bob, carol]);
+
+        let read = Messages::Read(AppMsg {
+            app: StormApp::Chat,
+            data: MesgId::default(),
+        });
+        network.send(alice, bob, &read).unwrap();
+        network.send(alice, carol, &read).unwrap();
+        let mut accepted = BTreeSet::new();
+        let delivered = network
+            .run(10, |node_id, received| match &*received.mesg {
+                Messages::Read(msg) => {
+                    let accept = msg.clone().map(Accept::from);
+                    vec![(received.from, Messages::Accept(accept))]
+                }
+                Messages::Accept(_) => {
+                    assert_eq!(node_id, alice);
+                    accepted.insert(received.from);
+                    vec![]
+                }
+                _ => vec![],
+            })
+            .unwrap();
+        assert_eq!(delivered, 4);
+        assert_eq!(accepted, bset! { bob, carol });
+
+        network.disconnect(alice, bob);
+        assert!(matches!(
+            network.send(alice, bob, &read),
+            Err(SimError::NotConnected(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_swarm_download() {
+        let (alice, bob, carol) = (node(0x11), node(0x22), node(0x33));
+        let mut network = SimNetwork::with_nodes([alice, bob, carol]);
+        let (container, chunks) = container();
+        let container_id = container.container_id();
+        // Each of the seeders has only a half of the chunks
+        let (first, second) = chunks.split_at(chunks.len() / 2);
+
+        let mut seeders = bmap! {
+            alice => Seeder::new(alice, carol, container_id, first),
+            bob => Seeder::new(bob, carol, container_id, second)
+        };
+        let mut peers = PeerBook::new();
+        let mut download =
+            Download::new(StormApp::Storage, MesgId::default(), container, 100);
+        download.set_max_in_flight(4);
+        for (seeder, part) in [(alice, first), (bob, second)] {
+            peers.update_advert(seeder, AppsAdvert::default(), 0);
+            download.add_availability(seeder, part.iter().map(Chunk::chunk_id));
+            let key = TransferKey::derive(&SECRET, container_id, seeder);
+            download.set_transfer_key(seeder, key);
+        }
+
+        let mut received = vec![];
+        for (peer, pull) in pulls(download.next_requests(&peers, 0)) {
+            network.send(carol, peer, &pull).unwrap();
+        }
+        network
+            .run(100, |node_id, sim| match &*sim.mesg {
+                Messages::PullChunk(pull) => {
+                    seeders.get_mut(&node_id).unwrap().serve(sim.from, pull)
+                }
+                Messages::PushChunk(push) => {
+                    assert_eq!(node_id, carol);
+                    let push = push.clone();
+                    received.extend(
+                        download.receive(sim.from, push, &mut peers).unwrap(),
+                    );
+                    pulls(download.next_requests(&peers, 0))
+                }
+                _ => vec![],
+            })
+            .unwrap();
+
+        assert!(download.is_complete());
+        received.sort_by_key(Chunk::chunk_id);
+        let mut expected = chunks;
+        expected.sort_by_key(Chunk::chunk_id);
+        assert_eq!(received, expected);
+        // Both of the seeders took part in the download
+        assert_eq!(download.retrieval_receipts(0).len(), 2);
+    }
+
+    #[test]
+    fn test_download_failover() {
+        let (alice, bob, carol) = (node(0x11), node(0x22), node(0x33));
+        let mut network = SimNetwork::with_nodes([alice, bob, carol]);
+        network.disconnect(bob, carol);
+        let (container, chunks) = container();
+        let container_id = container.container_id();
+
+        let mut seeder = Seeder::new(alice, carol, container_id, &chunks);
+        let mut peers = PeerBook::new();
+        let mut download =
+            Download::new(StormApp::Storage, MesgId::default(), container, 100);
+        for seeder in [alice, bob] {
+            peers.update_advert(seeder, AppsAdvert::default(), 0);
+            download.add_seeder(seeder);
+        }
+        // The offline peer is the preferred one
+        peers.record_success(bob);
+        let key = TransferKey::derive(&SECRET, container_id, alice);
+        download.set_transfer_key(alice, key);
+
+        let mut now = 0;
+        while !download.is_complete() {
+            assert!(now <= 1000, "download has stalled");
+            let requests = if now == 0 {
+                download.next_requests(&peers, now)
+            } else {
+                download.expire(&mut peers, now)
+            };
+            // Requests to the offline peer are lost and time out
+            for (peer, pull) in pulls(requests) {
+                if peer != bob {
+                    network.send(carol, peer, &pull).unwrap();
+                }
+            }
+            network
+                .run(100, |_, sim| match &*sim.mesg {
+                    Messages::PullChunk(pull) => seeder.serve(sim.from, pull),
+                    Messages::PushChunk(push) => {
+                        let push = push.clone();
+                        download.receive(sim.from, push, &mut peers).unwrap();
+                        vec![]
+                    }
+                    _ => vec![],
+                })
+                .unwrap();
+            now += 100;
+        }
+
+        let receipts = download.retrieval_receipts(now);
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].provider, alice);
+        assert!(peers.peer(bob).unwrap().failures > 0);
+    }
+
+    #[test]
+    fn test_reconciliation() {
+        let (alice, bob, carol) = (node(0x11), node(0x22), node(0x33));
+        let mut network = SimNetwork::with_nodes([alice, bob, carol]);
+        let topic_id = MesgId::default();
+        let ids = |range: std::ops::Range<u32>| {
+            range
+                .map(|no| MesgId::commit(&no.to_be_bytes()))
+                .collect::<BTreeSet<_>>()
+        };
+        let history = |ranges: &[std::ops::Range<u32>]| {
+            let mut history = TopicHistory::new();
+            for id in ranges.iter().cloned().flat_map(ids) {
+                history.insert(id);
+            }
+            history
+        };
+        let histories = bmap! {
+            alice => history(&[0..100]),
+            bob => history(&[50..150]),
+            carol => history(&[0..50, 120..200])
+        };
+
+        let initiate = Messages::Reconcile(AppMsg {
+            app: StormApp::Chat,
+            data: histories[&alice].initiate(topic_id),
+        });
+        network.send(alice, bob, &initiate).unwrap();
+        network.send(alice, carol, &initiate).unwrap();
+
+        // Ids each of the nodes misses, per peer having them
+        let mut missing =
+            BTreeMap::<(PublicKey, PublicKey), BTreeSet<MesgId>>::new();
+        network
+            .run(100, |node_id, sim| match &*sim.mesg {
+                Messages::Reconcile(msg) => {
+                    let outcome =
+                        histories[&node_id].reconcile(&msg.data.items);
+                    missing
+                        .entry((node_id, sim.from))
+                        .or_default()
+                        .extend(outcome.need);
+                    missing
+                        .entry((sim.from, node_id))
+                        .or_default()
+                        .extend(outcome.have);
+                    if outcome.response.is_empty() {
+                        return vec![];
+                    }
+                    let reply = AppMsg {
+                        app: msg.app,
+                        data: Reconciliation {
+                            topic_id: msg.data.topic_id,
+                            items: outcome.response,
+                        },
+                    };
+                    vec![(sim.from, Messages::Reconcile(reply))]
+                }
+                _ => vec![],
+            })
+            .unwrap();
+
+        missing.retain(|_, ids| !ids.is_empty());
+        assert_eq!(missing, bmap! {
+            (alice, bob) => ids(100..150),
+            (bob, alice) => ids(0..50),
+            (alice, carol) => ids(120..200),
+            (carol, alice) => ids(50..100)
+        });
+    }
+}
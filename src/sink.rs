@@ -0,0 +1,75 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Backpressure between the p2p layer and the consumers of received chunks.
+//!
+//! Chunks received from peers are handed over to a [`ChunkSink`], like a
+//! chunk store or a [`crate::ContainerAssembler`]. A sink which can't keep
+//! up (for instance, because of a slow disk) returns the offered chunk as
+//! [`SinkPoll::Busy`] instead of buffering it without bounds. The node
+//! stops requesting further chunks until [`ChunkSink::poll_ready`] reports
+//! that the sink is ready again and the returned chunks are taken, as done
+//! by [`crate::Download::receive_into`] and
+//! [`crate::Download::next_requests_for`].
+
+use crate::store::ChunkStore;
+use crate::Chunk;
+
+/// Result of offering a chunk to a [`ChunkSink`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SinkPoll {
+    /// Chunk is taken by the sink.
+    Accepted,
+
+    /// Sink can't take the chunk now and returns it back. The chunk should
+    /// be offered again once [`ChunkSink::poll_ready`] returns `true`.
+    Busy(Chunk),
+}
+
+/// Consumer of the received chunks, which may apply backpressure.
+pub trait ChunkSink {
+    type Error: std::error::Error;
+
+    /// Offers chunk to the sink.
+    fn offer(&mut self, chunk: Chunk) -> Result<SinkPoll, Self::Error>;
+
+    /// Makes progress on the work pending in the sink (like writing buffered
+    /// chunks to disk) and reports whether the sink takes new chunks.
+    fn poll_ready(&mut self) -> Result<bool, Self::Error>;
+}
+
+/// Chunk stores write chunks synchronously and are always ready.
+impl<S: ChunkStore> ChunkSink for S {
+    type Error = S::Error;
+
+    fn offer(&mut self, chunk: Chunk) -> Result<SinkPoll, Self::Error> {
+        self.store_chunk(chunk)?;
+        Ok(SinkPoll::Accepted)
+    }
+
+    fn poll_ready(&mut self) -> Result<bool, Self::Error> { Ok(true) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::ChunkId;
+
+    #[test]
+    fn test_store_sink() {
+        let chunk = Chunk::try_from(b"chunk data".to_vec()).unwrap();
+        let mut store = BTreeMap::<ChunkId, Chunk>::new();
+        assert_eq!(store.poll_ready(), Ok(true));
+        assert_eq!(store.offer(chunk.clone()), Ok(SinkPoll::Accepted));
+        assert_eq!(store.get(&chunk.chunk_id()), Some(&chunk));
+    }
+}
@@ -0,0 +1,318 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! PinSketch (minisketch) encoding of chunk id sets.
+//!
+//! Peers with mostly overlapping sets of chunks exchange sketches of a small
+//! capacity instead of full id lists; combining two sketches gives a sketch
+//! of the symmetric set difference, which can be decoded as long as the
+//! difference does not exceed sketch capacity.
+//!
+//! Sketches operate over 32-bit short chunk ids in `GF(2^32)`. Since peers
+//! reconcile chunks of a container known to both of them, decoded short ids
+//! are resolved back to full chunk ids using the container chunk list.
+//!
+//! Decoding takes time quadratic in the sketch capacity, so the capacity is
+//! limited to [`MAX_SKETCH_CAPACITY`] and sketches of a larger capacity
+//! received from peers are rejected during decoding.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use strict_encoding::StrictDecode;
+
+use crate::{ChunkId, Container};
+
+/// Maximum capacity of a sketch.
+pub const MAX_SKETCH_CAPACITY: u16 = 256;
+
+/// Reduction polynomial for `GF(2^32)`: `x^32 + x^7 + x^3 + x^2 + 1`.
+const GF32_POLY: u64 = 0x1_0000_008D;
+
+fn gf_mul(a: u32, b: u32) -> u32 {
+    let mut a = a as u64;
+    let mut b = b;
+    let mut r = 0u64;
+    while b != 0 {
+        if b & 1 == 1 {
+            r ^= a;
+        }
+        b >>= 1;
+        a <<= 1;
+        if a & (1 << 32) != 0 {
+            a ^= GF32_POLY;
+        }
+    }
+    r as u32
+}
+
+fn gf_inv(a: u32) -> u32 {
+    // a^(2^32 - 2) = a^-1 for a non-zero element
+    let mut result = 1u32;
+    let mut base = a;
+    let mut exp = u32::MAX - 1;
+    while exp != 0 {
+        if exp & 1 == 1 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Computes 32-bit short id of a chunk used in sketches. Short id is never
+/// zero, since zero element can't be represented in the sketch.
+pub fn short_chunk_id(chunk_id: ChunkId) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&chunk_id[..4]);
+    match u32::from_le_bytes(bytes) {
+        0 => 1,
+        id => id,
+    }
+}
+
+/// Errors working with the sketches.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SketchError {
+    /// sketches have different capacity ({0} and {1}) and can't be combined.
+    CapacityMismatch(usize, usize),
+
+    /// set difference exceeds sketch capacity and can't be decoded.
+    CapacityExceeded,
+}
+
+/// PinSketch of a set of 32-bit elements.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PinSketch {
+    /// Odd power sums `s_1, s_3, ..., s_(2c-1)` of the set elements.
+    syndromes: Vec<u32>,
+}
+
+impl StrictDecode for PinSketch {
+    fn strict_decode<D: io::Read>(
+        d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let syndromes = Vec::<u32>::strict_decode(d)?;
+        if syndromes.len() > MAX_SKETCH_CAPACITY as usize {
+            return Err(strict_encoding::Error::DataIntegrityError(format!(
+                "sketch capacity {} exceeds maximum of {}",
+                syndromes.len(),
+                MAX_SKETCH_CAPACITY
+            )));
+        }
+        Ok(PinSketch { syndromes })
+    }
+}
+
+impl PinSketch {
+    /// Constructs empty sketch able to decode set differences of up to
+    /// `capacity` elements. Capacity is capped at [`MAX_SKETCH_CAPACITY`].
+    pub fn with_capacity(capacity: u16) -> PinSketch {
+        PinSketch {
+            syndromes: vec![0; capacity.min(MAX_SKETCH_CAPACITY) as usize],
+        }
+    }
+
+    /// Constructs sketch of the chunk ids.
+    pub fn with_chunks<'a>(
+        capacity: u16,
+        chunk_ids: impl IntoIterator<Item = &'a ChunkId>,
+    ) -> PinSketch {
+        let mut sketch = PinSketch::with_capacity(capacity);
+        for chunk_id in chunk_ids {
+            sketch.add(short_chunk_id(*chunk_id));
+        }
+        sketch
+    }
+
+    pub fn capacity(&self) -> usize { self.syndromes.len() }
+
+    /// Adds element to the sketch. Adding the same element twice removes it
+    /// from the sketch. Zero elements are ignored.
+    pub fn add(&mut self, element: u32) {
+        let square = gf_mul(element, element);
+        let mut power = element;
+        for syndrome in &mut self.syndromes {
+            *syndrome ^= power;
+            power = gf_mul(power, square);
+        }
+    }
+
+    /// Combines sketch with other one, producing sketch of the symmetric
+    /// difference of the sets.
+    pub fn merge(&mut self, other: &PinSketch) -> Result<(), SketchError> {
+        if self.capacity() != other.capacity() {
+            return Err(SketchError::CapacityMismatch(
+                self.capacity(),
+                other.capacity(),
+            ));
+        }
+        for (a, b) in self.syndromes.iter_mut().zip(&other.syndromes) {
+            *a ^= *b;
+        }
+        Ok(())
+    }
+
+    /// Computes error locator polynomial from the syndromes using
+    /// Berlekamp-Massey algorithm. Coefficients are in the order of
+    /// increasing powers, starting with constant term `1`.
+    fn locator(&self) -> Vec<u32> {
+        let capacity = self.capacity();
+        // Restore even syndromes: s_2k = s_k^2 in characteristic 2.
+        let mut s = vec![0u32; capacity * 2];
+        for (i, syndrome) in self.syndromes.iter().enumerate() {
+            s[2 * i] = *syndrome;
+        }
+        for i in 1..=capacity {
+            s[2 * i - 1] = gf_mul(s[i - 1], s[i - 1]);
+        }
+
+        let mut c = vec![1u32];
+        let mut b = vec![1u32];
+        let mut len = 0usize;
+        let mut shift = 1usize;
+        let mut b_discr = 1u32;
+        for n in 0..s.len() {
+            let mut discr = s[n];
+            for i in 1..=len.min(c.len() - 1) {
+                discr ^= gf_mul(c[i], s[n - i]);
+            }
+            if discr == 0 {
+                shift += 1;
+                continue;
+            }
+            let coef = gf_mul(discr, gf_inv(b_discr));
+            let prev = c.clone();
+            if c.len() < b.len() + shift {
+                c.resize(b.len() + shift, 0);
+            }
+            for (i, bi) in b.iter().enumerate() {
+                c[i + shift] ^= gf_mul(coef, *bi);
+            }
+            if 2 * len <= n {
+                len = n + 1 - len;
+                b = prev;
+                b_discr = discr;
+                shift = 1;
+            } else {
+                shift += 1;
+            }
+        }
+        c.truncate(len + 1);
+        c
+    }
+
+    /// Decodes the sketch, looking for the set elements among the provided
+    /// candidates. Returns [`SketchError::CapacityExceeded`] if the sketch
+    /// can't be fully decoded, which happens if the set is larger than the
+    /// sketch capacity or contains elements outside of the candidate set.
+    pub fn decode_within(
+        &self,
+        candidates: impl IntoIterator<Item = u32>,
+    ) -> Result<BTreeSet<u32>, SketchError> {
+        let locator = self.locator();
+        let degree = locator.len() - 1;
+        if degree > self.capacity() {
+            return Err(SketchError::CapacityExceeded);
+        }
+        // Roots of the locator polynomial are inverses of the set elements,
+        // so we evaluate reversed polynomial which has elements as roots.
+        let found = candidates
+            .into_iter()
+            .filter(|x| *x != 0)
+            .filter(|x| {
+                locator.iter().fold(0u32, |acc, c| gf_mul(acc, *x) ^ c) == 0
+            })
+            .collect::<BTreeSet<_>>();
+        if found.len() != degree {
+            return Err(SketchError::CapacityExceeded);
+        }
+        Ok(found)
+    }
+
+    /// Computes symmetric difference between the local set of container
+    /// chunks and the remote set represented by its sketch. Returned ids are
+    /// either present locally and absent remotely or vice versa.
+    pub fn chunk_difference<'a>(
+        &self,
+        container: &Container,
+        local: impl IntoIterator<Item = &'a ChunkId>,
+    ) -> Result<BTreeSet<ChunkId>, SketchError> {
+        let mut sketch = PinSketch::with_chunks(self.capacity() as u16, local);
+        sketch.merge(self)?;
+        let resolver = container
+            .chunks
+            .iter()
+            .map(|chunk_id| (short_chunk_id(*chunk_id), *chunk_id))
+            .collect::<BTreeMap<_, _>>();
+        let diff = sketch.decode_within(resolver.keys().copied())?;
+        Ok(diff.into_iter().map(|short_id| resolver[&short_id]).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use strict_encoding::StrictEncode;
+
+    use super::*;
+
+    #[test]
+    fn test_gf_inv() {
+        for a in [1u32, 2, 3, 0xDEADBEEF, u32::MAX] {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_sketch_decode() {
+        let alice = (1u32..=200).collect::<Vec<_>>();
+        let bob = (5u32..=210).collect::<Vec<_>>();
+        let mut sketch_a = PinSketch::with_capacity(16);
+        alice.iter().for_each(|x| sketch_a.add(*x));
+        let mut sketch_b = PinSketch::with_capacity(16);
+        bob.iter().for_each(|x| sketch_b.add(*x));
+        sketch_a.merge(&sketch_b).unwrap();
+        let diff = sketch_a.decode_within(1u32..=300).unwrap();
+        assert_eq!(diff, (1..5).chain(201..=210).collect());
+
+        let mut small = PinSketch::with_capacity(4);
+        alice.iter().for_each(|x| small.add(*x));
+        assert_eq!(
+            small.decode_within(1u32..=300),
+            Err(SketchError::CapacityExceeded)
+        );
+    }
+
+    #[test]
+    fn test_capacity_limit() {
+        assert_eq!(
+            PinSketch::with_capacity(u16::MAX).capacity(),
+            MAX_SKETCH_CAPACITY as usize
+        );
+
+        let sketch = PinSketch::with_capacity(MAX_SKETCH_CAPACITY);
+        let data = sketch.strict_serialize().unwrap();
+        assert_eq!(PinSketch::strict_deserialize(&data).unwrap(), sketch);
+
+        let oversized = PinSketch {
+            syndromes: vec![0; MAX_SKETCH_CAPACITY as usize + 1],
+        };
+        let data = oversized.strict_serialize().unwrap();
+        assert!(PinSketch::strict_deserialize(&data).is_err());
+    }
+}
@@ -0,0 +1,622 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Storage challenges and proofs of storage agreement violations.
+//!
+//! During the lease the client periodically challenges the provider to
+//! prove it still holds a random container chunk: the provider must return
+//! a signed digest of the chunk data mixed with the challenge nonce. A signed
+//! response with a wrong digest, together with the provider storage receipt,
+//! the container header and the challenged chunk with the proof of its
+//! inclusion into the container, constitutes a [`ViolationProof`] which can be
+//! verified offline by any third party. The proof size does not depend on the
+//! number of the container chunks.
+//!
+//! Providers which do not respond to challenges at all can't be proven
+//! guilty this way; such cases are escalated with dispute messages.
+//!
+//! A client opens [`Dispute`] referencing the lease and either a violation
+//! proof or an unanswered challenge. Both parties may submit
+//! [`DisputeEvidence`] and an arbiter trusted by both of them closes the
+//! dispute with [`DisputeResolution`].
+
+use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine};
+use commit_verify::{
+    commit_encode, CommitVerify, ConsensusCommit, PrehashedProtocol, TaggedHash,
+};
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::signed::{Signable, Signed};
+use crate::storage::StorageReceipt;
+use crate::{
+    Chunk, ChunkProof, Container, ContainerHeader, ContainerId,
+    RetrievalReceipt,
+};
+
+// "storm:challenge"
+static MIDSTATE_CHALLENGE: [u8; 32] = [
+    12, 230, 133, 60, 200, 26, 159, 39, 234, 253, 201, 69, 60, 97, 108, 245,
+    133, 166, 239, 108, 38, 9, 74, 137, 185, 184, 219, 10, 191, 135, 70, 53,
+];
+
+/// Tag used for storage challenge response digests.
+pub struct ChallengeTag;
+
+impl sha256t::Tag for ChallengeTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_CHALLENGE);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+// "storm:dispute"
+static MIDSTATE_DISPUTE_ID: [u8; 32] = [
+    252, 63, 175, 112, 44, 18, 102, 249, 155, 117, 74, 227, 185, 58, 219, 66,
+    107, 128, 204, 68, 179, 230, 234, 6, 215, 38, 153, 205, 20, 198, 177, 75,
+];
+
+/// Tag used for [`DisputeId`] hash type
+pub struct DisputeIdTag;
+
+impl sha256t::Tag for DisputeIdTag {
+    #[inline]
+    fn engine() -> sha256::HashEngine {
+        let midstate = sha256::Midstate::from_inner(MIDSTATE_DISPUTE_ID);
+        sha256::HashEngine::from_midstate(midstate, 64)
+    }
+}
+
+/// Unique storage dispute identifier
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(
+    Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, From
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[wrapper(Debug, Display)]
+pub struct DisputeId(sha256t::Hash<DisputeIdTag>);
+
+impl<Msg> CommitVerify<Msg, PrehashedProtocol> for DisputeId
+where Msg: AsRef<[u8]>
+{
+    #[inline]
+    fn commit(msg: &Msg) -> DisputeId { DisputeId::hash(msg) }
+}
+
+/// Errors verifying storage agreement violation proofs.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SlaError {
+    /// signature of {0} is invalid.
+    InvalidSignature(PublicKey),
+
+    /// challenge response is signed by {0} and not the storage provider.
+    ProviderMismatch(PublicKey),
+
+    /// proof data relate to container {0} instead of the leased one.
+    ContainerMismatch(ContainerId),
+
+    /// container has no chunk with index {0}.
+    UnknownChunk(u32),
+
+    /// chunk data do not match the challenged container chunk.
+    ChunkMismatch,
+
+    /// challenge issued at {0} lies outside of the lease period.
+    OutsideLease(u64),
+
+    /// provider response to the challenge is correct.
+    NoViolation,
+
+    /// {0} is not a party of the disputed storage agreement.
+    NotParty(PublicKey),
+
+    /// evidence relates to dispute {0}.
+    DisputeMismatch(DisputeId),
+}
+
+/// Challenge to prove possession of a container chunk.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, chunk {chunk_index} at {timestamp}")]
+pub struct StorageChallenge {
+    pub container_id: ContainerId,
+    /// Index of the challenged chunk in the container.
+    pub chunk_index: u32,
+    /// Random nonce preventing precomputation of the responses.
+    pub nonce: [u8; 32],
+    /// Unix timestamp of the challenge.
+    pub timestamp: u64,
+}
+
+impl StorageChallenge {
+    /// Computes digest which must be returned in response to the challenge.
+    pub fn digest(&self, chunk: &Chunk) -> sha256t::Hash<ChallengeTag> {
+        let mut engine = sha256t::Hash::<ChallengeTag>::engine();
+        engine.input(&self.nonce);
+        engine.input(chunk.as_ref());
+        sha256t::Hash::from_engine(engine)
+    }
+}
+
+/// Provider response to a storage challenge.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{challenge}, {digest}")]
+pub struct ChallengeResponse {
+    pub challenge: StorageChallenge,
+    pub digest: sha256t::Hash<ChallengeTag>,
+}
+
+impl Signable for ChallengeResponse {
+    const SIGNED_TYPE: u8 = 15;
+}
+
+impl ChallengeResponse {
+    /// Constructs response to the challenge from the chunk data.
+    pub fn answer(challenge: StorageChallenge, chunk: &Chunk) -> Self {
+        ChallengeResponse {
+            digest: challenge.digest(chunk),
+            challenge,
+        }
+    }
+
+    /// Checks that the response is correct for the chunk data.
+    pub fn is_valid(&self, chunk: &Chunk) -> bool {
+        self.digest == self.challenge.digest(chunk)
+    }
+}
+
+/// Proof that a storage provider broke its storage agreement by responding
+/// incorrectly to a storage challenge within the lease period.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ViolationProof {
+    /// Provider receipt committing to the lease terms.
+    pub receipt: Signed<StorageReceipt>,
+    /// Header of the container covered by the lease.
+    pub header: ContainerHeader,
+    /// Proof of the challenged chunk inclusion into the container.
+    pub chunk_proof: ChunkProof,
+    /// Data of the challenged chunk.
+    pub chunk: Chunk,
+    /// Incorrect challenge response signed by the provider.
+    pub response: Signed<ChallengeResponse>,
+}
+
+impl ViolationProof {
+    /// Constructs proof for the container chunk challenged by the response.
+    pub fn new(
+        receipt: Signed<StorageReceipt>,
+        container: &Container,
+        chunk: Chunk,
+        response: Signed<ChallengeResponse>,
+    ) -> Result<ViolationProof, SlaError> {
+        let index = response.data.challenge.chunk_index;
+        let chunk_proof = container
+            .chunk_proof(index as usize)
+            .ok_or(SlaError::UnknownChunk(index))?;
+        Ok(ViolationProof {
+            receipt,
+            header: container.header.clone(),
+            chunk_proof,
+            chunk,
+            response,
+        })
+    }
+
+    /// Id of the container covered by the lease.
+    pub fn container_id(&self) -> ContainerId { self.receipt.data.container_id }
+
+    /// Storage provider accused of the violation.
+    pub fn provider(&self) -> PublicKey { self.receipt.signer }
+
+    /// Verifies the proof, returning the provider which broke the storage
+    /// agreement.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<PublicKey, SlaError> {
+        let provider = self.provider();
+        self.receipt
+            .verify(secp)
+            .map_err(|_| SlaError::InvalidSignature(provider))?;
+        if self.response.signer != provider {
+            return Err(SlaError::ProviderMismatch(self.response.signer));
+        }
+        self.response
+            .verify(secp)
+            .map_err(|_| SlaError::InvalidSignature(provider))?;
+
+        let challenge = self.response.data.challenge;
+        if challenge.container_id != self.container_id() {
+            return Err(SlaError::ContainerMismatch(challenge.container_id));
+        }
+        if self.chunk_proof.index != challenge.chunk_index
+            || !self.chunk_proof.verify(
+                self.chunk.chunk_id(),
+                self.container_id(),
+                &self.header,
+            )
+        {
+            return Err(SlaError::ChunkMismatch);
+        }
+        if !self.receipt.data.lease.is_active(challenge.timestamp) {
+            return Err(SlaError::OutsideLease(challenge.timestamp));
+        }
+        if self.response.data.is_valid(&self.chunk) {
+            return Err(SlaError::NoViolation);
+        }
+        Ok(provider)
+    }
+}
+
+/// Claim of the client against the storage provider.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum DisputeClaim {
+    /// Provider responded incorrectly to a storage challenge.
+    Violation(ViolationProof),
+
+    /// Provider did not respond to a storage challenge.
+    Unanswered(StorageChallenge),
+}
+
+/// Dispute over a storage agreement opened by the client.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Dispute {
+    /// Provider receipt committing to the disputed lease.
+    pub receipt: Signed<StorageReceipt>,
+    pub claim: DisputeClaim,
+    /// Unix timestamp of the dispute opening.
+    pub opened_at: u64,
+}
+
+impl commit_encode::Strategy for Dispute {
+    type Strategy = commit_encode::strategies::UsingStrict;
+}
+
+impl ConsensusCommit for Dispute {
+    type Commitment = DisputeId;
+}
+
+impl Signable for Dispute {
+    const SIGNED_TYPE: u8 = 16;
+}
+
+impl Dispute {
+    pub fn dispute_id(&self) -> DisputeId { self.consensus_commit() }
+
+    pub fn client(&self) -> PublicKey { self.receipt.data.client }
+
+    pub fn provider(&self) -> PublicKey { self.receipt.signer }
+
+    /// Checks whether the node is a party of the disputed agreement.
+    pub fn is_party(&self, node_id: PublicKey) -> bool {
+        node_id == self.client() || node_id == self.provider()
+    }
+
+    /// Checks that the dispute is opened by the client of a lease signed by
+    /// the provider and that the claim relates to that lease. Violation
+    /// proofs are fully verified.
+    pub fn check<C: Verification>(
+        secp: &Secp256k1<C>,
+        dispute: &Signed<Dispute>,
+    ) -> Result<(), SlaError> {
+        let data = &dispute.data;
+        if dispute.signer != data.client() {
+            return Err(SlaError::NotParty(dispute.signer));
+        }
+        dispute
+            .verify(secp)
+            .map_err(|_| SlaError::InvalidSignature(dispute.signer))?;
+        data.receipt
+            .verify(secp)
+            .map_err(|_| SlaError::InvalidSignature(data.provider()))?;
+        let container_id = data.receipt.data.container_id;
+        match &data.claim {
+            DisputeClaim::Violation(proof) => {
+                if proof.receipt != data.receipt {
+                    return Err(SlaError::ContainerMismatch(
+                        proof.container_id(),
+                    ));
+                }
+                proof.verify(secp)?;
+            }
+            DisputeClaim::Unanswered(challenge) => {
+                if challenge.container_id != container_id {
+                    return Err(SlaError::ContainerMismatch(
+                        challenge.container_id,
+                    ));
+                }
+                if !data.receipt.data.lease.is_active(challenge.timestamp) {
+                    return Err(SlaError::OutsideLease(challenge.timestamp));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Evidence submitted by a dispute party.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum Evidence {
+    /// Response to the disputed challenge signed by the provider.
+    ChallengeResponse(Signed<ChallengeResponse>),
+
+    /// Receipt of a successful retrieval of the container.
+    RetrievalReceipt(Signed<RetrievalReceipt>),
+
+    /// Application-specific evidence.
+    Other(Vec<u8>),
+}
+
+/// Evidence related to a dispute, signed by one of the dispute parties.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct DisputeEvidence {
+    pub dispute_id: DisputeId,
+    pub evidence: Evidence,
+}
+
+impl Signable for DisputeEvidence {
+    const SIGNED_TYPE: u8 = 17;
+}
+
+impl DisputeEvidence {
+    /// Checks that the evidence is signed by a dispute party.
+    pub fn check<C: Verification>(
+        secp: &Secp256k1<C>,
+        evidence: &Signed<DisputeEvidence>,
+        dispute: &Dispute,
+    ) -> Result<(), SlaError> {
+        if evidence.data.dispute_id != dispute.dispute_id() {
+            return Err(SlaError::DisputeMismatch(evidence.data.dispute_id));
+        }
+        if !dispute.is_party(evidence.signer) {
+            return Err(SlaError::NotParty(evidence.signer));
+        }
+        evidence
+            .verify(secp)
+            .map_err(|_| SlaError::InvalidSignature(evidence.signer))
+    }
+}
+
+/// Outcome of a dispute.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[strict_encoding(by_value, repr = u8)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[repr(u8)]
+pub enum DisputeVerdict {
+    /// Provider broke the storage agreement.
+    #[display("provider_at_fault")]
+    ProviderAtFault = 1,
+
+    /// Claim of the client is not justified.
+    #[display("dismissed")]
+    Dismissed = 2,
+}
+
+/// Resolution of a dispute signed by the arbiter.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{dispute_id}: {verdict}")]
+pub struct DisputeResolution {
+    pub dispute_id: DisputeId,
+    pub verdict: DisputeVerdict,
+    pub reason: String,
+}
+
+impl Signable for DisputeResolution {
+    const SIGNED_TYPE: u8 = 18;
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use commit_verify::tagged_hash;
+    use secp256k1::SecretKey;
+    use stens::AsciiString;
+
+    use super::*;
+    use crate::storage::LeaseTerms;
+    use crate::varint::VarInt;
+
+    #[test]
+    fn test_challenge_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:challenge");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_CHALLENGE);
+    }
+
+    #[test]
+    fn test_dispute_id_midstate() {
+        let midstate = tagged_hash::Midstate::with(b"storm:dispute");
+        assert_eq!(midstate.into_inner().into_inner(), MIDSTATE_DISPUTE_ID);
+    }
+
+    #[test]
+    fn test_violation_proof() {
+        let secp = Secp256k1::new();
+        let client_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let provider_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let client = PublicKey::from_secret_key(&secp, &client_key);
+        let provider = PublicKey::from_secret_key(&secp, &provider_key);
+
+        let data = (0u8..200).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 100).unwrap();
+        let receipt = StorageReceipt {
+            client,
+            container_id: container.container_id(),
+            size: VarInt::from(200u64),
+            lease: LeaseTerms {
+                start: 1000,
+                expiry: 2000,
+                fee_msat: 1000,
+                replicas: 1,
+            },
+            timestamp: 1000,
+        };
+        let challenge = StorageChallenge {
+            container_id: container.container_id(),
+            chunk_index: 1,
+            nonce: [7; 32],
+            timestamp: 1500,
+        };
+
+        let honest = ChallengeResponse::answer(challenge, &chunks[1]);
+        let mut proof = ViolationProof::new(
+            Signed::sign(&secp, receipt, &provider_key),
+            &container,
+            chunks[1].clone(),
+            Signed::sign(&secp, honest, &provider_key),
+        )
+        .unwrap();
+        assert_eq!(proof.verify(&secp), Err(SlaError::NoViolation));
+
+        let wrong = ChallengeResponse::answer(challenge, &chunks[0]);
+        proof.response = Signed::sign(&secp, wrong, &provider_key);
+        assert_eq!(proof.verify(&secp), Ok(provider));
+
+        proof.chunk = chunks[0].clone();
+        assert_eq!(proof.verify(&secp), Err(SlaError::ChunkMismatch));
+
+        // Proof of the other chunk can't be used for the challenged one
+        proof.chunk_proof = container.chunk_proof(0).unwrap();
+        assert_eq!(proof.verify(&secp), Err(SlaError::ChunkMismatch));
+        proof.chunk = chunks[1].clone();
+        proof.chunk_proof = container.chunk_proof(1).unwrap();
+        assert_eq!(proof.verify(&secp), Ok(provider));
+        proof.header.size += 1;
+        assert_eq!(proof.verify(&secp), Err(SlaError::ChunkMismatch));
+
+        let beyond = StorageChallenge {
+            chunk_index: 2,
+            ..challenge
+        };
+        assert_eq!(
+            ViolationProof::new(
+                proof.receipt.clone(),
+                &container,
+                chunks[0].clone(),
+                Signed::sign(
+                    &secp,
+                    ChallengeResponse::answer(beyond, &chunks[0]),
+                    &provider_key
+                ),
+            ),
+            Err(SlaError::UnknownChunk(2))
+        );
+    }
+
+    #[test]
+    fn test_dispute() {
+        let secp = Secp256k1::new();
+        let client_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let provider_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let other_key = SecretKey::from_slice(&[0x33; 32]).unwrap();
+        let client = PublicKey::from_secret_key(&secp, &client_key);
+        let other = PublicKey::from_secret_key(&secp, &other_key);
+
+        let receipt = StorageReceipt {
+            client,
+            container_id: ContainerId::default(),
+            size: VarInt::from(200u64),
+            lease: LeaseTerms {
+                start: 1000,
+                expiry: 2000,
+                fee_msat: 1000,
+                replicas: 1,
+            },
+            timestamp: 1000,
+        };
+        let challenge = StorageChallenge {
+            container_id: ContainerId::default(),
+            chunk_index: 0,
+            nonce: [7; 32],
+            timestamp: 1500,
+        };
+        let dispute = Dispute {
+            receipt: Signed::sign(&secp, receipt, &provider_key),
+            claim: DisputeClaim::Unanswered(challenge),
+            opened_at: 1600,
+        };
+        let forged = Signed::sign(&secp, dispute.clone(), &other_key);
+        assert_eq!(
+            Dispute::check(&secp, &forged),
+            Err(SlaError::NotParty(other))
+        );
+        let signed = Signed::sign(&secp, dispute.clone(), &client_key);
+        assert_eq!(Dispute::check(&secp, &signed), Ok(()));
+
+        let evidence = DisputeEvidence {
+            dispute_id: dispute.dispute_id(),
+            evidence: Evidence::Other(vec![]),
+        };
+        let signed = Signed::sign(&secp, evidence.clone(), &provider_key);
+        assert_eq!(DisputeEvidence::check(&secp, &signed, &dispute), Ok(()));
+        let signed = Signed::sign(&secp, evidence, &other_key);
+        assert_eq!(
+            DisputeEvidence::check(&secp, &signed, &dispute),
+            Err(SlaError::NotParty(other))
+        );
+    }
+}
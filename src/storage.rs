@@ -0,0 +1,553 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Requests for storing containers with storage providers.
+//!
+//! A client sends `RequestStorage` message describing the container, the
+//! storage duration and the replication requirements; the provider responds
+//! with `StoragePlacement` committing to a concrete list of nodes which will
+//! hold replicas of the container.
+//!
+//! After the container is uploaded the provider returns a signed
+//! [`StorageReceipt`] committing to the lease terms, which the client may
+//! later present in disputes or audits.
+//!
+//! Client which uploaded a container may later ask the provider to remove it
+//! with a signed `RequestRemoval` message; the provider confirms the removal
+//! with a signed [`RemovalAck`].
+//!
+//! Providers advertise their remaining capacity and the range of container
+//! sizes they accept with `StorageQuota` message, so clients may pick a
+//! provider able to hold the container before starting the upload.
+
+use std::collections::BTreeSet;
+
+use amplify::Wrapper;
+use secp256k1::{PublicKey, Secp256k1, Verification};
+
+use crate::signed::{Signable, Signed};
+use crate::varint::VarInt;
+use crate::{ContainerFullId, ContainerId};
+
+/// Maximum age of a removal request accepted by providers, in seconds.
+pub const REMOVAL_REQUEST_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// Errors in replica placement not satisfying the requested constraints.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PlacementError {
+    /// placement is for container {0} instead of the requested one.
+    ContainerMismatch(ContainerId),
+
+    /// placement has {0} distinct replicas while {1} were requested.
+    InsufficientReplicas(usize, u8),
+
+    /// replica is placed at excluded node {0}.
+    ExcludedNode(PublicKey),
+
+    /// replica is placed in region {0} which is not allowed.
+    RegionNotAllowed(String),
+
+    /// replicas span {0} distinct regions while at least {1} are required.
+    InsufficientDiversity(usize, u8),
+}
+
+/// Replication requirements for a stored container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ReplicationConstraints {
+    /// Number of distinct nodes which must hold a copy of the container.
+    pub factor: u8,
+    /// Minimal number of distinct regions replicas must be spread across.
+    pub min_regions: u8,
+    /// Regions (ISO 3166-1 alpha-2 country codes) where replicas are
+    /// allowed to be placed. Empty set allows any region.
+    pub allowed_regions: BTreeSet<String>,
+    /// Nodes which must not hold replicas.
+    pub excluded_nodes: BTreeSet<PublicKey>,
+}
+
+/// Request to store a container with a storage provider.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {size} bytes for {duration} sec, ...")]
+pub struct StorageRequest {
+    pub container_id: ContainerId,
+    /// Total size of the container data, in bytes.
+    pub size: u64,
+    /// Storage duration, in seconds.
+    pub duration: u64,
+    pub replication: ReplicationConstraints,
+}
+
+/// Storage capacity advertised by a storage provider.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display(
+    "{available} bytes available, containers of \
+     {min_container_size}..={max_container_size} bytes"
+)]
+pub struct StorageQuota {
+    /// Remaining storage capacity, in bytes.
+    pub available: u64,
+    /// Minimal size of a container accepted for storage, in bytes.
+    pub min_container_size: u64,
+    /// Maximal size of a container accepted for storage, in bytes.
+    pub max_container_size: u64,
+}
+
+impl StorageQuota {
+    /// Detects whether a container of `size` bytes fits into the quota.
+    pub fn accepts(&self, size: u64) -> bool {
+        size >= self.min_container_size
+            && size <= self.max_container_size
+            && size <= self.available
+    }
+
+    /// Detects whether the provider is able to hold the container from the
+    /// storage request.
+    pub fn accepts_request(&self, request: &StorageRequest) -> bool {
+        self.accepts(request.size)
+    }
+}
+
+/// Node holding a replica of a container.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Replica {
+    pub node_id: PublicKey,
+    /// Region (ISO 3166-1 alpha-2 country code) of the node, if known.
+    pub region: Option<String>,
+}
+
+/// Provider commitment to place container replicas at specific nodes.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, ...")]
+pub struct ReplicaPlacement {
+    pub container_id: ContainerId,
+    pub replicas: Vec<Replica>,
+}
+
+impl ReplicaPlacement {
+    /// Checks that the placement satisfies the storage request.
+    pub fn verify(
+        &self,
+        request: &StorageRequest,
+    ) -> Result<(), PlacementError> {
+        if self.container_id != request.container_id {
+            return Err(PlacementError::ContainerMismatch(self.container_id));
+        }
+        let constraints = &request.replication;
+        let mut nodes = BTreeSet::new();
+        let mut regions = BTreeSet::new();
+        for replica in &self.replicas {
+            if constraints.excluded_nodes.contains(&replica.node_id) {
+                return Err(PlacementError::ExcludedNode(replica.node_id));
+            }
+            match &replica.region {
+                Some(region)
+                    if constraints.allowed_regions.is_empty()
+                        || constraints.allowed_regions.contains(region) =>
+                {
+                    regions.insert(region.clone());
+                }
+                Some(region) => {
+                    return Err(PlacementError::RegionNotAllowed(
+                        region.clone(),
+                    ))
+                }
+                None if !constraints.allowed_regions.is_empty() => {
+                    return Err(PlacementError::RegionNotAllowed(s!("unknown")))
+                }
+                None => {}
+            }
+            nodes.insert(replica.node_id);
+        }
+        if nodes.len() < constraints.factor as usize {
+            return Err(PlacementError::InsufficientReplicas(
+                nodes.len(),
+                constraints.factor,
+            ));
+        }
+        if regions.len() < constraints.min_regions as usize {
+            return Err(PlacementError::InsufficientDiversity(
+                regions.len(),
+                constraints.min_regions,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Errors in storage receipts.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ReceiptError {
+    /// receipt is signed with an invalid signature of {0}.
+    InvalidSignature(PublicKey),
+
+    /// receipt is issued for container {0}.
+    ContainerMismatch(ContainerId),
+
+    /// receipt is issued for {actual} bytes instead of {expected}.
+    SizeMismatch { expected: u64, actual: u64 },
+
+    /// receipt lease ends at {0}, before the requested storage period ends.
+    LeaseTooShort(u64),
+}
+
+/// Terms under which a provider stores a container.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{start}..{expiry}, {fee_msat} msat")]
+pub struct LeaseTerms {
+    /// Unix timestamp of the lease start.
+    pub start: u64,
+    /// Unix timestamp of the lease end.
+    pub expiry: u64,
+    /// Fee paid for the whole lease period, in millisatoshis.
+    pub fee_msat: u64,
+    /// Number of replicas the provider maintains.
+    pub replicas: u8,
+}
+
+impl LeaseTerms {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.start <= now && now < self.expiry
+    }
+}
+
+/// Receipt of a successful container upload, signed by the storage
+/// provider.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {size} bytes for {client}, {lease}")]
+pub struct StorageReceipt {
+    /// Node which uploaded the container.
+    pub client: PublicKey,
+    pub container_id: ContainerId,
+    /// Size of the stored container data, in bytes.
+    pub size: VarInt,
+    pub lease: LeaseTerms,
+    /// Unix timestamp when the upload was completed.
+    pub timestamp: u64,
+}
+
+impl Signable for StorageReceipt {
+    const SIGNED_TYPE: u8 = 13;
+}
+
+impl StorageReceipt {
+    /// Checks that the receipt is signed by the `provider` and covers the
+    /// storage request.
+    pub fn check<C: Verification>(
+        secp: &Secp256k1<C>,
+        receipt: &Signed<StorageReceipt>,
+        request: &StorageRequest,
+        provider: PublicKey,
+    ) -> Result<(), ReceiptError> {
+        if receipt.signer != provider || receipt.verify(secp).is_err() {
+            return Err(ReceiptError::InvalidSignature(receipt.signer));
+        }
+        let data = &receipt.data;
+        if data.container_id != request.container_id {
+            return Err(ReceiptError::ContainerMismatch(data.container_id));
+        }
+        if data.size.into_inner() != request.size {
+            return Err(ReceiptError::SizeMismatch {
+                expected: request.size,
+                actual: data.size.into_inner(),
+            });
+        }
+        if data.lease.expiry.saturating_sub(data.lease.start) < request.duration
+        {
+            return Err(ReceiptError::LeaseTooShort(data.lease.expiry));
+        }
+        Ok(())
+    }
+}
+
+/// Errors in container removal requests and confirmations.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum RemovalError {
+    /// removal is signed with an invalid signature of {0}.
+    InvalidSignature(PublicKey),
+
+    /// {0} is not the owner of the container.
+    NotOwner(PublicKey),
+
+    /// removal request issued at {0} is outdated.
+    Outdated(u64),
+
+    /// removal confirmation does not match the request.
+    RequestMismatch,
+}
+
+/// Request to remove previously uploaded container from the provider
+/// storage, signed by the container owner.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("remove {id} at {timestamp}")]
+pub struct RemovalRequest {
+    pub id: ContainerFullId,
+    /// Unix timestamp of the request, protecting from request replays.
+    pub timestamp: u64,
+}
+
+impl Signable for RemovalRequest {
+    const SIGNED_TYPE: u8 = 11;
+}
+
+impl RemovalRequest {
+    /// Checks that the request is signed by the container `owner` and is not
+    /// older than [`REMOVAL_REQUEST_MAX_AGE`] at time `now`.
+    pub fn check<C: Verification>(
+        secp: &Secp256k1<C>,
+        request: &Signed<RemovalRequest>,
+        owner: PublicKey,
+        now: u64,
+    ) -> Result<(), RemovalError> {
+        request
+            .verify(secp)
+            .map_err(|_| RemovalError::InvalidSignature(request.signer))?;
+        if request.signer != owner {
+            return Err(RemovalError::NotOwner(request.signer));
+        }
+        if request.data.timestamp.saturating_add(REMOVAL_REQUEST_MAX_AGE) < now
+        {
+            return Err(RemovalError::Outdated(request.data.timestamp));
+        }
+        Ok(())
+    }
+}
+
+/// Confirmation of a container removal, signed by the storage provider.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("removed {request} at {removed_at}")]
+pub struct RemovalAck {
+    pub request: RemovalRequest,
+    /// Unix timestamp when the container was removed.
+    pub removed_at: u64,
+}
+
+impl Signable for RemovalAck {
+    const SIGNED_TYPE: u8 = 12;
+}
+
+impl RemovalAck {
+    /// Checks that the confirmation is signed by the `provider` and relates
+    /// to the request.
+    pub fn check<C: Verification>(
+        secp: &Secp256k1<C>,
+        ack: &Signed<RemovalAck>,
+        request: &RemovalRequest,
+        provider: PublicKey,
+    ) -> Result<(), RemovalError> {
+        if ack.signer != provider || ack.verify(secp).is_err() {
+            return Err(RemovalError::InvalidSignature(ack.signer));
+        }
+        if ack.data.request != *request {
+            return Err(RemovalError::RequestMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secp256k1::SecretKey;
+
+    use super::*;
+    use crate::fixtures::{alice, bob};
+
+    #[test]
+    fn test_placement() {
+        let alice = alice();
+        let bob = bob();
+        let request = StorageRequest {
+            container_id: ContainerId::default(),
+            size: 1024,
+            duration: 3600,
+            replication: ReplicationConstraints {
+                factor: 2,
+                min_regions: 2,
+                allowed_regions: empty!(),
+                excluded_nodes: empty!(),
+            },
+        };
+        let mut placement = ReplicaPlacement {
+            container_id: ContainerId::default(),
+            replicas: vec![
+                Replica {
+                    node_id: alice,
+                    region: Some(s!("CH")),
+                },
+                Replica {
+                    node_id: bob,
+                    region: Some(s!("CH")),
+                },
+            ],
+        };
+        assert_eq!(
+            placement.verify(&request),
+            Err(PlacementError::InsufficientDiversity(1, 2))
+        );
+        placement.replicas[1].region = Some(s!("DE"));
+        assert_eq!(placement.verify(&request), Ok(()));
+        placement.replicas[1].node_id = alice;
+        assert_eq!(
+            placement.verify(&request),
+            Err(PlacementError::InsufficientReplicas(1, 2))
+        );
+    }
+
+    #[test]
+    fn test_quota() {
+        let quota = StorageQuota {
+            available: 4096,
+            min_container_size: 16,
+            max_container_size: 8192,
+        };
+        assert!(!quota.accepts(8));
+        assert!(quota.accepts(16));
+        assert!(quota.accepts(4096));
+        assert!(!quota.accepts(4097));
+    }
+
+    #[test]
+    fn test_removal() {
+        let secp = Secp256k1::new();
+        let owner_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let provider_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let owner = PublicKey::from_secret_key(&secp, &owner_key);
+        let provider = PublicKey::from_secret_key(&secp, &provider_key);
+        let request = RemovalRequest {
+            id: ContainerFullId {
+                message_id: default!(),
+                container_id: default!(),
+            },
+            timestamp: 1000,
+        };
+
+        let forged = Signed::sign(&secp, request, &provider_key);
+        assert_eq!(
+            RemovalRequest::check(&secp, &forged, owner, 1000),
+            Err(RemovalError::NotOwner(provider))
+        );
+        let signed = Signed::sign(&secp, request, &owner_key);
+        assert_eq!(RemovalRequest::check(&secp, &signed, owner, 1000), Ok(()));
+        assert_eq!(
+            RemovalRequest::check(&secp, &signed, owner, 1000 + 2 * 86400),
+            Err(RemovalError::Outdated(1000))
+        );
+
+        let ack = RemovalAck {
+            request,
+            removed_at: 1010,
+        };
+        let ack = Signed::sign(&secp, ack, &provider_key);
+        assert_eq!(RemovalAck::check(&secp, &ack, &request, provider), Ok(()));
+        assert_eq!(
+            RemovalAck::check(&secp, &ack, &request, owner),
+            Err(RemovalError::InvalidSignature(provider))
+        );
+    }
+
+    #[test]
+    fn test_receipt() {
+        let secp = Secp256k1::new();
+        let client_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let provider_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let client = PublicKey::from_secret_key(&secp, &client_key);
+        let provider = PublicKey::from_secret_key(&secp, &provider_key);
+        let request = StorageRequest {
+            container_id: ContainerId::default(),
+            size: 1024,
+            duration: 3600,
+            replication: default!(),
+        };
+        let mut receipt = StorageReceipt {
+            client,
+            container_id: request.container_id,
+            size: VarInt::from(1024u64),
+            lease: LeaseTerms {
+                start: 1000,
+                expiry: 4600,
+                fee_msat: 10_000,
+                replicas: 1,
+            },
+            timestamp: 1000,
+        };
+        let signed = Signed::sign(&secp, receipt, &provider_key);
+        assert_eq!(
+            StorageReceipt::check(&secp, &signed, &request, provider),
+            Ok(())
+        );
+        assert_eq!(
+            StorageReceipt::check(&secp, &signed, &request, client),
+            Err(ReceiptError::InvalidSignature(provider))
+        );
+
+        receipt.lease.expiry = 2000;
+        let signed = Signed::sign(&secp, receipt, &provider_key);
+        assert_eq!(
+            StorageReceipt::check(&secp, &signed, &request, provider),
+            Err(ReceiptError::LeaseTooShort(2000))
+        );
+    }
+}
@@ -0,0 +1,410 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+
+use stens::AsciiString;
+
+use crate::{Chunk, ChunkId, Container};
+
+/// Storage of chunk data indexed by chunk ids.
+pub trait ChunkStore {
+    type Error: std::error::Error;
+
+    /// Checks whether the store contains chunk with a given id.
+    fn has_chunk(&self, chunk_id: ChunkId) -> Result<bool, Self::Error>;
+
+    /// Retrieves chunk data from the store, if present.
+    fn retrieve_chunk(
+        &self,
+        chunk_id: ChunkId,
+    ) -> Result<Option<Chunk>, Self::Error>;
+
+    /// Puts chunk into the store, returning its id. Storing chunk which is
+    /// already present in the store must not fail.
+    fn store_chunk(&mut self, chunk: Chunk) -> Result<ChunkId, Self::Error>;
+}
+
+impl ChunkStore for BTreeMap<ChunkId, Chunk> {
+    type Error = Infallible;
+
+    fn has_chunk(&self, chunk_id: ChunkId) -> Result<bool, Self::Error> {
+        Ok(self.contains_key(&chunk_id))
+    }
+
+    fn retrieve_chunk(
+        &self,
+        chunk_id: ChunkId,
+    ) -> Result<Option<Chunk>, Self::Error> {
+        Ok(self.get(&chunk_id).cloned())
+    }
+
+    fn store_chunk(&mut self, chunk: Chunk) -> Result<ChunkId, Self::Error> {
+        let chunk_id = chunk.chunk_id();
+        self.insert(chunk_id, chunk);
+        Ok(chunk_id)
+    }
+}
+
+/// Errors happening during deduplicated container construction.
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DedupError<E: std::error::Error> {
+    /// data too large to fit into a single container.
+    TooLarge,
+
+    /// chunk store failure: {0}
+    Store(E),
+}
+
+impl Container {
+    /// Produces container from the data in the same way as
+    /// [`Container::chunkify`], but returns only chunks which are not already
+    /// present in the `store`; the rest is referenced by their ids.
+    ///
+    /// To maximize deduplication across versions of the same file the chunk
+    /// size of the container holding previous version should be used, since
+    /// otherwise the chunk boundaries will not match.
+    pub fn chunkify_dedup<S: ChunkStore>(
+        mime: AsciiString,
+        info: impl ToString,
+        data: &[u8],
+        chunk_size: u32,
+        store: &S,
+    ) -> Result<(Container, Vec<Chunk>), DedupError<S::Error>> {
+        let (container, chunks) =
+            Container::chunkify(mime, info, data, chunk_size)
+                .map_err(|_| DedupError::TooLarge)?;
+        let mut fresh = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            if !store.has_chunk(chunk.chunk_id()).map_err(DedupError::Store)? {
+                fresh.push(chunk);
+            }
+        }
+        Ok((container, fresh))
+    }
+}
+
+/// Errors happening during iteration over container chunks.
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ChunkIterError<E: std::error::Error> {
+    /// chunk {index} with id {chunk_id} is not available.
+    Missing { index: usize, chunk_id: ChunkId },
+
+    /// chunk {index} data has id {actual} instead of {expected}.
+    Mismatch {
+        index: usize,
+        expected: ChunkId,
+        actual: ChunkId,
+    },
+
+    /// chunk fetching failure: {0}
+    Fetch(E),
+}
+
+/// Iterator over container chunks pulling chunk data on demand, created by
+/// [`Container::iter_chunks`].
+///
+/// Each chunk is verified against the container chunk id before it is
+/// yielded; the iteration stops after the first error.
+pub struct ChunkIter<'container, F> {
+    container: &'container Container,
+    fetcher: F,
+    index: usize,
+    failed: bool,
+}
+
+impl<'container, F, E> Iterator for ChunkIter<'container, F>
+where
+    F: FnMut(ChunkId) -> Result<Option<Chunk>, E>,
+    E: std::error::Error,
+{
+    type Item = Result<Chunk, ChunkIterError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let index = self.index;
+        let expected = *self.container.chunks.get(index)?;
+        self.index += 1;
+        let res = match (self.fetcher)(expected) {
+            Err(err) => Err(ChunkIterError::Fetch(err)),
+            Ok(None) => Err(ChunkIterError::Missing {
+                index,
+                chunk_id: expected,
+            }),
+            Ok(Some(chunk)) if chunk.chunk_id() != expected => {
+                Err(ChunkIterError::Mismatch {
+                    index,
+                    expected,
+                    actual: chunk.chunk_id(),
+                })
+            }
+            Ok(Some(chunk)) => Ok(chunk),
+        };
+        self.failed = res.is_err();
+        Some(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            return (0, Some(0));
+        }
+        let remaining = self.container.chunks.len() - self.index;
+        (0, Some(remaining))
+    }
+}
+
+impl Container {
+    /// Iterates over the container chunks in their natural order, pulling
+    /// the data of each chunk with the `fetcher` only when the chunk is
+    /// requested. This allows to process container data as a stream without
+    /// keeping all of its chunks in memory.
+    pub fn iter_chunks<F, E>(&self, fetcher: F) -> ChunkIter<F>
+    where
+        F: FnMut(ChunkId) -> Result<Option<Chunk>, E>,
+        E: std::error::Error,
+    {
+        ChunkIter {
+            container: self,
+            fetcher,
+            index: 0,
+            failed: false,
+        }
+    }
+
+    /// Iterates over the container chunks retrieving them from the `store`.
+    /// See [`Container::iter_chunks`] for the details.
+    pub fn iter_chunks_from<'a, S: ChunkStore>(
+        &'a self,
+        store: &'a S,
+    ) -> ChunkIter<
+        'a,
+        impl FnMut(ChunkId) -> Result<Option<Chunk>, S::Error> + 'a,
+    > {
+        self.iter_chunks(move |chunk_id| store.retrieve_chunk(chunk_id))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct CacheEntry {
+    chunk: Chunk,
+    expires_ms: u64,
+}
+
+/// Size- and time-bounded in-memory cache of chunks fronting a
+/// [`ChunkStore`].
+///
+/// Chunks are kept in the cache until their TTL expires or they are evicted
+/// to free space for newer chunks, starting from the ones expiring first.
+/// Expired entries are purged lazily on access and by
+/// [`ChunkCache::purge_expired`] maintenance calls.
+#[derive(Clone, Debug)]
+pub struct ChunkCache<S: ChunkStore> {
+    store: S,
+    max_size: usize,
+    ttl_ms: u64,
+    size: usize,
+    entries: BTreeMap<ChunkId, CacheEntry>,
+    expiry: BTreeSet<(u64, ChunkId)>,
+}
+
+impl<S: ChunkStore> ChunkCache<S> {
+    /// Constructs cache holding up to `max_size` bytes of chunk data, each
+    /// chunk for `ttl_ms` milliseconds by default.
+    pub fn new(store: S, max_size: usize, ttl_ms: u64) -> Self {
+        ChunkCache {
+            store,
+            max_size,
+            ttl_ms,
+            size: 0,
+            entries: empty!(),
+            expiry: empty!(),
+        }
+    }
+
+    pub fn store(&self) -> &S { &self.store }
+
+    pub fn store_mut(&mut self) -> &mut S { &mut self.store }
+
+    pub fn into_store(self) -> S { self.store }
+
+    /// Total size of cached chunk data, in bytes.
+    pub fn size(&self) -> usize { self.size }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    fn remove(&mut self, chunk_id: ChunkId) -> Option<Chunk> {
+        let entry = self.entries.remove(&chunk_id)?;
+        self.expiry.remove(&(entry.expires_ms, chunk_id));
+        self.size -= entry.chunk.len();
+        Some(entry.chunk)
+    }
+
+    /// Puts chunk into the cache with the default TTL.
+    pub fn insert(&mut self, chunk: Chunk, now_ms: u64) -> ChunkId {
+        self.insert_with_ttl(chunk, self.ttl_ms, now_ms)
+    }
+
+    /// Puts chunk into the cache for `ttl_ms` milliseconds, evicting other
+    /// chunks if required. Chunks larger than the cache size are not cached.
+    pub fn insert_with_ttl(
+        &mut self,
+        chunk: Chunk,
+        ttl_ms: u64,
+        now_ms: u64,
+    ) -> ChunkId {
+        let chunk_id = chunk.chunk_id();
+        self.remove(chunk_id);
+        if chunk.len() > self.max_size {
+            return chunk_id;
+        }
+        self.purge_expired(now_ms);
+        while self.size + chunk.len() > self.max_size {
+            let (_, evicted) = *self
+                .expiry
+                .iter()
+                .next()
+                .expect("non-empty cache exceeding size limit");
+            self.remove(evicted);
+        }
+        let expires_ms = now_ms.saturating_add(ttl_ms);
+        self.size += chunk.len();
+        self.expiry.insert((expires_ms, chunk_id));
+        self.entries.insert(chunk_id, CacheEntry { chunk, expires_ms });
+        chunk_id
+    }
+
+    /// Retrieves chunk from the cache or, if it is absent or expired, from
+    /// the underlying store.
+    pub fn retrieve(
+        &mut self,
+        chunk_id: ChunkId,
+        now_ms: u64,
+    ) -> Result<Option<Chunk>, S::Error> {
+        match self.entries.get(&chunk_id) {
+            Some(entry) if entry.expires_ms > now_ms => {
+                return Ok(Some(entry.chunk.clone()))
+            }
+            Some(_) => {
+                self.remove(chunk_id);
+            }
+            None => {}
+        }
+        self.store.retrieve_chunk(chunk_id)
+    }
+
+    /// Checks whether the chunk is present in the cache or in the
+    /// underlying store.
+    pub fn contains(
+        &mut self,
+        chunk_id: ChunkId,
+        now_ms: u64,
+    ) -> Result<bool, S::Error> {
+        match self.entries.get(&chunk_id) {
+            Some(entry) if entry.expires_ms > now_ms => return Ok(true),
+            Some(_) => {
+                self.remove(chunk_id);
+            }
+            None => {}
+        }
+        self.store.has_chunk(chunk_id)
+    }
+
+    /// Moves chunk from the cache into the underlying store.
+    pub fn persist(&mut self, chunk_id: ChunkId) -> Result<bool, S::Error> {
+        match self.remove(chunk_id) {
+            Some(chunk) => self.store.store_chunk(chunk).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Removes all expired entries, returning their number.
+    pub fn purge_expired(&mut self, now_ms: u64) -> usize {
+        let expired = self
+            .expiry
+            .iter()
+            .take_while(|(expires_ms, _)| *expires_ms <= now_ms)
+            .map(|(_, chunk_id)| *chunk_id)
+            .collect::<Vec<_>>();
+        for chunk_id in &expired {
+            self.remove(*chunk_id);
+        }
+        expired.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunk(byte: u8, len: usize) -> Chunk {
+        Chunk::try_from(vec![byte; len]).unwrap()
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut cache = ChunkCache::new(BTreeMap::new(), 100, 1000);
+        let a = cache.insert(chunk(1, 60), 0);
+        let b = cache.insert_with_ttl(chunk(2, 30), 500, 0);
+        assert_eq!(cache.size(), 90);
+
+        // Evicts chunk `b`, which expires first
+        let c = cache.insert(chunk(3, 30), 10);
+        assert_eq!(cache.retrieve(b, 10), Ok(None));
+        assert_eq!(cache.size(), 90);
+
+        assert_eq!(cache.persist(c), Ok(true));
+        assert_eq!(cache.contains(c, 10), Ok(true));
+        assert_eq!(cache.purge_expired(1000), 1);
+        assert_eq!(cache.retrieve(a, 1000), Ok(None));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_iter_chunks() {
+        let data = (0u8..=255).cycle().take(1000).collect::<Vec<_>>();
+        let (container, chunks) =
+            Container::chunkify(AsciiString::new(), "", &data, 300).unwrap();
+        let mut store = BTreeMap::new();
+        for chunk in chunks {
+            store.store_chunk(chunk).unwrap();
+        }
+
+        let streamed = container
+            .iter_chunks_from(&store)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .collect::<Vec<_>>()
+            .concat();
+        assert_eq!(streamed, data);
+
+        let mut iter =
+            container.iter_chunks(|_| Ok::<_, Infallible>(Some(chunk(1, 300))));
+        assert!(matches!(
+            iter.next(),
+            Some(Err(ChunkIterError::Mismatch { index: 0, .. }))
+        ));
+        assert!(iter.next().is_none());
+
+        store.remove(&container.chunks[1]);
+        let mut iter = container.iter_chunks_from(&store);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(
+            iter.next(),
+            Some(Err(ChunkIterError::Missing { index: 1, .. }))
+        ));
+        assert!(iter.next().is_none());
+    }
+}
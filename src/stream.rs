@@ -0,0 +1,132 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::ops::Range;
+
+use strict_encoding::MediumVec;
+
+use crate::{chunk, Container};
+
+/// Playback information for a single chunk of a streaming media container.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct StreamChunkInfo {
+    /// Playback duration of the chunk, in milliseconds.
+    pub duration_ms: u32,
+    /// Average bitrate of the chunk, in bits per second.
+    pub bitrate: u32,
+}
+
+/// Streaming media container profile.
+///
+/// Media containers (with `audio/*` or `video/*` MIME types) following this
+/// profile have their chunks ordered in playback order, such that the chunk
+/// `n` must be played right after chunk `n - 1`. The profile provides a
+/// playback table with per-chunk durations and bitrates, which allows to
+/// map time ranges to the chunks and serve progressive streaming.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct StreamInfo {
+    /// Playback table, one entry per container chunk.
+    pub chunks: MediumVec<StreamChunkInfo>,
+}
+
+impl chunk::encoding::ApplyStrictEncoding for StreamInfo {}
+
+impl StreamInfo {
+    /// Checks that the playback table covers all chunks of the container.
+    pub fn matches(&self, container: &Container) -> bool {
+        container.header.is_media()
+            && self.chunks.len() == container.chunks.len()
+    }
+
+    /// Total playback duration of the stream, in milliseconds.
+    pub fn duration_ms(&self) -> u64 {
+        self.chunks.iter().map(|info| info.duration_ms as u64).sum()
+    }
+
+    /// Returns range of chunk indexes which have to be retrieved to play the
+    /// time range `start_ms..end_ms`. The returned range is empty if the
+    /// time range lies outside of the stream.
+    pub fn chunks_for_time_range(
+        &self,
+        start_ms: u64,
+        end_ms: u64,
+    ) -> Range<usize> {
+        let mut first = None;
+        let mut last = 0usize;
+        let mut pos = 0u64;
+        for (index, info) in self.chunks.iter().enumerate() {
+            let end = pos + info.duration_ms as u64;
+            if end > start_ms && pos < end_ms {
+                first.get_or_insert(index);
+                last = index + 1;
+            }
+            if pos >= end_ms {
+                break;
+            }
+            pos = end;
+        }
+        match first {
+            Some(first) => first..last,
+            None => 0..0,
+        }
+    }
+
+    /// Returns playback start time of the chunk with the given index, in
+    /// milliseconds, or `None` if the index is out of the playback table.
+    pub fn chunk_start_ms(&self, index: usize) -> Option<u64> {
+        if index >= self.chunks.len() {
+            return None;
+        }
+        Some(
+            self.chunks
+                .iter()
+                .take(index)
+                .map(|info| info.duration_ms as u64)
+                .sum(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_time_range() {
+        let info = StreamInfo {
+            chunks: MediumVec::try_from(vec![
+                StreamChunkInfo {
+                    duration_ms: 1000,
+                    bitrate: 128_000
+                };
+                5
+            ])
+            .unwrap(),
+        };
+        assert_eq!(info.duration_ms(), 5000);
+        assert_eq!(info.chunks_for_time_range(0, 1000), 0..1);
+        assert_eq!(info.chunks_for_time_range(1500, 3200), 1..4);
+        assert_eq!(info.chunks_for_time_range(6000, 7000), 0..0);
+        assert_eq!(info.chunk_start_ms(2), Some(2000));
+        assert_eq!(info.chunk_start_ms(5), None);
+    }
+}
@@ -0,0 +1,671 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Atomic data-for-payment swap: a protocol for paid retrieval of container
+//! chunks built on top of hash-locked chunk delivery.
+//!
+//! The swap proceeds in the following steps:
+//! 1. Buyer sends [`SwapRequest`] listing chunks it wants to obtain;
+//! 2. Seller replies with [`SwapQuote`] containing lightning invoice;
+//! 3. Seller delivers all requested chunks locked with the invoice payment hash
+//!    using `PushLockedChunk` messages;
+//! 4. Buyer pays the invoice once it has received all locked chunks;
+//! 5. Seller confirms that the payment was received and only then releases the
+//!    key with `RevealKey` message (the buyer also learns it from the lightning
+//!    payment itself);
+//! 6. Buyer unlocks the chunks and verifies them against requested ids.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::limits::{LimitError, PeerLimits};
+use crate::p2p::{KeyReveal, LockedChunkPush};
+use crate::{
+    Chunk, ChunkId, ContainerId, HashLockError, PaymentHash, PaymentPreimage,
+    StormApp, StormEvent,
+};
+
+/// Request for a price quote on a set of container chunks.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, ...")]
+pub struct SwapRequest {
+    pub container_id: ContainerId,
+    pub chunk_ids: BTreeSet<ChunkId>,
+}
+
+/// Quote for the requested chunks provided by the seller.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[display("{container_id}, {amount_msat} msat, {payment_hash}")]
+pub struct SwapQuote {
+    pub container_id: ContainerId,
+    pub chunk_ids: BTreeSet<ChunkId>,
+    /// Price for all of the chunks, in millisatoshis.
+    pub amount_msat: u64,
+    /// Payment hash of the invoice, which also locks the chunk data.
+    pub payment_hash: PaymentHash,
+    /// BOLT-11 invoice to be paid by the buyer.
+    pub invoice: String,
+    /// Unix timestamp after which the quote is no longer valid.
+    pub expiry: u64,
+}
+
+/// State of a data-for-payment swap session.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum SwapState {
+    /// Request for a quote was sent or received.
+    #[display("requested")]
+    Requested,
+
+    /// Quote was issued; chunk delivery has not started yet.
+    #[display("quoted")]
+    Quoted,
+
+    /// Some of the locked chunks were delivered.
+    #[display("delivering")]
+    Delivering,
+
+    /// All locked chunks were delivered; the invoice can be paid.
+    #[display("delivered")]
+    Delivered,
+
+    /// Payment of the invoice was received by the seller; the key can be
+    /// released.
+    #[display("paid")]
+    Paid,
+
+    /// Key is released and all chunks are unlocked and verified.
+    #[display("completed")]
+    Completed,
+}
+
+/// Errors happening during data-for-payment swap.
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SwapError {
+    /// operation is not allowed when swap is in {0} state.
+    InvalidState(SwapState),
+
+    /// quote does not match the request.
+    QuoteMismatch,
+
+    /// quote does not cover any chunks.
+    EmptyQuote,
+
+    /// quote has expired at {0}.
+    QuoteExpired(u64),
+
+    /// chunk {0} was not part of the swap.
+    UnexpectedChunk(ChunkId),
+
+    /// locked chunk uses payment hash {0} different from the quoted one.
+    PaymentHashMismatch(PaymentHash),
+
+    /// chunk {0} is too large to be delivered hash-locked.
+    ChunkTooLarge(ChunkId),
+
+    /// payment of {paid} msat is less than the quoted {quoted} msat.
+    Underpaid { quoted: u64, paid: u64 },
+
+    #[from]
+    #[display(inner)]
+    HashLock(HashLockError),
+
+    #[from]
+    #[display(inner)]
+    Limit(LimitError),
+}
+
+/// Produces events corresponding to the swap state transition.
+fn transition_events(
+    app: StormApp,
+    quote: &SwapQuote,
+    from: SwapState,
+    to: SwapState,
+) -> Vec<StormEvent> {
+    let container_id = quote.container_id;
+    let mut events = vec![];
+    if from == SwapState::Quoted && to != SwapState::Quoted {
+        events.push(StormEvent::TransferStarted {
+            app,
+            container_id,
+            chunks: quote.chunk_ids.len() as u32,
+        });
+    }
+    if from != SwapState::Delivered && to == SwapState::Delivered {
+        events.push(StormEvent::TransferCompleted { app, container_id });
+    }
+    if to == SwapState::Completed {
+        events.push(StormEvent::AccessGranted {
+            app,
+            container_id,
+            payment_hash: quote.payment_hash,
+        });
+    }
+    events
+}
+
+/// Buyer side of the data-for-payment swap.
+#[derive(Clone, Debug)]
+pub struct BuyerSwap {
+    app: StormApp,
+    request: SwapRequest,
+    quote: Option<SwapQuote>,
+    locked: BTreeMap<ChunkId, Chunk>,
+    state: SwapState,
+    events: Vec<StormEvent>,
+}
+
+impl BuyerSwap {
+    /// Starts new swap session for the provided request, which should be
+    /// sent to the seller.
+    pub fn new(app: StormApp, request: SwapRequest) -> Self {
+        BuyerSwap {
+            app,
+            request,
+            quote: None,
+            locked: empty!(),
+            state: SwapState::Requested,
+            events: empty!(),
+        }
+    }
+
+    pub fn app(&self) -> StormApp { self.app }
+
+    pub fn state(&self) -> SwapState { self.state }
+
+    pub fn request(&self) -> &SwapRequest { &self.request }
+
+    /// Takes events emitted by the swap since the previous call.
+    pub fn take_events(&mut self) -> Vec<StormEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn transition(&mut self, state: SwapState) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            app = %self.app,
+            container_id = %self.request.container_id,
+            from = %self.state,
+            to = %state,
+            "buyer swap state transition"
+        );
+        if let Some(quote) = &self.quote {
+            self.events
+                .extend(transition_events(self.app, quote, self.state, state));
+        }
+        self.state = state;
+    }
+
+    pub fn quote(&self) -> Option<&SwapQuote> { self.quote.as_ref() }
+
+    /// Accepts quote from the seller, checking that it matches the request
+    /// and is not expired at the time `now`.
+    pub fn accept_quote(
+        &mut self,
+        quote: SwapQuote,
+        now: u64,
+    ) -> Result<(), SwapError> {
+        if self.state != SwapState::Requested {
+            return Err(SwapError::InvalidState(self.state));
+        }
+        if quote.container_id != self.request.container_id
+            || quote.chunk_ids != self.request.chunk_ids
+        {
+            return Err(SwapError::QuoteMismatch);
+        }
+        if quote.chunk_ids.is_empty() {
+            return Err(SwapError::EmptyQuote);
+        }
+        if quote.expiry <= now {
+            return Err(SwapError::QuoteExpired(quote.expiry));
+        }
+        self.quote = Some(quote);
+        self.transition(SwapState::Quoted);
+        Ok(())
+    }
+
+    /// Registers locked chunk received from the seller. Returns `true` once
+    /// all of the quoted chunks are received, meaning that the invoice can be
+    /// safely paid.
+    pub fn receive_locked(
+        &mut self,
+        push: LockedChunkPush,
+    ) -> Result<bool, SwapError> {
+        let quote = match (self.state, &self.quote) {
+            (SwapState::Quoted | SwapState::Delivering, Some(quote)) => quote,
+            _ => return Err(SwapError::InvalidState(self.state)),
+        };
+        if push.payment_hash != quote.payment_hash {
+            return Err(SwapError::PaymentHashMismatch(push.payment_hash));
+        }
+        if push.container_id != quote.container_id
+            || !quote.chunk_ids.contains(&push.chunk_id)
+        {
+            return Err(SwapError::UnexpectedChunk(push.chunk_id));
+        }
+        self.locked.insert(push.chunk_id, push.locked_chunk);
+        let state = if self.locked.len() == quote.chunk_ids.len() {
+            SwapState::Delivered
+        } else {
+            SwapState::Delivering
+        };
+        self.transition(state);
+        Ok(self.state == SwapState::Delivered)
+    }
+
+    /// Completes the swap with the preimage obtained either from the
+    /// lightning payment or from the seller `RevealKey` message, returning
+    /// unlocked and verified chunks.
+    pub fn complete(
+        &mut self,
+        preimage: PaymentPreimage,
+    ) -> Result<BTreeMap<ChunkId, Chunk>, SwapError> {
+        let quote = match (self.state, &self.quote) {
+            (SwapState::Delivered, Some(quote)) => quote,
+            _ => return Err(SwapError::InvalidState(self.state)),
+        };
+        preimage.verify(quote.payment_hash)?;
+        let chunks = self
+            .locked
+            .iter()
+            .map(|(chunk_id, locked)| {
+                preimage
+                    .unlock_chunk(*chunk_id, locked)
+                    .map(|chunk| (*chunk_id, chunk))
+            })
+            .collect::<Result<_, _>>()?;
+        self.locked.clear();
+        self.transition(SwapState::Completed);
+        Ok(chunks)
+    }
+}
+
+/// Seller side of the data-for-payment swap.
+#[derive(Clone, Debug)]
+pub struct SellerSwap {
+    app: StormApp,
+    quote: SwapQuote,
+    preimage: PaymentPreimage,
+    delivered: BTreeSet<ChunkId>,
+    peer_limits: PeerLimits,
+    state: SwapState,
+    events: Vec<StormEvent>,
+}
+
+impl SellerSwap {
+    /// Starts new swap session with the quote sent to the buyer and the
+    /// preimage of the quote invoice payment hash. Quotes which do not cover
+    /// any chunks are rejected, since they can't be delivered.
+    pub fn with(
+        app: StormApp,
+        quote: SwapQuote,
+        preimage: PaymentPreimage,
+    ) -> Result<Self, SwapError> {
+        if quote.chunk_ids.is_empty() {
+            return Err(SwapError::EmptyQuote);
+        }
+        preimage.verify(quote.payment_hash)?;
+        Ok(SellerSwap {
+            app,
+            quote,
+            preimage,
+            delivered: empty!(),
+            peer_limits: default!(),
+            state: SwapState::Quoted,
+            events: empty!(),
+        })
+    }
+
+    /// Applies limits declared by the buyer, which will be checked during
+    /// chunk delivery.
+    pub fn set_peer_limits(&mut self, limits: PeerLimits) {
+        self.peer_limits = limits;
+    }
+
+    pub fn app(&self) -> StormApp { self.app }
+
+    pub fn state(&self) -> SwapState { self.state }
+
+    pub fn quote(&self) -> &SwapQuote { &self.quote }
+
+    /// Takes events emitted by the swap since the previous call.
+    pub fn take_events(&mut self) -> Vec<StormEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn transition(&mut self, state: SwapState) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            app = %self.app,
+            container_id = %self.quote.container_id,
+            from = %self.state,
+            to = %state,
+            "seller swap state transition"
+        );
+        self.events.extend(transition_events(
+            self.app,
+            &self.quote,
+            self.state,
+            state,
+        ));
+        self.state = state;
+    }
+
+    /// Locks chunk data with the quote payment hash, producing message for
+    /// delivering it to the buyer.
+    pub fn deliver(
+        &mut self,
+        chunk: &Chunk,
+    ) -> Result<LockedChunkPush, SwapError> {
+        if !matches!(self.state, SwapState::Quoted | SwapState::Delivering) {
+            return Err(SwapError::InvalidState(self.state));
+        }
+        let chunk_id = chunk.chunk_id();
+        if !self.quote.chunk_ids.contains(&chunk_id) {
+            return Err(SwapError::UnexpectedChunk(chunk_id));
+        }
+        let locked_chunk = self
+            .preimage
+            .lock_chunk(chunk)
+            .map_err(|_| SwapError::ChunkTooLarge(chunk_id))?;
+        self.peer_limits.check_chunk(locked_chunk.len())?;
+        self.delivered.insert(chunk_id);
+        self.transition(
+            if self.delivered.len() == self.quote.chunk_ids.len() {
+                SwapState::Delivered
+            } else {
+                SwapState::Delivering
+            },
+        );
+        Ok(LockedChunkPush {
+            app: self.app,
+            container_id: self.quote.container_id,
+            chunk_id,
+            payment_hash: self.quote.payment_hash,
+            locked_chunk,
+        })
+    }
+
+    /// Registers payment received for the invoice with the given payment
+    /// hash and amount, as reported by the lightning node. The payment is
+    /// accepted only after all chunks are delivered.
+    pub fn confirm_payment(
+        &mut self,
+        payment_hash: PaymentHash,
+        amount_msat: u64,
+    ) -> Result<(), SwapError> {
+        if self.state != SwapState::Delivered {
+            return Err(SwapError::InvalidState(self.state));
+        }
+        if payment_hash != self.quote.payment_hash {
+            return Err(SwapError::PaymentHashMismatch(payment_hash));
+        }
+        if amount_msat < self.quote.amount_msat {
+            return Err(SwapError::Underpaid {
+                quoted: self.quote.amount_msat,
+                paid: amount_msat,
+            });
+        }
+        self.transition(SwapState::Paid);
+        Ok(())
+    }
+
+    /// Releases the key once the invoice is paid, completing the swap.
+    pub fn release_key(&mut self) -> Result<KeyReveal, SwapError> {
+        if self.state != SwapState::Paid {
+            return Err(SwapError::InvalidState(self.state));
+        }
+        self.transition(SwapState::Completed);
+        Ok(KeyReveal {
+            app: self.app,
+            container_id: self.quote.container_id,
+            payment_hash: self.quote.payment_hash,
+            preimage: self.preimage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Fixture {
+        chunks: Vec<Chunk>,
+        request: SwapRequest,
+        quote: SwapQuote,
+        preimage: PaymentPreimage,
+    }
+
+    fn fixture() -> Fixture {
+        let chunks = vec![
+            Chunk::try_from(b"first chunk".to_vec()).unwrap(),
+            Chunk::try_from(b"second chunk".to_vec()).unwrap(),
+        ];
+        let chunk_ids = chunks.iter().map(Chunk::chunk_id).collect();
+        let preimage = PaymentPreimage::from([7u8; 32]);
+        let request = SwapRequest {
+            container_id: ContainerId::default(),
+            chunk_ids,
+        };
+        let quote = SwapQuote {
+            container_id: request.container_id,
+            chunk_ids: request.chunk_ids.clone(),
+            amount_msat: 1000,
+            payment_hash: preimage.payment_hash(),
+            invoice: s!("lnbc..."),
+            expiry: 100,
+        };
+        Fixture {
+            chunks,
+            request,
+            quote,
+            preimage,
+        }
+    }
+
+    #[test]
+    fn test_swap() {
+        let Fixture {
+            chunks,
+            request,
+            quote,
+            preimage,
+        } = fixture();
+        let mut buyer = BuyerSwap::new(StormApp::Storage, request);
+        let mut seller =
+            SellerSwap::with(StormApp::Storage, quote.clone(), preimage)
+                .unwrap();
+        buyer.accept_quote(quote.clone(), 10).unwrap();
+        assert_eq!(buyer.state(), SwapState::Quoted);
+
+        let push = seller.deliver(&chunks[0]).unwrap();
+        assert_eq!(seller.state(), SwapState::Delivering);
+        assert!(!buyer.receive_locked(push).unwrap());
+        assert_eq!(
+            buyer.complete(preimage),
+            Err(SwapError::InvalidState(SwapState::Delivering))
+        );
+        let push = seller.deliver(&chunks[1]).unwrap();
+        assert!(buyer.receive_locked(push).unwrap());
+        assert_eq!(seller.state(), SwapState::Delivered);
+
+        // Key is not released before the payment
+        assert_eq!(
+            seller.release_key(),
+            Err(SwapError::InvalidState(SwapState::Delivered))
+        );
+        seller.confirm_payment(quote.payment_hash, 1000).unwrap();
+        assert_eq!(seller.state(), SwapState::Paid);
+        let reveal = seller.release_key().unwrap();
+        assert_eq!(reveal.preimage, preimage);
+        assert_eq!(seller.state(), SwapState::Completed);
+
+        let unlocked = buyer.complete(reveal.preimage).unwrap();
+        let expected = chunks
+            .iter()
+            .map(|chunk| (chunk.chunk_id(), chunk.clone()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(unlocked, expected);
+        assert_eq!(buyer.state(), SwapState::Completed);
+
+        let container_id = quote.container_id;
+        let payment_hash = quote.payment_hash;
+        let app = StormApp::Storage;
+        let expected = vec![
+            StormEvent::TransferStarted {
+                app,
+                container_id,
+                chunks: 2,
+            },
+            StormEvent::TransferCompleted { app, container_id },
+            StormEvent::AccessGranted {
+                app,
+                container_id,
+                payment_hash,
+            },
+        ];
+        assert_eq!(buyer.take_events(), expected);
+        assert_eq!(seller.take_events(), expected);
+        assert!(seller.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_quote_failures() {
+        let Fixture {
+            request,
+            quote,
+            preimage,
+            ..
+        } = fixture();
+        let mut buyer = BuyerSwap::new(StormApp::Storage, request);
+
+        let mut other = quote.clone();
+        other.chunk_ids.clear();
+        assert_eq!(
+            buyer.accept_quote(other.clone(), 10),
+            Err(SwapError::QuoteMismatch)
+        );
+        assert_eq!(
+            SellerSwap::with(StormApp::Storage, other, preimage).unwrap_err(),
+            SwapError::EmptyQuote
+        );
+        let mut empty = BuyerSwap::new(StormApp::Storage, SwapRequest {
+            container_id: quote.container_id,
+            chunk_ids: empty!(),
+        });
+        let mut empty_quote = quote.clone();
+        empty_quote.chunk_ids.clear();
+        assert_eq!(
+            empty.accept_quote(empty_quote, 10),
+            Err(SwapError::EmptyQuote)
+        );
+
+        assert_eq!(
+            buyer.accept_quote(quote.clone(), 100),
+            Err(SwapError::QuoteExpired(100))
+        );
+        assert_eq!(
+            SellerSwap::with(
+                StormApp::Storage,
+                quote.clone(),
+                PaymentPreimage::from([8u8; 32])
+            )
+            .unwrap_err(),
+            SwapError::HashLock(HashLockError::PreimageMismatch {
+                expected: quote.payment_hash
+            })
+        );
+        buyer.accept_quote(quote.clone(), 10).unwrap();
+        assert_eq!(
+            buyer.accept_quote(quote, 10),
+            Err(SwapError::InvalidState(SwapState::Quoted))
+        );
+    }
+
+    #[test]
+    fn test_delivery_failures() {
+        let Fixture {
+            chunks,
+            request,
+            quote,
+            preimage,
+        } = fixture();
+        let mut buyer = BuyerSwap::new(StormApp::Storage, request);
+        let mut seller =
+            SellerSwap::with(StormApp::Storage, quote.clone(), preimage)
+                .unwrap();
+
+        let push = seller.deliver(&chunks[0]).unwrap();
+        assert_eq!(
+            buyer.receive_locked(push.clone()),
+            Err(SwapError::InvalidState(SwapState::Requested))
+        );
+        buyer.accept_quote(quote.clone(), 10).unwrap();
+
+        let other = Chunk::try_from(b"other chunk".to_vec()).unwrap();
+        assert_eq!(
+            seller.deliver(&other),
+            Err(SwapError::UnexpectedChunk(other.chunk_id()))
+        );
+        let mut forged = push.clone();
+        forged.payment_hash = PaymentPreimage::from([8u8; 32]).payment_hash();
+        assert_eq!(
+            buyer.receive_locked(forged.clone()),
+            Err(SwapError::PaymentHashMismatch(forged.payment_hash))
+        );
+        let mut forged = push.clone();
+        forged.chunk_id = other.chunk_id();
+        assert_eq!(
+            buyer.receive_locked(forged),
+            Err(SwapError::UnexpectedChunk(other.chunk_id()))
+        );
+        assert!(!buyer.receive_locked(push).unwrap());
+
+        // Payment is not accepted before all chunks are delivered
+        assert_eq!(
+            seller.confirm_payment(quote.payment_hash, 1000),
+            Err(SwapError::InvalidState(SwapState::Delivering))
+        );
+        let push = seller.deliver(&chunks[1]).unwrap();
+        assert!(buyer.receive_locked(push).unwrap());
+        let other_hash = PaymentPreimage::from([8u8; 32]).payment_hash();
+        assert_eq!(
+            seller.confirm_payment(other_hash, 1000),
+            Err(SwapError::PaymentHashMismatch(other_hash))
+        );
+        assert_eq!(
+            seller.confirm_payment(quote.payment_hash, 999),
+            Err(SwapError::Underpaid {
+                quoted: 1000,
+                paid: 999
+            })
+        );
+        assert_eq!(seller.state(), SwapState::Delivered);
+
+        assert_eq!(
+            buyer.complete(PaymentPreimage::from([8u8; 32])),
+            Err(SwapError::HashLock(HashLockError::PreimageMismatch {
+                expected: quote.payment_hash
+            }))
+        );
+        assert_eq!(buyer.state(), SwapState::Delivered);
+    }
+}
@@ -0,0 +1,128 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Short labels used for discovery of topics and containers.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::str::FromStr;
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Maximal length of a tag, in characters.
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Maximal number of tags attached to a single topic or container.
+pub const MAX_TAGS: usize = 16;
+
+/// Errors parsing tags.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TagError {
+    /// tag can't be empty.
+    Empty,
+
+    /// tag has {0} characters, while at most 32 are allowed.
+    TooLong(usize),
+
+    /// tag contains invalid character '{0}'; only lowercase ASCII letters,
+    /// digits and '-' are allowed.
+    InvalidChar(char),
+
+    /// {0} tags are given, while at most 16 are allowed.
+    TooMany(usize),
+}
+
+/// Short lowercase ASCII label consisting of letters, digits and dashes.
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", try_from = "String", into = "String")
+)]
+#[display(inner)]
+pub struct Tag(String);
+
+impl Tag {
+    pub fn as_str(&self) -> &str { &self.0 }
+}
+
+impl FromStr for Tag {
+    type Err = TagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(TagError::Empty);
+        }
+        if s.len() > MAX_TAG_LEN {
+            return Err(TagError::TooLong(s.len()));
+        }
+        if let Some(c) = s.chars().find(|c| {
+            !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-')
+        }) {
+            return Err(TagError::InvalidChar(c));
+        }
+        Ok(Tag(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for Tag {
+    type Error = TagError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> { Tag::from_str(&s) }
+}
+
+impl From<Tag> for String {
+    fn from(tag: Tag) -> Self { tag.0 }
+}
+
+impl StrictEncode for Tag {
+    fn strict_encode<E: io::Write>(
+        &self,
+        e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        self.0.strict_encode(e)
+    }
+}
+
+impl StrictDecode for Tag {
+    fn strict_decode<D: io::Read>(
+        d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let s = String::strict_decode(d)?;
+        Tag::from_str(&s).map_err(|err| {
+            strict_encoding::Error::DataIntegrityError(err.to_string())
+        })
+    }
+}
+
+/// Checks that the number of tags does not exceed [`MAX_TAGS`].
+pub fn check_tags(tags: &BTreeSet<Tag>) -> Result<(), TagError> {
+    if tags.len() > MAX_TAGS {
+        return Err(TagError::TooMany(tags.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tag() {
+        assert_eq!(Tag::from_str("rust-lang").unwrap().as_str(), "rust-lang");
+        assert_eq!(Tag::from_str(""), Err(TagError::Empty));
+        assert_eq!(Tag::from_str("Rust"), Err(TagError::InvalidChar('R')));
+        assert_eq!(Tag::from_str(&"a".repeat(33)), Err(TagError::TooLong(33)));
+
+        let data = "bad tag".to_owned().strict_serialize().unwrap();
+        assert!(Tag::strict_deserialize(data).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use secp256k1::PublicKey;
+
+use crate::p2p::{AppMsg, ChunkPull};
+use crate::{ChunkId, ContainerFullId, ContainerId};
+
+/// Default time after which an unanswered request is considered expired, in
+/// milliseconds.
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30_000;
+
+/// Item requested from remote peers.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+pub enum RequestKey {
+    /// Container information requested with `PullContainer` message.
+    #[display("container({0})")]
+    Container(ContainerId),
+
+    /// Chunk data requested with `PullChunk` message.
+    #[display("chunk({0}, {1})")]
+    Chunk(ContainerId, ChunkId),
+}
+
+/// Classification of a response against the tracked requests.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Display)]
+pub enum ResponseMatch {
+    /// Response to an outstanding request.
+    #[display("expected")]
+    Expected,
+
+    /// Response to a request which has expired within the last timeout
+    /// period.
+    #[display("late")]
+    Late,
+
+    /// Response to an item which was never requested from the peer.
+    #[display("unsolicited")]
+    Unsolicited,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct Pending {
+    peer: PublicKey,
+    sent_ms: u64,
+}
+
+/// Tracks in-flight `PullChunk` and `PullContainer` requests to remote
+/// peers, suppressing duplicated requests for the same items and matching
+/// responses against them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RequestTracker {
+    timeout_ms: u64,
+    max_parallel: usize,
+    pending: BTreeMap<RequestKey, Vec<Pending>>,
+    /// Peers of the expired requests with the time of expiration, kept for
+    /// one more timeout period to match late responses.
+    expired: BTreeMap<RequestKey, BTreeMap<PublicKey, u64>>,
+}
+
+impl Default for RequestTracker {
+    fn default() -> Self { RequestTracker::new(DEFAULT_REQUEST_TIMEOUT_MS) }
+}
+
+impl RequestTracker {
+    /// Constructs tracker allowing a single outstanding request per item.
+    pub fn new(timeout_ms: u64) -> RequestTracker {
+        RequestTracker {
+            timeout_ms,
+            max_parallel: 1,
+            pending: empty!(),
+            expired: empty!(),
+        }
+    }
+
+    /// Sets maximum number of peers the same item may be simultaneously
+    /// requested from.
+    pub fn set_max_parallel(&mut self, max_parallel: usize) {
+        self.max_parallel = max_parallel.max(1);
+    }
+
+    pub fn timeout_ms(&self) -> u64 { self.timeout_ms }
+
+    pub fn is_pending(&self, key: RequestKey) -> bool {
+        self.pending.contains_key(&key)
+    }
+
+    /// Number of requests outstanding with the peer.
+    pub fn outstanding(&self, peer: PublicKey) -> usize {
+        self.pending
+            .values()
+            .flatten()
+            .filter(|pending| pending.peer == peer)
+            .count()
+    }
+
+    /// Registers request for the item, unless it is already requested from
+    /// the same peer or from the maximum allowed number of peers. Returns
+    /// whether the request should be sent.
+    pub fn register(
+        &mut self,
+        peer: PublicKey,
+        key: RequestKey,
+        now_ms: u64,
+    ) -> bool {
+        let pending = self.pending.entry(key).or_default();
+        if pending.len() >= self.max_parallel
+            || pending.iter().any(|pending| pending.peer == peer)
+        {
+            return false;
+        }
+        pending.push(Pending {
+            peer,
+            sent_ms: now_ms,
+        });
+        true
+    }
+
+    /// Registers chunk pull request, returning request containing only
+    /// chunks which are not already requested, or `None` if all of them are.
+    pub fn register_chunk_pull(
+        &mut self,
+        peer: PublicKey,
+        pull: &ChunkPull,
+        now_ms: u64,
+    ) -> Option<ChunkPull> {
+        let chunk_ids = pull
+            .chunk_ids
+            .iter()
+            .copied()
+            .filter(|chunk_id| {
+                self.register(
+                    peer,
+                    RequestKey::Chunk(pull.container_id, *chunk_id),
+                    now_ms,
+                )
+            })
+            .collect::<BTreeSet<_>>();
+        if chunk_ids.is_empty() {
+            return None;
+        }
+        Some(ChunkPull {
+            chunk_ids,
+            ..pull.clone()
+        })
+    }
+
+    /// Registers container pull request. Returns whether the request should
+    /// be sent.
+    pub fn register_container_pull(
+        &mut self,
+        peer: PublicKey,
+        pull: &AppMsg<ContainerFullId>,
+        now_ms: u64,
+    ) -> bool {
+        self.register(
+            peer,
+            RequestKey::Container(pull.data.container_id),
+            now_ms,
+        )
+    }
+
+    /// Matches response from the peer against tracked requests. Any response
+    /// for the item completes all outstanding requests for it.
+    pub fn complete(
+        &mut self,
+        peer: PublicKey,
+        key: RequestKey,
+    ) -> ResponseMatch {
+        let expected = self
+            .pending
+            .remove(&key)
+            .map(|pending| pending.iter().any(|pending| pending.peer == peer))
+            .unwrap_or_default();
+        let late = self
+            .expired
+            .remove(&key)
+            .map(|peers| peers.contains_key(&peer))
+            .unwrap_or_default();
+        match (expected, late) {
+            (true, _) => ResponseMatch::Expected,
+            (false, true) => ResponseMatch::Late,
+            (false, false) => ResponseMatch::Unsolicited,
+        }
+    }
+
+    /// Cancels all outstanding requests to the peer, e.g. when it
+    /// disconnects, returning affected items.
+    pub fn cancel_peer(&mut self, peer: PublicKey) -> Vec<RequestKey> {
+        let mut cancelled = vec![];
+        self.pending.retain(|key, pending| {
+            let len = pending.len();
+            pending.retain(|pending| pending.peer != peer);
+            if pending.len() != len {
+                cancelled.push(*key);
+            }
+            !pending.is_empty()
+        });
+        cancelled
+    }
+
+    /// Expires requests which were not answered within the timeout, returning
+    /// expired items together with the peers they were requested from.
+    /// Responses to the expired requests are matched as late for one more
+    /// timeout period; after that they are considered unsolicited.
+    pub fn expire(&mut self, now_ms: u64) -> Vec<(RequestKey, PublicKey)> {
+        let timeout_ms = self.timeout_ms;
+        self.expired.retain(|_, peers| {
+            peers.retain(|_, expired_ms| {
+                now_ms.saturating_sub(*expired_ms) < timeout_ms
+            });
+            !peers.is_empty()
+        });
+
+        let mut expired = vec![];
+        self.pending.retain(|key, pending| {
+            pending.retain(|pending| {
+                if now_ms.saturating_sub(pending.sent_ms) < timeout_ms {
+                    return true;
+                }
+                expired.push((*key, pending.peer));
+                false
+            });
+            !pending.is_empty()
+        });
+        for (key, peer) in &expired {
+            self.expired.entry(*key).or_default().insert(*peer, now_ms);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use commit_verify::TaggedHash;
+
+    use super::*;
+    use crate::fixtures::{alice, bob};
+
+    #[test]
+    fn test_tracker() {
+        let alice = alice();
+        let bob = bob();
+        let key = RequestKey::Container(ContainerId::default());
+
+        let mut tracker = RequestTracker::new(1000);
+        assert!(tracker.register(alice, key, 0));
+        assert!(!tracker.register(bob, key, 10));
+        assert_eq!(tracker.outstanding(alice), 1);
+
+        assert_eq!(tracker.expire(500), vec![]);
+        assert_eq!(tracker.expire(1000), vec![(key, alice)]);
+        assert!(tracker.register(bob, key, 1000));
+        assert_eq!(tracker.complete(alice, key), ResponseMatch::Late);
+        assert!(!tracker.is_pending(key));
+        assert_eq!(tracker.complete(bob, key), ResponseMatch::Unsolicited);
+    }
+
+    #[test]
+    fn test_late_window() {
+        let alice = alice();
+        let mut tracker = RequestTracker::new(1000);
+        for no in 0u8..100 {
+            let key = RequestKey::Container(ContainerId::hash(&[no]));
+            assert!(tracker.register(alice, key, no as u64 * 100));
+        }
+        assert_eq!(tracker.expire(5000).len(), 41);
+        assert_eq!(tracker.expired.len(), 41);
+        assert_eq!(tracker.expire(10_000).len(), 50);
+        assert_eq!(tracker.expired.len(), 50);
+        // Expired requests are forgotten after another timeout period
+        assert_eq!(tracker.expire(11_000).len(), 9);
+        assert_eq!(tracker.expired.len(), 9);
+        assert_eq!(
+            tracker.complete(
+                alice,
+                RequestKey::Container(ContainerId::hash(&[0]))
+            ),
+            ResponseMatch::Unsolicited
+        );
+        assert_eq!(
+            tracker.complete(
+                alice,
+                RequestKey::Container(ContainerId::hash(&[99]))
+            ),
+            ResponseMatch::Late
+        );
+        assert_eq!(tracker.expire(12_000), vec![]);
+        assert!(tracker.expired.is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use secp256k1::PublicKey;
+
+use crate::chunk::MAX_CHUNK_SIZE;
+use crate::p2p::ChunkPush;
+use crate::{
+    BandwidthLimit, ContainerId, LimitError, PeerLimits, TokenBucket,
+    TransferKey,
+};
+
+#[derive(Clone, Debug, Default)]
+struct PeerQueue {
+    pushes: VecDeque<ChunkPush>,
+    bucket: Option<TokenBucket>,
+}
+
+/// Queue of `PushChunk` responses pending for upload to remote peers.
+///
+/// Responses are scheduled round-robin between peers, such that a single
+/// greedy downloader can't starve other peers, and are subject to per-peer
+/// and global bandwidth limits. Responses for transfers with a
+/// [`TransferKey`] are encrypted when they are enqueued, and responses
+/// violating [`PeerLimits`] advertised by the peer are rejected.
+#[derive(Clone, Debug, Default)]
+pub struct UploadQueue {
+    global: Option<TokenBucket>,
+    peer_limit: Option<BandwidthLimit>,
+    peers: BTreeMap<PublicKey, PeerQueue>,
+    rotation: VecDeque<PublicKey>,
+    /// Keys of the transfers to each of the peers, with the counter of the
+    /// next message encrypted with the key.
+    keys: BTreeMap<(PublicKey, ContainerId), (TransferKey, u64)>,
+    limits: BTreeMap<PublicKey, PeerLimits>,
+}
+
+impl UploadQueue {
+    pub fn new() -> UploadQueue { UploadQueue::default() }
+
+    /// Limits total upload bandwidth to all peers.
+    pub fn set_global_limit(&mut self, limit: BandwidthLimit, now_ms: u64) {
+        self.global = Some(limit.token_bucket(now_ms));
+    }
+
+    /// Limits upload bandwidth to each of the peers.
+    pub fn set_peer_limit(&mut self, limit: BandwidthLimit, now_ms: u64) {
+        self.peer_limit = Some(limit);
+        for queue in self.peers.values_mut() {
+            queue.bucket = Some(limit.token_bucket(now_ms));
+        }
+    }
+
+    /// Applies limits advertised by the peer to the responses enqueued for
+    /// it.
+    pub fn set_limits(&mut self, peer: PublicKey, limits: PeerLimits) {
+        self.limits.insert(peer, limits);
+    }
+
+    /// Encrypts further responses to the peer with chunks of the container
+    /// with the transfer key. Setting the same key again keeps its message
+    /// counter, so nonces are never reused.
+    pub fn set_transfer_key(
+        &mut self,
+        peer: PublicKey,
+        container_id: ContainerId,
+        key: TransferKey,
+    ) {
+        let entry = self.keys.entry((peer, container_id)).or_insert((key, 0));
+        if entry.0 != key {
+            *entry = (key, 0);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.rotation.is_empty() }
+
+    /// Number of responses pending for the peer.
+    pub fn pending(&self, peer: PublicKey) -> usize {
+        self.peers
+            .get(&peer)
+            .map(|queue| queue.pushes.len())
+            .unwrap_or_default()
+    }
+
+    /// Adds response to the queue of the peer, encrypting it if the transfer
+    /// has a key. Fails if the (encrypted) chunk exceeds the chunk size limit
+    /// of the peer or the maximum chunk size.
+    pub fn enqueue(
+        &mut self,
+        peer: PublicKey,
+        push: ChunkPush,
+        now_ms: u64,
+    ) -> Result<(), LimitError> {
+        let len = push.chunk.len();
+        let push = match self.keys.get_mut(&(peer, push.container_id)) {
+            Some((key, counter)) => {
+                let push = key.seal_push(push, *counter).map_err(|_| {
+                    LimitError::ChunkTooLarge(len as u32, MAX_CHUNK_SIZE)
+                })?;
+                *counter += 1;
+                push
+            }
+            None => push,
+        };
+        if let Some(limits) = self.limits.get(&peer) {
+            limits.check_chunk(push.chunk.len())?;
+        }
+        let peer_limit = self.peer_limit;
+        let queue = self.peers.entry(peer).or_insert_with(|| PeerQueue {
+            pushes: empty!(),
+            bucket: peer_limit.map(|limit| limit.token_bucket(now_ms)),
+        });
+        if queue.pushes.is_empty() {
+            self.rotation.push_back(peer);
+        }
+        queue.pushes.push_back(push);
+        Ok(())
+    }
+
+    /// Drops all responses pending for the peer, e.g. when it disconnects.
+    pub fn remove_peer(&mut self, peer: PublicKey) -> usize {
+        self.rotation.retain(|p| *p != peer);
+        self.keys.retain(|(p, _), _| *p != peer);
+        self.limits.remove(&peer);
+        self.peers
+            .remove(&peer)
+            .map(|queue| queue.pushes.len())
+            .unwrap_or_default()
+    }
+
+    /// Takes next response which can be sent at time `now_ms` without
+    /// violating bandwidth limits.
+    pub fn next(&mut self, now_ms: u64) -> Option<(PublicKey, ChunkPush)> {
+        for _ in 0..self.rotation.len() {
+            let peer = self.rotation.pop_front()?;
+            let queue = self.peers.get_mut(&peer)?;
+            let len =
+                queue.pushes.front().map(|push| push.chunk.len() as u64)?;
+
+            let peer_ok = queue
+                .bucket
+                .as_mut()
+                .map(|bucket| {
+                    bucket.can_consume(len.min(bucket.burst()), now_ms)
+                })
+                .unwrap_or(true);
+            let global_ok = self
+                .global
+                .as_mut()
+                .map(|bucket| {
+                    bucket.can_consume(len.min(bucket.burst()), now_ms)
+                })
+                .unwrap_or(true);
+            if !peer_ok || !global_ok {
+                self.rotation.push_back(peer);
+                continue;
+            }
+
+            for bucket in queue.bucket.iter_mut().chain(self.global.iter_mut())
+            {
+                let amount = len.min(bucket.burst());
+                bucket.try_consume(amount, now_ms);
+            }
+            let push = queue.pushes.pop_front()?;
+            if !queue.pushes.is_empty() {
+                self.rotation.push_back(peer);
+            }
+            return Some((peer, push));
+        }
+        None
+    }
+
+    /// Takes all responses which can be sent at time `now_ms`.
+    pub fn drain_ready(&mut self, now_ms: u64) -> Vec<(PublicKey, ChunkPush)> {
+        let mut ready = vec![];
+        while let Some(item) = self.next(now_ms) {
+            ready.push(item);
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{alice, bob};
+    use crate::{Chunk, ChunkId, StormApp};
+
+    fn push(len: usize) -> ChunkPush {
+        ChunkPush {
+            app: StormApp::Storage,
+            container_id: ContainerId::default(),
+            chunk_id: ChunkId::default(),
+            chunk: Chunk::try_from(vec![0u8; len]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_fair_queue() {
+        let alice = alice();
+        let bob = bob();
+
+        let mut queue = UploadQueue::new();
+        for _ in 0..3 {
+            queue.enqueue(alice, push(10), 0).unwrap();
+        }
+        queue.enqueue(bob, push(10), 0).unwrap();
+        let order = queue
+            .drain_ready(0)
+            .into_iter()
+            .map(|(peer, _)| peer)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![alice, bob, alice, alice]);
+
+        queue.set_global_limit(
+            BandwidthLimit {
+                bytes_per_sec: 10,
+                burst: 10,
+            },
+            0,
+        );
+        queue.enqueue(alice, push(10), 0).unwrap();
+        queue.enqueue(bob, push(10), 0).unwrap();
+        assert_eq!(queue.drain_ready(0).len(), 1);
+        assert_eq!(queue.drain_ready(500).len(), 0);
+        assert_eq!(queue.drain_ready(1000).len(), 1);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_peer_limits() {
+        let alice = alice();
+
+        let mut queue = UploadQueue::new();
+        queue.set_limits(alice, PeerLimits {
+            max_chunk_size: 16,
+            ..default!()
+        });
+        queue.enqueue(alice, push(16), 0).unwrap();
+        assert_eq!(
+            queue.enqueue(alice, push(17), 0),
+            Err(LimitError::ChunkTooLarge(17, 16))
+        );
+
+        // Limits apply to the encrypted chunk size
+        let key =
+            TransferKey::derive(&[0x42; 32], ContainerId::default(), alice);
+        queue.set_transfer_key(alice, ContainerId::default(), key);
+        assert_eq!(
+            queue.enqueue(alice, push(16), 0),
+            Err(LimitError::ChunkTooLarge(40, 16))
+        );
+        assert_eq!(queue.pending(alice), 1);
+    }
+
+    #[test]
+    fn test_transfer_encryption() {
+        let alice = alice();
+        let bob = bob();
+        let container_id = ContainerId::default();
+        let key = TransferKey::derive(&[0x42; 32], container_id, bob);
+
+        let mut queue = UploadQueue::new();
+        queue.set_transfer_key(alice, container_id, key);
+        queue.enqueue(alice, push(10), 0).unwrap();
+        queue.set_transfer_key(alice, container_id, key);
+        queue.enqueue(alice, push(10), 0).unwrap();
+        queue.enqueue(bob, push(10), 0).unwrap();
+        let ready = queue.drain_ready(0);
+        assert_eq!(ready.len(), 3);
+
+        let (first, second) = (&ready[0].1, &ready[2].1);
+        assert_eq!(ready[1].1, push(10));
+        assert_ne!(first.chunk, second.chunk);
+        assert_eq!(key.open_push(first.clone()).unwrap(), push(10));
+        assert_eq!(key.open_push(second.clone()).unwrap(), push(10));
+    }
+}
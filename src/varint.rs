@@ -0,0 +1,234 @@
+// Storm Core library: distributed storage & messaging for lightning network.
+//
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2022 by LNP/BP Standards Association, Switzerland.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Variable-length integer encodings for compact protocol structures.
+//!
+//! Strict encoding uses fixed-size integers and 16-bit length prefixes,
+//! which dominate the size of small frequent messages such as receipts,
+//! acknowledgements and presence notifications. New protocol structures
+//! (like storage and retrieval receipts) use the types from this module for
+//! their counters, sizes and lists:
+//!
+//! - [`VarInt`] encodes `u64` as LEB128: seven bits per byte, least significant
+//!   group first, with the high bit set on all bytes except the last one.
+//!   Values below 128 take a single byte.
+//! - [`VarVec`] is a list prefixed with its length encoded as [`VarInt`].
+//!
+//! Following strict encoding rules, each value has a single valid encoding:
+//! decoders reject encodings with redundant trailing zero groups and values
+//! overflowing `u64`.
+
+use std::io;
+use std::ops::Deref;
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Maximal length of the [`VarInt`] encoding, in bytes.
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Maximal number of items in a [`VarVec`].
+pub const MAX_VARVEC_ITEMS: usize = u32::MAX as usize;
+
+/// Unsigned integer with variable-length encoding.
+#[derive(
+    Wrapper, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default,
+    Display, From
+)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[display(inner)]
+#[wrapper(FromStr, LowerHex, UpperHex)]
+pub struct VarInt(u64);
+
+impl VarInt {
+    /// Number of bytes taken by the value encoding.
+    pub fn encoded_len(self) -> usize {
+        let bits = 64 - self.0.leading_zeros() as usize;
+        (bits.max(1) + 6) / 7
+    }
+}
+
+impl From<usize> for VarInt {
+    fn from(value: usize) -> Self { VarInt(value as u64) }
+}
+
+impl From<u32> for VarInt {
+    fn from(value: u32) -> Self { VarInt(value as u64) }
+}
+
+impl From<u16> for VarInt {
+    fn from(value: u16) -> Self { VarInt(value as u64) }
+}
+
+impl StrictEncode for VarInt {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        let mut value = self.0;
+        let mut len = 0;
+        loop {
+            buf[len] = (value & 0x7F) as u8;
+            value >>= 7;
+            len += 1;
+            if value == 0 {
+                break;
+            }
+            buf[len - 1] |= 0x80;
+        }
+        e.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
+
+impl StrictDecode for VarInt {
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let mut value = 0u64;
+        for index in 0..MAX_VARINT_LEN {
+            let byte = u8::strict_decode(&mut d)?;
+            let group = (byte & 0x7F) as u64;
+            let shift = index as u32 * 7;
+            if shift == 63 && group > 1 {
+                return Err(strict_encoding::Error::DataIntegrityError(s!(
+                    "varint value overflows u64"
+                )));
+            }
+            value |= group << shift;
+            if byte & 0x80 == 0 {
+                if index > 0 && group == 0 {
+                    return Err(strict_encoding::Error::DataIntegrityError(
+                        s!("non-canonical varint encoding"),
+                    ));
+                }
+                return Ok(VarInt(value));
+            }
+        }
+        Err(strict_encoding::Error::DataIntegrityError(s!(
+            "varint encoding exceeds 10 bytes"
+        )))
+    }
+}
+
+/// List of items prefixed with its length encoded as [`VarInt`].
+#[derive(Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Default, From)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct VarVec<T>(Vec<T>);
+
+impl<T> Deref for VarVec<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl<T> VarVec<T> {
+    pub fn new() -> VarVec<T> { VarVec(vec![]) }
+
+    pub fn into_inner(self) -> Vec<T> { self.0 }
+
+    /// Adds item to the list, failing if the list is full.
+    pub fn push(&mut self, item: T) -> Result<(), strict_encoding::Error> {
+        if self.0.len() >= MAX_VARVEC_ITEMS {
+            return Err(strict_encoding::Error::ExceedMaxItems(
+                self.0.len() + 1,
+            ));
+        }
+        self.0.push(item);
+        Ok(())
+    }
+}
+
+impl<T> FromIterator<T> for VarVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        VarVec(iter.into_iter().collect())
+    }
+}
+
+impl<T> StrictEncode for VarVec<T>
+where T: StrictEncode
+{
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        if self.0.len() > MAX_VARVEC_ITEMS {
+            return Err(strict_encoding::Error::ExceedMaxItems(self.0.len()));
+        }
+        let mut len = VarInt::from(self.0.len()).strict_encode(&mut e)?;
+        for item in &self.0 {
+            len += item.strict_encode(&mut e)?;
+        }
+        Ok(len)
+    }
+}
+
+impl<T> StrictDecode for VarVec<T>
+where T: StrictDecode
+{
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let count = VarInt::strict_decode(&mut d)?.into_inner();
+        if count > MAX_VARVEC_ITEMS as u64 {
+            return Err(strict_encoding::Error::ExceedMaxItems(count as usize));
+        }
+        // Do not trust the length prefix for pre-allocation
+        let mut items = Vec::with_capacity((count as usize).min(1024));
+        for _ in 0..count {
+            items.push(T::strict_decode(&mut d)?);
+        }
+        Ok(VarVec(items))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for (value, encoding) in [
+            (0u64, vec![0x00u8]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x01]),
+            (300, vec![0xAC, 0x02]),
+            (u64::MAX, vec![
+                0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01,
+            ]),
+        ] {
+            let varint = VarInt::from(value);
+            assert_eq!(varint.strict_serialize().unwrap(), encoding);
+            assert_eq!(varint.encoded_len(), encoding.len());
+            assert_eq!(VarInt::strict_deserialize(&encoding).unwrap(), varint);
+        }
+
+        assert!(VarInt::strict_deserialize([0x80, 0x00]).is_err());
+        let mut overflow = vec![0xFF; 9];
+        overflow.push(0x02);
+        assert!(VarInt::strict_deserialize(overflow).is_err());
+    }
+
+    #[test]
+    fn test_varvec() {
+        let list = (0u16..3).collect::<VarVec<_>>();
+        let data = list.strict_serialize().unwrap();
+        assert_eq!(data, vec![3, 0, 0, 1, 0, 2, 0]);
+        assert_eq!(VarVec::<u16>::strict_deserialize(&data).unwrap(), list);
+    }
+}